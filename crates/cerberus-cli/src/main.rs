@@ -1,9 +1,24 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use cerberus_daemon::get_socket_path;
+use cerberus_store::database::{self, Backend, FileBackend, SqliteBackend};
 use tokio::{io::AsyncWriteExt, net::UnixStream};
 
+const USAGE: &str = "usage: cerberus migrate-backend <from> <to>
+
+backends are written as sqlite:<path> or files:<directory>";
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.as_slice() {
+        [command, from, to] if command == "migrate-backend" => migrate_backend(from, to).await,
+        [command, ..] if command == "migrate-backend" => bail!(USAGE),
+        _ => send_to_daemon().await,
+    }
+}
+
+async fn send_to_daemon() -> Result<()> {
     let stream = UnixStream::connect(get_socket_path()).await?;
     let (reader, mut writer) = stream.into_split();
 
@@ -11,3 +26,24 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Copies the store at `from` to `to`, which must not hold a store yet.
+async fn migrate_backend(from: &str, to: &str) -> Result<()> {
+    let from = open_backend(from, false).await?;
+    let to = open_backend(to, true).await?;
+
+    database::migrate(&*from, &*to).await?;
+
+    Ok(())
+}
+
+/// Opens the backend at `location`, creating it only if `create` is set.
+async fn open_backend(location: &str, create: bool) -> Result<Box<dyn Backend>> {
+    match (location.split_once(':'), create) {
+        (Some(("sqlite", path)), true) => Ok(Box::new(SqliteBackend::new(path).await?)),
+        (Some(("sqlite", path)), false) => Ok(Box::new(SqliteBackend::open(path).await?)),
+        (Some(("files", directory)), true) => Ok(Box::new(FileBackend::new(directory).await?)),
+        (Some(("files", directory)), false) => Ok(Box::new(FileBackend::open(directory).await?)),
+        _ => bail!("unknown backend {location}\n\n{USAGE}"),
+    }
+}
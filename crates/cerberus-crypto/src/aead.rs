@@ -0,0 +1,88 @@
+use chacha20poly1305::{
+    XChaCha20Poly1305,
+    aead::{Aead, AeadCore, KeyInit, Payload, generic_array::typenum::Unsigned},
+};
+use rand::rngs::OsRng;
+
+use crate::{CipherError, header::AeadAlgorithm};
+
+/// Encrypts `plaintext` with a freshly generated nonce, returning the nonce
+/// and the ciphertext.
+pub(crate) fn seal(
+    algorithm: AeadAlgorithm,
+    key: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), CipherError> {
+    match algorithm {
+        AeadAlgorithm::XChaCha20Poly1305 => seal_with::<XChaCha20Poly1305>(key, aad, plaintext),
+        #[cfg(feature = "aes-gcm-siv")]
+        AeadAlgorithm::Aes256GcmSiv => seal_with::<aes_gcm_siv::Aes256GcmSiv>(key, aad, plaintext),
+        #[allow(unreachable_patterns)]
+        unsupported => Err(CipherError::UnsupportedAlgorithm(unsupported)),
+    }
+}
+
+pub(crate) fn open(
+    algorithm: AeadAlgorithm,
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, CipherError> {
+    match algorithm {
+        AeadAlgorithm::XChaCha20Poly1305 => {
+            open_with::<XChaCha20Poly1305>(key, nonce, aad, ciphertext)
+        }
+        #[cfg(feature = "aes-gcm-siv")]
+        AeadAlgorithm::Aes256GcmSiv => {
+            open_with::<aes_gcm_siv::Aes256GcmSiv>(key, nonce, aad, ciphertext)
+        }
+        #[allow(unreachable_patterns)]
+        unsupported => Err(CipherError::UnsupportedAlgorithm(unsupported)),
+    }
+}
+
+fn seal_with<A: Aead + AeadCore + KeyInit>(
+    key: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<(Vec<u8>, Vec<u8>), CipherError> {
+    let cipher = A::new_from_slice(key).map_err(|_| CipherError::OperationFailed)?;
+    let nonce = A::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| CipherError::OperationFailed)?;
+
+    Ok((nonce.to_vec(), ciphertext))
+}
+
+fn open_with<A: Aead + AeadCore + KeyInit>(
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, CipherError> {
+    // from_slice panics on a length mismatch, the nonce comes from storage so
+    // it has to be checked first
+    if nonce.len() != A::NonceSize::USIZE {
+        return Err(CipherError::OperationFailed);
+    }
+
+    let cipher = A::new_from_slice(key).map_err(|_| CipherError::OperationFailed)?;
+    cipher
+        .decrypt(
+            chacha20poly1305::aead::Nonce::<A>::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CipherError::OperationFailed)
+}
@@ -0,0 +1,413 @@
+use std::{fmt, marker::PhantomData};
+
+use cerberus_secret::{ExposeSecret, SecretSlice};
+use cerberus_serde::{base64, base64_expose_secret};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::{
+    CipherError, KeyIdentifier, KeyMismatchError, NewKey, encoding, header::FormatVersion, hpke,
+};
+
+// binds sealed boxes to this use, a ciphertext sealed by another HPKE user
+// can't be passed off as one
+const SEALED_BOX_INFO: &[u8] = b"cerberus.sealed_box.v1";
+
+const KEY_SIZE: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum SignatureError {
+    #[error(transparent)]
+    KeyMismatch(#[from] KeyMismatchError),
+
+    #[error("invalid verifying key")]
+    InvalidKey,
+
+    #[error("invalid signature")]
+    InvalidSignature,
+}
+
+/// Private half of an X25519 key pair, data sealed to its
+/// [`AgreementPublicKey`] can only be opened with it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AgreementKey {
+    #[serde(
+        serialize_with = "base64_expose_secret::serialize",
+        deserialize_with = "deserialize_secret_key"
+    )]
+    key: SecretSlice<u8>,
+    id: KeyIdentifier,
+}
+
+impl AgreementKey {
+    pub fn id(&self) -> &KeyIdentifier {
+        &self.id
+    }
+
+    pub fn public_key(&self) -> AgreementPublicKey {
+        let public = x25519_dalek::PublicKey::from(&self.secret());
+
+        AgreementPublicKey {
+            key: public.to_bytes(),
+            id: self.id.clone(),
+        }
+    }
+
+    pub fn open<T: DeserializeOwned>(&self, sealed: &SealedBox<T>) -> Result<T, CipherError> {
+        self.id.verify_identifier(&sealed.key_id)?;
+
+        let plaintext = hpke::open(
+            &self.secret(),
+            &sealed.enc,
+            SEALED_BOX_INFO,
+            &[sealed.version.into()],
+            &sealed.ciphertext,
+        )?;
+
+        encoding::decode(sealed.version, &plaintext)
+    }
+
+    fn secret(&self) -> x25519_dalek::StaticSecret {
+        x25519_dalek::StaticSecret::from(*secret_bytes(&self.key))
+    }
+}
+
+impl NewKey for AgreementKey {
+    const KEY_SIZE: usize = KEY_SIZE;
+
+    fn new_unchecked(key: SecretSlice<u8>, id: KeyIdentifier) -> Self {
+        Self { key, id }
+    }
+}
+
+/// Public half of an X25519 key pair, shared with the profiles that seal data
+/// for its owner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgreementPublicKey {
+    #[serde(with = "base64")]
+    key: [u8; KEY_SIZE],
+    id: KeyIdentifier,
+}
+
+impl AgreementPublicKey {
+    pub fn from_bytes(key: [u8; KEY_SIZE], id: KeyIdentifier) -> Self {
+        Self { key, id }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE] {
+        &self.key
+    }
+
+    pub fn id(&self) -> &KeyIdentifier {
+        &self.id
+    }
+
+    /// Encrypts `data` so that only the owner of the matching
+    /// [`AgreementKey`] can decrypt it. Every box uses a fresh ephemeral key,
+    /// the sender can't open it again.
+    pub fn seal<T: Serialize>(&self, data: &T) -> Result<SealedBox<T>, CipherError> {
+        let version = FormatVersion::CURRENT;
+        let plaintext = encoding::encode(version, data)?;
+
+        let (enc, ciphertext) = hpke::seal(
+            x25519_dalek::StaticSecret::random_from_rng(OsRng),
+            &x25519_dalek::PublicKey::from(self.key),
+            SEALED_BOX_INFO,
+            &[version.into()],
+            &plaintext,
+        )?;
+
+        Ok(SealedBox {
+            version,
+            enc,
+            ciphertext,
+            key_id: self.id.clone(),
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// Data encrypted to an [`AgreementPublicKey`] with HPKE.
+#[derive(Serialize, Deserialize)]
+pub struct SealedBox<T> {
+    version: FormatVersion,
+    #[serde(with = "base64")]
+    enc: [u8; hpke::N_ENC],
+    #[serde(with = "base64")]
+    ciphertext: Vec<u8>,
+    key_id: KeyIdentifier,
+    #[serde(skip)]
+    _phantom: PhantomData<T>,
+}
+
+// derived impls would require the same traits of `T`, which is only ever
+// present encrypted
+impl<T> fmt::Debug for SealedBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SealedBox")
+            .field("version", &self.version)
+            .field("key_id", &self.key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> Clone for SealedBox<T> {
+    fn clone(&self) -> Self {
+        Self {
+            version: self.version,
+            enc: self.enc,
+            ciphertext: self.ciphertext.clone(),
+            key_id: self.key_id.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> SealedBox<T> {
+    /// Identifier of the key pair the box was sealed to.
+    pub fn key_id(&self) -> &KeyIdentifier {
+        &self.key_id
+    }
+}
+
+/// Ed25519 signing key, stored as its 32 byte seed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SigningKey {
+    #[serde(
+        serialize_with = "base64_expose_secret::serialize",
+        deserialize_with = "deserialize_secret_key"
+    )]
+    key: SecretSlice<u8>,
+    id: KeyIdentifier,
+}
+
+impl SigningKey {
+    pub fn id(&self) -> &KeyIdentifier {
+        &self.id
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey {
+            key: self.signing_key().verifying_key().to_bytes(),
+            id: self.id.clone(),
+        }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        use ed25519_dalek::Signer;
+
+        Signature {
+            signature: self.signing_key().sign(message).to_bytes(),
+            key_id: self.id.clone(),
+        }
+    }
+
+    fn signing_key(&self) -> ed25519_dalek::SigningKey {
+        ed25519_dalek::SigningKey::from_bytes(&secret_bytes(&self.key))
+    }
+}
+
+impl NewKey for SigningKey {
+    const KEY_SIZE: usize = KEY_SIZE;
+
+    fn new_unchecked(key: SecretSlice<u8>, id: KeyIdentifier) -> Self {
+        Self { key, id }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerifyingKey {
+    #[serde(with = "base64")]
+    key: [u8; KEY_SIZE],
+    id: KeyIdentifier,
+}
+
+impl VerifyingKey {
+    pub fn from_bytes(key: [u8; KEY_SIZE], id: KeyIdentifier) -> Self {
+        Self { key, id }
+    }
+
+    pub fn as_bytes(&self) -> &[u8; KEY_SIZE] {
+        &self.key
+    }
+
+    pub fn id(&self) -> &KeyIdentifier {
+        &self.id
+    }
+
+    /// Checks `signature` over `message`. Signatures are verified strictly,
+    /// so a valid signature can't be altered into another valid one.
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        self.id.verify_identifier(&signature.key_id)?;
+
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&self.key)
+            .map_err(|_| SignatureError::InvalidKey)?;
+        key.verify_strict(
+            message,
+            &ed25519_dalek::Signature::from_bytes(&signature.signature),
+        )
+        .map_err(|_| SignatureError::InvalidSignature)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Signature {
+    #[serde(with = "base64")]
+    signature: [u8; 64],
+    key_id: KeyIdentifier,
+}
+
+impl Signature {
+    pub fn key_id(&self) -> &KeyIdentifier {
+        &self.key_id
+    }
+}
+
+fn secret_bytes(key: &SecretSlice<u8>) -> Zeroizing<[u8; KEY_SIZE]> {
+    let mut bytes = Zeroizing::new([0u8; KEY_SIZE]);
+    // the length is checked when the key is created or deserialized
+    bytes.copy_from_slice(key.expose_secret());
+
+    bytes
+}
+
+fn deserialize_secret_key<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<SecretSlice<u8>, D::Error> {
+    let key = base64_expose_secret::deserialize(deserializer)?;
+    let len = key.expose_secret().len();
+    if len != KEY_SIZE {
+        return Err(serde::de::Error::invalid_length(len, &"a 32 byte key"));
+    }
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agreement_key() -> AgreementKey {
+        AgreementKey::generate(&mut OsRng, KeyIdentifier::uuid())
+    }
+
+    #[test]
+    fn sealed_box_round_trip() {
+        let recipient = agreement_key();
+        let sealed = recipient
+            .public_key()
+            .seal(&String::from("vault key"))
+            .unwrap();
+
+        assert_eq!(recipient.open(&sealed).unwrap(), "vault key");
+    }
+
+    #[test]
+    fn sealed_box_only_opens_for_recipient() {
+        let recipient = agreement_key();
+        let sealed = recipient.public_key().seal(&42u32).unwrap();
+
+        let other = agreement_key();
+        assert!(matches!(
+            other.open(&sealed),
+            Err(CipherError::KeyMismatch(_))
+        ));
+
+        // same identifier, different key
+        let impostor = AgreementKey::generate(&mut OsRng, recipient.id().clone());
+        assert!(matches!(
+            impostor.open(&sealed),
+            Err(CipherError::OperationFailed)
+        ));
+
+        let mut tampered = sealed.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(recipient.open(&tampered).is_err());
+
+        // a low order point as the ephemeral key
+        let mut low_order = sealed;
+        low_order.enc = [0u8; hpke::N_ENC];
+        assert!(matches!(
+            recipient.open(&low_order),
+            Err(CipherError::OperationFailed)
+        ));
+    }
+
+    #[test]
+    fn opens_hpke_from_another_implementation() {
+        // produced with the HPKE implementation of pyca/cryptography for the
+        // same suite, the key is 0x00..=0x1f
+        let secret = x25519_dalek::StaticSecret::from(std::array::from_fn(|i| i as u8));
+        let sealed = hex::decode(concat!(
+            "83c284bcb4b861fe42b2810e38307c9689fc920b22c14b1a9e5acee5b9caff15",
+            "2f22288acf99d90f89020bfdc67416384788fd300bdd541f20664351c51947ef",
+            "a8b2e802d5297d84cf05",
+        ))
+        .unwrap();
+        let (enc, ciphertext) = sealed.split_at(hpke::N_ENC);
+
+        let plaintext = hpke::open(
+            &secret,
+            enc.try_into().unwrap(),
+            b"cerberus test",
+            b"",
+            ciphertext,
+        )
+        .unwrap();
+        assert_eq!(plaintext.as_slice(), b"sealed for another profile");
+    }
+
+    #[test]
+    fn keys_serialize_with_identifier() {
+        let key = agreement_key();
+        let json = serde_json::to_string(&key).unwrap();
+        let restored: AgreementKey = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(restored.id(), key.id());
+        assert_eq!(restored.public_key(), key.public_key());
+
+        let mut short: serde_json::Value = serde_json::from_str(&json).unwrap();
+        short["key"] = "AAAA".into();
+        assert!(serde_json::from_value::<AgreementKey>(short).is_err());
+    }
+
+    #[test]
+    fn signs_rfc_8032_test_vector() {
+        let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+            .unwrap();
+        let key = SigningKey::new(seed.into(), KeyIdentifier::uuid()).unwrap();
+
+        assert_eq!(
+            hex::encode(key.verifying_key().as_bytes()),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        assert_eq!(
+            hex::encode(key.sign(b"").signature),
+            concat!(
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155",
+                "5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            )
+        );
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let key = SigningKey::generate(&mut OsRng, KeyIdentifier::uuid());
+        let verifying_key = key.verifying_key();
+        let signature = key.sign(b"export");
+
+        verifying_key.verify(b"export", &signature).unwrap();
+        assert!(matches!(
+            verifying_key.verify(b"exports", &signature),
+            Err(SignatureError::InvalidSignature)
+        ));
+
+        let other = SigningKey::generate(&mut OsRng, KeyIdentifier::uuid());
+        assert!(matches!(
+            other.verifying_key().verify(b"export", &signature),
+            Err(SignatureError::KeyMismatch(_))
+        ));
+    }
+}
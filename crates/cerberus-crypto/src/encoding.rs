@@ -0,0 +1,65 @@
+use std::io;
+
+use serde::{Serialize, de::DeserializeOwned};
+use zeroize::Zeroizing;
+
+use crate::{CipherError, header::FormatVersion};
+
+/// Serializes a plaintext with the encoding selected by `version`. The
+/// buffer is wiped when dropped.
+pub fn encode<T: Serialize + ?Sized>(
+    version: FormatVersion,
+    data: &T,
+) -> Result<Zeroizing<Vec<u8>>, CipherError> {
+    match version {
+        FormatVersion::Legacy | FormatVersion::V1 => {
+            let mut counter = ByteCounter(0);
+            serde_json::to_writer(&mut counter, data)
+                .map_err(|_| CipherError::SerializationError)?;
+
+            let mut buffer = Zeroizing::new(Vec::with_capacity(counter.0));
+            serde_json::to_writer(&mut *buffer, data)
+                .map_err(|_| CipherError::SerializationError)?;
+
+            Ok(buffer)
+        }
+        FormatVersion::V2 => {
+            let mut counter = ByteCounter(0);
+            ciborium::into_writer(data, &mut counter)
+                .map_err(|_| CipherError::SerializationError)?;
+
+            let mut buffer = Zeroizing::new(Vec::with_capacity(counter.0));
+            ciborium::into_writer(data, &mut *buffer)
+                .map_err(|_| CipherError::SerializationError)?;
+
+            Ok(buffer)
+        }
+    }
+}
+
+pub fn decode<T: DeserializeOwned>(version: FormatVersion, data: &[u8]) -> Result<T, CipherError> {
+    match version {
+        FormatVersion::Legacy | FormatVersion::V1 => {
+            serde_json::from_slice(data).map_err(|_| CipherError::SerializationError)
+        }
+        FormatVersion::V2 => {
+            ciborium::from_reader(data).map_err(|_| CipherError::SerializationError)
+        }
+    }
+}
+
+// Serializing twice, first only counting bytes, lets the real buffer be
+// allocated at its final size. A growing Vec would leave copies of the
+// plaintext behind in the allocations it frees.
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Layout of an [`EncryptedData`](crate::EncryptedData) record and the
+/// encoding used for its plaintext.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+#[repr(u8)]
+pub enum FormatVersion {
+    /// Records written before the header existed. The plaintext is JSON and
+    /// the header is not authenticated.
+    Legacy = 0,
+    /// JSON plaintext, the header is authenticated as associated data.
+    V1 = 1,
+    /// CBOR plaintext, the header is authenticated as associated data.
+    V2 = 2,
+}
+
+impl FormatVersion {
+    pub const CURRENT: FormatVersion = FormatVersion::V2;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+#[repr(u8)]
+pub enum AeadAlgorithm {
+    #[default]
+    XChaCha20Poly1305 = 1,
+    Aes256GcmSiv = 2,
+}
+
+impl AeadAlgorithm {
+    pub fn nonce_size(&self) -> usize {
+        match self {
+            AeadAlgorithm::XChaCha20Poly1305 => 24,
+            AeadAlgorithm::Aes256GcmSiv => 12,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        match self {
+            AeadAlgorithm::XChaCha20Poly1305 => true,
+            AeadAlgorithm::Aes256GcmSiv => cfg!(feature = "aes-gcm-siv"),
+        }
+    }
+}
+
+/// How the key that sealed a record was obtained.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+#[repr(u8)]
+pub enum KdfAlgorithm {
+    /// Not recorded, only found on [`FormatVersion::Legacy`] records.
+    Unspecified = 0,
+    /// Randomly generated key.
+    #[default]
+    None = 1,
+    HkdfSha256 = 2,
+    Argon2id = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Header {
+    pub(crate) version: FormatVersion,
+    pub(crate) aead: AeadAlgorithm,
+    pub(crate) kdf: KdfAlgorithm,
+}
+
+impl Header {
+    pub const LEGACY: Header = Header {
+        version: FormatVersion::Legacy,
+        aead: AeadAlgorithm::XChaCha20Poly1305,
+        kdf: KdfAlgorithm::Unspecified,
+    };
+
+    pub fn new(aead: AeadAlgorithm, kdf: KdfAlgorithm) -> Self {
+        Self {
+            version: FormatVersion::CURRENT,
+            aead,
+            kdf,
+        }
+    }
+
+    pub(crate) fn legacy() -> Self {
+        Self::LEGACY
+    }
+
+    pub fn version(&self) -> FormatVersion {
+        self.version
+    }
+
+    pub fn aead(&self) -> AeadAlgorithm {
+        self.aead
+    }
+
+    pub fn kdf(&self) -> KdfAlgorithm {
+        self.kdf
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.version == FormatVersion::CURRENT
+    }
+
+    /// Bytes bound to the ciphertext so that the header can't be swapped
+    /// without failing authentication. Legacy records were sealed without
+    /// any associated data.
+    pub(crate) fn associated_data(&self) -> Vec<u8> {
+        match self.version {
+            FormatVersion::Legacy => Vec::new(),
+            _ => vec![self.version as u8, self.aead as u8, self.kdf as u8],
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("unknown {kind} identifier: {value}")]
+pub struct UnknownIdentifierError {
+    kind: &'static str,
+    value: u8,
+}
+
+macro_rules! impl_u8_conversions {
+    ($ty:ident, $kind:literal, [$($variant:ident),+ $(,)?]) => {
+        impl From<$ty> for u8 {
+            fn from(value: $ty) -> Self {
+                value as u8
+            }
+        }
+
+        impl TryFrom<u8> for $ty {
+            type Error = UnknownIdentifierError;
+
+            fn try_from(value: u8) -> Result<Self, Self::Error> {
+                $(
+                    if value == $ty::$variant as u8 {
+                        return Ok($ty::$variant);
+                    }
+                )+
+
+                Err(UnknownIdentifierError { kind: $kind, value })
+            }
+        }
+    };
+}
+
+impl_u8_conversions!(FormatVersion, "format version", [Legacy, V1, V2]);
+impl_u8_conversions!(
+    AeadAlgorithm,
+    "aead algorithm",
+    [XChaCha20Poly1305, Aes256GcmSiv]
+);
+impl_u8_conversions!(
+    KdfAlgorithm,
+    "kdf",
+    [Unspecified, None, HkdfSha256, Argon2id]
+);
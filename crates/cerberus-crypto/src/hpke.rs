@@ -0,0 +1,175 @@
+//! HPKE base mode (RFC 9180) with DHKEM(X25519, HKDF-SHA256), HKDF-SHA256
+//! and ChaCha20Poly1305. Every context seals a single message, so only the
+//! base nonce of the key schedule is ever used.
+
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, Nonce,
+    aead::{Aead, KeyInit, Payload},
+};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use crate::{CipherError, kdf::HkdfSha256};
+
+const KEM_ID: u16 = 0x0020;
+const KDF_ID: u16 = 0x0001;
+const AEAD_ID: u16 = 0x0003;
+
+const MODE_BASE: u8 = 0x00;
+
+const N_SECRET: usize = 32;
+const N_H: usize = 32;
+const N_K: usize = 32;
+const N_N: usize = 12;
+
+/// Size of the encapsulated key sent along with the ciphertext.
+pub(crate) const N_ENC: usize = 32;
+
+type Prk = Zeroizing<[u8; N_H]>;
+
+fn kem_suite_id() -> Vec<u8> {
+    [b"KEM".as_slice(), &KEM_ID.to_be_bytes()].concat()
+}
+
+fn hpke_suite_id() -> Vec<u8> {
+    [
+        b"HPKE".as_slice(),
+        &KEM_ID.to_be_bytes(),
+        &KDF_ID.to_be_bytes(),
+        &AEAD_ID.to_be_bytes(),
+    ]
+    .concat()
+}
+
+fn labeled_extract(suite_id: &[u8], salt: &[u8], label: &[u8], ikm: &[u8]) -> Prk {
+    let labeled_ikm = Zeroizing::new([b"HPKE-v1", suite_id, label, ikm].concat());
+    let (prk, _) = HkdfSha256::extract(Some(salt), &labeled_ikm);
+
+    Zeroizing::new(prk.into())
+}
+
+fn labeled_expand(prk: &Prk, suite_id: &[u8], label: &[u8], info: &[u8], okm: &mut [u8]) {
+    let length = (okm.len() as u16).to_be_bytes();
+    let labeled_info = [&length, b"HPKE-v1".as_slice(), suite_id, label, info].concat();
+
+    HkdfSha256::from_prk(prk.as_slice())
+        .expect("the PRK is a full SHA-256 output")
+        .expand(&labeled_info, okm)
+        .expect("HPKE output lengths are far below the HKDF limit");
+}
+
+/// Runs the X25519 exchange and `ExtractAndExpand` of DHKEM, `enc` is the
+/// sender's ephemeral public key.
+fn shared_secret(
+    secret: &StaticSecret,
+    public: &PublicKey,
+    enc: &[u8; N_ENC],
+    recipient: &PublicKey,
+) -> Result<Zeroizing<[u8; N_SECRET]>, CipherError> {
+    let dh = secret.diffie_hellman(public);
+    // a low order public key gives an output anyone can compute
+    if !dh.was_contributory() {
+        return Err(CipherError::OperationFailed);
+    }
+
+    let suite_id = kem_suite_id();
+    let kem_context = [enc.as_slice(), recipient.as_bytes()].concat();
+    let eae_prk = labeled_extract(&suite_id, b"", b"eae_prk", dh.as_bytes());
+
+    let mut shared_secret = Zeroizing::new([0u8; N_SECRET]);
+    labeled_expand(
+        &eae_prk,
+        &suite_id,
+        b"shared_secret",
+        &kem_context,
+        shared_secret.as_mut(),
+    );
+
+    Ok(shared_secret)
+}
+
+/// Derives the AEAD key and base nonce of a base mode context.
+fn key_schedule(shared_secret: &[u8], info: &[u8]) -> (Zeroizing<[u8; N_K]>, [u8; N_N]) {
+    let suite_id = hpke_suite_id();
+
+    let psk_id_hash = labeled_extract(&suite_id, b"", b"psk_id_hash", b"");
+    let info_hash = labeled_extract(&suite_id, b"", b"info_hash", info);
+    let key_schedule_context =
+        [&[MODE_BASE], psk_id_hash.as_slice(), info_hash.as_slice()].concat();
+
+    // there is no PSK in base mode
+    let secret = labeled_extract(&suite_id, shared_secret, b"secret", b"");
+
+    let mut key = Zeroizing::new([0u8; N_K]);
+    labeled_expand(
+        &secret,
+        &suite_id,
+        b"key",
+        &key_schedule_context,
+        key.as_mut(),
+    );
+    let mut base_nonce = [0u8; N_N];
+    labeled_expand(
+        &secret,
+        &suite_id,
+        b"base_nonce",
+        &key_schedule_context,
+        &mut base_nonce,
+    );
+
+    (key, base_nonce)
+}
+
+/// Encrypts `plaintext` to `recipient` with the ephemeral key `ephemeral`,
+/// returning the encapsulated key and the ciphertext.
+pub(crate) fn seal(
+    ephemeral: StaticSecret,
+    recipient: &PublicKey,
+    info: &[u8],
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<([u8; N_ENC], Vec<u8>), CipherError> {
+    let enc = PublicKey::from(&ephemeral).to_bytes();
+    let shared_secret = shared_secret(&ephemeral, recipient, &enc, recipient)?;
+    let (key, base_nonce) = key_schedule(shared_secret.as_slice(), info);
+
+    let ciphertext = ChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+        .encrypt(
+            Nonce::from_slice(&base_nonce),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| CipherError::OperationFailed)?;
+
+    Ok((enc, ciphertext))
+}
+
+pub(crate) fn open(
+    recipient: &StaticSecret,
+    enc: &[u8; N_ENC],
+    info: &[u8],
+    aad: &[u8],
+    ciphertext: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, CipherError> {
+    let shared_secret = shared_secret(
+        recipient,
+        &PublicKey::from(*enc),
+        enc,
+        &PublicKey::from(recipient),
+    )?;
+    let (key, base_nonce) = key_schedule(shared_secret.as_slice(), info);
+
+    let plaintext = ChaCha20Poly1305::new(Key::from_slice(key.as_slice()))
+        .decrypt(
+            Nonce::from_slice(&base_nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| CipherError::OperationFailed)?;
+
+    Ok(Zeroizing::new(plaintext))
+}
@@ -1,4 +1,4 @@
-use cerberus_secret::{ExposeSecret, SecretSlice};
+use cerberus_secret::{ExposeSecret, ExposeSecretMut, SecretSlice};
 use hkdf::Hkdf;
 use serde::{Deserialize, Serialize};
 
@@ -12,21 +12,38 @@ pub struct DerivationMaterial {
     id: KeyIdentifier,
 }
 
-fn hkdf_extract(ikm: &[u8], label: &str, len: usize) -> SecretSlice<u8> {
-    let hkdf = HkdfSha256::new(None, ikm);
-    let mut okm = vec![0u8; len];
-    hkdf.expand(label.as_bytes(), &mut okm)
+/// HKDF salt used for every derivation. The input key material is always a
+/// uniformly random key, so a fixed, public salt is enough to separate these
+/// derivations from any other use of the same key (RFC 5869, section 3.1).
+const HKDF_SALT: &[u8] = b"cerberus.hkdf.v1";
+
+fn hkdf_derive(ikm: &[u8], info: &[u8], len: usize) -> SecretSlice<u8> {
+    let hkdf = HkdfSha256::new(Some(HKDF_SALT), ikm);
+    let mut okm = SecretSlice::from(vec![0u8; len]);
+    hkdf.expand(info, okm.expose_secret_mut())
         .expect("HKDF expand should not fail");
 
-    SecretSlice::from(okm)
+    okm
 }
 
-fn build_info(label: &str, suffix: &str) -> String {
-    format!("{}{}", label, suffix)
+/// Length-prefixes every part so that no two label and suffix pairs produce
+/// the same info.
+fn build_info(label: &str, suffix: &str) -> Vec<u8> {
+    let mut info = Vec::with_capacity(8 + label.len() + suffix.len());
+    for part in [label, suffix] {
+        info.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        info.extend_from_slice(part.as_bytes());
+    }
+
+    info
 }
 
 pub trait DeriveKey: NewKey {
     const MAC_INFO_SUFFIX: &'static str;
+
+    fn new_derived(key: SecretSlice<u8>, id: KeyIdentifier) -> Self {
+        Self::new_unchecked(key, id)
+    }
 }
 
 impl DerivationMaterial {
@@ -36,9 +53,11 @@ impl DerivationMaterial {
 
     pub fn derive_key<T: DeriveKey>(&self, label: &str) -> T {
         let kdf_info = build_info(label, T::MAC_INFO_SUFFIX);
-        let key = hkdf_extract(self.key.expose_secret(), &kdf_info, T::KEY_SIZE);
-        let id = KeyIdentifier::derived(kdf_info, Some(self.id.clone()));
+        let key = hkdf_derive(self.key.expose_secret(), &kdf_info, T::KEY_SIZE);
+        // only identifies the key, it is never used as HKDF input
+        let context = format!("{}{}", label, T::MAC_INFO_SUFFIX);
+        let id = KeyIdentifier::derived(context, Some(self.id.clone()));
 
-        T::new_unchecked(key, id)
+        T::new_derived(key, id)
     }
 }
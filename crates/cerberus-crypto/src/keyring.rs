@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use serde::{Serialize, de::DeserializeOwned};
+use thiserror::Error;
+
+use crate::{
+    Cipher, CipherError, EncryptedData, KeyIdentifier, KeyMismatchError, kdf::DeriveKey,
+    symmetric::SymmetricKey,
+};
+
+// wrapped keys pointing at each other would otherwise recurse forever
+const MAX_DEPTH: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum KeyringError {
+    #[error("keyring is locked")]
+    Locked,
+
+    #[error("no key with identifier {0:?} in the keyring")]
+    UnknownKey(KeyIdentifier),
+
+    #[error("key hierarchy is deeper than {MAX_DEPTH} levels")]
+    HierarchyTooDeep,
+
+    #[error(transparent)]
+    KeyMismatch(#[from] KeyMismatchError),
+
+    #[error(transparent)]
+    CipherError(#[from] CipherError),
+}
+
+/// Resolves the [`KeyIdentifier`] recorded in [`EncryptedData`] to the key
+/// that sealed it.
+///
+/// Keys form a hierarchy under a single root. Keys derived from a key in the
+/// keyring are derived again when needed, and wrapped keys are unwrapped with
+/// their parent, which is resolved the same way. Unwrapped keys are cached
+/// until the keyring is locked.
+#[derive(Debug)]
+pub struct Keyring {
+    root_id: KeyIdentifier,
+    wrapped: HashMap<KeyIdentifier, EncryptedData<SymmetricKey>>,
+    unlocked: HashMap<KeyIdentifier, SymmetricKey>,
+}
+
+impl Keyring {
+    pub fn new(root_id: KeyIdentifier) -> Self {
+        Self {
+            root_id,
+            wrapped: HashMap::new(),
+            unlocked: HashMap::new(),
+        }
+    }
+
+    pub fn root_id(&self) -> &KeyIdentifier {
+        &self.root_id
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.unlocked.contains_key(&self.root_id)
+    }
+
+    pub fn unlock(&mut self, root: SymmetricKey) -> Result<(), KeyringError> {
+        self.root_id.verify_identifier(root.id())?;
+        self.unlocked.insert(self.root_id.clone(), root);
+
+        Ok(())
+    }
+
+    /// Evicts the root and every key unwrapped or derived from it, the
+    /// wrapped keys are kept.
+    pub fn lock(&mut self) {
+        self.unlocked.clear();
+    }
+
+    /// Registers a wrapped key under `id`. The key that wrapped it is taken
+    /// from the identifier recorded in `wrapped`.
+    pub fn insert_wrapped(&mut self, id: KeyIdentifier, wrapped: EncryptedData<SymmetricKey>) {
+        // a replaced key must not be served from the cache
+        self.unlocked.remove(&id);
+        self.wrapped.insert(id, wrapped);
+    }
+
+    /// Wraps `key` with the key identified by `parent` and registers it,
+    /// returning the wrapped key so it can be persisted.
+    pub fn add_key(
+        &mut self,
+        key: SymmetricKey,
+        parent: &KeyIdentifier,
+    ) -> Result<EncryptedData<SymmetricKey>, KeyringError> {
+        let wrapped = self.key(parent)?.encrypt(&key)?;
+
+        let id = key.id().clone();
+        self.wrapped.insert(id.clone(), wrapped.clone());
+        self.unlocked.insert(id, key);
+
+        Ok(wrapped)
+    }
+
+    pub fn key(&mut self, id: &KeyIdentifier) -> Result<&SymmetricKey, KeyringError> {
+        self.resolve(id, 0)?;
+
+        Ok(self
+            .unlocked
+            .get(id)
+            .expect("resolve caches the key it resolved"))
+    }
+
+    pub fn encrypt<T: Serialize>(
+        &mut self,
+        id: &KeyIdentifier,
+        data: &T,
+    ) -> Result<EncryptedData<T>, KeyringError> {
+        Ok(self.key(id)?.encrypt(data)?)
+    }
+
+    pub fn decrypt<T: DeserializeOwned>(
+        &mut self,
+        data: &EncryptedData<T>,
+    ) -> Result<T, KeyringError> {
+        Ok(self.key(data.key_id())?.decrypt(data)?)
+    }
+
+    /// Decrypts `data` only if it was sealed by the key identified by
+    /// `expected`.
+    pub fn decrypt_with<T: DeserializeOwned>(
+        &mut self,
+        expected: &KeyIdentifier,
+        data: &EncryptedData<T>,
+    ) -> Result<T, KeyringError> {
+        expected.verify_identifier(data.key_id())?;
+        self.decrypt(data)
+    }
+
+    fn resolve(&mut self, id: &KeyIdentifier, depth: usize) -> Result<(), KeyringError> {
+        if self.unlocked.contains_key(id) {
+            return Ok(());
+        }
+        if !self.is_unlocked() {
+            return Err(KeyringError::Locked);
+        }
+        if depth == MAX_DEPTH {
+            return Err(KeyringError::HierarchyTooDeep);
+        }
+
+        let key = match id {
+            KeyIdentifier::Derived {
+                context,
+                derived_from: Some(parent),
+            } => {
+                let label = context
+                    .strip_suffix(SymmetricKey::MAC_INFO_SUFFIX)
+                    .ok_or_else(|| KeyringError::UnknownKey(id.clone()))?;
+
+                self.resolve(parent, depth + 1)?;
+                self.unlocked[parent.as_ref()]
+                    .derivation_material()
+                    .derive_key::<SymmetricKey>(label)
+            }
+            _ => {
+                let wrapped = self
+                    .wrapped
+                    .get(id)
+                    .ok_or_else(|| KeyringError::UnknownKey(id.clone()))?
+                    .clone();
+
+                self.resolve(wrapped.key_id(), depth + 1)?;
+                let key = self.unlocked[wrapped.key_id()].decrypt(&wrapped)?;
+                // the record could have been registered under the wrong id
+                id.verify_identifier(key.id())?;
+                key
+            }
+        };
+
+        self.unlocked.insert(id.clone(), key);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::OsRng;
+
+    use super::*;
+    use crate::NewKey;
+
+    fn unlocked_keyring() -> Keyring {
+        let root = SymmetricKey::generate(&mut OsRng, KeyIdentifier::uuid());
+        let mut keyring = Keyring::new(root.id().clone());
+        keyring.unlock(root).unwrap();
+
+        keyring
+    }
+
+    #[test]
+    fn unwraps_key_hierarchy_on_demand() {
+        let mut keyring = unlocked_keyring();
+        let root_id = keyring.root_id().clone();
+
+        let vault_key = SymmetricKey::generate(&mut OsRng, KeyIdentifier::uuid());
+        let vault_id = vault_key.id().clone();
+        let wrapped_vault_key = keyring.add_key(vault_key, &root_id).unwrap();
+
+        let item_key = SymmetricKey::generate(&mut OsRng, KeyIdentifier::uuid());
+        let item_id = item_key.id().clone();
+        let wrapped_item_key = keyring.add_key(item_key, &vault_id).unwrap();
+
+        let encrypted = keyring.encrypt(&item_id, &String::from("secret")).unwrap();
+
+        // a fresh keyring only knows the wrapped keys
+        let root = keyring.key(&root_id).unwrap().clone();
+        let mut keyring = Keyring::new(root_id);
+        keyring.insert_wrapped(vault_id, wrapped_vault_key);
+        keyring.insert_wrapped(item_id, wrapped_item_key);
+
+        assert!(matches!(
+            keyring.decrypt(&encrypted),
+            Err(KeyringError::Locked)
+        ));
+
+        keyring.unlock(root).unwrap();
+        assert_eq!(keyring.decrypt(&encrypted).unwrap(), "secret");
+
+        keyring.lock();
+        assert!(!keyring.is_unlocked());
+        assert!(matches!(
+            keyring.decrypt(&encrypted),
+            Err(KeyringError::Locked)
+        ));
+    }
+
+    #[test]
+    fn resolves_derived_keys() {
+        let mut keyring = unlocked_keyring();
+        let root_id = keyring.root_id().clone();
+
+        let derived: SymmetricKey = keyring
+            .key(&root_id)
+            .unwrap()
+            .derivation_material()
+            .derive_key("vault");
+        let encrypted = derived.encrypt(&42u32).unwrap();
+
+        assert_eq!(keyring.decrypt(&encrypted).unwrap(), 42);
+    }
+
+    #[test]
+    fn rejects_data_sealed_for_another_key() {
+        let mut keyring = unlocked_keyring();
+        let root_id = keyring.root_id().clone();
+
+        let other = SymmetricKey::generate(&mut OsRng, KeyIdentifier::uuid());
+        let encrypted = other.encrypt(&String::from("secret")).unwrap();
+
+        assert!(matches!(
+            keyring.decrypt_with(&root_id, &encrypted),
+            Err(KeyringError::KeyMismatch(_))
+        ));
+        assert!(matches!(
+            keyring.decrypt(&encrypted),
+            Err(KeyringError::UnknownKey(_))
+        ));
+        assert!(matches!(
+            keyring.key(&root_id).unwrap().decrypt(&encrypted),
+            Err(CipherError::KeyMismatch(_))
+        ));
+
+        let wrong_root = SymmetricKey::generate(&mut OsRng, KeyIdentifier::uuid());
+        assert!(matches!(
+            Keyring::new(root_id).unlock(wrong_root),
+            Err(KeyringError::KeyMismatch(_))
+        ));
+    }
+}
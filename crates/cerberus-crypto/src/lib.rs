@@ -8,18 +8,83 @@ use cerberus_secret::{ExposeSecret, SecretSlice};
 use cerberus_serde::base64;
 use rand::{rngs::OsRng, CryptoRng, RngCore};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 use chacha20poly1305::KeyInit;
 
 use uuid::Uuid;
 
+mod aead;
+pub mod asymmetric;
+pub mod encoding;
+pub mod header;
+mod hpke;
 pub mod kdf;
+pub mod keyring;
 pub mod mac;
+pub mod stream;
 pub mod symmetric;
 
+pub use cerberus_macros::Sealed;
+pub use header::{AeadAlgorithm, FormatVersion, Header, KdfAlgorithm};
+
+// used by code generated in cerberus-macros
+#[doc(hidden)]
+pub mod __private {
+    pub use cerberus_serde::base64;
+    pub use serde;
+    pub use zeroize;
+
+    use rand::{RngCore, rngs::OsRng};
+
+    /// A random value every section of a sealed struct is bound to.
+    pub fn record_nonce() -> Vec<u8> {
+        let mut nonce = vec![0u8; 16];
+        OsRng.fill_bytes(&mut nonce);
+        nonce
+    }
+
+    /// Binds a section to its name and record, so it can't be swapped with
+    /// a section of another record.
+    pub fn section_associated_data(section: &str, record_nonce: &[u8]) -> Vec<u8> {
+        let mut associated_data = format!("cerberus.sealed.{section}\0").into_bytes();
+        associated_data.extend_from_slice(record_nonce);
+        associated_data
+    }
+}
+
 pub trait Cipher {
-    fn encrypt<T: Serialize>(&self, data: &T) -> Result<EncryptedData<T>, CipherError>;
-    fn decrypt<T: DeserializeOwned>(&self, data: &EncryptedData<T>) -> Result<T, CipherError>;
+    fn encrypt<T: Serialize>(&self, data: &T) -> Result<EncryptedData<T>, CipherError> {
+        self.encrypt_with_associated_data(data, &[])
+    }
+
+    fn decrypt<T: DeserializeOwned>(&self, data: &EncryptedData<T>) -> Result<T, CipherError> {
+        self.decrypt_with_associated_data(data, &[])
+    }
+
+    /// Also authenticates `associated_data`, which isn't stored and has to
+    /// be passed again to decrypt.
+    fn encrypt_with_associated_data<T: Serialize>(
+        &self,
+        data: &T,
+        associated_data: &[u8],
+    ) -> Result<EncryptedData<T>, CipherError>;
+
+    fn decrypt_with_associated_data<T: DeserializeOwned>(
+        &self,
+        data: &EncryptedData<T>,
+        associated_data: &[u8],
+    ) -> Result<T, CipherError>;
+
+    /// Decrypts `data` and seals it again with this cipher's current header,
+    /// used to upgrade records written in an older format.
+    fn reencrypt<T: Serialize + DeserializeOwned>(
+        &self,
+        data: &EncryptedData<T>,
+    ) -> Result<EncryptedData<T>, CipherError> {
+        let plaintext = self.decrypt(data)?;
+        self.encrypt(&plaintext)
+    }
 }
 
 #[derive(Error, Debug)]
@@ -33,15 +98,21 @@ pub enum CipherError {
     SerializationError,
 
     #[error("failed cipher operation")]
-    OperationFailed
+    OperationFailed,
+
+    #[error("unsupported aead algorithm: {0:?}")]
+    UnsupportedAlgorithm(AeadAlgorithm),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
-struct Nonce(#[serde(with = "base64")] [u8; 24]);
+struct Nonce(#[serde(with = "base64")] Vec<u8>);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedData<T> {
+    // records written before the header was introduced don't have one
+    #[serde(default = "Header::legacy")]
+    header: Header,
     #[serde(with = "base64")]
     encrypted_data: Vec<u8>,
     key_id: KeyIdentifier,
@@ -50,7 +121,17 @@ pub struct EncryptedData<T> {
     _phantom: PhantomData<T>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+impl<T> EncryptedData<T> {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn key_id(&self) -> &KeyIdentifier {
+        &self.key_id
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]
 #[serde(rename_all = "snake_case")]
 pub enum KeyIdentifier {
@@ -72,6 +153,15 @@ impl KeyIdentifier {
         KeyIdentifier::Uuid(Uuid::new_v4())
     }
 
+    /// Identifies a key pair by a digest of its public key, so every holder
+    /// of the public key computes the same identifier.
+    pub fn fingerprint(public_key: &[u8]) -> Self {
+        let digest = Sha256::digest(public_key);
+        let bytes = digest[..16].try_into().expect("the digest is longer than a UUID");
+
+        KeyIdentifier::Uuid(uuid::Builder::from_custom_bytes(bytes).into_uuid())
+    }
+
     pub fn derived(context: String, derived_from: Option<KeyIdentifier>) -> Self {
         KeyIdentifier::Derived {
             context,
@@ -1,13 +1,16 @@
 use crate::{kdf::DeriveKey, NewKey};
 use cerberus_secret::{ExposeSecret, SecretSlice};
-use hmac::{Hmac, Mac, digest::CtOutput};
+use hmac::{Hmac, digest::CtOutput};
 use sha2::Sha256;
 
 pub use cerberus_macros::UpdateHmac;
+pub use hmac::Mac;
 use thiserror::Error;
 
 use crate::KeyIdentifier;
 
+pub mod canonical;
+
 pub(crate) type HmacSha256 = Hmac<Sha256>;
 
 pub trait UpdateHmac<M: Mac = HmacSha256> {
@@ -0,0 +1,39 @@
+//! The encoding `#[derive(UpdateHmac)]` feeds into the MAC.
+//!
+//! A value starts with its type name and version, enums follow that with the
+//! name of the variant. Then comes the number of fields and every field as
+//! its name followed by its value. Names and values are prefixed with their
+//! length as a big endian `u64`, so no two distinct values of any derived type
+//! produce the same input. Nested values are written by their own
+//! [`UpdateHmac`](super::UpdateHmac) impl instead of being length prefixed,
+//! which is unambiguous since that encoding is self delimiting as well.
+
+use serde::Serialize;
+use zeroize::Zeroizing;
+
+use super::Mac;
+
+pub fn update_domain(hmac: &mut impl Mac, type_name: &str, version: u32) {
+    update_bytes(hmac, type_name.as_bytes());
+    hmac.update(&version.to_be_bytes());
+}
+
+pub fn update_tag(hmac: &mut impl Mac, tag: &str) {
+    update_bytes(hmac, tag.as_bytes());
+}
+
+pub fn update_field_count(hmac: &mut impl Mac, count: u64) {
+    hmac.update(&count.to_be_bytes());
+}
+
+pub fn update_bytes(hmac: &mut impl Mac, bytes: &[u8]) {
+    hmac.update(&(bytes.len() as u64).to_be_bytes());
+    hmac.update(bytes);
+}
+
+/// Writes the JSON serialization of `value`, the default for fields without
+/// an `#[hmac(...)]` attribute.
+pub fn update_serialized<T: Serialize + ?Sized>(hmac: &mut impl Mac, value: &T) {
+    let bytes = Zeroizing::new(serde_json::to_vec(value).expect("data should be serializable"));
+    update_bytes(hmac, &bytes);
+}
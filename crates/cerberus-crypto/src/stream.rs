@@ -0,0 +1,476 @@
+//! Segmented encryption for payloads too large to encrypt in one piece, such
+//! as attachments and exports.
+//!
+//! This is the STREAM construction over XChaCha20-Poly1305. A stream starts
+//! with a header holding a version byte and a random 19 byte nonce prefix,
+//! followed by segments of [`SEGMENT_SIZE`] plaintext bytes that are each
+//! sealed with their own tag. The nonce of a segment is the prefix, a big
+//! endian `u32` counter and a flag that is only set on the final segment, so
+//! segments that are reordered, dropped or cut off at the end fail to
+//! decrypt. The header is authenticated as associated data of every segment.
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use cerberus_secret::ExposeSecret;
+use chacha20poly1305::{
+    KeyInit, XChaCha20Poly1305,
+    aead::stream::{DecryptorBE32, EncryptorBE32},
+};
+use rand::{RngCore, rngs::OsRng};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use zeroize::Zeroizing;
+
+use crate::symmetric::SymmetricKey;
+
+/// Plaintext bytes in every segment but the last.
+pub const SEGMENT_SIZE: usize = 64 * 1024;
+
+const STREAM_VERSION: u8 = 1;
+const NONCE_PREFIX_SIZE: usize = 19;
+const HEADER_SIZE: usize = 1 + NONCE_PREFIX_SIZE;
+const TAG_SIZE: usize = 16;
+const SEALED_SEGMENT_SIZE: usize = SEGMENT_SIZE + TAG_SIZE;
+
+type Header = [u8; HEADER_SIZE];
+
+fn cipher(key: &SymmetricKey) -> XChaCha20Poly1305 {
+    XChaCha20Poly1305::new_from_slice(key.key.expose_secret())
+        .expect("symmetric keys are the size XChaCha20Poly1305 expects")
+}
+
+fn segment_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream segment failed authentication",
+    )
+}
+
+fn new_segment_buffer() -> Zeroizing<Vec<u8>> {
+    // room for the tag, so sealing in place never reallocates and leaves a
+    // copy of the plaintext behind
+    Zeroizing::new(Vec::with_capacity(SEALED_SEGMENT_SIZE))
+}
+
+/// Encrypts everything written to it into `inner`.
+///
+/// The final segment is only written by `shutdown`, a stream that was never
+/// shut down can't be decrypted.
+pub struct EncryptWriter<W> {
+    inner: W,
+    header: Header,
+    encryptor: Option<EncryptorBE32<XChaCha20Poly1305>>,
+    plaintext: Zeroizing<Vec<u8>>,
+    output: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptWriter<W> {
+    pub fn new(key: &SymmetricKey, inner: W) -> Self {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_SIZE];
+        OsRng.fill_bytes(&mut nonce_prefix);
+
+        Self::with_nonce_prefix(key, inner, nonce_prefix)
+    }
+
+    fn with_nonce_prefix(
+        key: &SymmetricKey,
+        inner: W,
+        nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+    ) -> Self {
+        let mut header = [0u8; HEADER_SIZE];
+        header[0] = STREAM_VERSION;
+        header[1..].copy_from_slice(&nonce_prefix);
+
+        Self {
+            inner,
+            header,
+            encryptor: Some(EncryptorBE32::from_aead(cipher(key), &nonce_prefix.into())),
+            plaintext: new_segment_buffer(),
+            output: header.to_vec(),
+            written: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn seal_segment(&mut self, last: bool) -> io::Result<()> {
+        let mut segment = std::mem::replace(&mut self.plaintext, new_segment_buffer());
+
+        let sealed = match (self.encryptor.take(), last) {
+            (Some(encryptor), true) => encryptor.encrypt_last_in_place(&self.header, &mut *segment),
+            (Some(mut encryptor), false) => {
+                let sealed = encryptor.encrypt_next_in_place(&self.header, &mut *segment);
+                self.encryptor = Some(encryptor);
+                sealed
+            }
+            (None, _) => return Err(io::Error::other("stream has already been finished")),
+        };
+        // only fails once the segment counter runs out
+        sealed.map_err(|_| io::Error::other("stream is too long"))?;
+
+        // the buffer now only holds ciphertext
+        self.output = std::mem::take(&mut *segment);
+        self.written = 0;
+        Ok(())
+    }
+
+    fn poll_write_output(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.output.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.output[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+
+        self.output.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_output(cx))?;
+
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        if this.encryptor.is_none() {
+            return Poll::Ready(Err(io::Error::other("stream has already been finished")));
+        }
+
+        // a full segment is only sealed once more data arrives, so that the
+        // final segment sealed on shutdown is never empty
+        if this.plaintext.len() == SEGMENT_SIZE {
+            this.seal_segment(false)?;
+            // the segment is buffered, so the write can already be accepted
+            // even if the inner writer isn't ready for it yet
+            let _ = this.poll_write_output(cx)?;
+        }
+
+        let n = buf.len().min(SEGMENT_SIZE - this.plaintext.len());
+        this.plaintext.extend_from_slice(&buf[..n]);
+
+        Poll::Ready(Ok(n))
+    }
+
+    /// Flushes the sealed segments, plaintext that doesn't fill a segment
+    /// yet stays buffered until more is written or the stream is shut down.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_output(cx))?;
+
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_output(cx))?;
+
+        if this.encryptor.is_some() {
+            this.seal_segment(true)?;
+            ready!(this.poll_write_output(cx))?;
+        }
+
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Decrypts a stream written by [`EncryptWriter`].
+///
+/// Reading fails with [`io::ErrorKind::InvalidData`] when a segment doesn't
+/// authenticate, including when the stream was truncated, and with
+/// [`io::ErrorKind::UnexpectedEof`] when it ends inside the header.
+pub struct DecryptReader<R> {
+    inner: R,
+    cipher: Option<XChaCha20Poly1305>,
+    header: Header,
+    header_read: usize,
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    // one sealed segment plus a byte of lookahead to tell whether it is the
+    // final one
+    input: Vec<u8>,
+    input_read: usize,
+    plaintext: Zeroizing<Vec<u8>>,
+    plaintext_read: usize,
+    finished: bool,
+}
+
+impl<R: AsyncRead + Unpin> DecryptReader<R> {
+    pub fn new(key: &SymmetricKey, inner: R) -> Self {
+        Self {
+            inner,
+            cipher: Some(cipher(key)),
+            header: [0u8; HEADER_SIZE],
+            header_read: 0,
+            decryptor: None,
+            input: vec![0u8; SEALED_SEGMENT_SIZE + 1],
+            input_read: 0,
+            plaintext: new_segment_buffer(),
+            plaintext_read: 0,
+            finished: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn poll_read_header(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.header_read < HEADER_SIZE {
+            let mut buf = ReadBuf::new(&mut self.header[self.header_read..]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+
+            let n = buf.filled().len();
+            if n == 0 {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "stream ended inside the header",
+                )));
+            }
+            self.header_read += n;
+        }
+
+        if self.header[0] != STREAM_VERSION {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported stream version: {}", self.header[0]),
+            )));
+        }
+
+        let cipher = self.cipher.take().expect("the header is only read once");
+        let nonce_prefix: [u8; NONCE_PREFIX_SIZE] = self.header[1..]
+            .try_into()
+            .expect("header holds the nonce prefix");
+        self.decryptor = Some(DecryptorBE32::from_aead(cipher, &nonce_prefix.into()));
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Reads and decrypts the next segment into `plaintext`.
+    fn poll_next_segment(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut eof = false;
+        while self.input_read < self.input.len() {
+            let mut buf = ReadBuf::new(&mut self.input[self.input_read..]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf))?;
+
+            let n = buf.filled().len();
+            if n == 0 {
+                eof = true;
+                break;
+            }
+            self.input_read += n;
+        }
+
+        let segment_len = self.input_read.min(SEALED_SEGMENT_SIZE);
+        self.plaintext.clear();
+        self.plaintext.extend_from_slice(&self.input[..segment_len]);
+        self.plaintext_read = 0;
+
+        let decryptor = self.decryptor.take().expect("header has been read");
+        if eof {
+            self.finished = true;
+            decryptor
+                .decrypt_last_in_place(&self.header, &mut *self.plaintext)
+                .map_err(|_| segment_error())?;
+        } else {
+            let mut decryptor = decryptor;
+            decryptor
+                .decrypt_next_in_place(&self.header, &mut *self.plaintext)
+                .map_err(|_| segment_error())?;
+            self.decryptor = Some(decryptor);
+
+            // the lookahead byte starts the next segment
+            self.input[0] = self.input[SEALED_SEGMENT_SIZE];
+            self.input_read = 1;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DecryptReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.plaintext_read < this.plaintext.len() {
+                let n = buf
+                    .remaining()
+                    .min(this.plaintext.len() - this.plaintext_read);
+                buf.put_slice(&this.plaintext[this.plaintext_read..this.plaintext_read + n]);
+                this.plaintext_read += n;
+
+                return Poll::Ready(Ok(()));
+            }
+            if this.finished {
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.cipher.is_some() {
+                ready!(this.poll_read_header(cx))?;
+            }
+            if this.decryptor.is_none() {
+                // an earlier segment failed to decrypt
+                return Poll::Ready(Err(segment_error()));
+            }
+            ready!(this.poll_next_segment(cx))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chacha20poly1305::{
+        XNonce,
+        aead::{Aead, Payload},
+    };
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{KeyIdentifier, NewKey};
+
+    fn test_key() -> SymmetricKey {
+        SymmetricKey::new_unchecked((0u8..32).collect::<Vec<_>>().into(), KeyIdentifier::local())
+    }
+
+    async fn encrypt(
+        key: &SymmetricKey,
+        nonce_prefix: [u8; NONCE_PREFIX_SIZE],
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut writer = EncryptWriter::with_nonce_prefix(key, Vec::new(), nonce_prefix);
+        writer.write_all(data).await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        writer.into_inner()
+    }
+
+    async fn decrypt(key: &SymmetricKey, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut reader = DecryptReader::new(key, data);
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext).await?;
+
+        Ok(plaintext)
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn known_answer() {
+        let ciphertext = encrypt(&test_key(), [0x42; NONCE_PREFIX_SIZE], b"cerberus").await;
+
+        assert_eq!(
+            hex::encode(&ciphertext),
+            "0142424242424242424242424242424242424242\
+             21b4fac2345d614b89666c919af427d572afda42afce4122"
+        );
+    }
+
+    #[tokio::test]
+    async fn segments_match_the_stream_construction() {
+        let key = test_key();
+        let nonce_prefix = [7u8; NONCE_PREFIX_SIZE];
+        let data = payload(SEGMENT_SIZE + 10);
+        let ciphertext = encrypt(&key, nonce_prefix, &data).await;
+
+        let header = &ciphertext[..HEADER_SIZE];
+        let segments = [
+            (&data[..SEGMENT_SIZE], 0u32, 0u8),
+            (&data[SEGMENT_SIZE..], 1, 1),
+        ];
+        let mut expected = header.to_vec();
+        for (plaintext, counter, last) in segments {
+            let mut nonce = nonce_prefix.to_vec();
+            nonce.extend_from_slice(&counter.to_be_bytes());
+            nonce.push(last);
+
+            let sealed = cipher(&key)
+                .encrypt(
+                    XNonce::from_slice(&nonce),
+                    Payload {
+                        msg: plaintext,
+                        aad: header,
+                    },
+                )
+                .unwrap();
+            expected.extend_from_slice(&sealed);
+        }
+
+        assert_eq!(ciphertext, expected);
+    }
+
+    #[tokio::test]
+    async fn round_trips_across_segment_boundaries() {
+        let key = test_key();
+
+        for len in [
+            0,
+            1,
+            SEGMENT_SIZE - 1,
+            SEGMENT_SIZE,
+            SEGMENT_SIZE + 1,
+            3 * SEGMENT_SIZE + 5,
+        ] {
+            let data = payload(len);
+            let ciphertext = encrypt(&key, [1; NONCE_PREFIX_SIZE], &data).await;
+
+            let segments = len.div_ceil(SEGMENT_SIZE).max(1);
+            assert_eq!(ciphertext.len(), HEADER_SIZE + len + segments * TAG_SIZE);
+            assert_eq!(decrypt(&key, &ciphertext).await.unwrap(), data);
+        }
+    }
+
+    #[tokio::test]
+    async fn detects_truncation_and_reordering() {
+        let key = test_key();
+        let data = payload(3 * SEGMENT_SIZE + 5);
+        let ciphertext = encrypt(&key, [1; NONCE_PREFIX_SIZE], &data).await;
+
+        let invalid = |result: io::Result<Vec<u8>>| matches!(result, Err(err) if err.kind() == io::ErrorKind::InvalidData);
+
+        // cut off at a segment boundary, so every remaining segment is intact
+        let truncated = &ciphertext[..HEADER_SIZE + 2 * SEALED_SEGMENT_SIZE];
+        assert!(invalid(decrypt(&key, truncated).await));
+        assert!(invalid(
+            decrypt(&key, &ciphertext[..ciphertext.len() - 1]).await
+        ));
+        assert!(invalid(decrypt(&key, &ciphertext[..HEADER_SIZE]).await));
+
+        let mut reordered = ciphertext[..HEADER_SIZE].to_vec();
+        let first = HEADER_SIZE..HEADER_SIZE + SEALED_SEGMENT_SIZE;
+        let second = first.end..first.end + SEALED_SEGMENT_SIZE;
+        reordered.extend_from_slice(&ciphertext[second.clone()]);
+        reordered.extend_from_slice(&ciphertext[first]);
+        reordered.extend_from_slice(&ciphertext[second.end..]);
+        assert!(invalid(decrypt(&key, &reordered).await));
+
+        let mut tampered_header = ciphertext.clone();
+        tampered_header[1] ^= 1;
+        assert!(invalid(decrypt(&key, &tampered_header).await));
+
+        let other_key = SymmetricKey::new_unchecked(vec![9u8; 32].into(), KeyIdentifier::local());
+        assert!(invalid(decrypt(&other_key, &ciphertext).await));
+
+        let result = decrypt(&key, &ciphertext[..HEADER_SIZE - 1]).await;
+        assert!(matches!(result, Err(err) if err.kind() == io::ErrorKind::UnexpectedEof));
+    }
+}
@@ -1,64 +1,132 @@
 use std::marker::PhantomData;
 use rand::rngs::OsRng;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use chacha20poly1305::{aead::Aead, AeadCore, KeyInit, XChaCha20Poly1305};
 
 use cerberus_secret::{ExposeSecret, SecretSlice};
 use cerberus_serde::base64_expose_secret;
+use zeroize::Zeroizing;
 
-use crate::{kdf::DeriveKey, Cipher, CipherError, EncryptedData, KeyIdentifier, NewKey, Nonce};
+use crate::{
+    aead, encoding, hash_password,
+    header::{AeadAlgorithm, Header, KdfAlgorithm},
+    kdf::{DerivationMaterial, DeriveKey},
+    Cipher, CipherError, EncryptedData, KeyIdentifier, NewKey, Nonce,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SymmetricKey {
     #[serde(with = "base64_expose_secret")]
-    key: SecretSlice<u8>,
+    pub(crate) key: SecretSlice<u8>,
     id: KeyIdentifier,
+    #[serde(default)]
+    algorithm: AeadAlgorithm,
+    #[serde(default)]
+    kdf: KdfAlgorithm,
 }
 
 impl SymmetricKey {
     pub fn id(&self) -> &KeyIdentifier {
         &self.id
     }
+
+    pub fn from_password(password: &[u8], salt: &str, id: KeyIdentifier) -> Self {
+        Self {
+            kdf: KdfAlgorithm::Argon2id,
+            ..Self::new_unchecked(hash_password(password, salt), id)
+        }
+    }
+
+    /// Selects the AEAD used for new encryptions, data sealed with another
+    /// algorithm can still be decrypted.
+    pub fn with_algorithm(self, algorithm: AeadAlgorithm) -> Self {
+        Self { algorithm, ..self }
+    }
+
+    pub fn algorithm(&self) -> AeadAlgorithm {
+        self.algorithm
+    }
+
+    pub fn header(&self) -> Header {
+        Header::new(self.algorithm, self.kdf)
+    }
+
+    pub(crate) fn derivation_material(&self) -> DerivationMaterial {
+        DerivationMaterial::new(self.key.clone(), self.id.clone())
+    }
+
+    /// Whether `data` was written with a different header than this key
+    /// would produce now and should be passed through [`Cipher::reencrypt`].
+    pub fn needs_reencryption<T>(&self, data: &EncryptedData<T>) -> bool {
+        *data.header() != self.header()
+    }
 }
 
 impl NewKey for SymmetricKey {
     const KEY_SIZE: usize = 32;
 
     fn new_unchecked(key: SecretSlice<u8>, id: KeyIdentifier) -> Self {
-        Self { key, id }
+        Self {
+            key,
+            id,
+            algorithm: AeadAlgorithm::default(),
+            kdf: KdfAlgorithm::None,
+        }
     }
 }
 
 impl DeriveKey for SymmetricKey {
     const MAC_INFO_SUFFIX: &'static str = "_symmmetric_key";
+
+    fn new_derived(key: SecretSlice<u8>, id: KeyIdentifier) -> Self {
+        Self {
+            kdf: KdfAlgorithm::HkdfSha256,
+            ..Self::new_unchecked(key, id)
+        }
+    }
 }
 
 impl Cipher for SymmetricKey {
-    fn encrypt<T: Serialize>(&self, data: &T) -> Result<EncryptedData<T>, CipherError> {
-        let data = serde_json::to_string(&data).map_err(|_| CipherError::SerializationError)?;
+    fn encrypt_with_associated_data<T: Serialize>(
+        &self,
+        data: &T,
+        associated_data: &[u8],
+    ) -> Result<EncryptedData<T>, CipherError> {
+        let header = self.header();
+        let data = encoding::encode(header.version(), data)?;
 
-        let cipher = XChaCha20Poly1305::new(self.key.expose_secret().into());
-        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-        let encrypted_data = cipher.encrypt(&nonce, data.as_bytes())
-            .map_err(|_| CipherError::OperationFailed)?;
+        let (nonce, encrypted_data) = aead::seal(
+            header.aead(),
+            self.key.expose_secret(),
+            &[&header.associated_data(), associated_data].concat(),
+            &data,
+        )?;
 
         Ok(EncryptedData {
+            header,
             encrypted_data,
             key_id: self.id.clone(),
-            nonce: Nonce(nonce.into()),
+            nonce: Nonce(nonce),
             _phantom: PhantomData,
         })
     }
 
-    fn decrypt<T: DeserializeOwned>(&self, encrypted_data: &EncryptedData<T>) -> Result<T, CipherError> {
-        let cipher = XChaCha20Poly1305::new(self.key.expose_secret().into());
-        let decrypted_data = cipher.decrypt(
-            &encrypted_data.nonce.0.into(),
-            encrypted_data.encrypted_data.as_slice(),
-        ).map_err(|_| CipherError::OperationFailed)?;
+    fn decrypt_with_associated_data<T: DeserializeOwned>(
+        &self,
+        encrypted_data: &EncryptedData<T>,
+        associated_data: &[u8],
+    ) -> Result<T, CipherError> {
+        self.id.verify_identifier(&encrypted_data.key_id)?;
 
-        let data = serde_json::from_slice(&decrypted_data).map_err(|_| CipherError::SerializationError)?;
-        Ok(data)
+        let header = encrypted_data.header;
+        let decrypted_data = Zeroizing::new(aead::open(
+            header.aead(),
+            self.key.expose_secret(),
+            &encrypted_data.nonce.0,
+            &[&header.associated_data(), associated_data].concat(),
+            &encrypted_data.encrypted_data,
+        )?);
+
+        encoding::decode(header.version(), &decrypted_data)
     }
 }
 
@@ -94,6 +162,7 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::header::FormatVersion;
 
     #[derive(Serialize, Deserialize)]
     struct SecretOwned {
@@ -115,4 +184,86 @@ mod tests {
 
         let decrypted = envelope.open(&master_key).unwrap();
     }
+
+    fn encrypt_with_header<T: Serialize>(
+        key: &SymmetricKey,
+        header: Header,
+        data: &T,
+    ) -> EncryptedData<T> {
+        let plaintext = encoding::encode(header.version(), data).unwrap();
+        let (nonce, encrypted_data) = aead::seal(
+            header.aead(),
+            key.key.expose_secret(),
+            &header.associated_data(),
+            &plaintext,
+        )
+        .unwrap();
+
+        EncryptedData {
+            header,
+            encrypted_data,
+            key_id: key.id.clone(),
+            nonce: Nonce(nonce),
+            _phantom: PhantomData,
+        }
+    }
+
+    #[test]
+    fn decrypts_and_upgrades_legacy_records() {
+        let key = SymmetricKey::generate(&mut OsRng, KeyIdentifier::local());
+        let legacy = encrypt_with_header(&key, Header::LEGACY, &String::from("old secret"));
+
+        // legacy records were serialized without a header field at all
+        let mut json = serde_json::to_value(&legacy).unwrap();
+        json.as_object_mut().unwrap().remove("header");
+        let legacy: EncryptedData<String> = serde_json::from_value(json).unwrap();
+
+        assert_eq!(legacy.header(), &Header::LEGACY);
+        assert!(key.needs_reencryption(&legacy));
+        assert_eq!(key.decrypt(&legacy).unwrap(), "old secret");
+
+        let upgraded = key.reencrypt(&legacy).unwrap();
+        assert!(upgraded.header().is_current());
+        assert!(!key.needs_reencryption(&upgraded));
+        assert_eq!(key.decrypt(&upgraded).unwrap(), "old secret");
+    }
+
+    #[test]
+    fn decrypts_json_encoded_records() {
+        let key = SymmetricKey::generate(&mut OsRng, KeyIdentifier::local());
+        let header = Header {
+            version: FormatVersion::V1,
+            ..key.header()
+        };
+        let encrypted = encrypt_with_header(&key, header, &String::from("secret"));
+
+        assert_eq!(key.decrypt(&encrypted).unwrap(), "secret");
+        assert!(key.needs_reencryption(&encrypted));
+    }
+
+    #[test]
+    fn rejects_tampered_header() {
+        let key = SymmetricKey::generate(&mut OsRng, KeyIdentifier::local());
+        let mut encrypted = key.encrypt(&String::from("secret")).unwrap();
+        encrypted.header = Header::new(AeadAlgorithm::XChaCha20Poly1305, KdfAlgorithm::Argon2id);
+
+        assert!(matches!(
+            key.decrypt(&encrypted),
+            Err(CipherError::OperationFailed)
+        ));
+    }
+
+    #[cfg(feature = "aes-gcm-siv")]
+    #[test]
+    fn decrypt_dispatches_on_header_algorithm() {
+        let key = SymmetricKey::generate(&mut OsRng, KeyIdentifier::local());
+        let aes_key = key.clone().with_algorithm(AeadAlgorithm::Aes256GcmSiv);
+
+        let encrypted = aes_key.encrypt(&String::from("secret")).unwrap();
+        assert_eq!(encrypted.header().aead(), AeadAlgorithm::Aes256GcmSiv);
+        assert_eq!(encrypted.nonce.0.len(), AeadAlgorithm::Aes256GcmSiv.nonce_size());
+
+        assert_eq!(key.decrypt(&encrypted).unwrap(), "secret");
+        assert!(key.needs_reencryption(&encrypted));
+    }
 }
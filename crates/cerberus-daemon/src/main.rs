@@ -9,6 +9,9 @@ use tokio::{
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // keep keys unlocked by the daemon out of swap and core dumps
+    cerberus_secret::locked::set_enabled(true);
+
     let path = get_socket_path();
 
     let server = Server::bind(&path).await?;
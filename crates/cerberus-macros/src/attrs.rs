@@ -0,0 +1,148 @@
+use syn::{Attribute, LitInt, LitStr, Path, meta::ParseNestedMeta};
+
+#[derive(Default)]
+pub(crate) struct ContainerAttrs {
+    pub(crate) rename: Option<String>,
+    pub(crate) version: Option<u32>,
+}
+
+impl ContainerAttrs {
+    pub(crate) fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+
+        parse_hmac_attrs(attrs, |meta| {
+            if meta.path.is_ident("rename") {
+                let rename = meta.value()?.parse::<LitStr>()?.value();
+                set_once(&meta, &mut parsed.rename, rename)
+            } else if meta.path.is_ident("version") {
+                let version = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                set_once(&meta, &mut parsed.version, version)
+            } else {
+                Err(meta.error("unknown hmac attribute, expected `rename` or `version`"))
+            }
+        })?;
+
+        Ok(parsed)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct VariantAttrs {
+    pub(crate) rename: Option<String>,
+}
+
+impl VariantAttrs {
+    pub(crate) fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+
+        parse_hmac_attrs(attrs, |meta| {
+            if meta.path.is_ident("rename") {
+                let rename = meta.value()?.parse::<LitStr>()?.value();
+                set_once(&meta, &mut parsed.rename, rename)
+            } else {
+                Err(meta.error("unknown hmac attribute, expected `rename`"))
+            }
+        })?;
+
+        Ok(parsed)
+    }
+}
+
+pub(crate) enum FieldEncoding {
+    /// The JSON serialization of the field.
+    Serialize,
+    /// The field's own `UpdateHmac` impl.
+    Nested,
+    /// The bytes returned by the given function.
+    With(Path),
+}
+
+#[derive(Default)]
+pub(crate) struct FieldAttrs {
+    pub(crate) skip: bool,
+    pub(crate) rename: Option<String>,
+    nested: bool,
+    with: Option<Path>,
+}
+
+impl FieldAttrs {
+    pub(crate) fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+
+        parse_hmac_attrs(attrs, |meta| {
+            if meta.path.is_ident("skip") {
+                set_flag(&meta, &mut parsed.skip)
+            } else if meta.path.is_ident("nested") {
+                set_flag(&meta, &mut parsed.nested)
+            } else if meta.path.is_ident("rename") {
+                let rename = meta.value()?.parse::<LitStr>()?.value();
+                set_once(&meta, &mut parsed.rename, rename)
+            } else if meta.path.is_ident("with") {
+                let with = meta.value()?.parse::<Path>()?;
+                set_once(&meta, &mut parsed.with, with)
+            } else {
+                Err(meta
+                    .error("unknown hmac attribute, expected `skip`, `nested`, `rename` or `with`"))
+            }
+        })?;
+
+        let configured = parsed.nested || parsed.with.is_some() || parsed.rename.is_some();
+        if parsed.skip && configured {
+            return Err(error_at(
+                attrs,
+                "`skip` can't be combined with other hmac attributes",
+            ));
+        }
+        if parsed.nested && parsed.with.is_some() {
+            return Err(error_at(attrs, "`nested` and `with` can't be combined"));
+        }
+
+        Ok(parsed)
+    }
+
+    pub(crate) fn encoding(&self) -> FieldEncoding {
+        match (&self.with, self.nested) {
+            (Some(with), _) => FieldEncoding::With(with.clone()),
+            (None, true) => FieldEncoding::Nested,
+            (None, false) => FieldEncoding::Serialize,
+        }
+    }
+}
+
+fn parse_hmac_attrs(
+    attrs: &[Attribute],
+    mut parse: impl FnMut(ParseNestedMeta) -> syn::Result<()>,
+) -> syn::Result<()> {
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("hmac")) {
+        attr.parse_nested_meta(&mut parse)?;
+    }
+
+    Ok(())
+}
+
+fn set_once<T>(meta: &ParseNestedMeta, slot: &mut Option<T>, value: T) -> syn::Result<()> {
+    if slot.is_some() {
+        return Err(meta.error("duplicate hmac attribute"));
+    }
+
+    *slot = Some(value);
+    Ok(())
+}
+
+fn set_flag(meta: &ParseNestedMeta, flag: &mut bool) -> syn::Result<()> {
+    if *flag {
+        return Err(meta.error("duplicate hmac attribute"));
+    }
+
+    *flag = true;
+    Ok(())
+}
+
+fn error_at(attrs: &[Attribute], message: &str) -> syn::Error {
+    let attr = attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("hmac"))
+        .expect("only called after an hmac attribute was parsed");
+
+    syn::Error::new_spanned(attr, message)
+}
@@ -1,63 +1,178 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{DeriveInput, Field, parse_macro_input};
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::{Data, DeriveInput, Fields, Member, parse_macro_input, spanned::Spanned};
 
+mod attrs;
+mod sealed;
+
+use attrs::{ContainerAttrs, FieldAttrs, FieldEncoding, VariantAttrs};
+
+/// Feeds a canonical encoding of the type into a MAC, see
+/// `cerberus_crypto::mac::canonical` for the format.
+///
+/// Fields are serialized as JSON unless marked with one of:
+///
+/// - `#[hmac(skip)]` leaves the field out.
+/// - `#[hmac(nested)]` uses the field's own `UpdateHmac` impl.
+/// - `#[hmac(with = path)]` uses the bytes returned by `path(&field)`.
+/// - `#[hmac(rename = "name")]` changes the tag the field is written under.
+///
+/// On the type, `#[hmac(rename = "name")]` changes the name used as the
+/// domain separator and `#[hmac(version = n)]` sets its version, which
+/// defaults to 1. Variants can be renamed the same way.
 #[proc_macro_derive(UpdateHmac, attributes(hmac))]
 pub fn derive_update_hmac(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
 
     match impl_update_hmac(&input) {
-        Ok(tokens) => tokens,
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.into_compile_error().into(),
+    }
+}
+
+/// Splits a struct into plain fields and encrypted sections.
+///
+/// Fields marked `#[sealed(overview)]` or `#[sealed(secret)]` are collected
+/// into `FooOverview` and `FooSecret`, which are encrypted separately so the
+/// overview can be opened without touching the secrets. `SealedFoo` keeps
+/// the plain fields as they are next to the two sections, and is created with
+/// `Foo::seal` and turned back with `SealedFoo::open`. Both sections are
+/// bound to a random `record_nonce` kept in `SealedFoo`, the plain fields
+/// aren't authenticated. The `Debug` impl of `Foo` redacts every sealed
+/// field.
+#[proc_macro_derive(Sealed, attributes(sealed))]
+pub fn derive_sealed(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match sealed::impl_sealed(&input) {
+        Ok(tokens) => tokens.into(),
         Err(e) => e.into_compile_error().into(),
     }
 }
 
-fn impl_update_hmac(input: &DeriveInput) -> Result<TokenStream, syn::Error> {
+fn impl_update_hmac(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
     let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let container = ContainerAttrs::parse(&input.attrs)?;
 
-    match &input.data {
-        syn::Data::Struct(data) => {
-            let name = &input.ident;
-            let input_fields = data.fields.iter().enumerate().filter_map(|(i, field)| {
-                if has_hmac_skip(field) {
-                    None
-                } else {
-                    let tokens = match field.ident.as_ref() {
-                        Some(ident) => quote! { self.#ident },
-                        None => quote! { self.#i },
-                    };
-
-                    Some(tokens)
-                }
-            });
-
-            let expanded = quote! {
-                impl #impl_generics UpdateHmac for #name #type_generics #where_clause
-                {
-                    fn update_hmac(&self, hmac: &mut impl ::hmac::Mac) {
-                        #(hmac.update(&::serde_json::to_vec(&#input_fields).expect("data should be serializable"));)*
-                    }
-                }
-            };
+    let name = &input.ident;
+    let type_name = container.rename.unwrap_or_else(|| name.to_string());
+    let version = container.version.unwrap_or(1);
 
-            Ok(expanded.into())
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, fields) = update_fields(quote! { Self }, &data.fields)?;
+
+            quote! {
+                let #pattern = self;
+                #fields
+            }
         }
-        _ => Err(syn::Error::new_spanned(
-            input,
-            "#[derive(UpdateHmac)] can only be used with structs",
-        )),
-    }
-}
+        Data::Enum(data) if data.variants.is_empty() => quote! { match *self {} },
+        Data::Enum(data) => {
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let attrs = VariantAttrs::parse(&variant.attrs)?;
+                    let ident = &variant.ident;
+                    let tag = attrs.rename.unwrap_or_else(|| ident.to_string());
+                    let (pattern, fields) =
+                        update_fields(quote! { Self::#ident }, &variant.fields)?;
+
+                    Ok(quote! {
+                        #pattern => {
+                            ::cerberus_crypto::mac::canonical::update_tag(hmac, #tag);
+                            #fields
+                        }
+                    })
+                })
+                .collect::<Result<Vec<_>, syn::Error>>()?;
+
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "#[derive(UpdateHmac)] can only be used with structs and enums",
+            ));
+        }
+    };
 
-fn has_hmac_skip(field: &Field) -> bool {
-    field.attrs.iter().any(|attr| {
-        if attr.path().is_ident("hmac") {
-            match attr.parse_args::<syn::Path>() {
-                Ok(path) => path.is_ident("skip"),
-                _ => false,
+    Ok(quote! {
+        impl #impl_generics ::cerberus_crypto::mac::UpdateHmac for #name #type_generics #where_clause
+        {
+            fn update_hmac(&self, hmac: &mut impl ::cerberus_crypto::mac::Mac) {
+                ::cerberus_crypto::mac::canonical::update_domain(hmac, #type_name, #version);
+                #body
             }
-        } else {
-            false
         }
     })
 }
+
+/// Returns a pattern binding the included fields of a struct or variant and
+/// the statements writing them into `hmac`.
+fn update_fields(
+    path: TokenStream2,
+    fields: &Fields,
+) -> Result<(TokenStream2, TokenStream2), syn::Error> {
+    let mut bindings = Vec::new();
+    let mut updates = Vec::new();
+
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = FieldAttrs::parse(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(i.into()),
+        };
+        let tag = match (&attrs.rename, &field.ident) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(ident)) => ident.to_string(),
+            (None, None) => i.to_string(),
+        };
+        // generated names so that fields can't shadow `hmac`
+        let binding = Ident::new(&format!("__field{i}"), Span::call_site());
+
+        let update_value = match attrs.encoding() {
+            FieldEncoding::Serialize => quote! {
+                ::cerberus_crypto::mac::canonical::update_serialized(hmac, #binding);
+            },
+            // spanned so a missing impl is reported on the field's type
+            FieldEncoding::Nested => {
+                let ty = &field.ty;
+                quote_spanned! {ty.span()=>
+                    <#ty as ::cerberus_crypto::mac::UpdateHmac>::update_hmac(#binding, hmac);
+                }
+            }
+            FieldEncoding::With(with) => quote! {
+                ::cerberus_crypto::mac::canonical::update_bytes(
+                    hmac,
+                    ::core::convert::AsRef::<[u8]>::as_ref(&#with(#binding)),
+                );
+            },
+        };
+
+        updates.push(quote! {
+            ::cerberus_crypto::mac::canonical::update_tag(hmac, #tag);
+            #update_value
+        });
+        bindings.push(quote! { #member: #binding });
+    }
+
+    let count = updates.len() as u64;
+    let pattern = quote! { #path { #(#bindings,)* .. } };
+    let updates = quote! {
+        ::cerberus_crypto::mac::canonical::update_field_count(hmac, #count);
+        #(#updates)*
+    };
+
+    Ok((pattern, updates))
+}
@@ -0,0 +1,268 @@
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::{Attribute, Data, DeriveInput, Field, Fields, Ident, spanned::Spanned};
+
+#[derive(Clone, Copy)]
+enum Section {
+    Plain,
+    Overview,
+    Secret,
+}
+
+impl Section {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut section = None;
+
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("sealed")) {
+            attr.parse_nested_meta(|meta| {
+                let parsed = if meta.path.is_ident("overview") {
+                    Section::Overview
+                } else if meta.path.is_ident("secret") {
+                    Section::Secret
+                } else {
+                    return Err(
+                        meta.error("unknown sealed attribute, expected `overview` or `secret`")
+                    );
+                };
+
+                if section.replace(parsed).is_some() {
+                    return Err(meta.error("a field can only be in one sealed section"));
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(section.unwrap_or(Section::Plain))
+    }
+}
+
+pub(crate) fn impl_sealed(input: &DeriveInput) -> Result<TokenStream2, syn::Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    input,
+                    "#[derive(Sealed)] can only be used with structs with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "#[derive(Sealed)] can only be used with structs",
+            ));
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "#[derive(Sealed)] can't be used with generic structs",
+        ));
+    }
+
+    let mut plain = Vec::new();
+    let mut overview = Vec::new();
+    let mut secret = Vec::new();
+    for field in fields {
+        match Section::parse(&field.attrs)? {
+            Section::Plain => plain.push(field),
+            Section::Overview => overview.push(field),
+            Section::Secret => secret.push(field),
+        }
+    }
+
+    if let Some(field) = plain.iter().find(|field| {
+        let ident = field.ident.as_ref().expect("fields are named");
+        ident == "overview" || ident == "secret" || ident == "record_nonce"
+    }) {
+        return Err(syn::Error::new_spanned(
+            field,
+            "plain fields can't be named `overview`, `secret` or `record_nonce`, those hold the sealed sections",
+        ));
+    }
+
+    let vis = &input.vis;
+    let ident = &input.ident;
+    let overview_ident = format_ident!("{}Overview", ident);
+    let secret_ident = format_ident!("{}Secret", ident);
+    let sealed_ident = format_ident!("Sealed{}", ident);
+
+    let overview_type = section_type(input, &overview_ident, &overview, "overview");
+    let secret_type = section_type(input, &secret_ident, &secret, "secret");
+
+    let plain_idents = idents(&plain);
+    let overview_idents = idents(&overview);
+    let secret_idents = idents(&secret);
+    let plain_fields = plain.iter().map(|field| {
+        let Field { vis, ident, ty, .. } = field;
+        quote! { #vis #ident: #ty }
+    });
+
+    let debug_fields = fields.iter().map(|field| {
+        let ident = field.ident.as_ref().expect("fields are named");
+        let name = ident.to_string();
+
+        if plain
+            .iter()
+            .any(|plain| plain.ident.as_ref() == Some(ident))
+        {
+            quote! { .field(#name, &self.#ident) }
+        } else {
+            quote! { .field(#name, &"REDACTED") }
+        }
+    });
+    let name = ident.to_string();
+
+    let sealed_doc = format!("[`{ident}`] with its overview and secret fields encrypted.");
+
+    Ok(quote! {
+        #overview_type
+        #secret_type
+
+        #[doc = #sealed_doc]
+        #[derive(Debug, Clone, ::cerberus_crypto::__private::serde::Serialize, ::cerberus_crypto::__private::serde::Deserialize)]
+        #[serde(crate = "::cerberus_crypto::__private::serde")]
+        #vis struct #sealed_ident {
+            #(#plain_fields,)*
+            /// Both sections are bound to it, so neither can be swapped with a
+            /// section of another record.
+            #[serde(with = "::cerberus_crypto::__private::base64")]
+            #vis record_nonce: ::std::vec::Vec<u8>,
+            #vis overview: ::cerberus_crypto::EncryptedData<#overview_ident>,
+            #vis secret: ::cerberus_crypto::EncryptedData<#secret_ident>,
+        }
+
+        impl #ident {
+            #vis fn seal(
+                &self,
+                cipher: &impl ::cerberus_crypto::Cipher,
+            ) -> ::core::result::Result<#sealed_ident, ::cerberus_crypto::CipherError> {
+                // the copies handed to the cipher are wiped once sealed
+                let overview = ::cerberus_crypto::__private::zeroize::Zeroizing::new(#overview_ident {
+                    #(#overview_idents: ::core::clone::Clone::clone(&self.#overview_idents),)*
+                });
+                let secret = ::cerberus_crypto::__private::zeroize::Zeroizing::new(#secret_ident {
+                    #(#secret_idents: ::core::clone::Clone::clone(&self.#secret_idents),)*
+                });
+
+                let record_nonce = ::cerberus_crypto::__private::record_nonce();
+
+                ::core::result::Result::Ok(#sealed_ident {
+                    #(#plain_idents: ::core::clone::Clone::clone(&self.#plain_idents),)*
+                    overview: ::cerberus_crypto::Cipher::encrypt_with_associated_data(
+                        cipher,
+                        &*overview,
+                        &::cerberus_crypto::__private::section_associated_data("overview", &record_nonce),
+                    )?,
+                    secret: ::cerberus_crypto::Cipher::encrypt_with_associated_data(
+                        cipher,
+                        &*secret,
+                        &::cerberus_crypto::__private::section_associated_data("secret", &record_nonce),
+                    )?,
+                    record_nonce,
+                })
+            }
+        }
+
+        impl #sealed_ident {
+            #vis fn open(
+                &self,
+                cipher: &impl ::cerberus_crypto::Cipher,
+            ) -> ::core::result::Result<#ident, ::cerberus_crypto::CipherError> {
+                let #overview_ident { #(#overview_idents,)* } = self.open_overview(cipher)?;
+                let #secret_ident { #(#secret_idents,)* } =
+                    ::cerberus_crypto::Cipher::decrypt_with_associated_data(
+                        cipher,
+                        &self.secret,
+                        &::cerberus_crypto::__private::section_associated_data("secret", &self.record_nonce),
+                    )?;
+
+                ::core::result::Result::Ok(#ident {
+                    #(#plain_idents: ::core::clone::Clone::clone(&self.#plain_idents),)*
+                    #(#overview_idents,)*
+                    #(#secret_idents,)*
+                })
+            }
+
+            /// Decrypts only the overview, leaving the secret fields sealed.
+            #vis fn open_overview(
+                &self,
+                cipher: &impl ::cerberus_crypto::Cipher,
+            ) -> ::core::result::Result<#overview_ident, ::cerberus_crypto::CipherError> {
+                ::cerberus_crypto::Cipher::decrypt_with_associated_data(
+                    cipher,
+                    &self.overview,
+                    &::cerberus_crypto::__private::section_associated_data("overview", &self.record_nonce),
+                )
+            }
+        }
+
+        impl ::core::fmt::Debug for #ident {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.debug_struct(#name)
+                    #(#debug_fields)*
+                    .finish()
+            }
+        }
+    })
+}
+
+/// The plaintext of one sealed section, which is what gets serialized and
+/// encrypted.
+fn section_type(
+    input: &DeriveInput,
+    section_ident: &Ident,
+    fields: &[&Field],
+    section: &str,
+) -> TokenStream2 {
+    let vis = &input.vis;
+    let doc = format!("The {section} fields of [`{}`].", input.ident);
+    let name = section_ident.to_string();
+
+    let field_defs = fields.iter().map(|field| {
+        let Field { vis, ident, ty, .. } = field;
+        quote! { #vis #ident: #ty }
+    });
+    // spanned so a field type that can't be wiped is reported on the field
+    let zeroize_fields = fields.iter().map(|field| {
+        let Field { ident, ty, .. } = field;
+        quote_spanned! {ty.span()=>
+            ::cerberus_crypto::__private::zeroize::Zeroize::zeroize(&mut self.#ident);
+        }
+    });
+    let debug_fields = idents(fields).into_iter().map(|ident| {
+        let name = ident.to_string();
+        quote! { .field(#name, &"REDACTED") }
+    });
+
+    quote! {
+        #[doc = #doc]
+        #[derive(Clone, ::cerberus_crypto::__private::serde::Serialize, ::cerberus_crypto::__private::serde::Deserialize)]
+        #[serde(crate = "::cerberus_crypto::__private::serde")]
+        #vis struct #section_ident {
+            #(#field_defs,)*
+        }
+
+        impl ::cerberus_crypto::__private::zeroize::Zeroize for #section_ident {
+            fn zeroize(&mut self) {
+                #(#zeroize_fields)*
+            }
+        }
+
+        impl ::core::fmt::Debug for #section_ident {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                f.debug_struct(#name)
+                    #(#debug_fields)*
+                    .finish()
+            }
+        }
+    }
+}
+
+fn idents<'a>(fields: &[&'a Field]) -> Vec<&'a Ident> {
+    fields
+        .iter()
+        .map(|field| field.ident.as_ref().expect("fields are named"))
+        .collect()
+}
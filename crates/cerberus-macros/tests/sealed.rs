@@ -0,0 +1,85 @@
+use cerberus_crypto::{KeyIdentifier, NewKey, Sealed, symmetric::SymmetricKey};
+
+#[derive(Sealed, Clone, PartialEq)]
+pub struct Login {
+    pub id: i64,
+    #[sealed(overview)]
+    pub title: String,
+    #[sealed(overview)]
+    pub url: Option<String>,
+    #[sealed(secret)]
+    pub username: String,
+    #[sealed(secret)]
+    pub password: String,
+}
+
+fn key(byte: u8) -> SymmetricKey {
+    SymmetricKey::new_unchecked(
+        vec![byte; SymmetricKey::KEY_SIZE].into(),
+        KeyIdentifier::local(),
+    )
+}
+
+fn login() -> Login {
+    Login {
+        id: 7,
+        title: "Example".into(),
+        url: Some("https://example.com".into()),
+        username: "user".into(),
+        password: "hunter2".into(),
+    }
+}
+
+#[test]
+fn seals_and_opens() {
+    let key = key(1);
+    let sealed = login().seal(&key).unwrap();
+
+    assert_eq!(sealed.id, 7);
+    assert!(sealed.open(&key).unwrap() == login());
+
+    let overview = sealed.open_overview(&key).unwrap();
+    assert_eq!(overview.title, "Example");
+    assert_eq!(overview.url.as_deref(), Some("https://example.com"));
+}
+
+#[test]
+fn sealed_fields_are_not_stored_in_plaintext() {
+    let sealed = login().seal(&key(1)).unwrap();
+    let json = serde_json::to_string(&sealed).unwrap();
+
+    assert!(json.contains("\"id\":7"));
+    for plaintext in ["Example", "example.com", "user", "hunter2"] {
+        assert!(!json.contains(plaintext));
+    }
+
+    let restored: SealedLogin = serde_json::from_str(&json).unwrap();
+    assert!(restored.open(&key(1)).unwrap() == login());
+    assert!(restored.open(&key(2)).is_err());
+}
+
+#[test]
+fn debug_redacts_sealed_fields() {
+    let debug = format!("{:?}", login());
+
+    assert!(debug.contains("id: 7"));
+    assert!(debug.contains("password: \"REDACTED\""));
+    assert!(!debug.contains("hunter2"));
+    assert!(!debug.contains("Example"));
+}
+
+#[test]
+fn sections_of_another_record_are_rejected() {
+    let key = key(1);
+    let mut sealed = login().seal(&key).unwrap();
+    let other = Login {
+        password: "other".into(),
+        ..login()
+    }
+    .seal(&key)
+    .unwrap();
+
+    sealed.secret = other.secret;
+    assert!(sealed.open_overview(&key).is_ok());
+    assert!(sealed.open(&key).is_err());
+}
@@ -0,0 +1,9 @@
+use cerberus_macros::UpdateHmac;
+
+#[derive(UpdateHmac)]
+#[hmac(rename = "Login", rename = "Account")]
+struct Login {
+    username: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cerberus_macros::UpdateHmac;
+
+#[derive(UpdateHmac)]
+struct Login {
+    #[hmac(nested, with = str::as_bytes)]
+    username: String,
+}
+
+fn main() {}
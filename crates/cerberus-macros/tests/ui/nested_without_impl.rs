@@ -0,0 +1,13 @@
+use cerberus_macros::UpdateHmac;
+
+struct Credentials {
+    password: String,
+}
+
+#[derive(UpdateHmac)]
+struct Login {
+    #[hmac(nested)]
+    credentials: Credentials,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use cerberus_crypto::Sealed;
+
+#[derive(Sealed)]
+struct Login {
+    overview: String,
+    #[sealed(secret)]
+    password: String,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use cerberus_crypto::Sealed;
+
+#[derive(Sealed)]
+struct Login(#[sealed(secret)] String);
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cerberus_crypto::Sealed;
+
+#[derive(Sealed)]
+struct Login {
+    #[sealed(overview, secret)]
+    password: String,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use cerberus_macros::UpdateHmac;
+
+#[derive(UpdateHmac)]
+struct Login {
+    #[hmac(skip, rename = "user")]
+    username: String,
+}
+
+fn main() {}
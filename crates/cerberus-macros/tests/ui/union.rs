@@ -0,0 +1,9 @@
+use cerberus_macros::UpdateHmac;
+
+#[derive(UpdateHmac)]
+union Bits {
+    int: u32,
+    float: f32,
+}
+
+fn main() {}
@@ -1,4 +1,7 @@
-use cerberus_crypto::mac::UpdateHmac;
+use cerberus_crypto::{
+    KeyIdentifier, NewKey,
+    mac::{HmacKey, UpdateHmac},
+};
 use cerberus_macros::UpdateHmac as UpdateHmacDerive;
 
 #[allow(dead_code)]
@@ -10,3 +13,141 @@ struct CoolStruct {
     z: Vec<u32>,
     w: usize,
 }
+
+#[derive(UpdateHmacDerive)]
+struct Pair {
+    a: String,
+    b: String,
+}
+
+#[derive(UpdateHmacDerive)]
+#[hmac(rename = "Pair")]
+struct RenamedPair {
+    #[hmac(rename = "a")]
+    first: String,
+    #[hmac(rename = "b")]
+    second: String,
+}
+
+#[derive(UpdateHmacDerive)]
+struct OtherPair {
+    a: String,
+    b: String,
+}
+
+#[derive(UpdateHmacDerive)]
+#[hmac(rename = "Pair", version = 2)]
+struct PairV2 {
+    a: String,
+    b: String,
+}
+
+#[derive(UpdateHmacDerive)]
+struct Wrapper(#[hmac(nested)] Pair, u8);
+
+#[derive(UpdateHmacDerive)]
+enum Entry {
+    Login {
+        username: String,
+        #[hmac(nested)]
+        credentials: Pair,
+    },
+    Note(String),
+    #[hmac(rename = "Note")]
+    LegacyNote(String),
+    Empty,
+}
+
+#[derive(UpdateHmacDerive)]
+struct Digest {
+    #[hmac(with = str::as_bytes)]
+    value: String,
+}
+
+fn tag(data: impl UpdateHmac) -> Vec<u8> {
+    let key = HmacKey::new_unchecked(vec![7u8; HmacKey::KEY_SIZE].into(), KeyIdentifier::local());
+    key.compute_tag(data).into_bytes().to_vec()
+}
+
+fn pair(a: &str, b: &str) -> Pair {
+    Pair {
+        a: a.into(),
+        b: b.into(),
+    }
+}
+
+#[test]
+fn field_boundaries_are_unambiguous() {
+    assert_ne!(tag(pair("ab", "c")), tag(pair("a", "bc")));
+    assert_ne!(tag(pair("", "abc")), tag(pair("abc", "")));
+}
+
+#[test]
+fn type_name_and_version_separate_domains() {
+    let other = OtherPair {
+        a: "a".into(),
+        b: "b".into(),
+    };
+    let v2 = PairV2 {
+        a: "a".into(),
+        b: "b".into(),
+    };
+
+    assert_ne!(tag(pair("a", "b")), tag(other));
+    assert_ne!(tag(pair("a", "b")), tag(v2));
+}
+
+#[test]
+fn renames_keep_the_encoding_stable() {
+    let renamed = RenamedPair {
+        first: "a".into(),
+        second: "b".into(),
+    };
+
+    assert_eq!(tag(pair("a", "b")), tag(renamed));
+}
+
+#[test]
+fn skipped_fields_are_ignored() {
+    let cool = |x| CoolStruct {
+        x,
+        y: "y".into(),
+        z: vec![1, 2, 3],
+        w: 4,
+    };
+
+    assert_eq!(tag(cool(1)), tag(cool(2)));
+}
+
+#[test]
+fn nested_values_and_variants_are_tagged() {
+    assert_ne!(
+        tag(Wrapper(pair("a", "b"), 1)),
+        tag(Wrapper(pair("a", "b"), 2))
+    );
+    assert_ne!(
+        tag(Wrapper(pair("a", "b"), 1)),
+        tag(Wrapper(pair("b", "a"), 1))
+    );
+
+    let login = |username: &str| Entry::Login {
+        username: username.into(),
+        credentials: pair("a", "b"),
+    };
+    assert_ne!(tag(login("user")), tag(login("other")));
+    assert_ne!(tag(Entry::Note("note".into())), tag(Entry::Empty));
+    assert_eq!(
+        tag(Entry::Note("note".into())),
+        tag(Entry::LegacyNote("note".into()))
+    );
+}
+
+#[test]
+fn with_encodes_the_returned_bytes() {
+    let digest = |value: &str| Digest {
+        value: value.into(),
+    };
+
+    assert_eq!(tag(digest("abc")), tag(digest("abc")));
+    assert_ne!(tag(digest("abc")), tag(digest("abd")));
+}
@@ -1,12 +1,26 @@
+use std::{fmt, marker::PhantomData, ptr};
+
 use serde::{
     Deserialize, Serialize, Serializer,
-    de::{self},
+    de::{self, SeqAccess, Visitor},
 };
+use subtle::ConstantTimeEq;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-#[derive(Zeroize, ZeroizeOnDrop)]
+pub mod locked;
+pub mod sealed;
+mod string;
+
+use locked::LockedBox;
+pub use string::SecretString;
+
+enum Storage<T: ?Sized> {
+    Heap(Box<T>),
+    Locked(LockedBox<T>),
+}
+
 pub struct SecretBox<T: Zeroize + ?Sized> {
-    inner_secret: Box<T>,
+    inner_secret: Storage<T>,
 }
 
 impl<T: Zeroize + ?Sized> From<Box<T>> for SecretBox<T> {
@@ -18,15 +32,67 @@ impl<T: Zeroize + ?Sized> From<Box<T>> for SecretBox<T> {
 impl<T: Zeroize + ?Sized> SecretBox<T> {
     pub fn new(boxed_secret: Box<T>) -> Self {
         Self {
-            inner_secret: boxed_secret,
+            inner_secret: Storage::Heap(boxed_secret),
         }
     }
+
+    /// Whether the secret lives in locked memory, see [`locked`].
+    pub fn is_locked(&self) -> bool {
+        matches!(self.inner_secret, Storage::Locked(_))
+    }
 }
 
+impl<T: Zeroize> SecretBox<T> {
+    /// Moves the secret into locked memory, falling back to the heap when
+    /// none is available.
+    pub fn new_locked(boxed_secret: Box<T>) -> Self {
+        let inner_secret = match LockedBox::from_box(boxed_secret) {
+            Ok(locked) => Storage::Locked(locked),
+            Err(boxed_secret) => Storage::Heap(boxed_secret),
+        };
+
+        Self { inner_secret }
+    }
+}
+
+impl<S> SecretBox<[S]>
+where
+    [S]: Zeroize,
+{
+    /// Moves the secret into locked memory, falling back to the heap when
+    /// none is available.
+    pub fn new_locked(boxed_secret: Box<[S]>) -> Self {
+        let inner_secret = match LockedBox::from_boxed_slice(boxed_secret) {
+            Ok(locked) => Storage::Locked(locked),
+            Err(boxed_secret) => Storage::Heap(boxed_secret),
+        };
+
+        Self { inner_secret }
+    }
+}
+
+impl<T: Zeroize + ?Sized> Zeroize for SecretBox<T> {
+    fn zeroize(&mut self) {
+        self.expose_secret_mut().zeroize();
+    }
+}
+
+impl<T: Zeroize + ?Sized> Drop for SecretBox<T> {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<T: Zeroize + ?Sized> ZeroizeOnDrop for SecretBox<T> {}
+
 impl<T: Zeroize + Clone> Clone for SecretBox<T> {
     fn clone(&self) -> Self {
-        Self {
-            inner_secret: self.inner_secret.clone(),
+        let cloned = Box::new(self.expose_secret().clone());
+
+        if self.is_locked() {
+            Self::new_locked(cloned)
+        } else {
+            Self::new(cloned)
         }
     }
 }
@@ -39,24 +105,69 @@ where
     [S]: Zeroize,
 {
     fn clone(&self) -> Self {
-        Self {
-            inner_secret: self.inner_secret.clone(),
+        let cloned = Box::from(self.expose_secret());
+
+        if self.is_locked() {
+            Self::new_locked(cloned)
+        } else {
+            Self::new(cloned)
         }
     }
 }
 
+/// Allocates from locked memory when it has been enabled with
+/// [`locked::set_enabled`].
 impl<S> From<Vec<S>> for SecretSlice<S>
 where
     S: Zeroize,
     [S]: Zeroize,
 {
     fn from(value: Vec<S>) -> Self {
-        Self::from(value.into_boxed_slice())
+        let boxed_secret = into_exact_boxed_slice(value);
+
+        if locked::is_enabled() {
+            Self::new_locked(boxed_secret)
+        } else {
+            Self::new(boxed_secret)
+        }
     }
 }
 
-impl<T: Zeroize + ?Sized> std::fmt::Debug for SecretBox<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// Like `Vec::into_boxed_slice`, but when the vector has spare capacity the
+/// old buffer is wiped instead of being handed back to the allocator by a
+/// `realloc` with the secret still in it.
+fn into_exact_boxed_slice<S: Zeroize>(mut value: Vec<S>) -> Box<[S]> {
+    if value.len() == value.capacity() {
+        return value.into_boxed_slice();
+    }
+
+    let len = value.len();
+    let mut boxed = Box::<[S]>::new_uninit_slice(len);
+    unsafe {
+        ptr::copy_nonoverlapping(value.as_ptr(), boxed.as_mut_ptr().cast::<S>(), len);
+        // the elements were moved out, only their bytes are left behind
+        value.set_len(0);
+    }
+    wipe_spare_capacity(&mut value);
+
+    unsafe { boxed.assume_init() }
+}
+
+fn wipe_spare_capacity<S>(value: &mut Vec<S>) {
+    value.spare_capacity_mut().zeroize();
+}
+
+/// Compares in constant time, only the length of the secrets may leak.
+impl<T: Zeroize + ?Sized + ConstantTimeEq> PartialEq for SecretBox<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.expose_secret().ct_eq(other.expose_secret()).into()
+    }
+}
+
+impl<T: Zeroize + ?Sized + ConstantTimeEq> Eq for SecretBox<T> {}
+
+impl<T: Zeroize + ?Sized> fmt::Debug for SecretBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("SecretBox")
             .field("inner_secret", &"REDACTED")
             .finish()
@@ -73,13 +184,19 @@ pub trait ExposeSecretMut<T: Zeroize + ?Sized> {
 
 impl<T: Zeroize + ?Sized> ExposeSecret<T> for SecretBox<T> {
     fn expose_secret(&self) -> &T {
-        &self.inner_secret
+        match &self.inner_secret {
+            Storage::Heap(boxed) => boxed,
+            Storage::Locked(locked) => locked.as_ref(),
+        }
     }
 }
 
 impl<T: Zeroize + ?Sized> ExposeSecretMut<T> for SecretBox<T> {
     fn expose_secret_mut(&mut self) -> &mut T {
-        &mut self.inner_secret
+        match &mut self.inner_secret {
+            Storage::Heap(boxed) => boxed,
+            Storage::Locked(locked) => locked.as_mut(),
+        }
     }
 }
 
@@ -92,14 +209,15 @@ impl<T: Zeroize + ?Sized + Serialize> Serialize for SecretBox<T> {
     }
 }
 
-impl<'de, T: Zeroize + de::DeserializeOwned + Clone> Deserialize<'de> for SecretBox<T> {
+/// Deserializes straight into the boxed secret, so no copy of the value is
+/// left on the stack.
+impl<'de, T: Zeroize + de::DeserializeOwned + Default> Deserialize<'de> for SecretBox<T> {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: de::Deserializer<'de>,
     {
-        let mut data = T::deserialize(deserializer)?;
-        let secret_box = Self::from(Box::new(data.clone()));
-        data.zeroize();
+        let mut secret_box = Self::new(Box::default());
+        T::deserialize_in_place(deserializer, secret_box.expose_secret_mut())?;
         Ok(secret_box)
     }
 }
@@ -113,8 +231,96 @@ where
     where
         D: de::Deserializer<'de>,
     {
-        let data = Vec::deserialize(deserializer)?;
-        let secret_box = Self::from(data.into_boxed_slice());
+        let data = deserializer.deserialize_seq(SecretSeqVisitor(PhantomData))?;
+        let secret_box = Self::from(data);
         Ok(secret_box)
     }
 }
+
+// Collects the elements of a secret sequence, wiping every buffer that is
+// outgrown instead of letting the Vec reallocate with the data still in it.
+struct SecretSeqVisitor<S>(PhantomData<S>);
+
+impl<'de, S: Zeroize + Deserialize<'de>> Visitor<'de> for SecretSeqVisitor<S> {
+    type Value = Vec<S>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a sequence")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        // the hint comes from untrusted input, cap the up front allocation
+        let capacity = seq.size_hint().unwrap_or(0).min(4096);
+        let mut data = Vec::with_capacity(capacity);
+
+        loop {
+            let element = match seq.next_element() {
+                Ok(Some(element)) => element,
+                Ok(None) => break,
+                Err(err) => {
+                    data.zeroize();
+                    return Err(err);
+                }
+            };
+
+            if data.len() == data.capacity() {
+                let mut grown = Vec::with_capacity((data.capacity() * 2).max(32));
+                grown.append(&mut data);
+                wipe_spare_capacity(&mut data);
+                data = grown;
+            }
+            data.push(element);
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_string_deserializes_borrowed_and_owned_input() {
+        let borrowed: SecretString = serde_json::from_str(r#""hunter2""#).unwrap();
+        assert_eq!(borrowed.expose_secret(), "hunter2");
+
+        // escapes force serde_json to hand over an owned string
+        let owned: SecretString = serde_json::from_str(r#""hunter\n2""#).unwrap();
+        assert_eq!(owned.expose_secret(), "hunter\n2");
+
+        assert_eq!(serde_json::to_string(&borrowed).unwrap(), r#""hunter2""#);
+    }
+
+    #[test]
+    fn secret_string_rejects_invalid_utf8() {
+        let bytes = SecretSlice::from(vec![0xff, 0xfe]);
+        assert!(SecretString::from_utf8(bytes).is_err());
+    }
+
+    #[test]
+    fn secrets_compare_by_value() {
+        assert_eq!(SecretString::from("secret"), SecretString::from("secret"));
+        assert_ne!(SecretString::from("secret"), SecretString::from("secreT"));
+        assert_ne!(SecretString::from("secret"), SecretString::from("secrets"));
+
+        let slice = SecretSlice::from(vec![1u8, 2, 3]);
+        assert_eq!(slice, SecretSlice::from(vec![1u8, 2, 3]));
+        assert_ne!(slice, SecretSlice::from(vec![1u8, 2, 4]));
+    }
+
+    #[test]
+    fn secret_slice_deserializes_long_sequences() {
+        let data: Vec<u8> = (0..=255).cycle().take(5000).collect();
+        let json = serde_json::to_string(&data).unwrap();
+
+        let secret: SecretSlice<u8> = serde_json::from_str(&json).unwrap();
+        assert_eq!(secret.expose_secret(), data.as_slice());
+
+        let secret: SecretBox<String> = serde_json::from_str(r#""boxed""#).unwrap();
+        assert_eq!(secret.expose_secret(), "boxed");
+    }
+}
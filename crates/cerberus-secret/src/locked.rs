@@ -0,0 +1,325 @@
+//! Opt-in backing store that keeps secrets in `mlock`ed pages.
+//!
+//! Every allocation gets its own mapping, surrounded by inaccessible guard
+//! pages and excluded from core dumps. The value is placed against the
+//! trailing guard page so that overflows fault instead of silently reading
+//! neighbouring memory. When the pages can't be locked, most commonly because
+//! `RLIMIT_MEMLOCK` is exhausted, the secret is kept on the regular heap and
+//! the fallback is counted in [`stats`].
+//!
+//! Only the inline bytes of a value are locked, for types that own another
+//! heap allocation (such as `String`) that allocation stays on the heap.
+
+use std::{
+    alloc::Layout,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use zeroize::Zeroize;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static ACTIVE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_LOCKED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static FALLBACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes new [`SecretSlice`](crate::SecretSlice)s allocate from locked memory.
+/// Secrets that already exist are not moved.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockedMemoryStats {
+    /// Live allocations backed by locked memory.
+    pub active_allocations: usize,
+    /// Bytes currently locked, rounded up to whole pages.
+    pub locked_bytes: usize,
+    pub peak_locked_bytes: usize,
+    /// Allocations that asked for locked memory but ended up on the heap.
+    pub fallbacks: usize,
+    /// The soft `RLIMIT_MEMLOCK` of the process, `None` when unlimited or
+    /// unknown.
+    pub memlock_limit: Option<u64>,
+}
+
+pub fn stats() -> LockedMemoryStats {
+    LockedMemoryStats {
+        active_allocations: ACTIVE_ALLOCATIONS.load(Ordering::Relaxed),
+        locked_bytes: LOCKED_BYTES.load(Ordering::Relaxed),
+        peak_locked_bytes: PEAK_LOCKED_BYTES.load(Ordering::Relaxed),
+        fallbacks: FALLBACKS.load(Ordering::Relaxed),
+        memlock_limit: sys::memlock_limit(),
+    }
+}
+
+/// A value living in a locked region, the counterpart of `Box<T>`.
+pub(crate) struct LockedBox<T: ?Sized> {
+    ptr: NonNull<T>,
+    // only held so the region is released after the value is dropped
+    _region: LockedRegion,
+}
+
+// LockedBox owns its value exclusively, exactly like Box
+unsafe impl<T: ?Sized + Send> Send for LockedBox<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for LockedBox<T> {}
+
+impl<T> LockedBox<T> {
+    /// Moves the boxed value into locked memory, handing the box back when
+    /// no locked memory is available.
+    pub(crate) fn from_box(boxed: Box<T>) -> Result<Self, Box<T>> {
+        let layout = Layout::new::<T>();
+        let Some(region) = LockedRegion::allocate(layout) else {
+            return Err(boxed);
+        };
+
+        let ptr = region.ptr().cast::<T>();
+        unsafe {
+            let src = Box::into_raw(boxed);
+            ptr::copy_nonoverlapping(src, ptr.as_ptr(), 1);
+            wipe_and_free(src.cast(), layout);
+        }
+
+        Ok(Self {
+            ptr,
+            _region: region,
+        })
+    }
+}
+
+impl<S> LockedBox<[S]> {
+    pub(crate) fn from_boxed_slice(boxed: Box<[S]>) -> Result<Self, Box<[S]>> {
+        let len = boxed.len();
+        let layout = Layout::for_value(&*boxed);
+        let Some(region) = LockedRegion::allocate(layout) else {
+            return Err(boxed);
+        };
+
+        let data = region.ptr().cast::<S>();
+        unsafe {
+            let src = Box::into_raw(boxed);
+            ptr::copy_nonoverlapping(src.cast::<S>(), data.as_ptr(), len);
+            wipe_and_free(src.cast(), layout);
+        }
+
+        let ptr = NonNull::slice_from_raw_parts(data, len);
+        Ok(Self {
+            ptr,
+            _region: region,
+        })
+    }
+}
+
+impl<T: ?Sized> LockedBox<T> {
+    pub(crate) fn as_ref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+
+    pub(crate) fn as_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for LockedBox<T> {
+    fn drop(&mut self) {
+        // the region itself is wiped and unmapped once this returns
+        unsafe { ptr::drop_in_place(self.ptr.as_ptr()) }
+    }
+}
+
+/// Wipes the bytes a value was moved out of and frees them without running
+/// the value's destructor.
+unsafe fn wipe_and_free(src: *mut u8, layout: Layout) {
+    if layout.size() != 0 {
+        unsafe {
+            std::slice::from_raw_parts_mut(src, layout.size()).zeroize();
+            std::alloc::dealloc(src, layout);
+        }
+    }
+}
+
+struct LockedRegion {
+    base: NonNull<u8>,
+    mapping_len: usize,
+    data: NonNull<u8>,
+    data_len: usize,
+    value: NonNull<u8>,
+}
+
+impl LockedRegion {
+    fn allocate(layout: Layout) -> Option<Self> {
+        // zero sized values don't allocate on the heap either
+        if layout.size() == 0 {
+            return None;
+        }
+
+        let region = sys::allocate_locked(layout);
+        match &region {
+            Some(region) => {
+                ACTIVE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                let locked = LOCKED_BYTES.fetch_add(region.data_len, Ordering::Relaxed);
+                PEAK_LOCKED_BYTES.fetch_max(locked + region.data_len, Ordering::Relaxed);
+            }
+            None => {
+                FALLBACKS.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        region
+    }
+
+    fn ptr(&self) -> NonNull<u8> {
+        self.value
+    }
+}
+
+impl Drop for LockedRegion {
+    fn drop(&mut self) {
+        unsafe {
+            std::slice::from_raw_parts_mut(self.data.as_ptr(), self.data_len).zeroize();
+        }
+        sys::release_locked(self);
+
+        ACTIVE_ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+        LOCKED_BYTES.fetch_sub(self.data_len, Ordering::Relaxed);
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{alloc::Layout, ptr::NonNull};
+
+    use super::LockedRegion;
+
+    fn page_size() -> usize {
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+    }
+
+    pub(super) fn memlock_limit() -> Option<u64> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let result = unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) };
+
+        if result == 0 && limit.rlim_cur != libc::RLIM_INFINITY {
+            // rlim_t is narrower than u64 on some targets
+            #[allow(clippy::unnecessary_cast)]
+            Some(limit.rlim_cur as u64)
+        } else {
+            None
+        }
+    }
+
+    pub(super) fn allocate_locked(layout: Layout) -> Option<LockedRegion> {
+        let page_size = page_size();
+        if layout.align() > page_size {
+            return None;
+        }
+
+        let data_len = layout.size().div_ceil(page_size) * page_size;
+        let mapping_len = data_len + 2 * page_size;
+
+        unsafe {
+            let base = libc::mmap(
+                std::ptr::null_mut(),
+                mapping_len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if base == libc::MAP_FAILED {
+                return None;
+            }
+
+            let base = base.cast::<u8>();
+            let data = base.add(page_size);
+            let trailing_guard = data.add(data_len);
+
+            let guarded = libc::mprotect(base.cast(), page_size, libc::PROT_NONE) == 0
+                && libc::mprotect(trailing_guard.cast(), page_size, libc::PROT_NONE) == 0;
+            // mlock fails with ENOMEM once RLIMIT_MEMLOCK is exhausted
+            if !guarded || libc::mlock(data.cast(), data_len) != 0 {
+                libc::munmap(base.cast(), mapping_len);
+                return None;
+            }
+
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            libc::madvise(data.cast(), data_len, libc::MADV_DONTDUMP);
+
+            let offset = (data_len - layout.size()) & !(layout.align() - 1);
+
+            Some(LockedRegion {
+                base: NonNull::new_unchecked(base),
+                mapping_len,
+                data: NonNull::new_unchecked(data),
+                data_len,
+                value: NonNull::new_unchecked(data.add(offset)),
+            })
+        }
+    }
+
+    pub(super) fn release_locked(region: &LockedRegion) {
+        unsafe {
+            libc::munlock(region.data.as_ptr().cast(), region.data_len);
+            libc::munmap(region.base.as_ptr().cast(), region.mapping_len);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use std::alloc::Layout;
+
+    use super::LockedRegion;
+
+    pub(super) fn memlock_limit() -> Option<u64> {
+        None
+    }
+
+    pub(super) fn allocate_locked(_layout: Layout) -> Option<LockedRegion> {
+        None
+    }
+
+    pub(super) fn release_locked(_region: &LockedRegion) {}
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{ExposeSecret, SecretSlice};
+
+    // other tests allocate secrets at the same time, so only this allocation
+    // and counters that can't go back are checked
+    #[test]
+    fn secret_slice_uses_locked_memory_when_enabled() {
+        set_enabled(true);
+        let secret = SecretSlice::from(vec![7u8; 100]);
+        set_enabled(false);
+        assert_eq!(secret.expose_secret(), &[7u8; 100][..]);
+
+        if secret.is_locked() {
+            assert!(stats().locked_bytes >= 100);
+
+            // values sit against the trailing guard page
+            let end = secret.expose_secret().as_ptr() as usize + 100;
+            assert_eq!(end % sys_page_size(), 0);
+        } else {
+            assert!(stats().fallbacks > 0);
+        }
+
+        let cloned = secret.clone();
+        assert_eq!(cloned.is_locked(), secret.is_locked());
+    }
+
+    fn sys_page_size() -> usize {
+        unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+    }
+}
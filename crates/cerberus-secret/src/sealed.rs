@@ -0,0 +1,376 @@
+//! Keeps long-lived secrets encrypted while they are not being used.
+//!
+//! A [`SealedSecret`] holds its bytes encrypted with XChaCha20-Poly1305 under
+//! a key that is generated once per process and never written anywhere. The
+//! plaintext only exists inside the [`Exposed`] guard handed out by
+//! [`SealedSecret::expose_secret`], which wipes it again when dropped, so a
+//! heap dump taken while the secret is idle only contains ciphertext.
+//! [`SealedSecret::with_secret`] does the same for the length of a closure,
+//! decrypting into a locked buffer every thread keeps for that, so secrets
+//! used often don't cost a new mapping each time.
+//!
+//! Where the kernel supports it the process key is kept in a `memfd_secret(2)`
+//! region, which is removed from the kernel's direct map and can't be read
+//! even through `/proc/<pid>/mem`. Otherwise it falls back to locked memory,
+//! see [`locked`](crate::locked).
+
+use std::{cell::RefCell, fmt, marker::PhantomData, ops::Deref, sync::OnceLock};
+
+use chacha20poly1305::{AeadCore, AeadInPlace, Key, KeyInit, Tag, XChaCha20Poly1305, XNonce};
+use rand::{RngCore, rngs::OsRng};
+use zeroize::Zeroize;
+
+use crate::{ExposeSecret, ExposeSecretMut, SecretSlice};
+
+const KEY_SIZE: usize = 32;
+// large enough for any key
+const SCRATCH_SIZE: usize = 64;
+
+static PROCESS_KEY: OnceLock<ProcessKey> = OnceLock::new();
+
+thread_local! {
+    // decrypted into by `SealedSecret::with_secret`, allocated on first use
+    static SCRATCH: RefCell<Option<SecretSlice<u8>>> = const { RefCell::new(None) };
+}
+
+/// Where the per-process sealing key ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyBacking {
+    /// A `memfd_secret(2)` region.
+    SecretMemory,
+    Locked,
+    /// Neither was available, the key is on the regular heap.
+    Heap,
+}
+
+/// Generates the process key if that hasn't happened yet and reports where
+/// it is kept.
+pub fn key_backing() -> KeyBacking {
+    match &process_key().storage {
+        KeyStorage::SecretMemory(_) => KeyBacking::SecretMemory,
+        KeyStorage::Memory(key) if key.is_locked() => KeyBacking::Locked,
+        KeyStorage::Memory(_) => KeyBacking::Heap,
+    }
+}
+
+fn process_key() -> &'static ProcessKey {
+    PROCESS_KEY.get_or_init(ProcessKey::generate)
+}
+
+struct ProcessKey {
+    storage: KeyStorage,
+}
+
+enum KeyStorage {
+    SecretMemory(sys::SecretMemory),
+    Memory(SecretSlice<u8>),
+}
+
+impl ProcessKey {
+    fn generate() -> Self {
+        let storage = match sys::SecretMemory::allocate(KEY_SIZE) {
+            Some(mut region) => {
+                OsRng.fill_bytes(region.as_mut_slice());
+                KeyStorage::SecretMemory(region)
+            }
+            None => {
+                let mut key = SecretSlice::new_locked(vec![0u8; KEY_SIZE].into_boxed_slice());
+                OsRng.fill_bytes(key.expose_secret_mut());
+                KeyStorage::Memory(key)
+            }
+        };
+
+        Self { storage }
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        let key = match &self.storage {
+            KeyStorage::SecretMemory(region) => region.as_slice(),
+            KeyStorage::Memory(key) => key.expose_secret(),
+        };
+
+        XChaCha20Poly1305::new(Key::from_slice(key))
+    }
+}
+
+/// Bytes kept encrypted under the process key, see the [module
+/// documentation](self).
+#[derive(Clone)]
+pub struct SealedSecret {
+    nonce: XNonce,
+    tag: Tag,
+    ciphertext: Box<[u8]>,
+}
+
+impl SealedSecret {
+    pub fn seal(secret: &[u8]) -> Self {
+        let cipher = process_key().cipher();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+        // the copy is encrypted in place, so no plaintext is left behind in it
+        let mut ciphertext = Box::<[u8]>::from(secret);
+        let tag = cipher
+            .encrypt_in_place_detached(&nonce, &[], &mut ciphertext)
+            .expect("the buffer fits in a single XChaCha20 stream");
+
+        Self {
+            nonce,
+            tag,
+            ciphertext,
+        }
+    }
+
+    /// Decrypts the secret into locked memory for as long as the returned
+    /// guard is alive. Every call maps memory of its own, prefer
+    /// [`SealedSecret::with_secret`] where the secret is only needed briefly.
+    pub fn expose_secret(&self) -> Exposed<'_> {
+        Exposed {
+            secret: self.unseal_with(SecretSlice::new_locked),
+            _sealed: PhantomData,
+        }
+    }
+
+    /// Calls `f` with the decrypted secret, which is wiped again once it
+    /// returns or panics. The secret is decrypted into the locked buffer of
+    /// the calling thread, only when that is taken by an enclosing call or
+    /// too small does it get memory of its own.
+    pub fn with_secret<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+            Ok(mut scratch) if self.len() <= SCRATCH_SIZE => {
+                let scratch = scratch.get_or_insert_with(|| {
+                    SecretSlice::new_locked(vec![0u8; SCRATCH_SIZE].into_boxed_slice())
+                });
+                let secret = Wiped(&mut scratch.expose_secret_mut()[..self.len()]);
+                self.unseal_into(secret.0);
+
+                f(secret.0)
+            }
+            _ => f(&self.expose_secret()),
+        })
+    }
+
+    /// Decrypts the secret into a regular [`SecretSlice`], for handing it to
+    /// code that needs to own it.
+    pub fn unseal(&self) -> SecretSlice<u8> {
+        self.unseal_with(SecretSlice::from)
+    }
+
+    fn unseal_with<F>(&self, new: F) -> SecretSlice<u8>
+    where
+        F: FnOnce(Box<[u8]>) -> SecretSlice<u8>,
+    {
+        let mut secret = new(vec![0u8; self.len()].into_boxed_slice());
+        self.unseal_into(secret.expose_secret_mut());
+
+        secret
+    }
+
+    fn unseal_into(&self, buffer: &mut [u8]) {
+        buffer.copy_from_slice(&self.ciphertext);
+        process_key()
+            .cipher()
+            .decrypt_in_place_detached(&self.nonce, &[], buffer, &self.tag)
+            // the ciphertext never leaves this process, a failure means the
+            // memory was corrupted
+            .expect("sealed secret failed authentication");
+    }
+
+    pub fn len(&self) -> usize {
+        self.ciphertext.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ciphertext.is_empty()
+    }
+}
+
+impl From<SecretSlice<u8>> for SealedSecret {
+    fn from(value: SecretSlice<u8>) -> Self {
+        Self::seal(value.expose_secret())
+    }
+}
+
+impl fmt::Debug for SealedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SealedSecret")
+            .field("ciphertext", &"REDACTED")
+            .finish()
+    }
+}
+
+/// Wipes the part of the scratch buffer a secret was decrypted into.
+struct Wiped<'a>(&'a mut [u8]);
+
+impl Drop for Wiped<'_> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// The plaintext of a [`SealedSecret`], wiped when dropped. Keep it around
+/// only for as long as the secret is actually needed.
+pub struct Exposed<'a> {
+    secret: SecretSlice<u8>,
+    _sealed: PhantomData<&'a SealedSecret>,
+}
+
+impl Deref for Exposed<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.secret.expose_secret()
+    }
+}
+
+impl fmt::Debug for Exposed<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Exposed")
+            .field("secret", &"REDACTED")
+            .finish()
+    }
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod sys {
+    use std::ptr::NonNull;
+
+    pub(super) struct SecretMemory {
+        ptr: NonNull<u8>,
+        len: usize,
+    }
+
+    // the mapping is owned exclusively and never handed out mutably once
+    // the key has been written
+    unsafe impl Send for SecretMemory {}
+    unsafe impl Sync for SecretMemory {}
+
+    impl SecretMemory {
+        /// Fails on kernels older than 5.14, or when secret memory has been
+        /// disabled on the kernel command line.
+        pub(super) fn allocate(len: usize) -> Option<Self> {
+            unsafe {
+                let fd = libc::syscall(libc::SYS_memfd_secret, libc::O_CLOEXEC);
+                if fd < 0 {
+                    return None;
+                }
+                let fd = fd as libc::c_int;
+
+                if libc::ftruncate(fd, len as libc::off_t) != 0 {
+                    libc::close(fd);
+                    return None;
+                }
+
+                let ptr = libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd,
+                    0,
+                );
+                // the mapping keeps the memory alive on its own
+                libc::close(fd);
+
+                if ptr == libc::MAP_FAILED {
+                    return None;
+                }
+
+                Some(Self {
+                    ptr: NonNull::new_unchecked(ptr.cast()),
+                    len,
+                })
+            }
+        }
+
+        pub(super) fn as_slice(&self) -> &[u8] {
+            unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+        }
+
+        pub(super) fn as_mut_slice(&mut self) -> &mut [u8] {
+            unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+        }
+    }
+
+    impl Drop for SecretMemory {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.ptr.as_ptr().cast(), self.len);
+            }
+        }
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+mod sys {
+    pub(super) enum SecretMemory {}
+
+    impl SecretMemory {
+        pub(super) fn allocate(_len: usize) -> Option<Self> {
+            None
+        }
+
+        pub(super) fn as_slice(&self) -> &[u8] {
+            match *self {}
+        }
+
+        pub(super) fn as_mut_slice(&mut self) -> &mut [u8] {
+            match *self {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_secret_round_trips() {
+        let secret = SecretSlice::from(b"master key".to_vec());
+        let sealed = SealedSecret::from(secret.clone());
+
+        assert_ne!(&sealed.ciphertext[..], secret.expose_secret());
+        assert_eq!(&*sealed.expose_secret(), secret.expose_secret());
+        assert_eq!(sealed.unseal(), secret);
+
+        // every seal picks a fresh nonce
+        let resealed = SealedSecret::from(secret);
+        assert_ne!(sealed.ciphertext, resealed.ciphertext);
+    }
+
+    #[test]
+    fn secrets_are_exposed_to_closures() {
+        let outer = SealedSecret::seal(b"outer");
+        let inner = SealedSecret::seal(b"inner");
+        let large = SealedSecret::seal(&[7; SCRATCH_SIZE + 1]);
+
+        // the inner call finds the scratch buffer taken
+        let (outer_secret, inner_secret) =
+            outer.with_secret(|outer| (outer.to_vec(), inner.with_secret(|inner| inner.to_vec())));
+        assert_eq!(outer_secret, b"outer");
+        assert_eq!(inner_secret, b"inner");
+        assert_eq!(
+            large.with_secret(|large| large.to_vec()),
+            [7; SCRATCH_SIZE + 1]
+        );
+
+        // nothing is left in the scratch buffer
+        outer.with_secret(|_| ());
+        SCRATCH.with(|scratch| {
+            let scratch = scratch.borrow();
+            assert!(
+                scratch
+                    .as_ref()
+                    .unwrap()
+                    .expose_secret()
+                    .iter()
+                    .all(|&byte| byte == 0)
+            );
+        });
+    }
+}
@@ -0,0 +1,140 @@
+use std::{fmt, str::Utf8Error};
+
+use serde::{
+    Deserialize, Serialize, Serializer,
+    de::{self, Visitor},
+};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{ExposeSecret, SecretSlice};
+
+/// A zeroizing, UTF-8 validated string. The bytes are kept in a
+/// [`SecretSlice`] so they share its backing store.
+#[derive(Clone)]
+pub struct SecretString {
+    // always valid UTF-8
+    inner: SecretSlice<u8>,
+}
+
+impl SecretString {
+    pub fn from_utf8(bytes: SecretSlice<u8>) -> Result<Self, Utf8Error> {
+        std::str::from_utf8(bytes.expose_secret())?;
+
+        Ok(Self { inner: bytes })
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self {
+            inner: SecretSlice::from(value.into_bytes()),
+        }
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self {
+            inner: SecretSlice::from(value.as_bytes().to_vec()),
+        }
+    }
+}
+
+impl ExposeSecret<str> for SecretString {
+    fn expose_secret(&self) -> &str {
+        // validated when constructed and never mutated as anything but a str
+        unsafe { std::str::from_utf8_unchecked(self.inner.expose_secret()) }
+    }
+}
+
+impl Zeroize for SecretString {
+    fn zeroize(&mut self) {
+        self.inner.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for SecretString {}
+
+/// Compares in constant time, only the length of the strings may leak.
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        self.expose_secret()
+            .as_bytes()
+            .ct_eq(other.expose_secret().as_bytes())
+            .into()
+    }
+}
+
+impl Eq for SecretString {}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretString")
+            .field("inner_secret", &"REDACTED")
+            .finish()
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.expose_secret())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        deserializer.deserialize_string(SecretStringVisitor)
+    }
+}
+
+struct SecretStringVisitor;
+
+impl Visitor<'_> for SecretStringVisitor {
+    type Value = SecretString;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a string")
+    }
+
+    // borrowed input is copied once, straight into the secret allocation
+    fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(SecretString::from(v))
+    }
+
+    // owned strings are taken over without copying
+    fn visit_string<E>(self, v: String) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(SecretString::from(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        let v = std::str::from_utf8(v).map_err(E::custom)?;
+        Ok(SecretString::from(v))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        SecretString::from_utf8(SecretSlice::from(v)).map_err(E::custom)
+    }
+}
@@ -6,7 +6,8 @@ pub mod base64 {
     };
     use std::marker::PhantomData;
 
-    const ENGINE: engine::GeneralPurpose = ::base64::engine::general_purpose::STANDARD_NO_PAD;
+    pub(crate) const ENGINE: engine::GeneralPurpose =
+        ::base64::engine::general_purpose::STANDARD_NO_PAD;
 
     pub fn serialize<T: AsRef<[u8]>, S: Serializer>(
         source: &T,
@@ -45,24 +46,133 @@ pub mod base64 {
     }
 }
 
+/// [`base64`] for bytes that may be missing, written as `null`.
+pub mod base64_option {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(
+        source: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match source {
+            Some(source) => super::base64::serialize(source, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, S: TryFrom<Vec<u8>>, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<S>, D::Error> {
+        #[derive(Deserialize)]
+        struct Encoded<S: TryFrom<Vec<u8>>>(#[serde(with = "super::base64")] S);
+
+        let encoded = Option::<Encoded<S>>::deserialize(deserializer)?;
+        Ok(encoded.map(|Encoded(decoded)| decoded))
+    }
+}
+
+/// Base64 encoding for secret bytes. Decoding writes into the secret
+/// allocation and the encoded string is wiped after serialization.
 pub mod base64_expose_secret {
-    use cerberus_secret::{ExposeSecret, SecretBox};
-    use serde::Serializer;
-    use zeroize::Zeroize;
+    use base64::Engine;
+    use cerberus_secret::{ExposeSecret, SecretBox, SecretSlice};
+    use serde::{
+        Serializer,
+        de::{Deserializer, Visitor},
+    };
+    use zeroize::{Zeroize, Zeroizing};
 
-    pub use super::base64::deserialize;
+    use super::base64::ENGINE;
 
     pub fn serialize<T: AsRef<[u8]> + ?Sized + Zeroize, S: Serializer>(
         source: &SecretBox<T>,
         serializer: S,
     ) -> Result<S::Ok, S::Error> {
-        super::base64::serialize(&source.expose_secret(), serializer)
+        let encoded = Zeroizing::new(ENGINE.encode(source.expose_secret()));
+        serializer.serialize_str(&encoded)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SecretSlice<u8>, D::Error> {
+        struct Vis;
+        impl Visitor<'_> for Vis {
+            type Value = SecretSlice<u8>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(formatter, "a base64 string")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                let mut decoded = vec![0u8; base64::decoded_len_estimate(v.len())];
+                match ENGINE.decode_slice(v, &mut decoded) {
+                    Ok(len) => {
+                        decoded.truncate(len);
+                        Ok(SecretSlice::from(decoded))
+                    }
+                    Err(err) => {
+                        decoded.zeroize();
+                        Err(E::custom(err))
+                    }
+                }
+            }
+        }
+
+        deserializer.deserialize_str(Vis)
+    }
+}
+
+/// Hex encoding for secret bytes, decoded directly into the secret
+/// allocation.
+pub mod hex_expose_secret {
+    use cerberus_secret::{ExposeSecret, ExposeSecretMut, SecretBox, SecretSlice};
+    use serde::{
+        Serializer,
+        de::{Deserializer, Visitor},
+    };
+    use zeroize::{Zeroize, Zeroizing};
+
+    pub fn serialize<T: AsRef<[u8]> + ?Sized + Zeroize, S: Serializer>(
+        source: &SecretBox<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let encoded = Zeroizing::new(hex::encode(source.expose_secret()));
+        serializer.serialize_str(&encoded)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SecretSlice<u8>, D::Error> {
+        struct Vis;
+        impl Visitor<'_> for Vis {
+            type Value = SecretSlice<u8>;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(formatter, "a hex string")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                let mut decoded = SecretSlice::from(vec![0u8; v.len() / 2]);
+                hex::decode_to_slice(v, decoded.expose_secret_mut()).map_err(E::custom)?;
+
+                Ok(decoded)
+            }
+        }
+
+        deserializer.deserialize_str(Vis)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::base64;
+    use super::{base64, base64_expose_secret, base64_option, hex_expose_secret};
+    use cerberus_secret::{ExposeSecret, SecretSlice};
 
     use serde::{Deserialize, Serialize};
 
@@ -82,4 +192,55 @@ mod tests {
 
         assert_eq!(original.data, deserialized.data);
     }
+
+    #[derive(Serialize, Deserialize)]
+    struct OptionStruct {
+        #[serde(with = "base64_option")]
+        data: Option<Vec<u8>>,
+    }
+
+    #[test]
+    fn test_option_serialization() {
+        let some = OptionStruct {
+            data: Some(b"hello world".to_vec()),
+        };
+        let serialized = serde_json::to_string(&some).unwrap();
+        assert_eq!(serialized, r#"{"data":"aGVsbG8gd29ybGQ"}"#);
+        let deserialized: OptionStruct = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.data, some.data);
+
+        let deserialized: OptionStruct = serde_json::from_str(r#"{"data":null}"#).unwrap();
+        assert_eq!(deserialized.data, None);
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct SecretStruct {
+        #[serde(with = "base64_expose_secret")]
+        base64: SecretSlice<u8>,
+        #[serde(with = "hex_expose_secret")]
+        hex: SecretSlice<u8>,
+    }
+
+    #[test]
+    fn test_secret_serialization() {
+        let original = SecretStruct {
+            base64: SecretSlice::from(b"hello world".to_vec()),
+            hex: SecretSlice::from(vec![0xde, 0xad, 0xbe, 0xef]),
+        };
+        let serialized = serde_json::to_string(&original).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"base64":"aGVsbG8gd29ybGQ","hex":"deadbeef"}"#
+        );
+
+        let deserialized: SecretStruct = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.base64.expose_secret(), b"hello world");
+        assert_eq!(deserialized.hex.expose_secret(), &[0xde, 0xad, 0xbe, 0xef]);
+    }
+
+    #[test]
+    fn test_invalid_secret_encoding() {
+        assert!(serde_json::from_str::<SecretStruct>(r#"{"base64":"!!","hex":"00"}"#).is_err());
+        assert!(serde_json::from_str::<SecretStruct>(r#"{"base64":"","hex":"0"}"#).is_err());
+    }
 }
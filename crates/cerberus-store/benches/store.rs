@@ -0,0 +1,114 @@
+use std::path::Path;
+
+use cerberus_store::Store;
+use cerberus_store::item::{ItemData, ItemOverview, ItemQuery};
+use criterion::{Criterion, criterion_group, criterion_main};
+use tempfile::TempDir;
+use tokio::runtime::{Builder, Runtime};
+use tokio::task::JoinSet;
+use tokio_stream::StreamExt;
+
+const PASSWORD: &str = "password";
+const ITEM_COUNT: usize = 10_000;
+const CONCURRENT_LISTINGS: usize = 8;
+
+async fn create_store(path: &Path) -> Store {
+    let store = Store::new(path).await.unwrap();
+    store
+        .initialize_profile("User".to_owned(), PASSWORD)
+        .await
+        .unwrap();
+
+    store
+}
+
+async fn open_store(path: &Path) -> Store {
+    let store = Store::new(path).await.unwrap();
+    store.unlock(PASSWORD).await.unwrap();
+
+    store
+}
+
+fn unlock(c: &mut Criterion) {
+    let directory = TempDir::new().unwrap();
+    let runtime = Runtime::new().unwrap();
+    let store = runtime.block_on(create_store(&directory.path().join("store.db")));
+    store.lock().unwrap();
+
+    c.bench_function("unlock", |b| {
+        b.to_async(&runtime).iter(|| async {
+            store.unlock(PASSWORD).await.unwrap();
+            store.lock().unwrap();
+        })
+    });
+}
+
+fn list_items(c: &mut Criterion) {
+    let directory = TempDir::new().unwrap();
+    let path = directory.path().join("store.db");
+    let runtime = Runtime::new().unwrap();
+    runtime.block_on(async {
+        let store = create_store(&path).await;
+        let vault = store.create_vault("Personal".to_owned()).await.unwrap();
+        for i in 0..ITEM_COUNT {
+            vault
+                .create_item(
+                    ItemOverview::new(format!("item {i}"), "https://site.com".to_owned()),
+                    ItemData::new("secret".to_owned()),
+                )
+                .await
+                .unwrap();
+        }
+    });
+    // the batches of a listing take turns on a single blocking thread, as if
+    // the previews were decrypted one after the other
+    let sequential_runtime = Builder::new_multi_thread()
+        .max_blocking_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+    let query = ItemQuery::new().limit(ITEM_COUNT as u32);
+
+    let mut group = c.benchmark_group("list_10k_items");
+    group.sample_size(10);
+    for (name, runtime) in [("page", &runtime), ("page_sequential", &sequential_runtime)] {
+        let store = runtime.block_on(open_store(&path));
+        group.bench_function(name, |b| {
+            b.to_async(runtime).iter(|| async {
+                let page = store.items(&query).await.unwrap();
+                assert_eq!(page.items().len(), ITEM_COUNT);
+            })
+        });
+    }
+
+    let store = runtime.block_on(open_store(&path));
+    group.bench_function("page_concurrent", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut listings = JoinSet::new();
+            for _ in 0..CONCURRENT_LISTINGS {
+                let store = store.clone();
+                let query = query.clone();
+                listings.spawn(async move { store.items(&query).await.unwrap().items().len() });
+            }
+            while let Some(count) = listings.join_next().await {
+                assert_eq!(count.unwrap(), ITEM_COUNT);
+            }
+        })
+    });
+    group.bench_function("stream", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let count = store
+                .stream_items(ItemQuery::new())
+                .fold(0, |count, item| {
+                    item.unwrap();
+                    count + 1
+                })
+                .await;
+            assert_eq!(count, ITEM_COUNT);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, unlock, list_items);
+criterion_main!(benches);
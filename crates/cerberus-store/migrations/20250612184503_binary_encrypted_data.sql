@@ -0,0 +1,49 @@
+-- Encrypted data used to be stored as a JSON object holding the ciphertext and
+-- nonce as arrays of numbers. Move them into BLOB columns, existing rows keep
+-- their JSON plaintext and are tagged with format version 0.
+
+ALTER TABLE keys ADD COLUMN format_version INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE keys ADD COLUMN encrypted_key BLOB NOT NULL DEFAULT x'';
+ALTER TABLE keys ADD COLUMN nonce BLOB NOT NULL DEFAULT x'';
+ALTER TABLE keys ADD COLUMN parent_key_id INTEGER REFERENCES keys(id);
+
+UPDATE keys SET
+       encrypted_key = (
+              SELECT unhex(group_concat(printf('%02x', value), '' ORDER BY key))
+              FROM json_each(keys.key_encrypted_data, '$.enc_data')
+       ),
+       nonce = (
+              SELECT unhex(group_concat(printf('%02x', value), '' ORDER BY key))
+              FROM json_each(keys.key_encrypted_data, '$.nonce')
+       ),
+       parent_key_id = json_extract(key_encrypted_data, '$.key_id');
+
+ALTER TABLE keys DROP COLUMN key_encrypted_data;
+
+ALTER TABLE items ADD COLUMN overview_format_version INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE items ADD COLUMN overview_ciphertext BLOB NOT NULL DEFAULT x'';
+ALTER TABLE items ADD COLUMN overview_nonce BLOB NOT NULL DEFAULT x'';
+ALTER TABLE items ADD COLUMN item_format_version INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE items ADD COLUMN item_ciphertext BLOB NOT NULL DEFAULT x'';
+ALTER TABLE items ADD COLUMN item_nonce BLOB NOT NULL DEFAULT x'';
+
+UPDATE items SET
+       overview_ciphertext = (
+              SELECT unhex(group_concat(printf('%02x', value), '' ORDER BY key))
+              FROM json_each(items.overview_encrypted_data, '$.enc_data')
+       ),
+       overview_nonce = (
+              SELECT unhex(group_concat(printf('%02x', value), '' ORDER BY key))
+              FROM json_each(items.overview_encrypted_data, '$.nonce')
+       ),
+       item_ciphertext = (
+              SELECT unhex(group_concat(printf('%02x', value), '' ORDER BY key))
+              FROM json_each(items.item_encrypted_data, '$.enc_data')
+       ),
+       item_nonce = (
+              SELECT unhex(group_concat(printf('%02x', value), '' ORDER BY key))
+              FROM json_each(items.item_encrypted_data, '$.nonce')
+       );
+
+ALTER TABLE items DROP COLUMN overview_encrypted_data;
+ALTER TABLE items DROP COLUMN item_encrypted_data;
@@ -0,0 +1,10 @@
+-- Every profile owns an X25519 key pair for data sealed to it by other
+-- profiles and an Ed25519 key pair for signing. The private halves are stored
+-- in keys wrapped by the master key, the public halves are kept in the clear
+-- so they can be handed out while the store is locked. Profiles created before
+-- this migration get their key pairs on the next unlock.
+
+ALTER TABLE profiles ADD COLUMN agreement_key_id INTEGER REFERENCES keys(id);
+ALTER TABLE profiles ADD COLUMN agreement_public_key BLOB;
+ALTER TABLE profiles ADD COLUMN signing_key_id INTEGER REFERENCES keys(id);
+ALTER TABLE profiles ADD COLUMN verifying_key BLOB;
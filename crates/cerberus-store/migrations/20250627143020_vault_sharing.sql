@@ -0,0 +1,20 @@
+-- Vaults can be shared with other profiles. A vault records how this profile
+-- can access it, and vaults accepted from another profile remember the
+-- sender's verifying key and vault id so that an invitation reissued after a
+-- revocation updates the same vault.
+
+ALTER TABLE vaults ADD COLUMN access TEXT NOT NULL DEFAULT 'owner'
+      CHECK (access IN ('owner', 'read_write', 'read_only'));
+ALTER TABLE vaults ADD COLUMN shared_by BLOB;
+ALTER TABLE vaults ADD COLUMN shared_vault_id INTEGER;
+
+CREATE UNIQUE INDEX vaults_shared_origin ON vaults(shared_by, shared_vault_id);
+
+CREATE TABLE vault_shares(
+      id INTEGER PRIMARY KEY NOT NULL,
+      vault_id INTEGER REFERENCES vaults(id) NOT NULL,
+      recipient_public_key BLOB NOT NULL,
+      permission TEXT NOT NULL CHECK (permission IN ('read_write', 'read_only')),
+      created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+      revoked_at DATETIME
+);
@@ -0,0 +1,28 @@
+-- Emergency contacts can open chosen vaults of this profile once a request for
+-- access is approved, or once it has been pending for the contact's waiting
+-- period without being denied. The vault keys are sealed to the contact's
+-- public key when the contact is designated, so the store doesn't need to be
+-- unlocked for the contact to claim them.
+
+CREATE TABLE emergency_contacts(
+      id INTEGER PRIMARY KEY NOT NULL,
+      public_key BLOB NOT NULL UNIQUE,
+      wait_seconds INTEGER NOT NULL,
+      created_at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+);
+
+CREATE TABLE emergency_vault_keys(
+      contact_id INTEGER REFERENCES emergency_contacts(id) NOT NULL,
+      vault_id INTEGER REFERENCES vaults(id) NOT NULL,
+      sealed_key TEXT NOT NULL,
+      PRIMARY KEY (contact_id, vault_id)
+);
+
+CREATE TABLE emergency_requests(
+      id INTEGER PRIMARY KEY NOT NULL,
+      contact_id INTEGER REFERENCES emergency_contacts(id) NOT NULL,
+      state TEXT NOT NULL DEFAULT 'pending'
+            CHECK (state IN ('pending', 'approved', 'denied')),
+      requested_at DATETIME NOT NULL,
+      decided_at DATETIME
+);
@@ -0,0 +1,27 @@
+-- Every entry is encrypted under a key derived from the master key, only the
+-- kind and time are left readable so the log can be queried. Each hash is a
+-- MAC over the entry and the hash before it, so editing, removing or
+-- reordering entries breaks the chain.
+
+CREATE TABLE audit_log(
+      sequence INTEGER PRIMARY KEY NOT NULL,
+      kind TEXT NOT NULL,
+      occurred_at DATETIME NOT NULL,
+      format_version INTEGER NOT NULL,
+      ciphertext BLOB NOT NULL,
+      nonce BLOB NOT NULL,
+      hash BLOB NOT NULL
+);
+
+CREATE INDEX audit_log_occurred_at ON audit_log(occurred_at);
+CREATE INDEX audit_log_kind ON audit_log(kind, occurred_at);
+
+CREATE TRIGGER audit_log_no_update BEFORE UPDATE ON audit_log
+BEGIN
+      SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
+
+CREATE TRIGGER audit_log_no_delete BEFORE DELETE ON audit_log
+BEGIN
+      SELECT RAISE(ABORT, 'the audit log is append-only');
+END;
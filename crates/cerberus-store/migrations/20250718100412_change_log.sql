@@ -0,0 +1,52 @@
+-- Written by triggers, so changes made by any process using the database are
+-- seen by subscribers. Only ids are recorded, subscribers fetch what they
+-- need. The oldest changes are pruned once there are more than 10000.
+
+CREATE TABLE change_log(
+      sequence INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+      kind TEXT NOT NULL CHECK (kind IN (
+            'vault_created', 'vault_renamed', 'vault_deleted',
+            'item_created', 'item_updated', 'item_deleted'
+      )),
+      vault_id INTEGER NOT NULL,
+      item_id INTEGER
+);
+
+CREATE TRIGGER change_log_vault_created AFTER INSERT ON vaults
+BEGIN
+      INSERT INTO change_log(kind, vault_id) VALUES ('vault_created', NEW.id);
+END;
+
+CREATE TRIGGER change_log_vault_renamed AFTER UPDATE OF name ON vaults
+WHEN OLD.name IS NOT NEW.name
+BEGIN
+      INSERT INTO change_log(kind, vault_id) VALUES ('vault_renamed', NEW.id);
+END;
+
+CREATE TRIGGER change_log_vault_deleted AFTER DELETE ON vaults
+BEGIN
+      INSERT INTO change_log(kind, vault_id) VALUES ('vault_deleted', OLD.id);
+END;
+
+CREATE TRIGGER change_log_item_created AFTER INSERT ON items
+BEGIN
+      INSERT INTO change_log(kind, vault_id, item_id)
+      VALUES ('item_created', NEW.vault_id, NEW.id);
+END;
+
+CREATE TRIGGER change_log_item_updated AFTER UPDATE ON items
+BEGIN
+      INSERT INTO change_log(kind, vault_id, item_id)
+      VALUES ('item_updated', NEW.vault_id, NEW.id);
+END;
+
+CREATE TRIGGER change_log_item_deleted AFTER DELETE ON items
+BEGIN
+      INSERT INTO change_log(kind, vault_id, item_id)
+      VALUES ('item_deleted', OLD.vault_id, OLD.id);
+END;
+
+CREATE TRIGGER change_log_prune AFTER INSERT ON change_log
+BEGIN
+      DELETE FROM change_log WHERE sequence <= NEW.sequence - 10000;
+END;
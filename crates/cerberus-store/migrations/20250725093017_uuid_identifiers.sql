@@ -0,0 +1,100 @@
+-- Integer ids collide as soon as two stores are merged or copied into each
+-- other. Profiles, vaults, items and keys are given a UUIDv7 as their public
+-- id, integer ids stay internal. Existing rows get one made from the time they
+-- were created, keys don't record it and use the time of the migration.
+
+ALTER TABLE profiles ADD COLUMN uuid BLOB NOT NULL DEFAULT x'';
+ALTER TABLE vaults ADD COLUMN uuid BLOB NOT NULL DEFAULT x'';
+ALTER TABLE items ADD COLUMN uuid BLOB NOT NULL DEFAULT x'';
+ALTER TABLE keys ADD COLUMN uuid BLOB NOT NULL DEFAULT x'';
+
+-- 48 bits of milliseconds since the epoch, the version, 12 random bits, the
+-- variant and 62 random bits
+UPDATE profiles SET uuid = unhex(
+       printf('%012x', CAST(round((julianday(created_at) - 2440587.5) * 86400000) AS INTEGER))
+       || '7' || substr(hex(randomblob(2)), 2)
+       || substr('89ab', abs(random()) % 4 + 1, 1) || substr(hex(randomblob(8)), 2)
+);
+UPDATE vaults SET uuid = unhex(
+       printf('%012x', CAST(round((julianday(created_at) - 2440587.5) * 86400000) AS INTEGER))
+       || '7' || substr(hex(randomblob(2)), 2)
+       || substr('89ab', abs(random()) % 4 + 1, 1) || substr(hex(randomblob(8)), 2)
+);
+UPDATE items SET uuid = unhex(
+       printf('%012x', CAST(round((julianday(created_at) - 2440587.5) * 86400000) AS INTEGER))
+       || '7' || substr(hex(randomblob(2)), 2)
+       || substr('89ab', abs(random()) % 4 + 1, 1) || substr(hex(randomblob(8)), 2)
+);
+UPDATE keys SET uuid = unhex(
+       printf('%012x', CAST(round((julianday('now') - 2440587.5) * 86400000) AS INTEGER))
+       || '7' || substr(hex(randomblob(2)), 2)
+       || substr('89ab', abs(random()) % 4 + 1, 1) || substr(hex(randomblob(8)), 2)
+);
+
+CREATE UNIQUE INDEX profiles_uuid ON profiles(uuid);
+CREATE UNIQUE INDEX vaults_uuid ON vaults(uuid);
+CREATE UNIQUE INDEX items_uuid ON items(uuid);
+CREATE UNIQUE INDEX keys_uuid ON keys(uuid);
+
+-- Changes are reported by public id. Deleted rows can't be looked up, so the
+-- log records them itself. Only running subscribers read it, the changes made
+-- so far are dropped.
+
+DROP TRIGGER change_log_vault_created;
+DROP TRIGGER change_log_vault_renamed;
+DROP TRIGGER change_log_vault_deleted;
+DROP TRIGGER change_log_item_created;
+DROP TRIGGER change_log_item_updated;
+DROP TRIGGER change_log_item_deleted;
+DROP TRIGGER change_log_prune;
+DROP TABLE change_log;
+
+CREATE TABLE change_log(
+      sequence INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+      kind TEXT NOT NULL CHECK (kind IN (
+            'vault_created', 'vault_renamed', 'vault_deleted',
+            'item_created', 'item_updated', 'item_deleted'
+      )),
+      vault_id BLOB NOT NULL,
+      item_id BLOB
+);
+
+CREATE TRIGGER change_log_vault_created AFTER INSERT ON vaults
+BEGIN
+      INSERT INTO change_log(kind, vault_id) VALUES ('vault_created', NEW.uuid);
+END;
+
+CREATE TRIGGER change_log_vault_renamed AFTER UPDATE OF name ON vaults
+WHEN OLD.name IS NOT NEW.name
+BEGIN
+      INSERT INTO change_log(kind, vault_id) VALUES ('vault_renamed', NEW.uuid);
+END;
+
+CREATE TRIGGER change_log_vault_deleted AFTER DELETE ON vaults
+BEGIN
+      INSERT INTO change_log(kind, vault_id) VALUES ('vault_deleted', OLD.uuid);
+END;
+
+-- items are deleted before their vault
+CREATE TRIGGER change_log_item_created AFTER INSERT ON items
+BEGIN
+      INSERT INTO change_log(kind, vault_id, item_id)
+      VALUES ('item_created', (SELECT uuid FROM vaults WHERE id = NEW.vault_id), NEW.uuid);
+END;
+
+CREATE TRIGGER change_log_item_updated AFTER UPDATE ON items
+BEGIN
+      INSERT INTO change_log(kind, vault_id, item_id)
+      VALUES ('item_updated', (SELECT uuid FROM vaults WHERE id = NEW.vault_id), NEW.uuid);
+END;
+
+CREATE TRIGGER change_log_item_deleted AFTER DELETE ON items
+BEGIN
+      INSERT INTO change_log(kind, vault_id, item_id)
+      VALUES ('item_deleted', (SELECT uuid FROM vaults WHERE id = OLD.vault_id), OLD.uuid);
+END;
+
+CREATE TRIGGER change_log_prune AFTER INSERT ON change_log
+BEGIN
+      DELETE FROM change_log WHERE sequence <= NEW.sequence - 10000;
+END;
@@ -0,0 +1,6 @@
+-- Every invitation to a vault is numbered, so an older one can't replace the
+-- key and items of a newer one that was already accepted. Owned vaults count
+-- the invitations issued, accepted vaults keep the number of the last one
+-- accepted.
+
+ALTER TABLE vaults ADD COLUMN share_generation INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Pages of a vault's items are read in order of one of these, starting after
+-- the last item of the previous page. The rowid is part of every index, so
+-- the first one also orders by id.
+
+CREATE INDEX items_vault ON items(vault_id);
+CREATE INDEX items_vault_created_at ON items(vault_id, created_at, id);
+CREATE INDEX items_vault_updated_at ON items(vault_id, updated_at, id);
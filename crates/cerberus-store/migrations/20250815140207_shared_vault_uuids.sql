@@ -0,0 +1,10 @@
+-- Invitations name the shared vault by its UUID instead of the sender's
+-- integer id. The sender's UUIDs aren't known here, so vaults accepted before
+-- lose their origin, and an invitation reissued for one of them is accepted
+-- as a new vault.
+
+DROP INDEX vaults_shared_origin;
+ALTER TABLE vaults DROP COLUMN shared_vault_id;
+ALTER TABLE vaults ADD COLUMN shared_vault_id BLOB;
+
+CREATE UNIQUE INDEX vaults_shared_origin ON vaults(shared_by, shared_vault_id);
@@ -0,0 +1,10 @@
+-- The newest entry of the audit log under a MAC, kept outside of the log and
+-- replaced with every entry, so removing the newest entries is noticed too.
+-- Logs written before it get one with their next entry.
+
+CREATE TABLE audit_log_head(
+      id INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+      sequence INTEGER NOT NULL,
+      hash BLOB NOT NULL,
+      mac BLOB NOT NULL
+);
@@ -0,0 +1,27 @@
+-- Shares, emergency contacts and emergency requests are handed out of the
+-- store too, so they are given a UUIDv7 as their public id like the other
+-- records. Existing rows get one made from the time they were created.
+
+ALTER TABLE vault_shares ADD COLUMN uuid BLOB NOT NULL DEFAULT x'';
+ALTER TABLE emergency_contacts ADD COLUMN uuid BLOB NOT NULL DEFAULT x'';
+ALTER TABLE emergency_requests ADD COLUMN uuid BLOB NOT NULL DEFAULT x'';
+
+UPDATE vault_shares SET uuid = unhex(
+       printf('%012x', CAST(round((julianday(created_at) - 2440587.5) * 86400000) AS INTEGER))
+       || '7' || substr(hex(randomblob(2)), 2)
+       || substr('89ab', abs(random()) % 4 + 1, 1) || substr(hex(randomblob(8)), 2)
+);
+UPDATE emergency_contacts SET uuid = unhex(
+       printf('%012x', CAST(round((julianday(created_at) - 2440587.5) * 86400000) AS INTEGER))
+       || '7' || substr(hex(randomblob(2)), 2)
+       || substr('89ab', abs(random()) % 4 + 1, 1) || substr(hex(randomblob(8)), 2)
+);
+UPDATE emergency_requests SET uuid = unhex(
+       printf('%012x', CAST(round((julianday(requested_at) - 2440587.5) * 86400000) AS INTEGER))
+       || '7' || substr(hex(randomblob(2)), 2)
+       || substr('89ab', abs(random()) % 4 + 1, 1) || substr(hex(randomblob(8)), 2)
+);
+
+CREATE UNIQUE INDEX vault_shares_uuid ON vault_shares(uuid);
+CREATE UNIQUE INDEX emergency_contacts_uuid ON emergency_contacts(uuid);
+CREATE UNIQUE INDEX emergency_requests_uuid ON emergency_requests(uuid);
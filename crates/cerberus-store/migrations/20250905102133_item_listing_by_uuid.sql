@@ -0,0 +1,10 @@
+-- Listings break ties between items by UUID instead of rowid, so cursors
+-- don't hold ids that are internal to the database.
+
+DROP INDEX items_vault;
+DROP INDEX items_vault_created_at;
+DROP INDEX items_vault_updated_at;
+
+CREATE INDEX items_vault ON items(vault_id, uuid);
+CREATE INDEX items_vault_created_at ON items(vault_id, created_at, uuid);
+CREATE INDEX items_vault_updated_at ON items(vault_id, updated_at, uuid);
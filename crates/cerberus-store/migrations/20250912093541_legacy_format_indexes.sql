@@ -0,0 +1,7 @@
+-- Unlocking looks for rows still encrypted in format version 0 to upgrade
+-- them, which should only find the few rows left.
+
+CREATE INDEX keys_legacy_format ON keys(id) WHERE format_version = 0;
+
+CREATE INDEX items_legacy_format ON items(id)
+WHERE overview_format_version = 0 OR item_format_version = 0;
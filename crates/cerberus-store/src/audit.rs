@@ -0,0 +1,464 @@
+use std::sync::Arc;
+
+use cerberus_crypto::kdf::DerivationMaterial;
+use cerberus_crypto::mac::{HmacKey, UpdateHmac};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, de::IgnoredAny};
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    crypto::{Cipher, EncryptedData, SecureKey, SymmetricKey},
+    database::{
+        Database, Transaction,
+        record_types::{AuditEntryRecord, AuditHeadRecord},
+    },
+};
+
+/// What an audit log entry records, readable without decrypting the entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditEventKind {
+    Unlocked,
+    VaultCreated,
+    ItemCreated,
+    ItemRevealed,
+    ItemDeleted,
+    VaultShared,
+    ShareRevoked,
+    ShareAccepted,
+    EmergencyContactAdded,
+    EmergencyAccessApproved,
+    EmergencyAccessDenied,
+}
+
+impl AuditEventKind {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            AuditEventKind::Unlocked => "unlocked",
+            AuditEventKind::VaultCreated => "vault_created",
+            AuditEventKind::ItemCreated => "item_created",
+            AuditEventKind::ItemRevealed => "item_revealed",
+            AuditEventKind::ItemDeleted => "item_deleted",
+            AuditEventKind::VaultShared => "vault_shared",
+            AuditEventKind::ShareRevoked => "share_revoked",
+            AuditEventKind::ShareAccepted => "share_accepted",
+            AuditEventKind::EmergencyContactAdded => "emergency_contact_added",
+            AuditEventKind::EmergencyAccessApproved => "emergency_access_approved",
+            AuditEventKind::EmergencyAccessDenied => "emergency_access_denied",
+        }
+    }
+}
+
+/// An event in the audit log along with the UUIDs of what it happened to.
+/// Entries written when the log still recorded integer ids read them back as
+/// the nil UUID.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Unlocked,
+    VaultCreated {
+        #[serde(deserialize_with = "record_id")]
+        vault_id: Uuid,
+    },
+    ItemCreated {
+        #[serde(deserialize_with = "record_id")]
+        vault_id: Uuid,
+        #[serde(deserialize_with = "record_id")]
+        item_id: Uuid,
+    },
+    ItemRevealed {
+        #[serde(deserialize_with = "record_id")]
+        item_id: Uuid,
+    },
+    ItemDeleted {
+        #[serde(deserialize_with = "record_id")]
+        vault_id: Uuid,
+        #[serde(deserialize_with = "record_id")]
+        item_id: Uuid,
+    },
+    VaultShared {
+        #[serde(deserialize_with = "record_id")]
+        vault_id: Uuid,
+        #[serde(deserialize_with = "record_id")]
+        share_id: Uuid,
+    },
+    ShareRevoked {
+        #[serde(deserialize_with = "record_id")]
+        vault_id: Uuid,
+        #[serde(deserialize_with = "record_id")]
+        share_id: Uuid,
+    },
+    ShareAccepted {
+        #[serde(deserialize_with = "record_id")]
+        vault_id: Uuid,
+    },
+    EmergencyContactAdded {
+        #[serde(deserialize_with = "record_id")]
+        contact_id: Uuid,
+    },
+    EmergencyAccessApproved {
+        #[serde(deserialize_with = "record_id")]
+        request_id: Uuid,
+    },
+    EmergencyAccessDenied {
+        #[serde(deserialize_with = "record_id")]
+        request_id: Uuid,
+    },
+}
+
+fn record_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RecordId {
+        Uuid(Uuid),
+        // an integer id, which can't be told apart from the ones of other
+        // stores
+        Legacy(IgnoredAny),
+    }
+
+    Ok(match RecordId::deserialize(deserializer)? {
+        RecordId::Uuid(uuid) => uuid,
+        RecordId::Legacy(_) => Uuid::nil(),
+    })
+}
+
+impl AuditEvent {
+    pub fn kind(&self) -> AuditEventKind {
+        match self {
+            AuditEvent::Unlocked => AuditEventKind::Unlocked,
+            AuditEvent::VaultCreated { .. } => AuditEventKind::VaultCreated,
+            AuditEvent::ItemCreated { .. } => AuditEventKind::ItemCreated,
+            AuditEvent::ItemRevealed { .. } => AuditEventKind::ItemRevealed,
+            AuditEvent::ItemDeleted { .. } => AuditEventKind::ItemDeleted,
+            AuditEvent::VaultShared { .. } => AuditEventKind::VaultShared,
+            AuditEvent::ShareRevoked { .. } => AuditEventKind::ShareRevoked,
+            AuditEvent::ShareAccepted { .. } => AuditEventKind::ShareAccepted,
+            AuditEvent::EmergencyContactAdded { .. } => AuditEventKind::EmergencyContactAdded,
+            AuditEvent::EmergencyAccessApproved { .. } => AuditEventKind::EmergencyAccessApproved,
+            AuditEvent::EmergencyAccessDenied { .. } => AuditEventKind::EmergencyAccessDenied,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    sequence: i64,
+    occurred_at: DateTime<Utc>,
+    event: AuditEvent,
+}
+
+impl AuditEntry {
+    /// The position of the entry in the log, starting at 1 without gaps.
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+
+    pub fn occurred_at(&self) -> DateTime<Utc> {
+        self.occurred_at
+    }
+
+    pub fn event(&self) -> &AuditEvent {
+        &self.event
+    }
+}
+
+/// Selects the entries returned by
+/// [`Store::audit_entries`](crate::Store::audit_entries), every entry if
+/// nothing is set.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    kind: Option<AuditEventKind>,
+}
+
+impl AuditQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only entries that occurred at or after `since`.
+    pub fn since(self, since: DateTime<Utc>) -> Self {
+        Self {
+            since: Some(since),
+            ..self
+        }
+    }
+
+    /// Only entries that occurred before `until`.
+    pub fn until(self, until: DateTime<Utc>) -> Self {
+        Self {
+            until: Some(until),
+            ..self
+        }
+    }
+
+    pub fn kind(self, kind: AuditEventKind) -> Self {
+        Self {
+            kind: Some(kind),
+            ..self
+        }
+    }
+}
+
+/// What the hash of an entry is computed over, chaining it to the entry
+/// before it.
+#[derive(UpdateHmac)]
+#[hmac(rename = "cerberus.audit_log_entry")]
+struct ChainLink<'a> {
+    sequence: i64,
+    kind: &'a str,
+    occurred_at: i64,
+    format_version: i64,
+    ciphertext: &'a [u8],
+    nonce: &'a [u8],
+    previous_hash: &'a [u8],
+}
+
+impl<'a> ChainLink<'a> {
+    fn from_record(record: &'a AuditEntryRecord, previous_hash: &'a [u8]) -> Self {
+        Self {
+            sequence: record.sequence,
+            kind: &record.kind,
+            occurred_at: record.occurred_at.and_utc().timestamp_micros(),
+            format_version: record.format_version,
+            ciphertext: &record.ciphertext,
+            nonce: &record.nonce,
+            previous_hash,
+        }
+    }
+}
+
+/// What the MAC of the head of the log is computed over. The head names the
+/// newest entry, so removing entries from the end of the log is noticed.
+#[derive(UpdateHmac)]
+#[hmac(rename = "cerberus.audit_log_head")]
+struct ChainHead<'a> {
+    sequence: i64,
+    hash: &'a [u8],
+}
+
+/// Appends to and reads the audit log. Writing needs the master key, which
+/// vaults opened by an emergency contact don't have, so their reads aren't
+/// recorded.
+#[derive(Debug, Clone)]
+pub(crate) struct AuditLog {
+    database: Database,
+    master_key: Option<Arc<SecureKey>>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(database: Database, master_key: Option<Arc<SecureKey>>) -> Self {
+        Self {
+            database,
+            master_key,
+        }
+    }
+
+    /// Starts the log of a new profile with a head naming no entry, so one
+    /// that loses all of its entries doesn't pass as new.
+    pub(crate) async fn start_in(
+        transaction: &mut dyn Transaction,
+        derivation_material: &DerivationMaterial,
+    ) -> Result<(), Error> {
+        let (_, hmac_key) = audit_keys(derivation_material);
+
+        store_head(transaction, &hmac_key, 0, Vec::new()).await
+    }
+
+    /// Records `event` in a transaction of its own, for events that don't
+    /// write anything else.
+    pub(crate) async fn record(&self, event: AuditEvent) -> Result<(), Error> {
+        let audit_log = self.clone();
+
+        self.database
+            .transaction(|transaction| {
+                Box::pin(async move { audit_log.record_in(transaction, event).await })
+            })
+            .await
+    }
+
+    /// Records `event` in the transaction of the operation it describes, so
+    /// the entry is only kept if the operation is.
+    pub(crate) async fn record_in(
+        &self,
+        transaction: &mut dyn Transaction,
+        event: AuditEvent,
+    ) -> Result<(), Error> {
+        let Some(master_key) = self.master_key.as_ref() else {
+            return Ok(());
+        };
+
+        let (key, hmac_key) = audit_keys(&master_key.derivation_material()?);
+        let kind = event.kind().as_str();
+        let encrypted_event = key.encrypt(&event)?;
+        // stored with the precision the hash covers
+        let occurred_at =
+            DateTime::from_timestamp_micros(master_key.clock().now().timestamp_micros())
+                .expect("the current time is in range")
+                .naive_utc();
+
+        let (last_sequence, previous_hash) = last_link(transaction, &hmac_key).await?;
+        let sequence = last_sequence + 1;
+        let hash = hmac_key.compute_tag(ChainLink {
+            sequence,
+            kind,
+            occurred_at: occurred_at.and_utc().timestamp_micros(),
+            format_version: encrypted_event.version().into(),
+            ciphertext: encrypted_event.enc_data(),
+            nonce: encrypted_event.nonce(),
+            previous_hash: &previous_hash,
+        });
+
+        let hash = hash.into_bytes().to_vec();
+
+        transaction
+            .store_audit_entry(&AuditEntryRecord {
+                sequence,
+                kind: kind.to_owned(),
+                occurred_at,
+                format_version: encrypted_event.version().into(),
+                ciphertext: encrypted_event.enc_data().to_vec(),
+                nonce: encrypted_event.nonce().to_vec(),
+                hash: hash.clone(),
+            })
+            .await?;
+        store_head(transaction, &hmac_key, sequence, hash).await
+    }
+
+    pub(crate) async fn entries(&mut self, query: &AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        let (key, _) = audit_keys(&self.master_key()?.derivation_material()?);
+        let since = query.since.map(|since| since.naive_utc());
+        let until = query.until.map(|until| until.naive_utc());
+        let kind = query.kind.map(|kind| kind.as_str());
+
+        self.database
+            .list_audit_entries(since, until, kind)
+            .await?
+            .into_iter()
+            .map(|record| {
+                let encrypted_event = EncryptedData::<AuditEvent>::from_parts(
+                    record.format_version,
+                    record.ciphertext,
+                    record.nonce,
+                    None,
+                )?;
+
+                Ok(AuditEntry {
+                    sequence: record.sequence,
+                    occurred_at: record.occurred_at.and_utc(),
+                    event: key.decrypt(&encrypted_event)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Walks the whole chain, failing at the first entry that is missing,
+    /// out of place or was changed, and checks that the newest entry is the
+    /// one the head names. Returns the number of entries.
+    ///
+    /// Putting back an older copy of the whole store isn't noticed, and a
+    /// log whose head was removed gets a new one with its next entry, as the
+    /// logs written before heads were kept do.
+    pub(crate) async fn verify(&mut self) -> Result<i64, Error> {
+        let (_, hmac_key) = audit_keys(&self.master_key()?.derivation_material()?);
+
+        let mut previous_hash = Vec::new();
+        let mut expected_sequence = 1;
+        for record in self.database.list_audit_entries(None, None, None).await? {
+            if record.sequence != expected_sequence {
+                return Err(Error::AuditLogTampered(expected_sequence));
+            }
+            hmac_key
+                .verify_tag(
+                    ChainLink::from_record(&record, &previous_hash),
+                    &record.hash,
+                )
+                .map_err(|_| Error::AuditLogTampered(record.sequence))?;
+
+            previous_hash = record.hash;
+            expected_sequence += 1;
+        }
+
+        let head = self
+            .database
+            .find_audit_head()
+            .await?
+            .ok_or(Error::AuditLogTampered(expected_sequence))?;
+        hmac_key
+            .verify_tag(
+                ChainHead {
+                    sequence: head.sequence,
+                    hash: &head.hash,
+                },
+                &head.mac,
+            )
+            .map_err(|_| Error::AuditLogTampered(expected_sequence))?;
+        if head.sequence != expected_sequence - 1 || head.hash != previous_hash {
+            return Err(Error::AuditLogTampered(expected_sequence));
+        }
+
+        Ok(expected_sequence - 1)
+    }
+
+    fn master_key(&self) -> Result<&Arc<SecureKey>, Error> {
+        self.master_key.as_ref().ok_or(Error::Locked)
+    }
+}
+
+// no `KeyPurpose` maps to these, so `Store::derived_key` can't hand them out
+const ENCRYPTION_LABEL: &str = "audit_log.encryption";
+const CHAIN_LABEL: &str = "audit_log.chain";
+
+pub(crate) fn audit_keys(derivation_material: &DerivationMaterial) -> (SymmetricKey, HmacKey) {
+    (
+        derivation_material.derive_key(ENCRYPTION_LABEL),
+        derivation_material.derive_key(CHAIN_LABEL),
+    )
+}
+
+// the entry a new one follows, named by the head unless the log was written
+// before heads were kept
+async fn last_link(
+    transaction: &mut dyn Transaction,
+    hmac_key: &HmacKey,
+) -> Result<(i64, Vec<u8>), Error> {
+    if let Some(head) = transaction.find_audit_head().await? {
+        hmac_key
+            .verify_tag(
+                ChainHead {
+                    sequence: head.sequence,
+                    hash: &head.hash,
+                },
+                &head.mac,
+            )
+            .map_err(|_| Error::AuditLogTampered(head.sequence))?;
+
+        return Ok((head.sequence, head.hash));
+    }
+
+    Ok(match transaction.find_last_audit_entry().await? {
+        Some(last) => (last.sequence, last.hash),
+        None => (0, Vec::new()),
+    })
+}
+
+async fn store_head(
+    transaction: &mut dyn Transaction,
+    hmac_key: &HmacKey,
+    sequence: i64,
+    hash: Vec<u8>,
+) -> Result<(), Error> {
+    let mac = hmac_key.compute_tag(ChainHead {
+        sequence,
+        hash: &hash,
+    });
+
+    transaction
+        .store_audit_head(&AuditHeadRecord {
+            sequence,
+            hash,
+            mac: mac.into_bytes().to_vec(),
+        })
+        .await
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use tokio::sync::watch;
+use tokio::task::{self, AbortHandle};
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+use crate::events::{StoreEvent, Subscription};
+use crate::item::ItemPreview;
+use crate::lock::LockState;
+
+/// Decrypted item previews of the vaults listed most recently, so listing a
+/// vault again doesn't read and decrypt every overview. Overviews are zeroed
+/// when they are dropped, which happens when their vault changes and when the
+/// store is locked.
+///
+/// Previews are only cached while the cache is following the store's
+/// changes, see [`OverviewCache::follow`].
+#[derive(Debug)]
+pub(crate) struct OverviewCache {
+    // the most previews held across every vault
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    vaults: HashMap<Uuid, CachedVault>,
+    len: usize,
+    // bumped by every invalidation, so listings read before one aren't cached
+    generation: u64,
+    last_used: u64,
+    following: Option<AbortHandle>,
+}
+
+#[derive(Debug)]
+struct CachedVault {
+    previews: Vec<ItemPreview>,
+    last_used: u64,
+}
+
+impl OverviewCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::default(),
+        }
+    }
+
+    /// The previews of `vault_id`, if it was cached since it last changed.
+    pub(crate) fn get(&self, vault_id: Uuid) -> Option<Vec<ItemPreview>> {
+        let mut state = self.state();
+        state.last_used += 1;
+        let last_used = state.last_used;
+
+        let vault = state.vaults.get_mut(&vault_id)?;
+        vault.last_used = last_used;
+
+        Some(vault.previews.clone())
+    }
+
+    /// Taken before reading a listing and handed back to
+    /// [`OverviewCache::insert`].
+    pub(crate) fn generation(&self) -> u64 {
+        self.state().generation
+    }
+
+    /// Caches the listing of `vault_id`, unless anything was invalidated
+    /// since `generation` was taken. The vaults used longest ago make room
+    /// for it.
+    pub(crate) fn insert(&self, vault_id: Uuid, generation: u64, previews: &[ItemPreview]) {
+        let mut state = self.state();
+        if state.generation != generation
+            || state.following.is_none()
+            || previews.len() > self.capacity
+        {
+            return;
+        }
+
+        state.remove(vault_id);
+        while state.len + previews.len() > self.capacity {
+            let Some(&oldest) = state
+                .vaults
+                .iter()
+                .min_by_key(|(_, vault)| vault.last_used)
+                .map(|(id, _)| id)
+            else {
+                break;
+            };
+            state.remove(oldest);
+        }
+
+        state.last_used += 1;
+        state.len += previews.len();
+        let vault = CachedVault {
+            previews: previews.to_vec(),
+            last_used: state.last_used,
+        };
+        state.vaults.insert(vault_id, vault);
+    }
+
+    pub(crate) fn invalidate(&self, vault_id: Uuid) {
+        let mut state = self.state();
+        state.generation += 1;
+        state.remove(vault_id);
+    }
+
+    pub(crate) fn clear(&self) {
+        let mut state = self.state();
+        state.generation += 1;
+        state.vaults.clear();
+        state.len = 0;
+    }
+
+    /// Invalidates vaults as `subscription` reports changes to them, until
+    /// the store is locked. Replaces whatever the cache followed before.
+    pub(crate) fn follow(
+        self: &Arc<Self>,
+        subscription: Subscription,
+        lock_state: watch::Receiver<LockState>,
+    ) {
+        let task = tokio::spawn(follow_changes(
+            Arc::downgrade(self),
+            subscription,
+            lock_state,
+        ));
+
+        if let Some(previous) = self.state().following.replace(task.abort_handle()) {
+            previous.abort();
+        }
+    }
+
+    // nothing is cached again until the cache follows a new subscription
+    fn stop_following(&self, task: task::Id) {
+        let mut state = self.state();
+        if state
+            .following
+            .as_ref()
+            .is_some_and(|following| following.id() == task)
+        {
+            state.following = None;
+        }
+        drop(state);
+
+        self.clear();
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        // the state is left consistent if a holder panics
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl CacheState {
+    fn remove(&mut self, vault_id: Uuid) {
+        if let Some(vault) = self.vaults.remove(&vault_id) {
+            self.len -= vault.previews.len();
+        }
+    }
+}
+
+async fn follow_changes(
+    cache: Weak<OverviewCache>,
+    mut subscription: Subscription,
+    mut lock_state: watch::Receiver<LockState>,
+) {
+    loop {
+        let event = tokio::select! {
+            event = subscription.next() => event,
+            // also returns once the store is dropped
+            _ = lock_state.wait_for(|state| *state == LockState::Locked) => None,
+        };
+        let Some(cache) = cache.upgrade() else {
+            return;
+        };
+
+        match event {
+            Some(
+                StoreEvent::VaultCreated { vault_id }
+                | StoreEvent::VaultRenamed { vault_id }
+                | StoreEvent::VaultDeleted { vault_id }
+                | StoreEvent::ItemCreated { vault_id, .. }
+                | StoreEvent::ItemUpdated { vault_id, .. }
+                | StoreEvent::ItemDeleted { vault_id, .. },
+            ) => cache.invalidate(vault_id),
+            Some(StoreEvent::Lagged) => cache.clear(),
+            Some(StoreEvent::Locked | StoreEvent::Unlocked) => {}
+            // locked, or the changes can't be read anymore
+            None => return cache.stop_following(task::id()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item::ItemOverview;
+
+    fn vault(id: u128) -> Uuid {
+        Uuid::from_u128(id)
+    }
+
+    fn previews(vault_id: u128, count: u128) -> Vec<ItemPreview> {
+        (0..count)
+            .map(|id| {
+                let overview = ItemOverview::new(format!("Item {id}"), String::new());
+                ItemPreview::new(Uuid::from_u128(id), vault(vault_id), overview)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn evicts_the_vault_used_longest_ago() {
+        let cache = OverviewCache::new(4);
+        // as if following a subscription
+        cache.state().following = Some(tokio::spawn(async {}).abort_handle());
+
+        cache.insert(vault(1), cache.generation(), &previews(1, 2));
+        cache.insert(vault(2), cache.generation(), &previews(2, 2));
+        assert!(cache.get(vault(1)).is_some());
+
+        cache.insert(vault(3), cache.generation(), &previews(3, 2));
+        assert_eq!(cache.get(vault(1)).unwrap().len(), 2);
+        assert!(cache.get(vault(2)).is_none());
+        assert_eq!(cache.get(vault(3)).unwrap().len(), 2);
+
+        // too large to cache at all
+        cache.insert(vault(4), cache.generation(), &previews(4, 5));
+        assert!(cache.get(vault(4)).is_none());
+
+        let generation = cache.generation();
+        cache.invalidate(vault(1));
+        cache.insert(vault(2), generation, &previews(2, 1));
+        assert!(cache.get(vault(1)).is_none());
+        assert!(cache.get(vault(2)).is_none());
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+
+/// Source of the current time for anything the store schedules, so waiting
+/// periods can be tested without waiting.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system's wall clock, used unless [`Store::with_clock`](crate::Store::with_clock)
+/// sets another one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
@@ -1,6 +1,7 @@
 use crate::Error;
+use cerberus_crypto::FormatVersion;
 use rand::rngs::OsRng;
-use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde::{Serialize, de::DeserializeOwned};
 
 use std::marker::PhantomData;
 
@@ -12,8 +13,9 @@ pub(crate) use encrypted_key::EncryptedKey;
 pub(crate) use secure_key::{SecureKey, SecureKeyState};
 pub(crate) use symmetric_key::SymmetricKey;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug)]
 pub(crate) struct EncryptedData<T: Serialize + DeserializeOwned> {
+    version: FormatVersion,
     enc_data: Vec<u8>,
     nonce: [u8; 24],
     key_id: Option<i64>,
@@ -23,6 +25,7 @@ pub(crate) struct EncryptedData<T: Serialize + DeserializeOwned> {
 impl<T: Serialize + DeserializeOwned> Clone for EncryptedData<T> {
     fn clone(&self) -> Self {
         Self {
+            version: self.version,
             enc_data: self.enc_data.clone(),
             nonce: self.nonce,
             key_id: self.key_id,
@@ -32,6 +35,42 @@ impl<T: Serialize + DeserializeOwned> Clone for EncryptedData<T> {
 }
 
 impl<T: Serialize + DeserializeOwned> EncryptedData<T> {
+    /// Rebuilds encrypted data from the columns it is stored in.
+    pub(crate) fn from_parts(
+        version: i64,
+        enc_data: Vec<u8>,
+        nonce: Vec<u8>,
+        key_id: Option<i64>,
+    ) -> Result<Self, Error> {
+        let version = u8::try_from(version)
+            .ok()
+            .and_then(|version| FormatVersion::try_from(version).ok())
+            .ok_or(Error::MalformedEncryptedData)?;
+        let nonce = nonce
+            .try_into()
+            .map_err(|_| Error::MalformedEncryptedData)?;
+
+        Ok(Self {
+            version,
+            enc_data,
+            nonce,
+            key_id,
+            _phantom: PhantomData,
+        })
+    }
+
+    pub(crate) fn version(&self) -> u8 {
+        self.version.into()
+    }
+
+    pub(crate) fn enc_data(&self) -> &[u8] {
+        &self.enc_data
+    }
+
+    pub(crate) fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
     pub(crate) fn key_id(&self) -> Option<i64> {
         self.key_id
     }
@@ -64,7 +103,7 @@ impl<T: Serialize + DeserializeOwned> EncryptedDataKeyPair<T> {
     ) -> Result<Self, Error> {
         let key = SymmetricKey::generate(&mut OsRng);
         let encrypted_data = key.encrypt(&data).unwrap();
-        let encrypted_key = key.into_encrypted_key(parent_key);
+        let encrypted_key = key.into_encrypted_key(parent_key)?;
 
         Ok(Self::new(encrypted_data, encrypted_key))
     }
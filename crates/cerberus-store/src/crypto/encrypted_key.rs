@@ -1,31 +1,52 @@
 use super::{Cipher, EncryptedData, SymmetricKey};
 use crate::Error;
-use crate::database::Repository;
+use crate::database::{Repository, record_types::NewKeyRecord};
+use cerberus_secret::SecretSlice;
 
 #[derive(Debug, Clone)]
 pub(crate) struct EncryptedKey {
     id: Option<i64>,
-    key_encrypted_data: EncryptedData<Vec<u8>>,
+    key_encrypted_data: EncryptedData<SecretSlice<u8>>,
 }
 
 impl EncryptedKey {
-    pub(crate) fn new(id: Option<i64>, key_encrypted_data: EncryptedData<Vec<u8>>) -> Self {
+    pub(crate) fn new(id: Option<i64>, key_encrypted_data: EncryptedData<SecretSlice<u8>>) -> Self {
         Self {
             id,
             key_encrypted_data,
         }
     }
 
+    /// Wraps key material that isn't a [`SymmetricKey`], such as the private
+    /// half of a key pair.
+    pub(crate) fn from_secret<K: Cipher>(
+        secret: &SecretSlice<u8>,
+        parent_key: &K,
+    ) -> Result<Self, Error> {
+        Ok(Self::new(None, parent_key.encrypt(secret)?))
+    }
+
     pub(crate) fn try_to_symmetric_key<K: Cipher>(
         &self,
         parent_key: &K,
     ) -> Result<SymmetricKey, Error> {
-        let decrypted_key = parent_key.decrypt(&self.key_encrypted_data)?;
-        Ok(SymmetricKey::new(&decrypted_key, self.id))
+        Ok(SymmetricKey::new(self.try_to_secret(parent_key)?, self.id))
+    }
+
+    pub(crate) fn try_to_secret<K: Cipher>(
+        &self,
+        parent_key: &K,
+    ) -> Result<SecretSlice<u8>, Error> {
+        parent_key.decrypt(&self.key_encrypted_data)
     }
 
-    pub(crate) async fn store<R: Repository>(&mut self, repo: &mut R) -> Result<(), Error> {
-        let key_record = repo.store_key(&self.key_encrypted_data).await?;
+    pub(crate) async fn store<R: Repository + ?Sized>(
+        &mut self,
+        repo: &mut R,
+    ) -> Result<(), Error> {
+        let key_record = repo
+            .store_key(&NewKeyRecord::from_encrypted_data(&self.key_encrypted_data))
+            .await?;
 
         self.id = Some(key_record.id);
 
@@ -1,43 +1,118 @@
 use super::{Cipher, EncryptedData, EncryptedKey, SymmetricKey};
 use crate::Error;
+use crate::clock::Clock;
+use crate::lock::{AutoLock, LockState};
+use cerberus_crypto::kdf::DerivationMaterial;
+use chrono::{DateTime, Utc};
 use serde::{Serialize, de::DeserializeOwned};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+use tokio::sync::watch;
 
 pub(crate) enum SecureKeyState {
     Locked,
     Unlocked,
 }
 
+#[derive(Debug, Clone)]
+struct UnlockedKey {
+    key: Arc<SymmetricKey>,
+    unlocked_at: DateTime<Utc>,
+}
+
+/// The master key, shared by the store and every handle created from it.
+/// Locking and unlocking replace the unlocked key as a whole, so readers
+/// only ever hold it for as long as it takes to copy an `Arc`.
 #[derive(Debug)]
 pub(crate) struct SecureKey {
-    decrypted_key: Option<SymmetricKey>,
     encrypted_key: EncryptedKey,
+    unlocked_key: watch::Sender<Option<UnlockedKey>>,
+    auto_lock: AutoLock,
+    // microseconds since the epoch, updated by every use of the key
+    last_access: AtomicI64,
 }
 
 impl SecureKey {
-    pub(crate) fn new(encrypted_key: EncryptedKey) -> Self {
+    pub(crate) fn new(encrypted_key: EncryptedKey, auto_lock: AutoLock) -> Self {
+        let now = auto_lock.clock.now();
+
         Self {
             encrypted_key,
-            decrypted_key: None,
+            unlocked_key: watch::Sender::new(None),
+            auto_lock,
+            last_access: AtomicI64::new(now.timestamp_micros()),
         }
     }
 
-    pub(crate) fn new_unlocked(encrypted_key: EncryptedKey, decrypted_key: SymmetricKey) -> Self {
-        Self {
-            decrypted_key: Some(decrypted_key),
-            encrypted_key,
-        }
+    pub(crate) fn new_unlocked(
+        encrypted_key: EncryptedKey,
+        decrypted_key: SymmetricKey,
+        auto_lock: AutoLock,
+    ) -> Self {
+        let secure_key = Self::new(encrypted_key, auto_lock);
+        secure_key.set_unlocked(decrypted_key);
+
+        secure_key
     }
 
-    pub(crate) fn unlock(&mut self, parent_key: &SymmetricKey) -> Result<(), Error> {
+    pub(crate) fn unlock(&self, parent_key: &SymmetricKey) -> Result<(), Error> {
         let symmetric_key = self.encrypted_key.try_to_symmetric_key(parent_key)?;
 
-        self.decrypted_key = Some(symmetric_key);
+        self.set_unlocked(symmetric_key);
 
         Ok(())
     }
 
-    pub(crate) fn lock(&mut self) {
-        self.decrypted_key = None;
+    pub(crate) fn lock(&self) {
+        self.unlocked_key.send_replace(None);
+        self.auto_lock.broadcast(LockState::Locked);
+    }
+
+    /// The clock of the store the key belongs to.
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        &*self.auto_lock.clock
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<LockState> {
+        self.auto_lock.state.subscribe()
+    }
+
+    /// Locks the key if its lock policy has run out, otherwise returns when
+    /// it will. `None` if the key is locked or never locks by itself.
+    pub(crate) fn lock_if_expired(&self) -> Option<DateTime<Utc>> {
+        let unlocked_key = self.unlocked_key.borrow().clone()?;
+        let deadline = self.deadline(&unlocked_key);
+        if self.is_expired(deadline) {
+            self.lock();
+            return None;
+        }
+
+        deadline
+    }
+
+    fn set_unlocked(&self, decrypted_key: SymmetricKey) {
+        let now = self.auto_lock.clock.now();
+
+        self.last_access
+            .store(now.timestamp_micros(), Ordering::Relaxed);
+        self.unlocked_key.send_replace(Some(UnlockedKey {
+            key: Arc::new(decrypted_key.sealed()),
+            unlocked_at: now,
+        }));
+        self.auto_lock.broadcast(LockState::Unlocked);
+    }
+
+    fn deadline(&self, unlocked_key: &UnlockedKey) -> Option<DateTime<Utc>> {
+        let last_access = DateTime::from_timestamp_micros(self.last_access.load(Ordering::Relaxed))
+            .expect("stored from a valid time");
+
+        self.auto_lock
+            .policy
+            .deadline(unlocked_key.unlocked_at, last_access)
+    }
+
+    fn is_expired(&self, deadline: Option<DateTime<Utc>>) -> bool {
+        deadline.is_some_and(|deadline| self.auto_lock.clock.now() >= deadline)
     }
 
     pub(crate) fn get_state(&self) -> SecureKeyState {
@@ -49,11 +124,29 @@ impl SecureKey {
     }
 
     pub(crate) fn is_locked(&self) -> bool {
-        self.decrypted_key.is_none()
+        match self.unlocked_key.borrow().as_ref() {
+            Some(unlocked_key) => self.is_expired(self.deadline(unlocked_key)),
+            None => true,
+        }
+    }
+
+    pub(crate) fn derivation_material(&self) -> Result<DerivationMaterial, Error> {
+        Ok(self.get_decrypted_key()?.derivation_material())
     }
 
-    fn get_decrypted_key(&self) -> Result<&SymmetricKey, Error> {
-        self.decrypted_key.as_ref().ok_or(Error::Locked)
+    fn get_decrypted_key(&self) -> Result<Arc<SymmetricKey>, Error> {
+        let unlocked_key = self.unlocked_key.borrow().clone().ok_or(Error::Locked)?;
+        if self.is_expired(self.deadline(&unlocked_key)) {
+            self.lock();
+            return Err(Error::Locked);
+        }
+
+        self.last_access.store(
+            self.auto_lock.clock.now().timestamp_micros(),
+            Ordering::Relaxed,
+        );
+
+        Ok(unlocked_key.key)
     }
 }
 
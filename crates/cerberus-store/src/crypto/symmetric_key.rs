@@ -1,46 +1,105 @@
 use super::{Cipher, EncryptedData, EncryptedKey};
 use crate::{Error, hash_password};
+use cerberus_crypto::{
+    FormatVersion, KeyIdentifier, NewKey, encoding,
+    kdf::{DerivationMaterial, DeriveKey},
+};
+use cerberus_secret::{ExposeSecret, ExposeSecretMut, SecretSlice, sealed::SealedSecret};
 use chacha20poly1305::{
-    XChaCha20Poly1305,
-    aead::{Aead, AeadCore, KeyInit},
+    Key, XChaCha20Poly1305,
+    aead::{Aead, AeadCore, KeyInit, Payload},
 };
 use rand::{CryptoRng, RngCore, rngs::OsRng};
 use serde::{Serialize, de::DeserializeOwned};
 use std::marker::PhantomData;
+use zeroize::Zeroizing;
+
+const KEY_SIZE: usize = 32;
 
 #[derive(Debug, Clone)]
 pub(crate) struct SymmetricKey {
     id: Option<i64>,
-    key: Vec<u8>,
+    key: KeyMaterial,
+}
+
+#[derive(Debug, Clone)]
+enum KeyMaterial {
+    Plain(SecretSlice<u8>),
+    // only decrypted while an operation is using it
+    Sealed(SealedSecret),
 }
 
 impl SymmetricKey {
-    pub(crate) fn new(key: &[u8], id: Option<i64>) -> Self {
+    pub(crate) fn new(key: SecretSlice<u8>, id: Option<i64>) -> Self {
         Self {
             id,
-            key: key.to_vec(),
+            key: KeyMaterial::Plain(key),
         }
     }
 
-    pub(crate) fn generate(rng: impl CryptoRng + RngCore) -> Self {
-        let key = XChaCha20Poly1305::generate_key(rng);
+    /// Keeps the key sealed while it isn't used, for keys that live for as
+    /// long as the store is unlocked.
+    pub(crate) fn sealed(self) -> Self {
+        let key = match self.key {
+            KeyMaterial::Plain(key) => KeyMaterial::Sealed(SealedSecret::from(key)),
+            sealed => sealed,
+        };
 
-        Self {
-            id: None,
-            key: key.to_vec(),
-        }
+        Self { id: self.id, key }
+    }
+
+    pub(crate) fn generate(mut rng: impl CryptoRng + RngCore) -> Self {
+        // fill the secret allocation directly so no copy of the key is left
+        // on the stack
+        let mut key = SecretSlice::from(vec![0u8; KEY_SIZE]);
+        rng.fill_bytes(key.expose_secret_mut());
+
+        Self::new(key, None)
     }
 
     pub(crate) fn from_password(password: &[u8], salt: &str) -> Self {
-        let key = hash_password(password, salt);
+        Self::new(hash_password(password, salt), None)
+    }
+
+    /// Runs [`SymmetricKey::from_password`] on the blocking pool, Argon2
+    /// would otherwise hold up every task sharing the worker thread.
+    pub(crate) async fn from_password_blocking(password: &str, salt: &str) -> Self {
+        let password = Zeroizing::new(password.as_bytes().to_vec());
+        let salt = salt.to_owned();
 
-        Self { id: None, key }
+        tokio::task::spawn_blocking(move || Self::from_password(&password, &salt))
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
     }
 
-    pub(crate) fn into_encrypted_key<K: Cipher>(self, parent_key: &K) -> EncryptedKey {
-        let encrypted_key = parent_key.encrypt(&self.key).unwrap();
+    pub(crate) fn into_encrypted_key<K: Cipher>(
+        self,
+        parent_key: &K,
+    ) -> Result<EncryptedKey, Error> {
+        let encrypted_key = parent_key.encrypt(&self.secret())?;
 
-        EncryptedKey::new(self.id, encrypted_key)
+        Ok(EncryptedKey::new(self.id, encrypted_key))
+    }
+
+    /// Material for deriving keys that can be recreated whenever this key is
+    /// available, instead of being stored wrapped.
+    pub(crate) fn derivation_material(&self) -> DerivationMaterial {
+        DerivationMaterial::new(self.secret(), KeyIdentifier::local())
+    }
+
+    pub(crate) fn secret(&self) -> SecretSlice<u8> {
+        match &self.key {
+            KeyMaterial::Plain(key) => key.clone(),
+            KeyMaterial::Sealed(key) => key.unseal(),
+        }
+    }
+
+    fn with_cipher<R>(&self, f: impl FnOnce(&XChaCha20Poly1305) -> R) -> R {
+        let with_key = |key: &[u8]| f(&XChaCha20Poly1305::new(Key::from_slice(key)));
+        match &self.key {
+            KeyMaterial::Plain(key) => with_key(key.expose_secret()),
+            KeyMaterial::Sealed(key) => key.with_secret(with_key),
+        }
     }
 
     pub(crate) fn id(&self) -> Option<i64> {
@@ -48,18 +107,36 @@ impl SymmetricKey {
     }
 }
 
+impl NewKey for SymmetricKey {
+    const KEY_SIZE: usize = KEY_SIZE;
+
+    // derived keys are recreated rather than stored, so they have no id
+    fn new_unchecked(key: SecretSlice<u8>, _id: KeyIdentifier) -> Self {
+        Self::new(key, None)
+    }
+}
+
+impl DeriveKey for SymmetricKey {
+    const MAC_INFO_SUFFIX: &'static str = "_store_symmetric_key";
+}
+
 impl Cipher for SymmetricKey {
     fn encrypt<T: Serialize + DeserializeOwned>(
         &self,
         data: &T,
     ) -> Result<EncryptedData<T>, Error> {
-        let data = serde_json::to_string(&data)?;
+        let version = FormatVersion::CURRENT;
+        let data = encoding::encode(version, data)?;
 
-        let cipher = XChaCha20Poly1305::new(self.key.as_slice().into());
         let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
-        let encrypted_data = cipher.encrypt(&nonce, data.as_bytes())?;
+        let payload = Payload {
+            msg: &data,
+            aad: &associated_data(version),
+        };
+        let encrypted_data = self.with_cipher(|cipher| cipher.encrypt(&nonce, payload))?;
 
         Ok(EncryptedData {
+            version,
             enc_data: encrypted_data,
             nonce: nonce.into(),
             key_id: self.id,
@@ -71,10 +148,23 @@ impl Cipher for SymmetricKey {
         &self,
         data: &EncryptedData<T>,
     ) -> Result<T, Error> {
-        let cipher = XChaCha20Poly1305::new(self.key.as_slice().into());
-        let decrypted_data = cipher.decrypt(&data.nonce.into(), data.enc_data.as_slice())?;
+        let payload = Payload {
+            msg: &data.enc_data,
+            aad: &associated_data(data.version),
+        };
+        let decrypted_data =
+            Zeroizing::new(self.with_cipher(|cipher| cipher.decrypt(&data.nonce.into(), payload))?);
 
-        let data = serde_json::from_slice(&decrypted_data)?;
+        let data = encoding::decode(data.version, &decrypted_data)?;
         Ok(data)
     }
 }
+
+/// The format version is authenticated from version 1 on, so it can't be
+/// changed to have a plaintext decoded the way another version would be.
+fn associated_data(version: FormatVersion) -> Vec<u8> {
+    match version {
+        FormatVersion::Legacy => Vec::new(),
+        version => vec![version.into()],
+    }
+}
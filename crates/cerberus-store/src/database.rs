@@ -1,269 +1,373 @@
+//! Where a store keeps its data. A [`Backend`] hands out [`Repository`]s,
+//! which read and write records, and [`Transaction`]s, which are committed
+//! as a whole. The store encrypts everything secret before it reaches a
+//! backend.
+//!
+//! [`SqliteBackend`] is the default, built with the `sqlite` feature,
+//! [`MemoryBackend`] keeps everything in
+//! memory for tests and tools that don't need a file, and [`FileBackend`]
+//! keeps a file per record for versioning a store with git. [`migrate`]
+//! copies a store from one backend to another.
+
+use std::fmt;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "sqlite")]
+use std::path::Path;
 use std::pin::Pin;
-use std::{future::Future, path::Path};
+use std::sync::Arc;
+use std::thread;
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::Error;
+use crate::emergency::EmergencyRequestState;
+use crate::item::{ItemCursor, ItemSortKey};
+use crate::share::{SharePermission, VaultAccess};
+
+mod files;
+mod memory;
+pub mod record_types;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 
-use sqlx::Error as SqlxError;
-use sqlx::{Executor, Sqlite, SqlitePool, Transaction, sqlite::SqliteConnectOptions, types::Json};
+pub use files::FileBackend;
+pub use memory::MemoryBackend;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{MIGRATOR, SqliteBackend};
 
-use crate::item::{ItemData, ItemOverview};
-use crate::{Error, crypto::EncryptedData};
+use record_types::{
+    AuditEntryRecord, AuditHeadRecord, ChangeRecord, EmergencyContactRecord,
+    EmergencyRequestRecord, EmergencyVaultKeyRecord, EncryptedKeyRecord, ItemCiphertextsRecord,
+    ItemPreviewRecord, ItemRecord, ItemRecordWithKeys, NewItemRecord, NewKeyRecord,
+    ProfileRecord, Snapshot, VaultPreviewRecord, VaultRecord, VaultShareRecord,
+};
 
-pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+/// Reads and writes the records of a store. Records are stored under an
+/// integer id and a UUID, both picked by the repository. Only the UUIDs are
+/// handed out of the store.
+#[async_trait]
+pub trait Repository: Send {
+    /// Stores a vault owned by this profile.
+    async fn store_vault(&mut self, name: &str, key_id: i64) -> Result<VaultRecord, Error>;
 
-pub mod record_types;
+    async fn find_vault(&mut self, id: i64) -> Result<Option<VaultRecord>, Error>;
 
-use record_types::{
-    EncryptedKeyRecord, ItemPreviewRecord, ItemRecord, ItemRecordWithKeys, ProfileRecord,
-    VaultPreviewRecord, VaultRecord,
-};
+    async fn find_vault_by_uuid(&mut self, uuid: Uuid) -> Result<Option<VaultRecord>, Error>;
 
-pub(crate) trait Repository {
-    async fn store_vault(&mut self, name: &str, key_id: i64) -> Result<VaultRecord, Error> {
-        let vault_record = sqlx::query_as!(
-            VaultRecord,
-            "INSERT INTO vaults(name, key_id) VALUES (?, ?) RETURNING *",
-            name,
-            key_id
-        )
-        .fetch_one(self.get_executor())
-        .await?;
-
-        Ok(vault_record)
-    }
+    async fn store_shared_vault(
+        &mut self,
+        name: &str,
+        key_id: i64,
+        access: VaultAccess,
+        shared_by: &[u8],
+        shared_vault_id: Uuid,
+        share_generation: i64,
+    ) -> Result<VaultRecord, Error>;
 
-    async fn find_vault(&mut self, id: i64) -> Result<Option<VaultRecord>, Error> {
-        let vault_record = sqlx::query_as!(VaultRecord, "SELECT * FROM vaults WHERE id = ?", id)
-            .fetch_optional(self.get_executor())
-            .await?;
+    async fn find_shared_vault(
+        &mut self,
+        shared_by: &[u8],
+        shared_vault_id: Uuid,
+    ) -> Result<Option<VaultRecord>, Error>;
 
-        Ok(vault_record)
-    }
+    async fn update_vault(
+        &mut self,
+        id: i64,
+        name: &str,
+        key_id: i64,
+        access: VaultAccess,
+    ) -> Result<VaultRecord, Error>;
 
-    async fn store_key(
+    /// Numbers the next invitation to a vault owned by this profile.
+    async fn next_share_generation(&mut self, id: i64) -> Result<i64, Error>;
+
+    /// Records the generation of the last invitation accepted for a shared
+    /// vault.
+    async fn update_share_generation(&mut self, id: i64, generation: i64) -> Result<(), Error>;
+
+    async fn store_vault_share(
         &mut self,
-        key: &EncryptedData<Vec<u8>>,
-    ) -> Result<EncryptedKeyRecord, Error> {
-        let serialized_key = serde_json::to_string(key)?;
-        let key_record = sqlx::query_as!(
-            EncryptedKeyRecord,
-            "INSERT INTO keys(key_encrypted_data)
-             VALUES (?)
-             RETURNING id, key_encrypted_data as 'key_encrypted_data: Json<EncryptedData<Vec<u8>>>'",
-            serialized_key,
-        )
-        .fetch_one(self.get_executor())
-        .await?;
-
-        Ok(key_record)
-    }
+        vault_id: i64,
+        recipient_public_key: &[u8],
+        permission: SharePermission,
+    ) -> Result<VaultShareRecord, Error>;
 
-    async fn find_key(&mut self, key_id: i64) -> Result<Option<EncryptedKeyRecord>, Error> {
-        let key_record = sqlx::query_as!(
-            EncryptedKeyRecord,
-            "SELECT id, key_encrypted_data as 'key_encrypted_data: Json<EncryptedData<Vec<u8>>>'
-             FROM keys
-             WHERE id = ?",
-            key_id
-        )
-        .fetch_optional(self.get_executor())
-        .await?;
-
-        Ok(key_record)
-    }
+    async fn list_vault_shares(&mut self, vault_id: i64) -> Result<Vec<VaultShareRecord>, Error>;
 
-    async fn get_profile(&mut self) -> Result<Option<ProfileRecord>, Error> {
-        let profile = sqlx::query_as!(
-            ProfileRecord,
-            "SELECT id, name, salt, key_id, created_at, updated_at FROM profiles WHERE id = 1"
-        )
-        .fetch_optional(self.get_executor())
-        .await?;
+    /// Returns whether an active share was revoked.
+    async fn revoke_vault_share(&mut self, vault_id: i64, share_id: Uuid) -> Result<bool, Error>;
 
-        Ok(profile)
-    }
+    async fn store_emergency_contact(
+        &mut self,
+        public_key: &[u8],
+        wait_seconds: i64,
+    ) -> Result<EmergencyContactRecord, Error>;
+
+    async fn find_emergency_contact(
+        &mut self,
+        public_key: &[u8],
+    ) -> Result<Option<EmergencyContactRecord>, Error>;
+
+    /// Lists the emergency contacts the key of a vault is sealed to.
+    async fn list_vault_emergency_contacts(
+        &mut self,
+        vault_id: i64,
+    ) -> Result<Vec<EmergencyContactRecord>, Error>;
+
+    /// Stores the key of a vault sealed to an emergency contact, replacing
+    /// the one sealed before.
+    async fn store_emergency_vault_key(
+        &mut self,
+        contact_id: i64,
+        vault_id: i64,
+        sealed_key: &str,
+    ) -> Result<(), Error>;
+
+    async fn list_emergency_vault_keys(
+        &mut self,
+        contact_id: i64,
+    ) -> Result<Vec<EmergencyVaultKeyRecord>, Error>;
+
+    async fn store_emergency_request(
+        &mut self,
+        contact_id: i64,
+        requested_at: NaiveDateTime,
+    ) -> Result<EmergencyRequestRecord, Error>;
+
+    async fn find_emergency_request(
+        &mut self,
+        uuid: Uuid,
+    ) -> Result<Option<EmergencyRequestRecord>, Error>;
+
+    /// Finds the request of a contact that hasn't been denied.
+    async fn find_open_emergency_request(
+        &mut self,
+        contact_id: i64,
+    ) -> Result<Option<EmergencyRequestRecord>, Error>;
+
+    async fn list_emergency_requests(&mut self) -> Result<Vec<EmergencyRequestRecord>, Error>;
+
+    async fn update_emergency_request_state(
+        &mut self,
+        uuid: Uuid,
+        state: EmergencyRequestState,
+        decided_at: NaiveDateTime,
+    ) -> Result<EmergencyRequestRecord, Error>;
+
+    async fn store_audit_entry(&mut self, entry: &AuditEntryRecord) -> Result<(), Error>;
+
+    async fn find_last_audit_entry(&mut self) -> Result<Option<AuditEntryRecord>, Error>;
+
+    /// Replaces the head of the audit log, written with every entry.
+    async fn store_audit_head(&mut self, head: &AuditHeadRecord) -> Result<(), Error>;
+
+    async fn find_audit_head(&mut self) -> Result<Option<AuditHeadRecord>, Error>;
+
+    /// Lists entries in the order they were written, bounds that are `None`
+    /// don't filter.
+    async fn list_audit_entries(
+        &mut self,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+        kind: Option<&str>,
+    ) -> Result<Vec<AuditEntryRecord>, Error>;
+
+    async fn store_key(&mut self, key: &NewKeyRecord) -> Result<EncryptedKeyRecord, Error>;
+
+    async fn find_key(&mut self, key_id: i64) -> Result<Option<EncryptedKeyRecord>, Error>;
+
+    async fn list_child_keys(
+        &mut self,
+        parent_key_id: i64,
+    ) -> Result<Vec<EncryptedKeyRecord>, Error>;
+
+    async fn update_key(&mut self, key_id: i64, key: &NewKeyRecord) -> Result<(), Error>;
+
+    /// The keys written before their format version was authenticated.
+    async fn list_legacy_keys(&mut self) -> Result<Vec<EncryptedKeyRecord>, Error>;
+
+    async fn delete_key(&mut self, key_id: i64) -> Result<(), Error>;
+
+    /// The store holds a single profile.
+    async fn get_profile(&mut self) -> Result<Option<ProfileRecord>, Error>;
 
     async fn store_profile(
         &mut self,
         name: &str,
         salt: &str,
         key_id: i64,
-    ) -> Result<ProfileRecord, Error> {
-        let profile = sqlx::query_as!(
-            ProfileRecord,
-            "INSERT INTO profiles(name, salt, key_id)
-             VALUES (?, ?, ?)
-             RETURNING id, name, salt, key_id, created_at, updated_at",
-            name,
-            salt,
-            key_id
-        )
-        .fetch_one(self.get_executor())
-        .await?;
-
-        Ok(profile)
-    }
+    ) -> Result<ProfileRecord, Error>;
 
-    async fn list_vault_previews(&mut self) -> Result<Vec<VaultPreviewRecord>, Error> {
-        let vault_overview_records =
-            sqlx::query_as!(VaultPreviewRecord, "SELECT id, name FROM vaults")
-                .fetch_all(self.get_executor())
-                .await?;
+    async fn store_profile_key_pairs(
+        &mut self,
+        profile_id: i64,
+        agreement_key_id: i64,
+        agreement_public_key: &[u8],
+        signing_key_id: i64,
+        verifying_key: &[u8],
+    ) -> Result<ProfileRecord, Error>;
 
-        Ok(vault_overview_records)
-    }
+    async fn list_vault_previews(&mut self) -> Result<Vec<VaultPreviewRecord>, Error>;
 
-    async fn store_item(
-        &mut self,
-        vault_id: i64,
-        enc_item_overview: &EncryptedData<ItemOverview>,
-        item_overview_key_id: i64,
-        enc_item_data: &EncryptedData<ItemData>,
-        item_data_key_id: i64,
-    ) -> Result<ItemRecord, Error> {
-        let serialized_item_overview = serde_json::to_string(enc_item_overview)?;
-        let serialized_item_data = serde_json::to_string(enc_item_data)?;
-        let item_record = sqlx::query_as!(
-            ItemRecord,
-            "INSERT INTO items(
-                 vault_id,
-                 overview_encrypted_data,
-                 overview_key_id,
-                 item_encrypted_data,
-                 item_key_id
-             )
-             VALUES (?, ?, ?, ?, ?)
-             RETURNING
-                 id,
-                 vault_id,
-                 overview_encrypted_data as 'overview_encrypted_data: Json<EncryptedData<ItemOverview>>',
-                 overview_key_id,
-                 item_encrypted_data as 'item_encrypted_data: Json<EncryptedData<ItemData>>',
-                 item_key_id,
-                 created_at,
-                 updated_at",
-            vault_id,
-            serialized_item_overview,
-            item_overview_key_id,
-            serialized_item_data,
-            item_data_key_id,
-        )
-            .fetch_one(self.get_executor())
-            .await?;
-
-        Ok(item_record)
-    }
+    async fn store_item(&mut self, item: &NewItemRecord) -> Result<ItemRecord, Error>;
 
-    async fn find_item(&mut self, id: i64) -> Result<ItemRecordWithKeys, Error> {
-        let record = sqlx::query!(
-            "SELECT
-                 items.id,
-                 items.vault_id,
-                 items.overview_encrypted_data as 'overview_encrypted_data: Json<EncryptedData<ItemOverview>>',
-                 items.item_encrypted_data as 'item_encrypted_data: Json<EncryptedData<ItemData>>',
-                 items.created_at,
-                 items.updated_at,
-                 overview_keys.id as 'overview_key_id',
-                 overview_keys.key_encrypted_data as 'overview_key_encrypted_data: Json<EncryptedData<Vec<u8>>>',
-                 data_keys.id as 'item_key_id',
-                 data_keys.key_encrypted_data as 'data_key_encrypted_data: Json<EncryptedData<Vec<u8>>>'
-             FROM items
-             INNER JOIN keys AS overview_keys ON overview_keys.id = items.overview_key_id
-             INNER JOIN keys AS data_keys ON data_keys.id = items.item_key_id
-             WHERE items.id = ?",
-            id
-        )
-            .fetch_one(self.get_executor())
-            .await?;
-
-        let item_record_with_keys = ItemRecordWithKeys {
-            item_record: ItemRecord {
-                id: record.id,
-                vault_id: record.vault_id,
-                overview_encrypted_data: record.overview_encrypted_data,
-                overview_key_id: record.overview_key_id,
-                item_encrypted_data: record.item_encrypted_data,
-                item_key_id: record.item_key_id,
-                created_at: record.created_at,
-                updated_at: record.updated_at,
-            },
-            overview_key: record.overview_key_encrypted_data,
-            data_key: record.data_key_encrypted_data,
-        };
-
-        Ok(item_record_with_keys)
-    }
+    /// Fails if the item doesn't exist in the vault.
+    async fn find_item(&mut self, vault_id: i64, uuid: Uuid) -> Result<ItemRecordWithKeys, Error>;
 
+    /// Lists item previews ordered by `sort_key` and UUID, starting after
+    /// `after`. Every vault's items are listed without a `vault_id`.
     async fn list_item_previews(
         &mut self,
         vault_id: Option<i64>,
-    ) -> Result<Vec<ItemPreviewRecord>, Error> {
-        let vault_id = vault_id.unwrap();
-        let item_preview_records = sqlx::query_as!(
-            ItemPreviewRecord,
-            "SELECT
-                 items.id,
-                 items.vault_id,
-                 items.overview_encrypted_data as 'overview_encrypted_data: Json<EncryptedData<ItemOverview>>',
-                 items.created_at,
-                 items.updated_at,
-                 keys.id as 'overview_key_id',
-                 keys.key_encrypted_data as 'overview_key_encrypted_data: Json<EncryptedData<Vec<u8>>>'
-             FROM items
-             INNER JOIN (SELECT id, key_encrypted_data  FROM keys) AS keys ON keys.id = items.overview_key_id
-             WHERE items.vault_id = ?
-             GROUP BY items.id",
-            vault_id
-        )
-            .fetch_all(self.get_executor())
-            .await?;
-
-        Ok(item_preview_records)
+        sort_key: ItemSortKey,
+        after: Option<&ItemCursor>,
+        limit: Option<u32>,
+    ) -> Result<Vec<ItemPreviewRecord>, Error>;
+
+    /// The items with an overview or data written before their format
+    /// version was authenticated.
+    async fn list_legacy_items(&mut self) -> Result<Vec<ItemRecord>, Error>;
+
+    /// Replaces the encrypted overview and data of an item, which is seen as
+    /// updated.
+    async fn update_item_ciphertexts(
+        &mut self,
+        id: i64,
+        item: &ItemCiphertextsRecord,
+    ) -> Result<(), Error>;
+
+    /// Deletes an item of a vault along with its keys, returning whether it
+    /// existed.
+    async fn delete_item(&mut self, vault_id: i64, uuid: Uuid) -> Result<bool, Error>;
+
+    /// Deletes the items of a vault along with their keys.
+    async fn delete_vault_items(&mut self, vault_id: i64) -> Result<(), Error>;
+}
+
+/// Writes made through a transaction are only seen by others once it is
+/// committed. A backend runs one transaction at a time.
+#[async_trait]
+pub trait Transaction: Repository {
+    async fn commit(self: Box<Self>) -> Result<(), Error>;
+
+    async fn rollback(self: Box<Self>) -> Result<(), Error>;
+}
+
+/// Reads the changes made to vaults and items, by this or any other process
+/// sharing the backend. The oldest changes may be pruned.
+#[async_trait]
+pub trait ChangeFeed: Send {
+    /// Changes whenever changes may have been committed, the changes are
+    /// only listed when it does.
+    async fn data_version(&mut self) -> Result<i64, Error>;
+
+    /// The sequence of the latest change, 0 if nothing has changed.
+    async fn find_last_change_sequence(&mut self) -> Result<i64, Error>;
+
+    /// Lists the changes after `after` in the order they were made.
+    async fn list_changes(&mut self, after: i64) -> Result<Vec<ChangeRecord>, Error>;
+}
+
+/// A store's storage, shared by every clone of the store.
+#[async_trait]
+pub trait Backend: fmt::Debug + Send + Sync {
+    /// Reads and writes outside of a transaction, every write is committed
+    /// on its own.
+    fn repository(&self) -> Box<dyn Repository + Sync>;
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>, Error>;
+
+    /// Follows the changes committed from now on.
+    async fn change_feed(&self) -> Result<Box<dyn ChangeFeed>, Error>;
+
+    /// Every record in the backend, as of a single commit.
+    async fn export(&self) -> Result<Snapshot, Error>;
+
+    /// Writes every record of `snapshot`, keeping their ids, in one commit.
+    async fn import(&self, snapshot: Snapshot) -> Result<(), Error>;
+}
+
+/// Copies the store in `from` to `to`, which must not hold a store yet.
+/// Records keep their ids, so the copy unlocks with the same password and its
+/// audit log still verifies.
+pub async fn migrate(from: &dyn Backend, to: &dyn Backend) -> Result<(), Error> {
+    if from.repository().get_profile().await?.is_none() {
+        return Err(Error::StoreNotInitialized);
+    }
+    if to.repository().get_profile().await?.is_some() {
+        return Err(Error::ProfileAlreadyExists);
     }
 
-    fn get_executor(&mut self) -> impl Executor<'_, Database = Sqlite>;
+    to.import(from.export().await?).await
 }
 
-#[derive(Debug, Clone)]
+/// The backend of a store, dereferencing to a repository outside of any
+/// transaction.
 pub(crate) struct Database {
-    pool: SqlitePool,
+    backend: Arc<dyn Backend>,
+    repository: Box<dyn Repository + Sync>,
+    // shared by every handle of the store, see `decrypt_permits`
+    decrypt_permits: Arc<Semaphore>,
 }
 
 impl Database {
+    #[cfg(feature = "sqlite")]
     pub(crate) async fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
-        let options = SqliteConnectOptions::new()
-            .filename(path)
-            .create_if_missing(true);
+        Ok(Self::from_backend(Arc::new(
+            SqliteBackend::new(path).await?,
+        )))
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn from_pool(pool: SqlitePool) -> Self {
+        Self::from_backend(Arc::new(SqliteBackend::from_pool(pool)))
+    }
 
-        let pool = SqlitePool::connect_with(options).await?;
+    pub(crate) fn from_backend(backend: Arc<dyn Backend>) -> Self {
+        let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get);
 
-        MIGRATOR.run(&pool).await.map_err(sqlx::Error::from)?;
+        Self {
+            repository: backend.repository(),
+            backend,
+            decrypt_permits: Arc::new(Semaphore::new(workers)),
+        }
+    }
 
-        Ok(Self { pool })
+    /// Bounds the batches of item previews decrypted on the blocking pool at
+    /// once, across every listing of the store, to one per core.
+    pub(crate) fn decrypt_permits(&self) -> Arc<Semaphore> {
+        self.decrypt_permits.clone()
     }
 
-    pub(crate) fn from_pool(pool: SqlitePool) -> Self {
-        Self { pool }
+    pub(crate) async fn change_feed(&self) -> Result<Box<dyn ChangeFeed>, Error> {
+        self.backend.change_feed().await
     }
 
     pub(crate) async fn transaction<F, O, E>(&self, func: F) -> Result<O, E>
     where
         for<'a> F: FnOnce(
-            &'a mut DatabaseTransaction<'_>,
+            &'a mut dyn Transaction,
         ) -> Pin<Box<dyn Future<Output = Result<O, E>> + Send + 'a>>,
         O: Send,
-        E: From<SqlxError> + Send,
+        E: From<Error> + Send,
     {
-        let mut transaction = DatabaseTransaction {
-            transaction: self.pool.begin().await?,
-        };
-        let result = func(&mut transaction).await;
+        let mut transaction = self.backend.begin().await?;
+        let result = func(&mut *transaction).await;
         match result {
             Ok(ret) => {
-                transaction.into_inner().commit().await?;
+                transaction.commit().await?;
 
                 Ok(ret)
             }
             Err(err) => {
-                transaction.into_inner().rollback().await?;
+                transaction.rollback().await?;
 
                 Err(err)
             }
@@ -271,38 +375,34 @@ impl Database {
     }
 }
 
-impl Repository for Database {
-    fn get_executor(&mut self) -> impl Executor<'_, Database = Sqlite> {
-        &self.pool
+impl Clone for Database {
+    fn clone(&self) -> Self {
+        Self {
+            backend: self.backend.clone(),
+            repository: self.backend.repository(),
+            decrypt_permits: self.decrypt_permits.clone(),
+        }
     }
 }
 
-pub(crate) struct DatabaseTransaction<'a> {
-    transaction: Transaction<'a, Sqlite>,
-}
-
-impl<'a> DatabaseTransaction<'a> {
-    pub(crate) fn get_inner(&self) -> &Transaction<'a, Sqlite> {
-        &self.transaction
-    }
-
-    pub(crate) fn get_inner_mut(&'a mut self) -> &'a mut Transaction<'a, Sqlite> {
-        &mut self.transaction
-    }
-
-    pub(crate) fn into_inner(self) -> Transaction<'a, Sqlite> {
-        self.transaction
+impl fmt::Debug for Database {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Database")
+            .field("backend", &self.backend)
+            .finish_non_exhaustive()
     }
 }
 
-impl Repository for DatabaseTransaction<'_> {
-    fn get_executor(&mut self) -> impl Executor<'_, Database = Sqlite> {
-        &mut *self.transaction
+impl Deref for Database {
+    type Target = dyn Repository + Sync;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.repository
     }
 }
 
-impl Repository for &mut DatabaseTransaction<'_> {
-    fn get_executor(&mut self) -> impl Executor<'_, Database = Sqlite> {
-        (**self).get_executor()
+impl DerefMut for Database {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut *self.repository
     }
 }
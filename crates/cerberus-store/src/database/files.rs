@@ -0,0 +1,857 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use cerberus_serde::{base64, base64_option};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use uuid::Uuid;
+
+use super::memory::{MemoryBackend, Persist, Table, Tables};
+use super::record_types::{
+    AuditEntryRecord, AuditHeadRecord, EmergencyContactRecord, EmergencyRequestRecord,
+    EmergencyVaultKeyRecord, EncryptedKeyRecord, ItemRecord, ProfileRecord, Snapshot, VaultRecord,
+    VaultShareRecord,
+};
+use super::{Backend, ChangeFeed, Repository, Transaction};
+use crate::Error;
+
+// marks a directory as a store
+const INDEX_FILE: &str = "index.json";
+const FORMAT_VERSION: u32 = 2;
+// keeps the audit log and unfinished writes out of git
+const GITIGNORE: &str = "/audit-log/\n*.partial\n";
+
+// the directory each table's records are written to
+const PROFILES: &str = "profiles";
+const KEYS: &str = "keys";
+const VAULTS: &str = "vaults";
+const VAULT_SHARES: &str = "vault-shares";
+const EMERGENCY_CONTACTS: &str = "emergency-contacts";
+const EMERGENCY_VAULT_KEYS: &str = "emergency-vault-keys";
+const EMERGENCY_REQUESTS: &str = "emergency-requests";
+const AUDIT_LOG: &str = "audit-log";
+// kept next to the entries of the audit log, but isn't one
+const AUDIT_HEAD: &str = "head.json";
+const ITEMS: &str = "items";
+
+/// Keeps a store in a directory that can be versioned and merged with git.
+/// Every record is a file of its own next to an `index.json` holding the
+/// layout's version. Files are named after the UUID of their record, such as
+/// `items/0198350e-7b51-7c3a-9f2e-5d1c6a4b8e07.json`, and refer to other
+/// records by their UUID, so records created in two copies of a store don't
+/// collide when they are merged. Ids are given to the records as they are
+/// read and aren't kept from one opening to the next, which is why nothing
+/// outside the backend, whether cursors, audit entries or anything handed to
+/// callers, refers to a record by its id. Everything secret is encrypted.
+///
+/// The audit log isn't merged, every copy of a store keeps its own.
+///
+/// The records are read when the backend is opened and a commit only writes
+/// the files it changed, synced to disk before the commit is seen. Changes made to the directory afterwards, by another
+/// process or by pulling, are only seen once it is opened again.
+#[derive(Debug, Clone)]
+pub struct FileBackend {
+    root: PathBuf,
+    memory: MemoryBackend,
+}
+
+impl FileBackend {
+    /// Opens the store in `root`, creating it if the directory doesn't hold
+    /// one yet.
+    pub async fn new(root: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::load(root.as_ref().to_owned(), true).await
+    }
+
+    /// Opens the store in `root`, failing if the directory doesn't hold one.
+    pub async fn open(root: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::load(root.as_ref().to_owned(), false).await
+    }
+
+    async fn load(root: PathBuf, create: bool) -> Result<Self, Error> {
+        let directory = Directory { root };
+
+        tokio::task::spawn_blocking(move || {
+            let tables = directory.open(create)?;
+
+            Ok(Self {
+                root: directory.root.clone(),
+                memory: MemoryBackend::with_persistence(tables, directory),
+            })
+        })
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+#[async_trait]
+impl Backend for FileBackend {
+    fn repository(&self) -> Box<dyn Repository + Sync> {
+        self.memory.repository()
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>, Error> {
+        self.memory.begin().await
+    }
+
+    async fn change_feed(&self) -> Result<Box<dyn ChangeFeed>, Error> {
+        self.memory.change_feed().await
+    }
+
+    async fn export(&self) -> Result<Snapshot, Error> {
+        self.memory.export().await
+    }
+
+    async fn import(&self, snapshot: Snapshot) -> Result<(), Error> {
+        self.memory.import(snapshot).await
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Index {
+    format_version: u32,
+}
+
+#[derive(Debug)]
+struct Directory {
+    root: PathBuf,
+}
+
+impl Directory {
+    fn open(&self, create: bool) -> Result<Tables, Error> {
+        let index_path = self.root.join(INDEX_FILE);
+        match fs::read(&index_path) {
+            Ok(index) => {
+                let index: Index = serde_json::from_slice(&index)?;
+                if index.format_version != FORMAT_VERSION {
+                    return Err(Error::MalformedStoreDirectory);
+                }
+            }
+            Err(err) if create && err.kind() == io::ErrorKind::NotFound => {
+                fs::create_dir_all(&self.root)?;
+                fs::write(self.root.join(".gitignore"), GITIGNORE)?;
+                write_file(
+                    &index_path,
+                    &Index {
+                        format_version: FORMAT_VERSION,
+                    },
+                )?;
+                sync_directory(&self.root)?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let profiles: Vec<ProfileFile> = self.read_table(PROFILES)?;
+        let keys: Vec<KeyFile> = self.read_table(KEYS)?;
+        let vaults: Vec<VaultFile> = self.read_table(VAULTS)?;
+        let vault_shares: Vec<VaultShareFile> = self.read_table(VAULT_SHARES)?;
+        let emergency_contacts: Vec<EmergencyContactFile> = self.read_table(EMERGENCY_CONTACTS)?;
+        let emergency_vault_keys: Vec<EmergencyVaultKeyFile> =
+            self.read_table(EMERGENCY_VAULT_KEYS)?;
+        let emergency_requests: Vec<EmergencyRequestFile> = self.read_table(EMERGENCY_REQUESTS)?;
+        let items: Vec<ItemFile> = self.read_table(ITEMS)?;
+
+        let ids = Ids {
+            profiles: number(profiles.iter().map(|profile| profile.uuid))?,
+            keys: number(keys.iter().map(|key| key.uuid))?,
+            vaults: number(vaults.iter().map(|vault| vault.uuid))?,
+            vault_shares: number(vault_shares.iter().map(|share| share.uuid))?,
+            emergency_contacts: number(emergency_contacts.iter().map(|contact| contact.uuid))?,
+            emergency_requests: number(emergency_requests.iter().map(|request| request.uuid))?,
+            items: number(items.iter().map(|item| item.uuid))?,
+        };
+        let emergency_contacts = emergency_contacts
+            .into_iter()
+            .map(|contact| contact.into_record(&ids))
+            .collect::<Result<Vec<_>, _>>()?;
+        let contacts: BTreeMap<_, _> = emergency_contacts
+            .iter()
+            .map(|contact| (contact.id, contact))
+            .collect();
+
+        let mut tables = Tables::default();
+        tables.import(Snapshot {
+            profiles: into_records(profiles, |profile| profile.into_record(&ids))?,
+            keys: into_records(keys, |key| key.into_record(&ids))?,
+            vaults: into_records(vaults, |vault| vault.into_record(&ids))?,
+            vault_shares: into_records(vault_shares, |share| share.into_record(&ids))?,
+            emergency_vault_keys: into_records(emergency_vault_keys, |vault_key| {
+                vault_key.into_record(&ids)
+            })?,
+            emergency_requests: into_records(emergency_requests, |request| {
+                request.into_record(&ids, &contacts)
+            })?,
+            audit_log: self.read_table(AUDIT_LOG)?,
+            audit_head: self.read_audit_head()?,
+            items: into_records(items, |item| item.into_record(&ids))?,
+            emergency_contacts,
+        })?;
+
+        Ok(tables)
+    }
+
+    fn read_table<T: DeserializeOwned>(&self, table: &str) -> Result<Vec<T>, Error> {
+        let entries = match fs::read_dir(self.root.join(table)) {
+            Ok(entries) => entries,
+            // nothing was written to it yet
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut records = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            // skips files left by an interrupted write
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+                && !path.ends_with(AUDIT_HEAD)
+            {
+                records.push(serde_json::from_slice(&fs::read(path)?)?);
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn read_audit_head(&self) -> Result<Option<AuditHeadRecord>, Error> {
+        match fs::read(self.root.join(AUDIT_LOG).join(AUDIT_HEAD)) {
+            Ok(head) => Ok(Some(serde_json::from_slice(&head)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Creates the directory of a table the first time a record is written
+    /// to it.
+    fn create_dir(&self, directory: &Path) -> Result<(), Error> {
+        if !directory.exists() {
+            fs::create_dir_all(directory)?;
+            sync_directory(&self.root)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the records of `table` that changed between `before` and
+    /// `after` and removes the files of the ones that are gone. Only the
+    /// records the commit touched are compared.
+    fn write_table<K: Ord + Clone, F: RecordFile>(
+        &self,
+        table: &str,
+        before: &Uuids,
+        after: &Uuids,
+        records: impl Fn(&Tables) -> &Table<K, F::Record>,
+    ) -> Result<(), Error> {
+        let directory = self.root.join(table);
+        let path = |file: &F| directory.join(format!("{}.json", file.name()));
+
+        let mut changed = false;
+        for key in records(after.tables).touched() {
+            let removed = records(before.tables).get(key);
+            let kept = records(after.tables).get(key);
+            if removed == kept {
+                continue;
+            }
+            changed = true;
+
+            let file = kept.map(|record| F::new(record, after)).transpose()?;
+            if let Some(record) = removed {
+                // an id can be taken by a new record in the same commit
+                let removed = F::new(record, before)?;
+                if file
+                    .as_ref()
+                    .is_none_or(|file| file.name() != removed.name())
+                {
+                    match fs::remove_file(path(&removed)) {
+                        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                            return Err(err.into());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            if let Some(file) = file {
+                self.create_dir(&directory)?;
+                write_file(&path(&file), &file)?;
+            }
+        }
+
+        if changed && directory.exists() {
+            sync_directory(&directory)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Persist for Directory {
+    fn persist(&self, before: &Tables, after: &Tables) -> Result<(), Error> {
+        let before = Uuids { tables: before };
+        let after = Uuids { tables: after };
+
+        self.write_table::<_, ProfileFile>(PROFILES, &before, &after, |tables| &tables.profiles)?;
+        self.write_table::<_, KeyFile>(KEYS, &before, &after, |tables| &tables.keys)?;
+        self.write_table::<_, VaultFile>(VAULTS, &before, &after, |tables| &tables.vaults)?;
+        self.write_table::<_, VaultShareFile>(VAULT_SHARES, &before, &after, |tables| {
+            &tables.vault_shares
+        })?;
+        self.write_table::<_, EmergencyContactFile>(
+            EMERGENCY_CONTACTS,
+            &before,
+            &after,
+            |tables| &tables.emergency_contacts,
+        )?;
+        self.write_table::<_, EmergencyVaultKeyFile>(
+            EMERGENCY_VAULT_KEYS,
+            &before,
+            &after,
+            |tables| &tables.emergency_vault_keys,
+        )?;
+        self.write_table::<_, EmergencyRequestFile>(
+            EMERGENCY_REQUESTS,
+            &before,
+            &after,
+            |tables| &tables.emergency_requests,
+        )?;
+        self.write_table::<_, AuditEntryRecord>(AUDIT_LOG, &before, &after, |tables| {
+            &tables.audit_log
+        })?;
+        if let Some(head) = &after.tables.audit_head
+            && before.tables.audit_head.as_ref() != Some(head)
+        {
+            let directory = self.root.join(AUDIT_LOG);
+            self.create_dir(&directory)?;
+            write_file(&directory.join(AUDIT_HEAD), head)?;
+            sync_directory(&directory)?;
+        }
+        self.write_table::<_, ItemFile>(ITEMS, &before, &after, |tables| &tables.items)?;
+
+        Ok(())
+    }
+}
+
+/// The ids of the records read, by their UUID.
+struct Ids {
+    profiles: BTreeMap<Uuid, i64>,
+    keys: BTreeMap<Uuid, i64>,
+    vaults: BTreeMap<Uuid, i64>,
+    vault_shares: BTreeMap<Uuid, i64>,
+    emergency_contacts: BTreeMap<Uuid, i64>,
+    emergency_requests: BTreeMap<Uuid, i64>,
+    items: BTreeMap<Uuid, i64>,
+}
+
+/// Numbers records in the order of their UUIDs, which is the order version 7
+/// UUIDs were created in. Two files holding the same record are an error.
+fn number(uuids: impl Iterator<Item = Uuid>) -> Result<BTreeMap<Uuid, i64>, Error> {
+    let mut ids = BTreeMap::new();
+    for uuid in uuids {
+        if ids.insert(uuid, 0).is_some() {
+            return Err(Error::MalformedStoreDirectory);
+        }
+    }
+    for (id, number) in ids.values_mut().zip(1..) {
+        *id = number;
+    }
+
+    Ok(ids)
+}
+
+fn id(ids: &BTreeMap<Uuid, i64>, uuid: Uuid) -> Result<i64, Error> {
+    ids.get(&uuid)
+        .copied()
+        .ok_or(Error::MalformedStoreDirectory)
+}
+
+fn into_records<F, R>(
+    files: Vec<F>,
+    into_record: impl Fn(F) -> Result<R, Error>,
+) -> Result<Vec<R>, Error> {
+    files.into_iter().map(into_record).collect()
+}
+
+/// Finds the UUIDs of the records an id refers to in the tables of a commit.
+struct Uuids<'a> {
+    tables: &'a Tables,
+}
+
+impl Uuids<'_> {
+    fn key(&self, id: i64) -> Result<Uuid, Error> {
+        let key = self.tables.keys.get(&id).ok_or(Error::KeyDoesNotExist)?;
+
+        Ok(key.uuid)
+    }
+
+    fn vault(&self, id: i64) -> Result<Uuid, Error> {
+        let vault = self
+            .tables
+            .vaults
+            .get(&id)
+            .ok_or(Error::VaultDoesNotExist)?;
+
+        Ok(vault.uuid)
+    }
+
+    fn emergency_contact(&self, id: i64) -> Result<Uuid, Error> {
+        let contact = self
+            .tables
+            .emergency_contacts
+            .get(&id)
+            .ok_or(Error::UnknownEmergencyContact)?;
+
+        Ok(contact.uuid)
+    }
+}
+
+/// How a record is written to its file, referring to other records by their
+/// UUID.
+trait RecordFile: Serialize + Sized {
+    type Record: PartialEq + Clone;
+
+    fn new(record: &Self::Record, uuids: &Uuids) -> Result<Self, Error>;
+
+    /// The name of the file, without its extension.
+    fn name(&self) -> String;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProfileFile {
+    uuid: Uuid,
+    name: String,
+    salt: String,
+    key: Uuid,
+    agreement_key: Option<Uuid>,
+    #[serde(with = "base64_option")]
+    agreement_public_key: Option<Vec<u8>>,
+    signing_key: Option<Uuid>,
+    #[serde(with = "base64_option")]
+    verifying_key: Option<Vec<u8>>,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+impl ProfileFile {
+    fn into_record(self, ids: &Ids) -> Result<ProfileRecord, Error> {
+        Ok(ProfileRecord {
+            id: id(&ids.profiles, self.uuid)?,
+            uuid: self.uuid,
+            name: self.name,
+            salt: self.salt,
+            key_id: id(&ids.keys, self.key)?,
+            agreement_key_id: self
+                .agreement_key
+                .map(|key| id(&ids.keys, key))
+                .transpose()?,
+            agreement_public_key: self.agreement_public_key,
+            signing_key_id: self.signing_key.map(|key| id(&ids.keys, key)).transpose()?,
+            verifying_key: self.verifying_key,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+impl RecordFile for ProfileFile {
+    type Record = ProfileRecord;
+
+    fn new(record: &ProfileRecord, uuids: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            uuid: record.uuid,
+            name: record.name.clone(),
+            salt: record.salt.clone(),
+            key: uuids.key(record.key_id)?,
+            agreement_key: record
+                .agreement_key_id
+                .map(|id| uuids.key(id))
+                .transpose()?,
+            agreement_public_key: record.agreement_public_key.clone(),
+            signing_key: record.signing_key_id.map(|id| uuids.key(id)).transpose()?,
+            verifying_key: record.verifying_key.clone(),
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.uuid.to_string()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    uuid: Uuid,
+    format_version: i64,
+    #[serde(with = "base64")]
+    encrypted_key: Vec<u8>,
+    #[serde(with = "base64")]
+    nonce: Vec<u8>,
+    parent_key: Option<Uuid>,
+}
+
+impl KeyFile {
+    fn into_record(self, ids: &Ids) -> Result<EncryptedKeyRecord, Error> {
+        Ok(EncryptedKeyRecord {
+            id: id(&ids.keys, self.uuid)?,
+            uuid: self.uuid,
+            format_version: self.format_version,
+            encrypted_key: self.encrypted_key,
+            nonce: self.nonce,
+            parent_key_id: self.parent_key.map(|key| id(&ids.keys, key)).transpose()?,
+        })
+    }
+}
+
+impl RecordFile for KeyFile {
+    type Record = EncryptedKeyRecord;
+
+    fn new(record: &EncryptedKeyRecord, uuids: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            uuid: record.uuid,
+            format_version: record.format_version,
+            encrypted_key: record.encrypted_key.clone(),
+            nonce: record.nonce.clone(),
+            parent_key: record.parent_key_id.map(|id| uuids.key(id)).transpose()?,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.uuid.to_string()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultFile {
+    uuid: Uuid,
+    name: String,
+    key: Uuid,
+    access: String,
+    #[serde(with = "base64_option")]
+    shared_by: Option<Vec<u8>>,
+    shared_vault_id: Option<Uuid>,
+    share_generation: i64,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+impl VaultFile {
+    fn into_record(self, ids: &Ids) -> Result<VaultRecord, Error> {
+        Ok(VaultRecord {
+            id: id(&ids.vaults, self.uuid)?,
+            uuid: self.uuid,
+            name: self.name,
+            key_id: id(&ids.keys, self.key)?,
+            access: self.access,
+            shared_by: self.shared_by,
+            shared_vault_id: self.shared_vault_id,
+            share_generation: self.share_generation,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+impl RecordFile for VaultFile {
+    type Record = VaultRecord;
+
+    fn new(record: &VaultRecord, uuids: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            uuid: record.uuid,
+            name: record.name.clone(),
+            key: uuids.key(record.key_id)?,
+            access: record.access.clone(),
+            shared_by: record.shared_by.clone(),
+            shared_vault_id: record.shared_vault_id,
+            share_generation: record.share_generation,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.uuid.to_string()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VaultShareFile {
+    uuid: Uuid,
+    vault: Uuid,
+    #[serde(with = "base64")]
+    recipient_public_key: Vec<u8>,
+    permission: String,
+    created_at: NaiveDateTime,
+    revoked_at: Option<NaiveDateTime>,
+}
+
+impl VaultShareFile {
+    fn into_record(self, ids: &Ids) -> Result<VaultShareRecord, Error> {
+        Ok(VaultShareRecord {
+            id: id(&ids.vault_shares, self.uuid)?,
+            uuid: self.uuid,
+            vault_id: id(&ids.vaults, self.vault)?,
+            recipient_public_key: self.recipient_public_key,
+            permission: self.permission,
+            created_at: self.created_at,
+            revoked_at: self.revoked_at,
+        })
+    }
+}
+
+impl RecordFile for VaultShareFile {
+    type Record = VaultShareRecord;
+
+    fn new(record: &VaultShareRecord, uuids: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            uuid: record.uuid,
+            vault: uuids.vault(record.vault_id)?,
+            recipient_public_key: record.recipient_public_key.clone(),
+            permission: record.permission.clone(),
+            created_at: record.created_at,
+            revoked_at: record.revoked_at,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.uuid.to_string()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmergencyContactFile {
+    uuid: Uuid,
+    #[serde(with = "base64")]
+    public_key: Vec<u8>,
+    wait_seconds: i64,
+    created_at: NaiveDateTime,
+}
+
+impl EmergencyContactFile {
+    fn into_record(self, ids: &Ids) -> Result<EmergencyContactRecord, Error> {
+        Ok(EmergencyContactRecord {
+            id: id(&ids.emergency_contacts, self.uuid)?,
+            uuid: self.uuid,
+            public_key: self.public_key,
+            wait_seconds: self.wait_seconds,
+            created_at: self.created_at,
+        })
+    }
+}
+
+impl RecordFile for EmergencyContactFile {
+    type Record = EmergencyContactRecord;
+
+    fn new(record: &EmergencyContactRecord, _: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            uuid: record.uuid,
+            public_key: record.public_key.clone(),
+            wait_seconds: record.wait_seconds,
+            created_at: record.created_at,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.uuid.to_string()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EmergencyVaultKeyFile {
+    contact: Uuid,
+    vault: Uuid,
+    sealed_key: String,
+}
+
+impl EmergencyVaultKeyFile {
+    fn into_record(self, ids: &Ids) -> Result<EmergencyVaultKeyRecord, Error> {
+        Ok(EmergencyVaultKeyRecord {
+            contact_id: id(&ids.emergency_contacts, self.contact)?,
+            vault_id: id(&ids.vaults, self.vault)?,
+            sealed_key: self.sealed_key,
+        })
+    }
+}
+
+impl RecordFile for EmergencyVaultKeyFile {
+    type Record = EmergencyVaultKeyRecord;
+
+    fn new(record: &EmergencyVaultKeyRecord, uuids: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            contact: uuids.emergency_contact(record.contact_id)?,
+            vault: uuids.vault(record.vault_id)?,
+            sealed_key: record.sealed_key.clone(),
+        })
+    }
+
+    fn name(&self) -> String {
+        format!("{}-{}", self.contact, self.vault)
+    }
+}
+
+/// The public key and waiting period of a request are the ones of its
+/// contact, so they aren't written.
+#[derive(Debug, Serialize, Deserialize)]
+struct EmergencyRequestFile {
+    uuid: Uuid,
+    contact: Uuid,
+    state: String,
+    requested_at: NaiveDateTime,
+    decided_at: Option<NaiveDateTime>,
+}
+
+impl EmergencyRequestFile {
+    fn into_record(
+        self,
+        ids: &Ids,
+        contacts: &BTreeMap<i64, &EmergencyContactRecord>,
+    ) -> Result<EmergencyRequestRecord, Error> {
+        let contact_id = id(&ids.emergency_contacts, self.contact)?;
+        let contact = contacts
+            .get(&contact_id)
+            .ok_or(Error::MalformedStoreDirectory)?;
+
+        Ok(EmergencyRequestRecord {
+            id: id(&ids.emergency_requests, self.uuid)?,
+            uuid: self.uuid,
+            contact_id,
+            public_key: contact.public_key.clone(),
+            wait_seconds: contact.wait_seconds,
+            state: self.state,
+            requested_at: self.requested_at,
+            decided_at: self.decided_at,
+        })
+    }
+}
+
+impl RecordFile for EmergencyRequestFile {
+    type Record = EmergencyRequestRecord;
+
+    fn new(record: &EmergencyRequestRecord, uuids: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            uuid: record.uuid,
+            contact: uuids.emergency_contact(record.contact_id)?,
+            state: record.state.clone(),
+            requested_at: record.requested_at,
+            decided_at: record.decided_at,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.uuid.to_string()
+    }
+}
+
+// entries are chained to the one before them, so two logs can't be merged
+// and the log is kept out of git
+impl RecordFile for AuditEntryRecord {
+    type Record = AuditEntryRecord;
+
+    fn new(record: &AuditEntryRecord, _: &Uuids) -> Result<Self, Error> {
+        Ok(record.clone())
+    }
+
+    fn name(&self) -> String {
+        self.sequence.to_string()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ItemFile {
+    uuid: Uuid,
+    vault: Uuid,
+    overview_format_version: i64,
+    #[serde(with = "base64")]
+    overview_ciphertext: Vec<u8>,
+    #[serde(with = "base64")]
+    overview_nonce: Vec<u8>,
+    overview_key: Uuid,
+    item_format_version: i64,
+    #[serde(with = "base64")]
+    item_ciphertext: Vec<u8>,
+    #[serde(with = "base64")]
+    item_nonce: Vec<u8>,
+    item_key: Uuid,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+impl ItemFile {
+    fn into_record(self, ids: &Ids) -> Result<ItemRecord, Error> {
+        Ok(ItemRecord {
+            id: id(&ids.items, self.uuid)?,
+            uuid: self.uuid,
+            vault_id: id(&ids.vaults, self.vault)?,
+            overview_format_version: self.overview_format_version,
+            overview_ciphertext: self.overview_ciphertext,
+            overview_nonce: self.overview_nonce,
+            overview_key_id: id(&ids.keys, self.overview_key)?,
+            item_format_version: self.item_format_version,
+            item_ciphertext: self.item_ciphertext,
+            item_nonce: self.item_nonce,
+            item_key_id: id(&ids.keys, self.item_key)?,
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+        })
+    }
+}
+
+impl RecordFile for ItemFile {
+    type Record = ItemRecord;
+
+    fn new(record: &ItemRecord, uuids: &Uuids) -> Result<Self, Error> {
+        Ok(Self {
+            uuid: record.uuid,
+            vault: uuids.vault(record.vault_id)?,
+            overview_format_version: record.overview_format_version,
+            overview_ciphertext: record.overview_ciphertext.clone(),
+            overview_nonce: record.overview_nonce.clone(),
+            overview_key: uuids.key(record.overview_key_id)?,
+            item_format_version: record.item_format_version,
+            item_ciphertext: record.item_ciphertext.clone(),
+            item_nonce: record.item_nonce.clone(),
+            item_key: uuids.key(record.item_key_id)?,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+        })
+    }
+
+    fn name(&self) -> String {
+        self.uuid.to_string()
+    }
+}
+
+/// Writes `value` next to `path` and moves it into place, so a reader never
+/// sees half a file. The move only survives a crash once the directory is
+/// synced.
+fn write_file<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    let mut contents = serde_json::to_vec_pretty(value)?;
+    contents.push(b'\n');
+
+    let partial_path = path.with_extension("json.partial");
+    let mut file = fs::File::create(&partial_path)?;
+    file.write_all(&contents)?;
+    file.sync_all()?;
+    fs::rename(partial_path, path)?;
+
+    Ok(())
+}
+
+/// Makes the files moved into or removed from `path` survive a crash.
+#[cfg(unix)]
+fn sync_directory(path: &Path) -> Result<(), Error> {
+    fs::File::open(path)?.sync_all()?;
+
+    Ok(())
+}
+
+// directories can't be opened to be synced everywhere
+#[cfg(not(unix))]
+fn sync_directory(_: &Path) -> Result<(), Error> {
+    Ok(())
+}
@@ -0,0 +1,1314 @@
+use std::cmp;
+use std::collections::{BTreeMap, btree_map};
+use std::fmt;
+use std::iter::Peekable;
+use std::mem;
+use std::ops::RangeBounds;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, Utc};
+use tokio::sync::OwnedMutexGuard;
+use uuid::Uuid;
+
+use super::record_types::{
+    AuditEntryRecord, AuditHeadRecord, ChangeRecord, EmergencyContactRecord,
+    EmergencyRequestRecord, EmergencyVaultKeyRecord, EncryptedKeyRecord, ItemCiphertextsRecord,
+    ItemPreviewRecord, ItemRecord, ItemRecordWithKeys, NewItemRecord, NewKeyRecord, ProfileRecord,
+    Snapshot, VaultPreviewRecord, VaultRecord, VaultShareRecord,
+};
+use super::{Backend, ChangeFeed, Repository, Transaction};
+use crate::Error;
+use crate::emergency::EmergencyRequestState;
+use crate::item::{ItemCursor, ItemSortKey};
+use crate::share::{SharePermission, VaultAccess};
+
+// as many as the SQLite change log keeps
+const MAX_CHANGES: i64 = 10_000;
+
+/// Keeps a store in memory, nothing is written anywhere. Clones share the
+/// same data, which is gone once every clone and store using it is dropped.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    shared: Arc<Shared>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts from `tables`, handing every commit to `persist` before it is
+    /// seen.
+    pub(super) fn with_persistence(mut tables: Tables, persist: impl Persist + 'static) -> Self {
+        tables.apply_pending();
+
+        Self {
+            shared: Arc::new(Shared {
+                tables: Mutex::new(tables),
+                persist: Some(Box::new(persist)),
+                ..Shared::default()
+            }),
+        }
+    }
+}
+
+/// Writes the tables of a commit somewhere they outlive the process.
+pub(super) trait Persist: fmt::Debug + Send + Sync {
+    /// Called on a blocking thread with the tables before and after the
+    /// commit, which is undone if this fails. Only the rows `Table::touched`
+    /// lists for `after` can differ between the two.
+    fn persist(&self, before: &Tables, after: &Tables) -> Result<(), Error>;
+}
+
+#[derive(Default)]
+struct Shared {
+    tables: Mutex<Tables>,
+    // held by a transaction until it ends, so writes happen one at a time
+    writer: Arc<tokio::sync::Mutex<()>>,
+    // bumped by every commit
+    version: AtomicI64,
+    persist: Option<Box<dyn Persist>>,
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shared")
+            .field("version", &self.version)
+            .field("persist", &self.persist)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Shared {
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        // tables are only replaced as a whole, so a panic can't leave them
+        // half written
+        self.tables.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Persists `tables` and makes them the committed ones, releasing
+    /// `writer` once done. Persisting happens on a blocking thread without
+    /// holding the tables, so reads go on meanwhile, and finishes even if the
+    /// caller stops waiting, so what is seen never differs from what was
+    /// written.
+    async fn commit(
+        self: Arc<Self>,
+        writer: OwnedMutexGuard<()>,
+        tables: Tables,
+    ) -> Result<(), Error> {
+        if self.persist.is_none() {
+            self.apply(tables);
+            return Ok(());
+        }
+
+        tokio::task::spawn_blocking(move || {
+            let _writer = writer;
+            if let Some(persist) = &self.persist {
+                let before = self.tables().clone();
+                persist.persist(&before, &tables)?;
+            }
+            self.apply(tables);
+
+            Ok(())
+        })
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+    }
+
+    fn apply(&self, mut tables: Tables) {
+        let mut committed = self.tables();
+        // dropped first, so the rows `tables` shares with them are changed in
+        // place rather than copied
+        *committed = Tables::default();
+        tables.apply_pending();
+        *committed = tables;
+        self.version.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl Backend for MemoryBackend {
+    fn repository(&self) -> Box<dyn Repository + Sync> {
+        Box::new(MemoryRepository {
+            shared: self.shared.clone(),
+            transaction: None,
+        })
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>, Error> {
+        let writer = self.shared.writer.clone().lock_owned().await;
+        let tables = self.shared.tables().clone();
+
+        Ok(Box::new(MemoryRepository {
+            shared: self.shared.clone(),
+            transaction: Some(PendingTables {
+                _writer: writer,
+                tables,
+            }),
+        }))
+    }
+
+    async fn change_feed(&self) -> Result<Box<dyn ChangeFeed>, Error> {
+        Ok(Box::new(MemoryChangeFeed {
+            shared: self.shared.clone(),
+        }))
+    }
+
+    async fn export(&self) -> Result<Snapshot, Error> {
+        Ok(self.shared.tables().export())
+    }
+
+    async fn import(&self, snapshot: Snapshot) -> Result<(), Error> {
+        let writer = self.shared.writer.clone().lock_owned().await;
+        let mut tables = self.shared.tables().clone();
+        tables.import(snapshot)?;
+
+        self.shared.clone().commit(writer, tables).await
+    }
+}
+
+/// A copy of the tables a transaction writes to, replacing the shared ones
+/// when it is committed. Only the rows it writes are copied.
+struct PendingTables {
+    _writer: OwnedMutexGuard<()>,
+    tables: Tables,
+}
+
+struct MemoryRepository {
+    shared: Arc<Shared>,
+    transaction: Option<PendingTables>,
+}
+
+impl MemoryRepository {
+    fn read<T>(&self, read: impl FnOnce(&Tables) -> T) -> T {
+        match &self.transaction {
+            Some(transaction) => read(&transaction.tables),
+            None => read(&self.shared.tables()),
+        }
+    }
+
+    /// Changes the tables, committing them right away outside of a
+    /// transaction. `write` only fails before it changes anything.
+    async fn write<T>(
+        &mut self,
+        write: impl FnOnce(&mut Tables) -> Result<T, Error> + Send,
+    ) -> Result<T, Error> {
+        if let Some(transaction) = &mut self.transaction {
+            return write(&mut transaction.tables);
+        }
+
+        let writer = self.shared.writer.clone().lock_owned().await;
+        // written to a copy, so the tables are unchanged if persisting fails
+        let mut tables = self.shared.tables().clone();
+        let result = write(&mut tables)?;
+        self.shared.clone().commit(writer, tables).await?;
+
+        Ok(result)
+    }
+}
+
+#[async_trait]
+impl Transaction for MemoryRepository {
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        match self.transaction {
+            Some(transaction) => {
+                self.shared
+                    .commit(transaction._writer, transaction.tables)
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Repository for MemoryRepository {
+    async fn store_vault(&mut self, name: &str, key_id: i64) -> Result<VaultRecord, Error> {
+        let access = VaultAccess::Owner.as_str();
+
+        self.write(|tables| Ok(tables.insert_vault(name, key_id, access, None, None, 0)))
+            .await
+    }
+
+    async fn find_vault(&mut self, id: i64) -> Result<Option<VaultRecord>, Error> {
+        Ok(self.read(|tables| tables.vaults.get(&id).cloned()))
+    }
+
+    async fn find_vault_by_uuid(&mut self, uuid: Uuid) -> Result<Option<VaultRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .vaults
+                .values()
+                .find(|vault| vault.uuid == uuid)
+                .cloned()
+        }))
+    }
+
+    async fn store_shared_vault(
+        &mut self,
+        name: &str,
+        key_id: i64,
+        access: VaultAccess,
+        shared_by: &[u8],
+        shared_vault_id: Uuid,
+        share_generation: i64,
+    ) -> Result<VaultRecord, Error> {
+        self.write(|tables| {
+            Ok(tables.insert_vault(
+                name,
+                key_id,
+                access.as_str(),
+                Some(shared_by.to_vec()),
+                Some(shared_vault_id),
+                share_generation,
+            ))
+        })
+        .await
+    }
+
+    async fn find_shared_vault(
+        &mut self,
+        shared_by: &[u8],
+        shared_vault_id: Uuid,
+    ) -> Result<Option<VaultRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .vaults
+                .values()
+                .find(|vault| {
+                    vault.shared_by.as_deref() == Some(shared_by)
+                        && vault.shared_vault_id == Some(shared_vault_id)
+                })
+                .cloned()
+        }))
+    }
+
+    async fn update_vault(
+        &mut self,
+        id: i64,
+        name: &str,
+        key_id: i64,
+        access: VaultAccess,
+    ) -> Result<VaultRecord, Error> {
+        self.write(|tables| {
+            let vault = tables.vaults.get_mut(&id).ok_or(Error::VaultDoesNotExist)?;
+            let renamed = vault.name != name;
+            vault.name = name.to_owned();
+            vault.key_id = key_id;
+            vault.access = access.as_str().to_owned();
+            vault.updated_at = now();
+            let record = vault.clone();
+
+            if renamed {
+                tables.record_change("vault_renamed", record.uuid, None);
+            }
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn next_share_generation(&mut self, id: i64) -> Result<i64, Error> {
+        self.write(|tables| {
+            let vault = tables.vaults.get_mut(&id).ok_or(Error::VaultDoesNotExist)?;
+            vault.share_generation += 1;
+
+            Ok(vault.share_generation)
+        })
+        .await
+    }
+
+    async fn update_share_generation(&mut self, id: i64, generation: i64) -> Result<(), Error> {
+        self.write(|tables| {
+            let vault = tables.vaults.get_mut(&id).ok_or(Error::VaultDoesNotExist)?;
+            vault.share_generation = generation;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn store_vault_share(
+        &mut self,
+        vault_id: i64,
+        recipient_public_key: &[u8],
+        permission: SharePermission,
+    ) -> Result<VaultShareRecord, Error> {
+        self.write(|tables| {
+            let record = VaultShareRecord {
+                id: next_id(&tables.vault_shares),
+                uuid: Uuid::now_v7(),
+                vault_id,
+                recipient_public_key: recipient_public_key.to_vec(),
+                permission: permission.as_str().to_owned(),
+                created_at: now(),
+                revoked_at: None,
+            };
+            tables.vault_shares.insert(record.id, record.clone());
+
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn list_vault_shares(&mut self, vault_id: i64) -> Result<Vec<VaultShareRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .vault_shares
+                .values()
+                .filter(|share| share.vault_id == vault_id && share.revoked_at.is_none())
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn revoke_vault_share(&mut self, vault_id: i64, share_id: Uuid) -> Result<bool, Error> {
+        self.write(|tables| {
+            let share = tables
+                .vault_shares
+                .values()
+                .find(|share| share.uuid == share_id)
+                .map(|share| share.id);
+            match share.and_then(|id| tables.vault_shares.get_mut(&id)) {
+                Some(share) if share.vault_id == vault_id && share.revoked_at.is_none() => {
+                    share.revoked_at = Some(now());
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        })
+        .await
+    }
+
+    async fn store_emergency_contact(
+        &mut self,
+        public_key: &[u8],
+        wait_seconds: i64,
+    ) -> Result<EmergencyContactRecord, Error> {
+        self.write(|tables| {
+            let record = EmergencyContactRecord {
+                id: next_id(&tables.emergency_contacts),
+                uuid: Uuid::now_v7(),
+                public_key: public_key.to_vec(),
+                wait_seconds,
+                created_at: now(),
+            };
+            tables.emergency_contacts.insert(record.id, record.clone());
+
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn find_emergency_contact(
+        &mut self,
+        public_key: &[u8],
+    ) -> Result<Option<EmergencyContactRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .emergency_contacts
+                .values()
+                .find(|contact| contact.public_key == public_key)
+                .cloned()
+        }))
+    }
+
+    async fn list_vault_emergency_contacts(
+        &mut self,
+        vault_id: i64,
+    ) -> Result<Vec<EmergencyContactRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .emergency_vault_keys
+                .values()
+                .filter(|vault_key| vault_key.vault_id == vault_id)
+                .filter_map(|vault_key| tables.emergency_contacts.get(&vault_key.contact_id))
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn store_emergency_vault_key(
+        &mut self,
+        contact_id: i64,
+        vault_id: i64,
+        sealed_key: &str,
+    ) -> Result<(), Error> {
+        self.write(|tables| {
+            let record = EmergencyVaultKeyRecord {
+                contact_id,
+                vault_id,
+                sealed_key: sealed_key.to_owned(),
+            };
+            tables
+                .emergency_vault_keys
+                .insert((contact_id, vault_id), record);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_emergency_vault_keys(
+        &mut self,
+        contact_id: i64,
+    ) -> Result<Vec<EmergencyVaultKeyRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .emergency_vault_keys
+                .range((contact_id, i64::MIN)..=(contact_id, i64::MAX))
+                .map(|(_, vault_key)| vault_key.clone())
+                .collect()
+        }))
+    }
+
+    async fn store_emergency_request(
+        &mut self,
+        contact_id: i64,
+        requested_at: NaiveDateTime,
+    ) -> Result<EmergencyRequestRecord, Error> {
+        self.write(|tables| {
+            let contact = tables
+                .emergency_contacts
+                .get(&contact_id)
+                .ok_or(Error::UnknownEmergencyContact)?;
+            let record = EmergencyRequestRecord {
+                id: next_id(&tables.emergency_requests),
+                uuid: Uuid::now_v7(),
+                contact_id,
+                public_key: contact.public_key.clone(),
+                wait_seconds: contact.wait_seconds,
+                state: EmergencyRequestState::Pending.as_str().to_owned(),
+                requested_at,
+                decided_at: None,
+            };
+            tables.emergency_requests.insert(record.id, record.clone());
+
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn find_emergency_request(
+        &mut self,
+        uuid: Uuid,
+    ) -> Result<Option<EmergencyRequestRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .emergency_requests
+                .values()
+                .find(|request| request.uuid == uuid)
+                .cloned()
+        }))
+    }
+
+    async fn find_open_emergency_request(
+        &mut self,
+        contact_id: i64,
+    ) -> Result<Option<EmergencyRequestRecord>, Error> {
+        let denied = EmergencyRequestState::Denied.as_str();
+
+        Ok(self.read(|tables| {
+            tables
+                .emergency_requests
+                .values()
+                .find(|request| request.contact_id == contact_id && request.state != denied)
+                .cloned()
+        }))
+    }
+
+    async fn list_emergency_requests(&mut self) -> Result<Vec<EmergencyRequestRecord>, Error> {
+        let mut requests: Vec<_> =
+            self.read(|tables| tables.emergency_requests.values().cloned().collect());
+        requests.sort_by_key(|request: &EmergencyRequestRecord| request.requested_at);
+
+        Ok(requests)
+    }
+
+    async fn update_emergency_request_state(
+        &mut self,
+        uuid: Uuid,
+        state: EmergencyRequestState,
+        decided_at: NaiveDateTime,
+    ) -> Result<EmergencyRequestRecord, Error> {
+        self.write(|tables| {
+            let id = tables
+                .emergency_requests
+                .values()
+                .find(|request| request.uuid == uuid)
+                .map(|request| request.id)
+                .ok_or(Error::EmergencyRequestDoesNotExist)?;
+            let request = tables
+                .emergency_requests
+                .get_mut(&id)
+                .ok_or(Error::EmergencyRequestDoesNotExist)?;
+            request.state = state.as_str().to_owned();
+            request.decided_at = Some(decided_at);
+
+            Ok(request.clone())
+        })
+        .await
+    }
+
+    async fn store_audit_entry(&mut self, entry: &AuditEntryRecord) -> Result<(), Error> {
+        self.write(|tables| {
+            tables.audit_log.insert(entry.sequence, entry.clone());
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_last_audit_entry(&mut self) -> Result<Option<AuditEntryRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .audit_log
+                .last_key_value()
+                .map(|(_, entry)| entry.clone())
+        }))
+    }
+
+    async fn store_audit_head(&mut self, head: &AuditHeadRecord) -> Result<(), Error> {
+        self.write(|tables| {
+            tables.audit_head = Some(head.clone());
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_audit_head(&mut self) -> Result<Option<AuditHeadRecord>, Error> {
+        Ok(self.read(|tables| tables.audit_head.clone()))
+    }
+
+    async fn list_audit_entries(
+        &mut self,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+        kind: Option<&str>,
+    ) -> Result<Vec<AuditEntryRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .audit_log
+                .values()
+                .filter(|entry| since.is_none_or(|since| entry.occurred_at >= since))
+                .filter(|entry| until.is_none_or(|until| entry.occurred_at < until))
+                .filter(|entry| kind.is_none_or(|kind| entry.kind == kind))
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn store_key(&mut self, key: &NewKeyRecord) -> Result<EncryptedKeyRecord, Error> {
+        self.write(|tables| {
+            let record = EncryptedKeyRecord {
+                id: next_id(&tables.keys),
+                uuid: Uuid::now_v7(),
+                format_version: key.format_version,
+                encrypted_key: key.encrypted_key.clone(),
+                nonce: key.nonce.clone(),
+                parent_key_id: key.parent_key_id,
+            };
+            tables.keys.insert(record.id, record.clone());
+
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn find_key(&mut self, key_id: i64) -> Result<Option<EncryptedKeyRecord>, Error> {
+        Ok(self.read(|tables| tables.keys.get(&key_id).cloned()))
+    }
+
+    async fn list_child_keys(
+        &mut self,
+        parent_key_id: i64,
+    ) -> Result<Vec<EncryptedKeyRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .keys
+                .values()
+                .filter(|key| key.parent_key_id == Some(parent_key_id))
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn update_key(&mut self, key_id: i64, key: &NewKeyRecord) -> Result<(), Error> {
+        self.write(|tables| {
+            let record = tables.keys.get_mut(&key_id).ok_or(Error::KeyDoesNotExist)?;
+            record.format_version = key.format_version;
+            record.encrypted_key = key.encrypted_key.clone();
+            record.nonce = key.nonce.clone();
+            record.parent_key_id = key.parent_key_id;
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_legacy_keys(&mut self) -> Result<Vec<EncryptedKeyRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .keys
+                .values()
+                .filter(|key| key.format_version == 0)
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn delete_key(&mut self, key_id: i64) -> Result<(), Error> {
+        self.write(|tables| {
+            tables.keys.remove(&key_id);
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_profile(&mut self) -> Result<Option<ProfileRecord>, Error> {
+        Ok(self.read(|tables| tables.profiles.get(&1).cloned()))
+    }
+
+    async fn store_profile(
+        &mut self,
+        name: &str,
+        salt: &str,
+        key_id: i64,
+    ) -> Result<ProfileRecord, Error> {
+        self.write(|tables| {
+            let now = now();
+            let record = ProfileRecord {
+                id: next_id(&tables.profiles),
+                uuid: Uuid::now_v7(),
+                name: name.to_owned(),
+                salt: salt.to_owned(),
+                key_id,
+                agreement_key_id: None,
+                agreement_public_key: None,
+                signing_key_id: None,
+                verifying_key: None,
+                created_at: now,
+                updated_at: now,
+            };
+            tables.profiles.insert(record.id, record.clone());
+
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn store_profile_key_pairs(
+        &mut self,
+        profile_id: i64,
+        agreement_key_id: i64,
+        agreement_public_key: &[u8],
+        signing_key_id: i64,
+        verifying_key: &[u8],
+    ) -> Result<ProfileRecord, Error> {
+        self.write(|tables| {
+            let record = tables
+                .profiles
+                .get_mut(&profile_id)
+                .ok_or(Error::StoreNotInitialized)?;
+            record.agreement_key_id = Some(agreement_key_id);
+            record.agreement_public_key = Some(agreement_public_key.to_vec());
+            record.signing_key_id = Some(signing_key_id);
+            record.verifying_key = Some(verifying_key.to_vec());
+            record.updated_at = now();
+
+            Ok(record.clone())
+        })
+        .await
+    }
+
+    async fn list_vault_previews(&mut self) -> Result<Vec<VaultPreviewRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .vaults
+                .values()
+                .map(|vault| VaultPreviewRecord {
+                    id: vault.id,
+                    uuid: vault.uuid,
+                    name: vault.name.clone(),
+                })
+                .collect()
+        }))
+    }
+
+    async fn store_item(&mut self, item: &NewItemRecord) -> Result<ItemRecord, Error> {
+        self.write(|tables| {
+            let now = now();
+            let record = ItemRecord {
+                id: next_id(&tables.items),
+                uuid: Uuid::now_v7(),
+                vault_id: item.vault_id,
+                overview_format_version: item.overview_format_version,
+                overview_ciphertext: item.overview_ciphertext.clone(),
+                overview_nonce: item.overview_nonce.clone(),
+                overview_key_id: item.overview_key_id,
+                item_format_version: item.item_format_version,
+                item_ciphertext: item.item_ciphertext.clone(),
+                item_nonce: item.item_nonce.clone(),
+                item_key_id: item.item_key_id,
+                created_at: now,
+                updated_at: now,
+            };
+            tables.items.insert(record.id, record.clone());
+            tables.record_item_change("item_created", &record);
+
+            Ok(record)
+        })
+        .await
+    }
+
+    async fn find_item(&mut self, vault_id: i64, uuid: Uuid) -> Result<ItemRecordWithKeys, Error> {
+        self.read(|tables| {
+            let item_record = tables
+                .item_by_uuid(uuid)
+                .filter(|item| item.vault_id == vault_id)
+                .ok_or(Error::ItemDoesNotExist)?;
+
+            Ok(ItemRecordWithKeys {
+                item_record: item_record.clone(),
+                overview_key: tables.key(item_record.overview_key_id)?,
+                data_key: tables.key(item_record.item_key_id)?,
+            })
+        })
+    }
+
+    async fn list_item_previews(
+        &mut self,
+        vault_id: Option<i64>,
+        sort_key: ItemSortKey,
+        after: Option<&ItemCursor>,
+        limit: Option<u32>,
+    ) -> Result<Vec<ItemPreviewRecord>, Error> {
+        let mut previews = self.read(|tables| {
+            tables
+                .items
+                .values()
+                .filter(|item| vault_id.is_none_or(|vault_id| item.vault_id == vault_id))
+                .map(|item| tables.item_preview(item, sort_key))
+                .collect::<Result<Vec<_>, Error>>()
+        })?;
+
+        if let Some(after) = after {
+            previews.retain(|preview| {
+                (preview.sort_value.as_str(), preview.uuid) > (after.sort_value(), after.id())
+            });
+        }
+        previews.sort_by(|a, b| (&a.sort_value, a.uuid).cmp(&(&b.sort_value, b.uuid)));
+        if let Some(limit) = limit {
+            previews.truncate(limit as usize);
+        }
+
+        Ok(previews)
+    }
+
+    async fn delete_item(&mut self, vault_id: i64, uuid: Uuid) -> Result<bool, Error> {
+        self.write(|tables| {
+            let id = match tables.item_by_uuid(uuid) {
+                Some(item) if item.vault_id == vault_id => item.id,
+                _ => return Ok(false),
+            };
+
+            tables.remove_item(id);
+            Ok(true)
+        })
+        .await
+    }
+
+    async fn list_legacy_items(&mut self) -> Result<Vec<ItemRecord>, Error> {
+        Ok(self.read(|tables| {
+            tables
+                .items
+                .values()
+                .filter(|item| item.overview_format_version == 0 || item.item_format_version == 0)
+                .cloned()
+                .collect()
+        }))
+    }
+
+    async fn update_item_ciphertexts(
+        &mut self,
+        id: i64,
+        item: &ItemCiphertextsRecord,
+    ) -> Result<(), Error> {
+        self.write(|tables| {
+            let record = tables.items.get_mut(&id).ok_or(Error::ItemDoesNotExist)?;
+            record.overview_format_version = item.overview_format_version;
+            record.overview_ciphertext = item.overview_ciphertext.clone();
+            record.overview_nonce = item.overview_nonce.clone();
+            record.item_format_version = item.item_format_version;
+            record.item_ciphertext = item.item_ciphertext.clone();
+            record.item_nonce = item.item_nonce.clone();
+            let record = record.clone();
+
+            tables.record_item_change("item_updated", &record);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_vault_items(&mut self, vault_id: i64) -> Result<(), Error> {
+        self.write(|tables| {
+            let ids: Vec<_> = tables
+                .items
+                .values()
+                .filter(|item| item.vault_id == vault_id)
+                .map(|item| item.id)
+                .collect();
+            for id in ids {
+                tables.remove_item(id);
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[derive(Debug)]
+struct MemoryChangeFeed {
+    shared: Arc<Shared>,
+}
+
+#[async_trait]
+impl ChangeFeed for MemoryChangeFeed {
+    async fn data_version(&mut self) -> Result<i64, Error> {
+        Ok(self.shared.version.load(Ordering::Relaxed))
+    }
+
+    async fn find_last_change_sequence(&mut self) -> Result<i64, Error> {
+        Ok(self.shared.tables().last_change)
+    }
+
+    async fn list_changes(&mut self, after: i64) -> Result<Vec<ChangeRecord>, Error> {
+        Ok(self
+            .shared
+            .tables()
+            .change_log
+            .range(after + 1..)
+            .map(|(_, change)| change.clone())
+            .collect())
+    }
+}
+
+/// Every record by its id. The changes are only kept in memory.
+#[derive(Debug, Clone, Default)]
+pub(super) struct Tables {
+    pub(super) profiles: Table<i64, ProfileRecord>,
+    pub(super) keys: Table<i64, EncryptedKeyRecord>,
+    pub(super) vaults: Table<i64, VaultRecord>,
+    pub(super) vault_shares: Table<i64, VaultShareRecord>,
+    pub(super) emergency_contacts: Table<i64, EmergencyContactRecord>,
+    // keyed by contact and vault
+    pub(super) emergency_vault_keys: Table<(i64, i64), EmergencyVaultKeyRecord>,
+    pub(super) emergency_requests: Table<i64, EmergencyRequestRecord>,
+    pub(super) audit_log: Table<i64, AuditEntryRecord>,
+    pub(super) audit_head: Option<AuditHeadRecord>,
+    pub(super) items: Table<i64, ItemRecord>,
+    change_log: Table<i64, ChangeRecord>,
+    last_change: i64,
+}
+
+impl Tables {
+    pub(super) fn export(&self) -> Snapshot {
+        Snapshot {
+            profiles: self.profiles.values().cloned().collect(),
+            keys: self.keys.values().cloned().collect(),
+            vaults: self.vaults.values().cloned().collect(),
+            vault_shares: self.vault_shares.values().cloned().collect(),
+            emergency_contacts: self.emergency_contacts.values().cloned().collect(),
+            emergency_vault_keys: self.emergency_vault_keys.values().cloned().collect(),
+            emergency_requests: self.emergency_requests.values().cloned().collect(),
+            audit_log: self.audit_log.values().cloned().collect(),
+            audit_head: self.audit_head.clone(),
+            items: self.items.values().cloned().collect(),
+        }
+    }
+
+    /// Adds the records of `snapshot`, failing if one of them has the id of
+    /// a record already in the tables.
+    pub(super) fn import(&mut self, snapshot: Snapshot) -> Result<(), Error> {
+        let Snapshot {
+            profiles,
+            keys,
+            vaults,
+            vault_shares,
+            emergency_contacts,
+            emergency_vault_keys,
+            emergency_requests,
+            audit_log,
+            audit_head,
+            items,
+        } = snapshot;
+
+        insert_new(
+            &mut self.profiles,
+            profiles.into_iter().map(|record| (record.id, record)),
+        )?;
+        insert_new(
+            &mut self.keys,
+            keys.into_iter().map(|record| (record.id, record)),
+        )?;
+        for vault in &vaults {
+            self.record_change("vault_created", vault.uuid, None);
+        }
+        insert_new(
+            &mut self.vaults,
+            vaults.into_iter().map(|record| (record.id, record)),
+        )?;
+        insert_new(
+            &mut self.vault_shares,
+            vault_shares.into_iter().map(|record| (record.id, record)),
+        )?;
+        insert_new(
+            &mut self.emergency_contacts,
+            emergency_contacts
+                .into_iter()
+                .map(|record| (record.id, record)),
+        )?;
+        insert_new(
+            &mut self.emergency_vault_keys,
+            emergency_vault_keys
+                .into_iter()
+                .map(|record| ((record.contact_id, record.vault_id), record)),
+        )?;
+        insert_new(
+            &mut self.emergency_requests,
+            emergency_requests
+                .into_iter()
+                .map(|record| (record.id, record)),
+        )?;
+        insert_new(
+            &mut self.audit_log,
+            audit_log
+                .into_iter()
+                .map(|record| (record.sequence, record)),
+        )?;
+        if audit_head.is_some() {
+            if self.audit_head.is_some() {
+                return Err(Error::RecordAlreadyExists);
+            }
+            self.audit_head = audit_head;
+        }
+        for item in items {
+            if self.items.contains_key(&item.id) {
+                return Err(Error::RecordAlreadyExists);
+            }
+            self.record_item_change("item_created", &item);
+            self.items.insert(item.id, item);
+        }
+
+        Ok(())
+    }
+
+    fn insert_vault(
+        &mut self,
+        name: &str,
+        key_id: i64,
+        access: &str,
+        shared_by: Option<Vec<u8>>,
+        shared_vault_id: Option<Uuid>,
+        share_generation: i64,
+    ) -> VaultRecord {
+        let now = now();
+        let record = VaultRecord {
+            id: next_id(&self.vaults),
+            uuid: Uuid::now_v7(),
+            name: name.to_owned(),
+            key_id,
+            access: access.to_owned(),
+            shared_by,
+            shared_vault_id,
+            share_generation,
+            created_at: now,
+            updated_at: now,
+        };
+        self.vaults.insert(record.id, record.clone());
+        self.record_change("vault_created", record.uuid, None);
+
+        record
+    }
+
+    fn key(&self, id: i64) -> Result<EncryptedKeyRecord, Error> {
+        self.keys.get(&id).cloned().ok_or(Error::KeyDoesNotExist)
+    }
+
+    fn item_by_uuid(&self, uuid: Uuid) -> Option<&ItemRecord> {
+        self.items.values().find(|item| item.uuid == uuid)
+    }
+
+    fn item_preview(
+        &self,
+        item: &ItemRecord,
+        sort_key: ItemSortKey,
+    ) -> Result<ItemPreviewRecord, Error> {
+        let overview_key = self.key(item.overview_key_id)?;
+        let vault = self
+            .vaults
+            .get(&item.vault_id)
+            .ok_or(Error::VaultDoesNotExist)?;
+        // fixed width, so the text sorts in the order of the times
+        let sort_value = match sort_key {
+            ItemSortKey::Id => String::new(),
+            ItemSortKey::CreatedAt => item.created_at.format("%F %T%.6f").to_string(),
+            ItemSortKey::UpdatedAt => item.updated_at.format("%F %T%.6f").to_string(),
+        };
+
+        Ok(ItemPreviewRecord {
+            id: item.id,
+            uuid: item.uuid,
+            vault_id: item.vault_id,
+            vault_uuid: vault.uuid,
+            overview_format_version: item.overview_format_version,
+            overview_ciphertext: item.overview_ciphertext.clone(),
+            overview_nonce: item.overview_nonce.clone(),
+            overview_key_id: overview_key.id,
+            overview_key_format_version: overview_key.format_version,
+            overview_key_encrypted_key: overview_key.encrypted_key,
+            overview_key_nonce: overview_key.nonce,
+            overview_key_parent_key_id: overview_key.parent_key_id,
+            created_at: item.created_at,
+            updated_at: item.updated_at,
+            sort_value,
+        })
+    }
+
+    fn remove_item(&mut self, id: i64) {
+        if let Some(item) = self.items.remove(&id) {
+            self.keys.remove(&item.overview_key_id);
+            self.keys.remove(&item.item_key_id);
+            self.record_item_change("item_deleted", &item);
+        }
+    }
+
+    // items are removed before their vault
+    fn record_item_change(&mut self, kind: &str, item: &ItemRecord) {
+        if let Some(vault) = self.vaults.get(&item.vault_id) {
+            self.record_change(kind, vault.uuid, Some(item.uuid));
+        }
+    }
+
+    fn record_change(&mut self, kind: &str, vault_id: Uuid, item_id: Option<Uuid>) {
+        self.last_change += 1;
+        self.change_log.insert(
+            self.last_change,
+            ChangeRecord {
+                sequence: self.last_change,
+                kind: kind.to_owned(),
+                vault_id,
+                item_id,
+            },
+        );
+
+        while let Some((&sequence, _)) = self.change_log.first_key_value()
+            && sequence <= self.last_change - MAX_CHANGES
+        {
+            self.change_log.remove(&sequence);
+        }
+    }
+
+    /// Makes the rows written to the tables part of the ones they share
+    /// with their copies.
+    fn apply_pending(&mut self) {
+        self.profiles.apply_pending();
+        self.keys.apply_pending();
+        self.vaults.apply_pending();
+        self.vault_shares.apply_pending();
+        self.emergency_contacts.apply_pending();
+        self.emergency_vault_keys.apply_pending();
+        self.emergency_requests.apply_pending();
+        self.audit_log.apply_pending();
+        self.items.apply_pending();
+        self.change_log.apply_pending();
+    }
+}
+
+/// The rows of a table, shared between its copies until they are written
+/// to. Rows written to a copy are kept apart from the shared ones until
+/// they are applied, so copying a table is cheap and a commit knows which
+/// rows it touched without comparing all of them.
+#[derive(Debug)]
+pub(super) struct Table<K, V> {
+    committed: Arc<BTreeMap<K, V>>,
+    // `None` where a row was removed
+    pending: BTreeMap<K, Option<V>>,
+}
+
+impl<K: Ord + Clone, V: Clone> Table<K, V> {
+    pub(super) fn get(&self, key: &K) -> Option<&V> {
+        match self.pending.get(key) {
+            Some(row) => row.as_ref(),
+            None => self.committed.get(key),
+        }
+    }
+
+    pub(super) fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        if !self.pending.contains_key(key) {
+            let row = self.committed.get(key)?.clone();
+            self.pending.insert(key.clone(), Some(row));
+        }
+
+        self.pending.get_mut(key)?.as_mut()
+    }
+
+    pub(super) fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub(super) fn insert(&mut self, key: K, row: V) {
+        self.pending.insert(key, Some(row));
+    }
+
+    pub(super) fn remove(&mut self, key: &K) -> Option<V> {
+        let row = self.get(key).cloned();
+        if row.is_some() {
+            self.pending.insert(key.clone(), None);
+        }
+
+        row
+    }
+
+    pub(super) fn iter(&self) -> Rows<'_, K, V> {
+        self.range(..)
+    }
+
+    pub(super) fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, row)| row)
+    }
+
+    pub(super) fn range(&self, range: impl RangeBounds<K> + Clone) -> Rows<'_, K, V> {
+        Rows {
+            committed: self.committed.range(range.clone()).peekable(),
+            pending: self.pending.range(range).peekable(),
+        }
+    }
+
+    pub(super) fn first_key_value(&self) -> Option<(&K, &V)> {
+        self.iter().next()
+    }
+
+    pub(super) fn last_key_value(&self) -> Option<(&K, &V)> {
+        // the last committed rows may have been written to since
+        let committed = self
+            .committed
+            .iter()
+            .rev()
+            .find(|(key, _)| !self.pending.contains_key(key));
+        let pending = self
+            .pending
+            .iter()
+            .rev()
+            .find_map(|(key, row)| Some((key, row.as_ref()?)));
+
+        match (committed, pending) {
+            (Some(committed), Some(pending)) => {
+                Some(cmp::max_by_key(committed, pending, |row| row.0))
+            }
+            (committed, pending) => committed.or(pending),
+        }
+    }
+
+    /// The keys of the rows written to since the table was copied, which
+    /// may have been left as they were.
+    pub(super) fn touched(&self) -> impl Iterator<Item = &K> {
+        self.pending.keys()
+    }
+
+    fn apply_pending(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let committed = Arc::make_mut(&mut self.committed);
+        for (key, row) in mem::take(&mut self.pending) {
+            match row {
+                Some(row) => committed.insert(key, row),
+                None => committed.remove(&key),
+            };
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> Clone for Table<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            committed: self.committed.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for Table<K, V> {
+    fn default() -> Self {
+        Self {
+            committed: Arc::default(),
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+/// The rows of a table in the order of their keys, the written ones in
+/// place of the shared ones.
+pub(super) struct Rows<'a, K, V> {
+    committed: Peekable<btree_map::Range<'a, K, V>>,
+    pending: Peekable<btree_map::Range<'a, K, Option<V>>>,
+}
+
+impl<'a, K: Ord, V> Iterator for Rows<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let order = match (self.committed.peek(), self.pending.peek()) {
+                (Some((committed, _)), Some((pending, _))) => committed.cmp(pending),
+                (Some(_), None) => cmp::Ordering::Less,
+                (None, Some(_)) => cmp::Ordering::Greater,
+                (None, None) => return None,
+            };
+            match order {
+                cmp::Ordering::Less => return self.committed.next(),
+                cmp::Ordering::Equal => {
+                    self.committed.next();
+                }
+                cmp::Ordering::Greater => {}
+            }
+            // skips removed rows
+            if let (key, Some(row)) = self.pending.next()? {
+                return Some((key, row));
+            }
+        }
+    }
+}
+
+/// Inserts `records` into `table`, failing if one of their keys is taken.
+fn insert_new<K: Ord + Clone, T: Clone>(
+    table: &mut Table<K, T>,
+    records: impl IntoIterator<Item = (K, T)>,
+) -> Result<(), Error> {
+    for (key, record) in records {
+        if table.contains_key(&key) {
+            return Err(Error::RecordAlreadyExists);
+        }
+        table.insert(key, record);
+    }
+
+    Ok(())
+}
+
+fn next_id<T: Clone>(rows: &Table<i64, T>) -> i64 {
+    rows.last_key_value().map_or(1, |(&id, _)| id + 1)
+}
+
+fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
@@ -1,105 +1,360 @@
-use chrono::NaiveDateTime;
-use sqlx::types::Json;
+//! Rows as a [`Repository`](super::Repository) reads and writes them. Times
+//! are UTC, encrypted data is stored as its format version, ciphertext and
+//! nonce.
+
+use cerberus_crypto::asymmetric::AgreementPublicKey;
+use cerberus_serde::base64;
+use chrono::{NaiveDateTime, TimeDelta};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use uuid::Uuid;
 
 use crate::{
     Error,
-    crypto::{Cipher, EncryptedData, EncryptedDataKeyPair, EncryptedKey},
+    crypto::{Cipher, EncryptedData, EncryptedKey},
+    emergency::{EmergencyContact, EmergencyRequest, EmergencyRequestState},
+    events::StoreEvent,
     item::{Item, ItemData, ItemOverview, ItemPreview},
-    store::Profile,
+    share::{self, Share, SharePermission},
+    store::{Profile, ProfileKeyPairs, profile_key_identifier},
     vault::{Vault, VaultKey, VaultPreview},
 };
 
 use super::Database;
 
-#[derive(Debug)]
-pub(crate) struct ProfileRecord {
-    pub(crate) id: i64,
-    pub(crate) name: String,
-    pub(crate) salt: String,
-    pub(crate) key_id: i64,
-    pub(crate) created_at: NaiveDateTime,
-    pub(crate) updated_at: NaiveDateTime,
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub name: String,
+    pub salt: String,
+    pub key_id: i64,
+    pub agreement_key_id: Option<i64>,
+    pub agreement_public_key: Option<Vec<u8>>,
+    pub signing_key_id: Option<i64>,
+    pub verifying_key: Option<Vec<u8>>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
 }
 
 impl ProfileRecord {
-    pub(crate) fn into_profile(self) -> Profile {
-        Profile::new(
+    pub(crate) fn try_into_profile(self) -> Result<Profile, Error> {
+        let key_pairs = match (
+            self.agreement_key_id,
+            self.agreement_public_key,
+            self.signing_key_id,
+            self.verifying_key,
+        ) {
+            (
+                Some(agreement_key_id),
+                Some(agreement_public_key),
+                Some(signing_key_id),
+                Some(verifying_key),
+            ) => Some(ProfileKeyPairs::new(
+                agreement_key_id,
+                public_key_bytes(agreement_public_key)?,
+                signing_key_id,
+                public_key_bytes(verifying_key)?,
+            )),
+            // written by a version without key pairs
+            (None, None, None, None) => None,
+            _ => return Err(Error::MalformedKeyPair),
+        };
+
+        Ok(Profile::new(
             self.id,
             self.name,
             self.salt,
             self.key_id,
+            key_pairs,
             self.created_at.and_utc(),
             self.updated_at.and_utc(),
-        )
+        ))
     }
 }
 
-impl From<ProfileRecord> for Profile {
-    fn from(record: ProfileRecord) -> Self {
-        record.into_profile()
+impl TryFrom<ProfileRecord> for Profile {
+    type Error = Error;
+
+    fn try_from(record: ProfileRecord) -> Result<Self, Self::Error> {
+        record.try_into_profile()
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct VaultRecord {
-    pub(crate) id: i64,
-    pub(crate) name: String,
-    pub(crate) key_id: i64,
-    pub(crate) created_at: NaiveDateTime,
-    pub(crate) updated_at: NaiveDateTime,
+fn public_key_bytes(key: Vec<u8>) -> Result<[u8; 32], Error> {
+    key.try_into().map_err(|_| Error::MalformedKeyPair)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VaultRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub name: String,
+    pub key_id: i64,
+    pub access: String,
+    pub shared_by: Option<Vec<u8>>,
+    pub shared_vault_id: Option<Uuid>,
+    pub share_generation: i64,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
 }
 
 impl VaultRecord {
-    pub(crate) fn into_vault(self, vault_key: VaultKey, database: Database) -> Vault {
-        Vault::new(
-            self.id,
-            self.name,
-            self.created_at.and_utc(),
-            self.updated_at.and_utc(),
-            database,
-            vault_key,
-        )
+    pub(crate) fn try_into_vault(
+        self,
+        vault_key: VaultKey,
+        database: Database,
+    ) -> Result<Vault, Error> {
+        Vault::from_record(self, vault_key, database)
     }
 }
 
-pub(crate) struct VaultPreviewRecord {
-    pub(crate) id: i64,
-    pub(crate) name: String,
+#[derive(Debug, Clone)]
+pub struct VaultPreviewRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub name: String,
 }
 
 impl VaultPreviewRecord {
     pub(crate) fn into_vault_preview(self) -> VaultPreview {
-        VaultPreview::new(self.id, self.name)
+        VaultPreview::new(self.uuid, self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VaultShareRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub vault_id: i64,
+    pub recipient_public_key: Vec<u8>,
+    pub permission: String,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+impl VaultShareRecord {
+    pub(crate) fn try_into_share(self) -> Result<Share, Error> {
+        Ok(Share::new(
+            self.uuid,
+            share::recipient_public_key(self.recipient_public_key)?,
+            SharePermission::parse(&self.permission)?,
+            self.created_at.and_utc(),
+        ))
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct EncryptedKeyRecord {
-    pub(crate) id: i64,
-    pub(crate) key_encrypted_data: Json<EncryptedData<Vec<u8>>>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyContactRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub public_key: Vec<u8>,
+    pub wait_seconds: i64,
+    pub created_at: NaiveDateTime,
+}
+
+impl EmergencyContactRecord {
+    pub(crate) fn try_into_emergency_contact(self) -> Result<EmergencyContact, Error> {
+        Ok(EmergencyContact::new(
+            self.uuid,
+            emergency_contact_public_key(self.public_key)?,
+            TimeDelta::seconds(self.wait_seconds),
+            self.created_at.and_utc(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyVaultKeyRecord {
+    pub contact_id: i64,
+    pub vault_id: i64,
+    pub sealed_key: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmergencyRequestRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub contact_id: i64,
+    pub public_key: Vec<u8>,
+    pub wait_seconds: i64,
+    pub state: String,
+    pub requested_at: NaiveDateTime,
+    pub decided_at: Option<NaiveDateTime>,
+}
+
+impl EmergencyRequestRecord {
+    pub(crate) fn try_into_emergency_request(self) -> Result<EmergencyRequest, Error> {
+        let requested_at = self.requested_at.and_utc();
+
+        Ok(EmergencyRequest::new(
+            self.uuid,
+            emergency_contact_public_key(self.public_key)?,
+            EmergencyRequestState::parse(&self.state)?,
+            requested_at,
+            requested_at + TimeDelta::seconds(self.wait_seconds),
+            self.decided_at.map(|decided_at| decided_at.and_utc()),
+        ))
+    }
+}
+
+pub(crate) fn emergency_contact_public_key(key: Vec<u8>) -> Result<AgreementPublicKey, Error> {
+    let key = key
+        .try_into()
+        .map_err(|_| Error::MalformedEmergencyAccess)?;
+
+    Ok(AgreementPublicKey::from_bytes(
+        key,
+        profile_key_identifier(&key),
+    ))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntryRecord {
+    pub sequence: i64,
+    pub kind: String,
+    pub occurred_at: NaiveDateTime,
+    pub format_version: i64,
+    #[serde(with = "base64")]
+    pub ciphertext: Vec<u8>,
+    #[serde(with = "base64")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "base64")]
+    pub hash: Vec<u8>,
+}
+
+/// The newest entry of the audit log, kept outside of it under a MAC.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditHeadRecord {
+    pub sequence: i64,
+    #[serde(with = "base64")]
+    pub hash: Vec<u8>,
+    #[serde(with = "base64")]
+    pub mac: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeRecord {
+    pub sequence: i64,
+    pub kind: String,
+    pub vault_id: Uuid,
+    pub item_id: Option<Uuid>,
+}
+
+impl ChangeRecord {
+    pub(crate) fn try_into_event(self) -> Result<StoreEvent, Error> {
+        let vault_id = self.vault_id;
+        let item_id = || self.item_id.ok_or(Error::MalformedChangeLog);
+
+        Ok(match self.kind.as_str() {
+            "vault_created" => StoreEvent::VaultCreated { vault_id },
+            "vault_renamed" => StoreEvent::VaultRenamed { vault_id },
+            "vault_deleted" => StoreEvent::VaultDeleted { vault_id },
+            "item_created" => StoreEvent::ItemCreated {
+                vault_id,
+                item_id: item_id()?,
+            },
+            "item_updated" => StoreEvent::ItemUpdated {
+                vault_id,
+                item_id: item_id()?,
+            },
+            "item_deleted" => StoreEvent::ItemDeleted {
+                vault_id,
+                item_id: item_id()?,
+            },
+            _ => return Err(Error::MalformedChangeLog),
+        })
+    }
+}
+
+/// Every record of a store, as copied between backends by
+/// [`migrate`](super::migrate). Changes aren't part of it, a backend only
+/// reports the changes made to it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub profiles: Vec<ProfileRecord>,
+    pub keys: Vec<EncryptedKeyRecord>,
+    pub vaults: Vec<VaultRecord>,
+    pub vault_shares: Vec<VaultShareRecord>,
+    pub emergency_contacts: Vec<EmergencyContactRecord>,
+    pub emergency_vault_keys: Vec<EmergencyVaultKeyRecord>,
+    pub emergency_requests: Vec<EmergencyRequestRecord>,
+    pub audit_log: Vec<AuditEntryRecord>,
+    pub audit_head: Option<AuditHeadRecord>,
+    pub items: Vec<ItemRecord>,
+}
+
+/// A key to store, its ids are picked by the repository.
+#[derive(Debug, Clone)]
+pub struct NewKeyRecord {
+    pub format_version: i64,
+    pub encrypted_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub parent_key_id: Option<i64>,
+}
+
+impl NewKeyRecord {
+    pub(crate) fn from_encrypted_data<T: Serialize + DeserializeOwned>(
+        key: &EncryptedData<T>,
+    ) -> Self {
+        Self {
+            format_version: key.version().into(),
+            encrypted_key: key.enc_data().to_vec(),
+            nonce: key.nonce().to_vec(),
+            parent_key_id: key.key_id(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptedKeyRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub format_version: i64,
+    pub encrypted_key: Vec<u8>,
+    pub nonce: Vec<u8>,
+    pub parent_key_id: Option<i64>,
 }
 
 impl EncryptedKeyRecord {
-    pub(crate) fn into_encrypted_key(self) -> EncryptedKey {
-        EncryptedKey::new(Some(self.id), self.key_encrypted_data.0)
+    pub(crate) fn try_into_encrypted_key(self) -> Result<EncryptedKey, Error> {
+        let key_encrypted_data = EncryptedData::from_parts(
+            self.format_version,
+            self.encrypted_key,
+            self.nonce,
+            self.parent_key_id,
+        )?;
+
+        Ok(EncryptedKey::new(Some(self.id), key_encrypted_data))
     }
 }
 
-impl From<EncryptedKeyRecord> for EncryptedKey {
-    fn from(record: EncryptedKeyRecord) -> Self {
-        record.into_encrypted_key()
+impl TryFrom<EncryptedKeyRecord> for EncryptedKey {
+    type Error = Error;
+
+    fn try_from(record: EncryptedKeyRecord) -> Result<Self, Self::Error> {
+        record.try_into_encrypted_key()
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct ItemPreviewRecord {
-    pub(crate) id: i64,
-    pub(crate) vault_id: i64,
-    pub(crate) overview_encrypted_data: Json<EncryptedData<ItemOverview>>,
-    pub(crate) overview_key_id: i64,
-    pub(crate) overview_key_encrypted_data: Json<EncryptedData<Vec<u8>>>,
-    pub(crate) created_at: NaiveDateTime,
-    pub(crate) updated_at: NaiveDateTime,
+#[derive(Debug, Clone)]
+pub struct ItemPreviewRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub vault_id: i64,
+    pub vault_uuid: Uuid,
+    pub overview_format_version: i64,
+    pub overview_ciphertext: Vec<u8>,
+    pub overview_nonce: Vec<u8>,
+    pub overview_key_id: i64,
+    pub overview_key_format_version: i64,
+    pub overview_key_encrypted_key: Vec<u8>,
+    pub overview_key_nonce: Vec<u8>,
+    pub overview_key_parent_key_id: Option<i64>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    /// The sort key's value as text, previews are ordered by it and then by
+    /// UUID. Only compared with values of the same repository.
+    pub sort_value: String,
 }
 
 impl ItemPreviewRecord {
@@ -109,62 +364,138 @@ impl ItemPreviewRecord {
     ) -> Result<ItemPreview, Error> {
         let enc_overview_key = EncryptedKey::new(
             Some(self.overview_key_id),
-            self.overview_key_encrypted_data.0,
+            EncryptedData::from_parts(
+                self.overview_key_format_version,
+                self.overview_key_encrypted_key,
+                self.overview_key_nonce,
+                self.overview_key_parent_key_id,
+            )?,
         );
         let overview_key = enc_overview_key.try_to_symmetric_key(parent_key)?;
 
-        let overview_data = overview_key.decrypt(&self.overview_encrypted_data.0)?;
+        let enc_overview = EncryptedData::from_parts(
+            self.overview_format_version,
+            self.overview_ciphertext,
+            self.overview_nonce,
+            Some(self.overview_key_id),
+        )?;
+        let overview_data = overview_key.decrypt(&enc_overview)?;
 
-        let preview = ItemPreview::new(self.id, overview_data);
+        let preview = ItemPreview::new(self.uuid, self.vault_uuid, overview_data);
         Ok(preview)
     }
 }
 
-#[derive(Debug)]
-pub(crate) struct ItemRecord {
-    pub(crate) id: i64,
-    pub(crate) vault_id: i64,
-    pub(crate) overview_encrypted_data: Json<EncryptedData<ItemOverview>>,
-    pub(crate) overview_key_id: i64,
-    pub(crate) item_encrypted_data: Json<EncryptedData<ItemData>>,
-    pub(crate) item_key_id: i64,
-    pub(crate) created_at: NaiveDateTime,
-    pub(crate) updated_at: NaiveDateTime,
+/// An item to store, its ids and times are set by the repository.
+#[derive(Debug, Clone)]
+pub struct NewItemRecord {
+    pub vault_id: i64,
+    pub overview_format_version: i64,
+    pub overview_ciphertext: Vec<u8>,
+    pub overview_nonce: Vec<u8>,
+    pub overview_key_id: i64,
+    pub item_format_version: i64,
+    pub item_ciphertext: Vec<u8>,
+    pub item_nonce: Vec<u8>,
+    pub item_key_id: i64,
+}
+
+impl NewItemRecord {
+    pub(crate) fn new(
+        vault_id: i64,
+        enc_overview: &EncryptedData<ItemOverview>,
+        overview_key_id: i64,
+        enc_data: &EncryptedData<ItemData>,
+        data_key_id: i64,
+    ) -> Self {
+        Self {
+            vault_id,
+            overview_format_version: enc_overview.version().into(),
+            overview_ciphertext: enc_overview.enc_data().to_vec(),
+            overview_nonce: enc_overview.nonce().to_vec(),
+            overview_key_id,
+            item_format_version: enc_data.version().into(),
+            item_ciphertext: enc_data.enc_data().to_vec(),
+            item_nonce: enc_data.nonce().to_vec(),
+            item_key_id: data_key_id,
+        }
+    }
+}
+
+/// The encrypted overview and data of an item, rewritten under the keys it
+/// already has.
+#[derive(Debug, Clone)]
+pub struct ItemCiphertextsRecord {
+    pub overview_format_version: i64,
+    pub overview_ciphertext: Vec<u8>,
+    pub overview_nonce: Vec<u8>,
+    pub item_format_version: i64,
+    pub item_ciphertext: Vec<u8>,
+    pub item_nonce: Vec<u8>,
+}
+
+impl ItemCiphertextsRecord {
+    pub(crate) fn new(
+        enc_overview: &EncryptedData<ItemOverview>,
+        enc_data: &EncryptedData<ItemData>,
+    ) -> Self {
+        Self {
+            overview_format_version: enc_overview.version().into(),
+            overview_ciphertext: enc_overview.enc_data().to_vec(),
+            overview_nonce: enc_overview.nonce().to_vec(),
+            item_format_version: enc_data.version().into(),
+            item_ciphertext: enc_data.enc_data().to_vec(),
+            item_nonce: enc_data.nonce().to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemRecord {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub vault_id: i64,
+    pub overview_format_version: i64,
+    pub overview_ciphertext: Vec<u8>,
+    pub overview_nonce: Vec<u8>,
+    pub overview_key_id: i64,
+    pub item_format_version: i64,
+    pub item_ciphertext: Vec<u8>,
+    pub item_nonce: Vec<u8>,
+    pub item_key_id: i64,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
 }
 
 impl ItemRecord {
-    pub(crate) fn into_item(
+    pub(crate) fn try_into_item(
         self,
         overview_key: EncryptedKey,
         data_key: EncryptedKey,
         vault_key: VaultKey,
         database: Database,
-    ) -> Item {
-        Item::new(
-            self.id,
-            EncryptedDataKeyPair::new(self.overview_encrypted_data.0, overview_key),
-            EncryptedDataKeyPair::new(self.item_encrypted_data.0, data_key),
-            self.created_at.and_utc(),
-            self.updated_at.and_utc(),
-            vault_key,
-            database,
-        )
+    ) -> Result<Item, Error> {
+        Item::from_record(self, overview_key, data_key, vault_key, database)
     }
 }
 
-pub(crate) struct ItemRecordWithKeys {
-    pub(crate) item_record: ItemRecord,
-    pub(crate) overview_key: Json<EncryptedData<Vec<u8>>>,
-    pub(crate) data_key: Json<EncryptedData<Vec<u8>>>,
+#[derive(Debug, Clone)]
+pub struct ItemRecordWithKeys {
+    pub item_record: ItemRecord,
+    pub overview_key: EncryptedKeyRecord,
+    pub data_key: EncryptedKeyRecord,
 }
 
 impl ItemRecordWithKeys {
-    pub(crate) fn into_item(self, vault_key: VaultKey, database: Database) -> Item {
-        let overview_key =
-            EncryptedKey::new(Some(self.item_record.overview_key_id), self.overview_key.0);
-        let data_key = EncryptedKey::new(Some(self.item_record.item_key_id), self.data_key.0);
+    pub(crate) fn try_into_item(
+        self,
+        vault_key: VaultKey,
+        database: Database,
+    ) -> Result<Item, Error> {
+        let overview_key = self.overview_key.try_into_encrypted_key()?;
+        let data_key = self.data_key.try_into_encrypted_key()?;
 
         self.item_record
-            .into_item(overview_key, data_key, vault_key, database)
+            .try_into_item(overview_key, data_key, vault_key, database)
     }
 }
@@ -0,0 +1,1829 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use sqlx::{
+    ConnectOptions, Executor, Sqlite, SqliteConnection, SqlitePool, Transaction as SqlxTransaction,
+    sqlite::SqliteConnectOptions,
+};
+
+use super::record_types::{
+    AuditEntryRecord, AuditHeadRecord, ChangeRecord, EmergencyContactRecord,
+    EmergencyRequestRecord, EmergencyVaultKeyRecord, EncryptedKeyRecord, ItemCiphertextsRecord,
+    ItemPreviewRecord, ItemRecord, ItemRecordWithKeys, NewItemRecord, NewKeyRecord, ProfileRecord,
+    Snapshot, VaultPreviewRecord, VaultRecord, VaultShareRecord,
+};
+use super::{Backend, ChangeFeed, Repository, Transaction};
+use crate::Error;
+use crate::emergency::EmergencyRequestState;
+use crate::item::{ItemCursor, ItemSortKey};
+use crate::share::{SharePermission, VaultAccess};
+
+pub static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+/// The default backend, a SQLite database migrated with [`MIGRATOR`].
+#[derive(Debug, Clone)]
+pub struct SqliteBackend {
+    pool: SqlitePool,
+}
+
+impl SqliteBackend {
+    /// Opens the database at `path`, creating and migrating it as needed.
+    pub async fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::connect(path, true).await
+    }
+
+    /// Opens the database at `path`, failing if there is none. It is
+    /// migrated as needed.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::connect(path, false).await
+    }
+
+    async fn connect(path: impl AsRef<Path>, create: bool) -> Result<Self, Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(create);
+
+        let pool = SqlitePool::connect_with(options).await?;
+
+        MIGRATOR.run(&pool).await.map_err(sqlx::Error::from)?;
+
+        Ok(Self { pool })
+    }
+
+    /// Uses a pool whose database has already been migrated.
+    pub fn from_pool(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl Backend for SqliteBackend {
+    fn repository(&self) -> Box<dyn Repository + Sync> {
+        Box::new(SqliteRepository(self.pool.clone()))
+    }
+
+    async fn begin(&self) -> Result<Box<dyn Transaction>, Error> {
+        // take the write lock up front, a deferred transaction that reads
+        // before writing fails instead of waiting when another one commits
+        let transaction = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+
+        Ok(Box::new(SqliteRepository(transaction)))
+    }
+
+    async fn change_feed(&self) -> Result<Box<dyn ChangeFeed>, Error> {
+        let mut connection = self.pool.connect_options().connect().await?;
+
+        // a private in-memory database is only visible to the connection
+        // that created it, the feed's own connection opens an empty one
+        let schema_version = "PRAGMA schema_version";
+        let feed_version: i64 = sqlx::query_scalar(schema_version)
+            .fetch_one(&mut connection)
+            .await?;
+        let pool_version: i64 = sqlx::query_scalar(schema_version)
+            .fetch_one(&self.pool)
+            .await?;
+        if feed_version == 0 && pool_version != 0 {
+            return Err(Error::PrivateInMemoryDatabase);
+        }
+
+        Ok(Box::new(SqliteChangeFeed { connection }))
+    }
+
+    async fn export(&self) -> Result<Snapshot, Error> {
+        // reads in one transaction see a single commit
+        let mut transaction = self.pool.begin().await?;
+        let snapshot = export(&mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(snapshot)
+    }
+
+    async fn import(&self, snapshot: Snapshot) -> Result<(), Error> {
+        let mut transaction = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        // keys may be wrapped by keys with a larger id
+        sqlx::query("PRAGMA defer_foreign_keys = ON")
+            .execute(&mut *transaction)
+            .await?;
+        import(&mut transaction, &snapshot).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Something queries can be run on, the pool or a transaction.
+trait Connection: Send {
+    fn executor(&mut self) -> impl Executor<'_, Database = Sqlite>;
+}
+
+impl Connection for SqlitePool {
+    fn executor(&mut self) -> impl Executor<'_, Database = Sqlite> {
+        &*self
+    }
+}
+
+impl Connection for SqlxTransaction<'static, Sqlite> {
+    fn executor(&mut self) -> impl Executor<'_, Database = Sqlite> {
+        &mut **self
+    }
+}
+
+struct SqliteRepository<C>(C);
+
+/// Item previews matching `$filter` that come after the cursor, ordered by
+/// `$order`, which the listing indexes cover. `$value` is what the cursor
+/// holds for the sort key.
+macro_rules! item_previews {
+    (
+        $executor:expr,
+        $filter:literal,
+        $value:literal,
+        $after:literal,
+        $order:literal,
+        $vault_id:expr,
+        $after_value:expr,
+        $after_uuid:expr,
+        $limit:expr
+    ) => {
+        sqlx::query_as!(
+            ItemPreviewRecord,
+            r#"SELECT
+                 items.id,
+                 items.uuid as "uuid: Uuid",
+                 items.vault_id,
+                 vaults.uuid as "vault_uuid: Uuid",
+                 items.overview_format_version,
+                 items.overview_ciphertext,
+                 items.overview_nonce,
+                 items.created_at,
+                 items.updated_at,
+                 keys.id as 'overview_key_id',
+                 keys.format_version as 'overview_key_format_version',
+                 keys.encrypted_key as 'overview_key_encrypted_key',
+                 keys.nonce as 'overview_key_nonce',
+                 keys.parent_key_id as 'overview_key_parent_key_id',
+                 CAST("#
+                + $value
+                + r#" AS TEXT) as "sort_value!: String"
+             FROM items
+             INNER JOIN vaults ON vaults.id = items.vault_id
+             INNER JOIN keys ON keys.id = items.overview_key_id
+             WHERE "#
+                + $filter
+                + " AND "
+                + $after
+                + "
+             ORDER BY "
+                + $order
+                + "
+             LIMIT ?4",
+            $vault_id,
+            $after_value,
+            $after_uuid,
+            $limit
+        )
+        .fetch_all($executor)
+        .await?
+    };
+}
+
+#[async_trait]
+impl<C: Connection> Repository for SqliteRepository<C> {
+    async fn store_vault(&mut self, name: &str, key_id: i64) -> Result<VaultRecord, Error> {
+        let uuid = Uuid::now_v7();
+        let vault_record = sqlx::query_as!(
+            VaultRecord,
+            "INSERT INTO vaults(uuid, name, key_id) VALUES (?, ?, ?) RETURNING id, uuid as 'uuid: Uuid', name, key_id, access, shared_by, shared_vault_id as 'shared_vault_id: Uuid', share_generation, created_at, updated_at",
+            uuid,
+            name,
+            key_id
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(vault_record)
+    }
+
+    async fn find_vault(&mut self, id: i64) -> Result<Option<VaultRecord>, Error> {
+        let vault_record = sqlx::query_as!(VaultRecord, "SELECT id, uuid as 'uuid: Uuid', name, key_id, access, shared_by, shared_vault_id as 'shared_vault_id: Uuid', share_generation, created_at, updated_at FROM vaults WHERE id = ?", id)
+            .fetch_optional(self.0.executor())
+            .await?;
+
+        Ok(vault_record)
+    }
+
+    async fn find_vault_by_uuid(&mut self, uuid: Uuid) -> Result<Option<VaultRecord>, Error> {
+        let vault_record = sqlx::query_as!(VaultRecord, "SELECT id, uuid as 'uuid: Uuid', name, key_id, access, shared_by, shared_vault_id as 'shared_vault_id: Uuid', share_generation, created_at, updated_at FROM vaults WHERE uuid = ?", uuid)
+            .fetch_optional(self.0.executor())
+            .await?;
+
+        Ok(vault_record)
+    }
+
+    async fn store_shared_vault(
+        &mut self,
+        name: &str,
+        key_id: i64,
+        access: VaultAccess,
+        shared_by: &[u8],
+        shared_vault_id: Uuid,
+        share_generation: i64,
+    ) -> Result<VaultRecord, Error> {
+        let access = access.as_str();
+        let uuid = Uuid::now_v7();
+        let vault_record = sqlx::query_as!(
+            VaultRecord,
+            "INSERT INTO vaults(uuid, name, key_id, access, shared_by, shared_vault_id, share_generation)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             RETURNING id, uuid as 'uuid: Uuid', name, key_id, access, shared_by, shared_vault_id as 'shared_vault_id: Uuid', share_generation, created_at, updated_at",
+            uuid,
+            name,
+            key_id,
+            access,
+            shared_by,
+            shared_vault_id,
+            share_generation
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(vault_record)
+    }
+
+    async fn find_shared_vault(
+        &mut self,
+        shared_by: &[u8],
+        shared_vault_id: Uuid,
+    ) -> Result<Option<VaultRecord>, Error> {
+        let vault_record = sqlx::query_as!(
+            VaultRecord,
+            "SELECT id, uuid as 'uuid: Uuid', name, key_id, access, shared_by, shared_vault_id as 'shared_vault_id: Uuid', share_generation, created_at, updated_at FROM vaults WHERE shared_by = ? AND shared_vault_id = ?",
+            shared_by,
+            shared_vault_id
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(vault_record)
+    }
+
+    async fn update_vault(
+        &mut self,
+        id: i64,
+        name: &str,
+        key_id: i64,
+        access: VaultAccess,
+    ) -> Result<VaultRecord, Error> {
+        let access = access.as_str();
+        let vault_record = sqlx::query_as!(
+            VaultRecord,
+            "UPDATE vaults
+             SET name = ?, key_id = ?, access = ?, updated_at = CURRENT_TIMESTAMP
+             WHERE id = ?
+             RETURNING id, uuid as 'uuid: Uuid', name, key_id, access, shared_by, shared_vault_id as 'shared_vault_id: Uuid', share_generation, created_at, updated_at",
+            name,
+            key_id,
+            access,
+            id
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(vault_record)
+    }
+
+    async fn next_share_generation(&mut self, id: i64) -> Result<i64, Error> {
+        let generation = sqlx::query_scalar!(
+            "UPDATE vaults SET share_generation = share_generation + 1 WHERE id = ? RETURNING share_generation",
+            id
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        generation.ok_or(Error::VaultDoesNotExist)
+    }
+
+    async fn update_share_generation(&mut self, id: i64, generation: i64) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE vaults SET share_generation = ? WHERE id = ?",
+            generation,
+            id
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn store_vault_share(
+        &mut self,
+        vault_id: i64,
+        recipient_public_key: &[u8],
+        permission: SharePermission,
+    ) -> Result<VaultShareRecord, Error> {
+        let uuid = Uuid::now_v7();
+        let permission = permission.as_str();
+        let share_record = sqlx::query_as!(
+            VaultShareRecord,
+            "INSERT INTO vault_shares(uuid, vault_id, recipient_public_key, permission)
+             VALUES (?, ?, ?, ?)
+             RETURNING id, uuid as 'uuid: Uuid', vault_id, recipient_public_key, permission, created_at, revoked_at",
+            uuid,
+            vault_id,
+            recipient_public_key,
+            permission
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(share_record)
+    }
+
+    async fn list_vault_shares(&mut self, vault_id: i64) -> Result<Vec<VaultShareRecord>, Error> {
+        let share_records = sqlx::query_as!(
+            VaultShareRecord,
+            "SELECT id, uuid as 'uuid: Uuid', vault_id, recipient_public_key, permission, created_at, revoked_at
+             FROM vault_shares
+             WHERE vault_id = ? AND revoked_at IS NULL",
+            vault_id
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(share_records)
+    }
+
+    /// Returns whether an active share was revoked.
+    async fn revoke_vault_share(&mut self, vault_id: i64, share_id: Uuid) -> Result<bool, Error> {
+        let result = sqlx::query!(
+            "UPDATE vault_shares
+             SET revoked_at = CURRENT_TIMESTAMP
+             WHERE uuid = ? AND vault_id = ? AND revoked_at IS NULL",
+            share_id,
+            vault_id
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn store_emergency_contact(
+        &mut self,
+        public_key: &[u8],
+        wait_seconds: i64,
+    ) -> Result<EmergencyContactRecord, Error> {
+        let uuid = Uuid::now_v7();
+        let contact_record = sqlx::query_as!(
+            EmergencyContactRecord,
+            "INSERT INTO emergency_contacts(uuid, public_key, wait_seconds)
+             VALUES (?, ?, ?)
+             RETURNING id, uuid as 'uuid: Uuid', public_key, wait_seconds, created_at",
+            uuid,
+            public_key,
+            wait_seconds
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(contact_record)
+    }
+
+    async fn find_emergency_contact(
+        &mut self,
+        public_key: &[u8],
+    ) -> Result<Option<EmergencyContactRecord>, Error> {
+        let contact_record = sqlx::query_as!(
+            EmergencyContactRecord,
+            "SELECT id, uuid as 'uuid: Uuid', public_key, wait_seconds, created_at
+             FROM emergency_contacts
+             WHERE public_key = ?",
+            public_key
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(contact_record)
+    }
+
+    /// Lists the emergency contacts the key of a vault is sealed to.
+    async fn list_vault_emergency_contacts(
+        &mut self,
+        vault_id: i64,
+    ) -> Result<Vec<EmergencyContactRecord>, Error> {
+        let contact_records = sqlx::query_as!(
+            EmergencyContactRecord,
+            "SELECT
+                 emergency_contacts.id,
+                 emergency_contacts.uuid as 'uuid: Uuid',
+                 emergency_contacts.public_key,
+                 emergency_contacts.wait_seconds,
+                 emergency_contacts.created_at
+             FROM emergency_contacts
+             INNER JOIN emergency_vault_keys
+                 ON emergency_vault_keys.contact_id = emergency_contacts.id
+             WHERE emergency_vault_keys.vault_id = ?",
+            vault_id
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(contact_records)
+    }
+
+    /// Stores the key of a vault sealed to an emergency contact, replacing
+    /// the one sealed before.
+    async fn store_emergency_vault_key(
+        &mut self,
+        contact_id: i64,
+        vault_id: i64,
+        sealed_key: &str,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO emergency_vault_keys(contact_id, vault_id, sealed_key)
+             VALUES (?, ?, ?)
+             ON CONFLICT(contact_id, vault_id) DO UPDATE SET sealed_key = excluded.sealed_key",
+            contact_id,
+            vault_id,
+            sealed_key
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_emergency_vault_keys(
+        &mut self,
+        contact_id: i64,
+    ) -> Result<Vec<EmergencyVaultKeyRecord>, Error> {
+        let key_records = sqlx::query_as!(
+            EmergencyVaultKeyRecord,
+            "SELECT contact_id, vault_id, sealed_key FROM emergency_vault_keys WHERE contact_id = ?",
+            contact_id
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(key_records)
+    }
+
+    async fn store_emergency_request(
+        &mut self,
+        contact_id: i64,
+        requested_at: NaiveDateTime,
+    ) -> Result<EmergencyRequestRecord, Error> {
+        let uuid = Uuid::now_v7();
+        sqlx::query!(
+            "INSERT INTO emergency_requests(uuid, contact_id, requested_at)
+             VALUES (?, ?, ?)",
+            uuid,
+            contact_id,
+            requested_at
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        self.find_emergency_request(uuid)
+            .await?
+            .ok_or(Error::EmergencyRequestDoesNotExist)
+    }
+
+    async fn find_emergency_request(
+        &mut self,
+        uuid: Uuid,
+    ) -> Result<Option<EmergencyRequestRecord>, Error> {
+        let request_record = sqlx::query_as!(
+            EmergencyRequestRecord,
+            "SELECT
+                 emergency_requests.id,
+                 emergency_requests.uuid as 'uuid: Uuid',
+                 emergency_requests.contact_id,
+                 emergency_contacts.public_key,
+                 emergency_contacts.wait_seconds,
+                 emergency_requests.state,
+                 emergency_requests.requested_at,
+                 emergency_requests.decided_at
+             FROM emergency_requests
+             INNER JOIN emergency_contacts
+                 ON emergency_contacts.id = emergency_requests.contact_id
+             WHERE emergency_requests.uuid = ?",
+            uuid
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(request_record)
+    }
+
+    /// Finds the request of a contact that hasn't been denied.
+    async fn find_open_emergency_request(
+        &mut self,
+        contact_id: i64,
+    ) -> Result<Option<EmergencyRequestRecord>, Error> {
+        let request_record = sqlx::query_as!(
+            EmergencyRequestRecord,
+            "SELECT
+                 emergency_requests.id,
+                 emergency_requests.uuid as 'uuid: Uuid',
+                 emergency_requests.contact_id,
+                 emergency_contacts.public_key,
+                 emergency_contacts.wait_seconds,
+                 emergency_requests.state,
+                 emergency_requests.requested_at,
+                 emergency_requests.decided_at
+             FROM emergency_requests
+             INNER JOIN emergency_contacts
+                 ON emergency_contacts.id = emergency_requests.contact_id
+             WHERE emergency_requests.contact_id = ?
+                 AND emergency_requests.state != 'denied'",
+            contact_id
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(request_record)
+    }
+
+    async fn list_emergency_requests(&mut self) -> Result<Vec<EmergencyRequestRecord>, Error> {
+        let request_records = sqlx::query_as!(
+            EmergencyRequestRecord,
+            "SELECT
+                 emergency_requests.id,
+                 emergency_requests.uuid as 'uuid: Uuid',
+                 emergency_requests.contact_id,
+                 emergency_contacts.public_key,
+                 emergency_contacts.wait_seconds,
+                 emergency_requests.state,
+                 emergency_requests.requested_at,
+                 emergency_requests.decided_at
+             FROM emergency_requests
+             INNER JOIN emergency_contacts
+                 ON emergency_contacts.id = emergency_requests.contact_id
+             ORDER BY emergency_requests.requested_at"
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(request_records)
+    }
+
+    async fn update_emergency_request_state(
+        &mut self,
+        uuid: Uuid,
+        state: EmergencyRequestState,
+        decided_at: NaiveDateTime,
+    ) -> Result<EmergencyRequestRecord, Error> {
+        let state = state.as_str();
+        sqlx::query!(
+            "UPDATE emergency_requests SET state = ?, decided_at = ? WHERE uuid = ?",
+            state,
+            decided_at,
+            uuid
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        self.find_emergency_request(uuid)
+            .await?
+            .ok_or(Error::EmergencyRequestDoesNotExist)
+    }
+
+    async fn store_audit_entry(&mut self, entry: &AuditEntryRecord) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO audit_log(sequence, kind, occurred_at, format_version, ciphertext, nonce, hash)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            entry.sequence,
+            entry.kind,
+            entry.occurred_at,
+            entry.format_version,
+            entry.ciphertext,
+            entry.nonce,
+            entry.hash
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_last_audit_entry(&mut self) -> Result<Option<AuditEntryRecord>, Error> {
+        let entry_record = sqlx::query_as!(
+            AuditEntryRecord,
+            "SELECT sequence, kind, occurred_at, format_version, ciphertext, nonce, hash
+             FROM audit_log
+             ORDER BY sequence DESC
+             LIMIT 1"
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(entry_record)
+    }
+
+    async fn store_audit_head(&mut self, head: &AuditHeadRecord) -> Result<(), Error> {
+        sqlx::query!(
+            "INSERT INTO audit_log_head(id, sequence, hash, mac)
+             VALUES (1, ?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET
+                 sequence = excluded.sequence,
+                 hash = excluded.hash,
+                 mac = excluded.mac",
+            head.sequence,
+            head.hash,
+            head.mac
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn find_audit_head(&mut self) -> Result<Option<AuditHeadRecord>, Error> {
+        let head_record = sqlx::query_as!(
+            AuditHeadRecord,
+            "SELECT sequence, hash, mac
+             FROM audit_log_head"
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(head_record)
+    }
+
+    /// Lists entries in the order they were written, bounds that are `None`
+    /// don't filter.
+    async fn list_audit_entries(
+        &mut self,
+        since: Option<NaiveDateTime>,
+        until: Option<NaiveDateTime>,
+        kind: Option<&str>,
+    ) -> Result<Vec<AuditEntryRecord>, Error> {
+        let entry_records = sqlx::query_as!(
+            AuditEntryRecord,
+            "SELECT sequence, kind, occurred_at, format_version, ciphertext, nonce, hash
+             FROM audit_log
+             WHERE (? IS NULL OR occurred_at >= ?)
+                 AND (? IS NULL OR occurred_at < ?)
+                 AND (? IS NULL OR kind = ?)
+             ORDER BY sequence",
+            since,
+            since,
+            until,
+            until,
+            kind,
+            kind
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(entry_records)
+    }
+
+    async fn store_key(&mut self, key: &NewKeyRecord) -> Result<EncryptedKeyRecord, Error> {
+        let uuid = Uuid::now_v7();
+        let key_record = sqlx::query_as!(
+            EncryptedKeyRecord,
+            "INSERT INTO keys(uuid, format_version, encrypted_key, nonce, parent_key_id)
+             VALUES (?, ?, ?, ?, ?)
+             RETURNING id, uuid as 'uuid: Uuid', format_version, encrypted_key, nonce, parent_key_id",
+            uuid,
+            key.format_version,
+            key.encrypted_key,
+            key.nonce,
+            key.parent_key_id,
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(key_record)
+    }
+
+    async fn find_key(&mut self, key_id: i64) -> Result<Option<EncryptedKeyRecord>, Error> {
+        let key_record = sqlx::query_as!(
+            EncryptedKeyRecord,
+            "SELECT id, uuid as 'uuid: Uuid', format_version, encrypted_key, nonce, parent_key_id
+             FROM keys
+             WHERE id = ?",
+            key_id
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(key_record)
+    }
+
+    async fn list_child_keys(
+        &mut self,
+        parent_key_id: i64,
+    ) -> Result<Vec<EncryptedKeyRecord>, Error> {
+        let key_records = sqlx::query_as!(
+            EncryptedKeyRecord,
+            "SELECT id, uuid as 'uuid: Uuid', format_version, encrypted_key, nonce, parent_key_id
+             FROM keys
+             WHERE parent_key_id = ?",
+            parent_key_id
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(key_records)
+    }
+
+    async fn update_key(&mut self, key_id: i64, key: &NewKeyRecord) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE keys
+             SET format_version = ?, encrypted_key = ?, nonce = ?, parent_key_id = ?
+             WHERE id = ?",
+            key.format_version,
+            key.encrypted_key,
+            key.nonce,
+            key.parent_key_id,
+            key_id
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn list_legacy_keys(&mut self) -> Result<Vec<EncryptedKeyRecord>, Error> {
+        let key_records = sqlx::query_as!(
+            EncryptedKeyRecord,
+            "SELECT id, uuid as 'uuid: Uuid', format_version, encrypted_key, nonce, parent_key_id
+             FROM keys
+             WHERE format_version = 0"
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(key_records)
+    }
+
+    async fn delete_key(&mut self, key_id: i64) -> Result<(), Error> {
+        sqlx::query!("DELETE FROM keys WHERE id = ?", key_id)
+            .execute(self.0.executor())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_profile(&mut self) -> Result<Option<ProfileRecord>, Error> {
+        let profile = sqlx::query_as!(
+            ProfileRecord,
+            "SELECT
+                 id,
+                 uuid as 'uuid: Uuid',
+                 name,
+                 salt,
+                 key_id,
+                 agreement_key_id,
+                 agreement_public_key,
+                 signing_key_id,
+                 verifying_key,
+                 created_at,
+                 updated_at
+             FROM profiles
+             WHERE id = 1"
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        Ok(profile)
+    }
+
+    async fn store_profile(
+        &mut self,
+        name: &str,
+        salt: &str,
+        key_id: i64,
+    ) -> Result<ProfileRecord, Error> {
+        let uuid = Uuid::now_v7();
+        let profile = sqlx::query_as!(
+            ProfileRecord,
+            "INSERT INTO profiles(uuid, name, salt, key_id)
+             VALUES (?, ?, ?, ?)
+             RETURNING
+                 id,
+                 uuid as 'uuid: Uuid',
+                 name,
+                 salt,
+                 key_id,
+                 agreement_key_id,
+                 agreement_public_key,
+                 signing_key_id,
+                 verifying_key,
+                 created_at,
+                 updated_at",
+            uuid,
+            name,
+            salt,
+            key_id
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(profile)
+    }
+
+    async fn store_profile_key_pairs(
+        &mut self,
+        profile_id: i64,
+        agreement_key_id: i64,
+        agreement_public_key: &[u8],
+        signing_key_id: i64,
+        verifying_key: &[u8],
+    ) -> Result<ProfileRecord, Error> {
+        let profile = sqlx::query_as!(
+            ProfileRecord,
+            "UPDATE profiles
+             SET
+                 agreement_key_id = ?,
+                 agreement_public_key = ?,
+                 signing_key_id = ?,
+                 verifying_key = ?,
+                 updated_at = CURRENT_TIMESTAMP
+             WHERE id = ?
+             RETURNING
+                 id,
+                 uuid as 'uuid: Uuid',
+                 name,
+                 salt,
+                 key_id,
+                 agreement_key_id,
+                 agreement_public_key,
+                 signing_key_id,
+                 verifying_key,
+                 created_at,
+                 updated_at",
+            agreement_key_id,
+            agreement_public_key,
+            signing_key_id,
+            verifying_key,
+            profile_id
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(profile)
+    }
+
+    async fn list_vault_previews(&mut self) -> Result<Vec<VaultPreviewRecord>, Error> {
+        let vault_overview_records = sqlx::query_as!(
+            VaultPreviewRecord,
+            "SELECT id, uuid as 'uuid: Uuid', name FROM vaults"
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(vault_overview_records)
+    }
+
+    async fn store_item(&mut self, item: &NewItemRecord) -> Result<ItemRecord, Error> {
+        let uuid = Uuid::now_v7();
+        let item_record = sqlx::query_as!(
+            ItemRecord,
+            "INSERT INTO items(
+                 uuid,
+                 vault_id,
+                 overview_format_version,
+                 overview_ciphertext,
+                 overview_nonce,
+                 overview_key_id,
+                 item_format_version,
+                 item_ciphertext,
+                 item_nonce,
+                 item_key_id
+             )
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             RETURNING
+                 id,
+                 uuid as 'uuid: Uuid',
+                 vault_id,
+                 overview_format_version,
+                 overview_ciphertext,
+                 overview_nonce,
+                 overview_key_id,
+                 item_format_version,
+                 item_ciphertext,
+                 item_nonce,
+                 item_key_id,
+                 created_at,
+                 updated_at",
+            uuid,
+            item.vault_id,
+            item.overview_format_version,
+            item.overview_ciphertext,
+            item.overview_nonce,
+            item.overview_key_id,
+            item.item_format_version,
+            item.item_ciphertext,
+            item.item_nonce,
+            item.item_key_id,
+        )
+        .fetch_one(self.0.executor())
+        .await?;
+
+        Ok(item_record)
+    }
+
+    async fn find_item(&mut self, vault_id: i64, uuid: Uuid) -> Result<ItemRecordWithKeys, Error> {
+        let record = sqlx::query!(
+            "SELECT
+                 items.id,
+                 items.uuid as 'uuid: Uuid',
+                 items.vault_id,
+                 items.overview_format_version,
+                 items.overview_ciphertext,
+                 items.overview_nonce,
+                 items.item_format_version,
+                 items.item_ciphertext,
+                 items.item_nonce,
+                 items.created_at,
+                 items.updated_at,
+                 overview_keys.id as 'overview_key_id',
+                 overview_keys.uuid as 'overview_key_uuid: Uuid',
+                 overview_keys.format_version as 'overview_key_format_version',
+                 overview_keys.encrypted_key as 'overview_key_encrypted_key',
+                 overview_keys.nonce as 'overview_key_nonce',
+                 overview_keys.parent_key_id as 'overview_key_parent_key_id',
+                 data_keys.id as 'item_key_id',
+                 data_keys.uuid as 'data_key_uuid: Uuid',
+                 data_keys.format_version as 'data_key_format_version',
+                 data_keys.encrypted_key as 'data_key_encrypted_key',
+                 data_keys.nonce as 'data_key_nonce',
+                 data_keys.parent_key_id as 'data_key_parent_key_id'
+             FROM items
+             INNER JOIN keys AS overview_keys ON overview_keys.id = items.overview_key_id
+             INNER JOIN keys AS data_keys ON data_keys.id = items.item_key_id
+             WHERE items.vault_id = ? AND items.uuid = ?",
+            vault_id,
+            uuid
+        )
+        .fetch_optional(self.0.executor())
+        .await?
+        .ok_or(Error::ItemDoesNotExist)?;
+
+        let item_record_with_keys = ItemRecordWithKeys {
+            item_record: ItemRecord {
+                id: record.id,
+                uuid: record.uuid,
+                vault_id: record.vault_id,
+                overview_format_version: record.overview_format_version,
+                overview_ciphertext: record.overview_ciphertext,
+                overview_nonce: record.overview_nonce,
+                overview_key_id: record.overview_key_id,
+                item_format_version: record.item_format_version,
+                item_ciphertext: record.item_ciphertext,
+                item_nonce: record.item_nonce,
+                item_key_id: record.item_key_id,
+                created_at: record.created_at,
+                updated_at: record.updated_at,
+            },
+            overview_key: EncryptedKeyRecord {
+                id: record.overview_key_id,
+                uuid: record.overview_key_uuid,
+                format_version: record.overview_key_format_version,
+                encrypted_key: record.overview_key_encrypted_key,
+                nonce: record.overview_key_nonce,
+                parent_key_id: record.overview_key_parent_key_id,
+            },
+            data_key: EncryptedKeyRecord {
+                id: record.item_key_id,
+                uuid: record.data_key_uuid,
+                format_version: record.data_key_format_version,
+                encrypted_key: record.data_key_encrypted_key,
+                nonce: record.data_key_nonce,
+                parent_key_id: record.data_key_parent_key_id,
+            },
+        };
+
+        Ok(item_record_with_keys)
+    }
+
+    /// Lists item previews ordered by `sort_key` and UUID, starting after
+    /// `after`. Every vault's items are listed without a `vault_id`.
+    async fn list_item_previews(
+        &mut self,
+        vault_id: Option<i64>,
+        sort_key: ItemSortKey,
+        after: Option<&ItemCursor>,
+        limit: Option<u32>,
+    ) -> Result<Vec<ItemPreviewRecord>, Error> {
+        // without a cursor, start before anything a page can end on
+        let after_value = after.map_or("", |cursor| cursor.sort_value());
+        let after_uuid = after.map_or(Uuid::nil(), |cursor| cursor.id());
+        // a negative limit is no limit
+        let limit = limit.map_or(-1, i64::from);
+        let executor = self.0.executor();
+        let item_preview_records = match (vault_id, sort_key) {
+            (Some(vault_id), ItemSortKey::Id) => item_previews!(
+                executor,
+                "items.vault_id = ?1",
+                "''",
+                "items.uuid > ?3",
+                "items.uuid",
+                vault_id,
+                after_value,
+                after_uuid,
+                limit
+            ),
+            (Some(vault_id), ItemSortKey::CreatedAt) => item_previews!(
+                executor,
+                "items.vault_id = ?1",
+                "items.created_at",
+                "(items.created_at, items.uuid) > (?2, ?3)",
+                "items.created_at, items.uuid",
+                vault_id,
+                after_value,
+                after_uuid,
+                limit
+            ),
+            (Some(vault_id), ItemSortKey::UpdatedAt) => item_previews!(
+                executor,
+                "items.vault_id = ?1",
+                "items.updated_at",
+                "(items.updated_at, items.uuid) > (?2, ?3)",
+                "items.updated_at, items.uuid",
+                vault_id,
+                after_value,
+                after_uuid,
+                limit
+            ),
+            (None, ItemSortKey::Id) => item_previews!(
+                executor,
+                "?1 IS NULL",
+                "''",
+                "items.uuid > ?3",
+                "items.uuid",
+                vault_id,
+                after_value,
+                after_uuid,
+                limit
+            ),
+            (None, ItemSortKey::CreatedAt) => item_previews!(
+                executor,
+                "?1 IS NULL",
+                "items.created_at",
+                "(items.created_at, items.uuid) > (?2, ?3)",
+                "items.created_at, items.uuid",
+                vault_id,
+                after_value,
+                after_uuid,
+                limit
+            ),
+            (None, ItemSortKey::UpdatedAt) => item_previews!(
+                executor,
+                "?1 IS NULL",
+                "items.updated_at",
+                "(items.updated_at, items.uuid) > (?2, ?3)",
+                "items.updated_at, items.uuid",
+                vault_id,
+                after_value,
+                after_uuid,
+                limit
+            ),
+        };
+
+        Ok(item_preview_records)
+    }
+
+    /// Deletes an item of a vault along with its keys, returning its id if it
+    /// existed.
+    async fn delete_item(&mut self, vault_id: i64, uuid: Uuid) -> Result<bool, Error> {
+        let deleted = sqlx::query!(
+            "DELETE FROM items WHERE uuid = ? AND vault_id = ? RETURNING overview_key_id, item_key_id",
+            uuid,
+            vault_id
+        )
+        .fetch_optional(self.0.executor())
+        .await?;
+
+        match deleted {
+            Some(record) => {
+                self.delete_key(record.overview_key_id).await?;
+                self.delete_key(record.item_key_id).await?;
+
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Deletes the items of a vault along with their keys.
+    async fn list_legacy_items(&mut self) -> Result<Vec<ItemRecord>, Error> {
+        let item_records = sqlx::query_as!(
+            ItemRecord,
+            "SELECT
+                 id,
+                 uuid as 'uuid: Uuid',
+                 vault_id,
+                 overview_format_version,
+                 overview_ciphertext,
+                 overview_nonce,
+                 overview_key_id,
+                 item_format_version,
+                 item_ciphertext,
+                 item_nonce,
+                 item_key_id,
+                 created_at,
+                 updated_at
+             FROM items
+             WHERE overview_format_version = 0 OR item_format_version = 0"
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        Ok(item_records)
+    }
+
+    async fn update_item_ciphertexts(
+        &mut self,
+        id: i64,
+        item: &ItemCiphertextsRecord,
+    ) -> Result<(), Error> {
+        sqlx::query!(
+            "UPDATE items
+             SET overview_format_version = ?, overview_ciphertext = ?, overview_nonce = ?,
+                 item_format_version = ?, item_ciphertext = ?, item_nonce = ?
+             WHERE id = ?",
+            item.overview_format_version,
+            item.overview_ciphertext,
+            item.overview_nonce,
+            item.item_format_version,
+            item.item_ciphertext,
+            item.item_nonce,
+            id
+        )
+        .execute(self.0.executor())
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_vault_items(&mut self, vault_id: i64) -> Result<(), Error> {
+        let deleted = sqlx::query!(
+            "DELETE FROM items WHERE vault_id = ? RETURNING overview_key_id, item_key_id",
+            vault_id
+        )
+        .fetch_all(self.0.executor())
+        .await?;
+
+        for record in deleted {
+            self.delete_key(record.overview_key_id).await?;
+            self.delete_key(record.item_key_id).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transaction for SqliteRepository<SqlxTransaction<'static, Sqlite>> {
+    async fn commit(self: Box<Self>) -> Result<(), Error> {
+        self.0.commit().await?;
+
+        Ok(())
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<(), Error> {
+        self.0.rollback().await?;
+
+        Ok(())
+    }
+}
+
+/// A connection of its own, `PRAGMA data_version` only changes for commits
+/// made by other connections.
+#[derive(Debug)]
+struct SqliteChangeFeed {
+    connection: SqliteConnection,
+}
+
+#[async_trait]
+impl ChangeFeed for SqliteChangeFeed {
+    async fn data_version(&mut self) -> Result<i64, Error> {
+        let data_version = sqlx::query_scalar("PRAGMA data_version")
+            .fetch_one(&mut self.connection)
+            .await?;
+
+        Ok(data_version)
+    }
+
+    async fn find_last_change_sequence(&mut self) -> Result<i64, Error> {
+        let sequence = sqlx::query_scalar!("SELECT MAX(sequence) FROM change_log")
+            .fetch_one(&mut self.connection)
+            .await?;
+
+        Ok(sequence.unwrap_or_default())
+    }
+
+    async fn list_changes(&mut self, after: i64) -> Result<Vec<ChangeRecord>, Error> {
+        let change_records = sqlx::query_as!(
+            ChangeRecord,
+            "SELECT sequence, kind, vault_id as 'vault_id: Uuid', item_id as 'item_id: Uuid' FROM change_log
+             WHERE sequence > ?
+             ORDER BY sequence",
+            after
+        )
+        .fetch_all(&mut self.connection)
+        .await?;
+
+        Ok(change_records)
+    }
+}
+
+async fn export(connection: &mut SqliteConnection) -> Result<Snapshot, Error> {
+    let profiles = sqlx::query_as!(
+        ProfileRecord,
+        "SELECT
+             id,
+             uuid as 'uuid: Uuid',
+             name,
+             salt,
+             key_id,
+             agreement_key_id,
+             agreement_public_key,
+             signing_key_id,
+             verifying_key,
+             created_at,
+             updated_at
+         FROM profiles
+         ORDER BY id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let keys = sqlx::query_as!(
+        EncryptedKeyRecord,
+        "SELECT id, uuid as 'uuid: Uuid', format_version, encrypted_key, nonce, parent_key_id
+         FROM keys
+         ORDER BY id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let vaults = sqlx::query_as!(
+        VaultRecord,
+        "SELECT id, uuid as 'uuid: Uuid', name, key_id, access, shared_by, shared_vault_id as 'shared_vault_id: Uuid', share_generation, created_at, updated_at
+         FROM vaults
+         ORDER BY id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let vault_shares = sqlx::query_as!(
+        VaultShareRecord,
+        "SELECT id, uuid as 'uuid: Uuid', vault_id, recipient_public_key, permission, created_at, revoked_at
+         FROM vault_shares
+         ORDER BY id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let emergency_contacts = sqlx::query_as!(
+        EmergencyContactRecord,
+        "SELECT id, uuid as 'uuid: Uuid', public_key, wait_seconds, created_at
+         FROM emergency_contacts
+         ORDER BY id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let emergency_vault_keys = sqlx::query_as!(
+        EmergencyVaultKeyRecord,
+        "SELECT contact_id, vault_id, sealed_key
+         FROM emergency_vault_keys
+         ORDER BY contact_id, vault_id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let emergency_requests = sqlx::query_as!(
+        EmergencyRequestRecord,
+        "SELECT
+             emergency_requests.id,
+             emergency_requests.uuid as 'uuid: Uuid',
+             emergency_requests.contact_id,
+             emergency_contacts.public_key,
+             emergency_contacts.wait_seconds,
+             emergency_requests.state,
+             emergency_requests.requested_at,
+             emergency_requests.decided_at
+         FROM emergency_requests
+         INNER JOIN emergency_contacts
+             ON emergency_contacts.id = emergency_requests.contact_id
+         ORDER BY emergency_requests.id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let audit_log = sqlx::query_as!(
+        AuditEntryRecord,
+        "SELECT sequence, kind, occurred_at, format_version, ciphertext, nonce, hash
+         FROM audit_log
+         ORDER BY sequence"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+    let audit_head = sqlx::query_as!(
+        AuditHeadRecord,
+        "SELECT sequence, hash, mac
+         FROM audit_log_head"
+    )
+    .fetch_optional(&mut *connection)
+    .await?;
+    let items = sqlx::query_as!(
+        ItemRecord,
+        "SELECT
+             id,
+             uuid as 'uuid: Uuid',
+             vault_id,
+             overview_format_version,
+             overview_ciphertext,
+             overview_nonce,
+             overview_key_id,
+             item_format_version,
+             item_ciphertext,
+             item_nonce,
+             item_key_id,
+             created_at,
+             updated_at
+         FROM items
+         ORDER BY id"
+    )
+    .fetch_all(&mut *connection)
+    .await?;
+
+    Ok(Snapshot {
+        profiles,
+        keys,
+        vaults,
+        vault_shares,
+        emergency_contacts,
+        emergency_vault_keys,
+        emergency_requests,
+        audit_log,
+        audit_head,
+        items,
+    })
+}
+
+async fn import(connection: &mut SqliteConnection, snapshot: &Snapshot) -> Result<(), Error> {
+    for key in &snapshot.keys {
+        sqlx::query!(
+            "INSERT INTO keys(id, uuid, format_version, encrypted_key, nonce, parent_key_id)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            key.id,
+            key.uuid,
+            key.format_version,
+            key.encrypted_key,
+            key.nonce,
+            key.parent_key_id
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for profile in &snapshot.profiles {
+        sqlx::query!(
+            "INSERT INTO profiles(
+                 id,
+                 uuid,
+                 name,
+                 salt,
+                 key_id,
+                 agreement_key_id,
+                 agreement_public_key,
+                 signing_key_id,
+                 verifying_key,
+                 created_at,
+                 updated_at
+             )
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            profile.id,
+            profile.uuid,
+            profile.name,
+            profile.salt,
+            profile.key_id,
+            profile.agreement_key_id,
+            profile.agreement_public_key,
+            profile.signing_key_id,
+            profile.verifying_key,
+            profile.created_at,
+            profile.updated_at
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for vault in &snapshot.vaults {
+        sqlx::query!(
+            "INSERT INTO vaults(
+                 id,
+                 uuid,
+                 name,
+                 key_id,
+                 access,
+                 shared_by,
+                 shared_vault_id,
+                 share_generation,
+                 created_at,
+                 updated_at
+             )
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            vault.id,
+            vault.uuid,
+            vault.name,
+            vault.key_id,
+            vault.access,
+            vault.shared_by,
+            vault.shared_vault_id,
+            vault.share_generation,
+            vault.created_at,
+            vault.updated_at
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for share in &snapshot.vault_shares {
+        sqlx::query!(
+            "INSERT INTO vault_shares(
+                 id, uuid, vault_id, recipient_public_key, permission, created_at, revoked_at
+             )
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            share.id,
+            share.uuid,
+            share.vault_id,
+            share.recipient_public_key,
+            share.permission,
+            share.created_at,
+            share.revoked_at
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for contact in &snapshot.emergency_contacts {
+        sqlx::query!(
+            "INSERT INTO emergency_contacts(id, uuid, public_key, wait_seconds, created_at)
+             VALUES (?, ?, ?, ?, ?)",
+            contact.id,
+            contact.uuid,
+            contact.public_key,
+            contact.wait_seconds,
+            contact.created_at
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for vault_key in &snapshot.emergency_vault_keys {
+        sqlx::query!(
+            "INSERT INTO emergency_vault_keys(contact_id, vault_id, sealed_key) VALUES (?, ?, ?)",
+            vault_key.contact_id,
+            vault_key.vault_id,
+            vault_key.sealed_key
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for request in &snapshot.emergency_requests {
+        sqlx::query!(
+            "INSERT INTO emergency_requests(id, uuid, contact_id, state, requested_at, decided_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+            request.id,
+            request.uuid,
+            request.contact_id,
+            request.state,
+            request.requested_at,
+            request.decided_at
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for entry in &snapshot.audit_log {
+        sqlx::query!(
+            "INSERT INTO audit_log(sequence, kind, occurred_at, format_version, ciphertext, nonce, hash)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            entry.sequence,
+            entry.kind,
+            entry.occurred_at,
+            entry.format_version,
+            entry.ciphertext,
+            entry.nonce,
+            entry.hash
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    if let Some(head) = &snapshot.audit_head {
+        sqlx::query!(
+            "INSERT INTO audit_log_head(id, sequence, hash, mac)
+             VALUES (1, ?, ?, ?)",
+            head.sequence,
+            head.hash,
+            head.mac
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    for item in &snapshot.items {
+        sqlx::query!(
+            "INSERT INTO items(
+                 id,
+                 uuid,
+                 vault_id,
+                 overview_format_version,
+                 overview_ciphertext,
+                 overview_nonce,
+                 overview_key_id,
+                 item_format_version,
+                 item_ciphertext,
+                 item_nonce,
+                 item_key_id,
+                 created_at,
+                 updated_at
+             )
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            item.id,
+            item.uuid,
+            item.vault_id,
+            item.overview_format_version,
+            item.overview_ciphertext,
+            item.overview_nonce,
+            item.overview_key_id,
+            item.item_format_version,
+            item.item_ciphertext,
+            item.item_nonce,
+            item.item_key_id,
+            item.created_at,
+            item.updated_at
+        )
+        .execute(&mut *connection)
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Cipher, EncryptedData, SymmetricKey};
+    use crate::item::{ItemData, ItemOverview};
+    use cerberus_secret::ExposeSecret;
+    use chacha20poly1305::{
+        XChaCha20Poly1305,
+        aead::{Aead, AeadCore, KeyInit},
+    };
+    use rand::{RngCore, rngs::OsRng};
+    use serde::Serialize;
+
+    // encrypts `data` the way rows were written before the binary format,
+    // a JSON plaintext inside a JSON object with number arrays
+    fn legacy_encrypted_json<T: Serialize>(key: &[u8], data: &T, key_id: Option<i64>) -> String {
+        let cipher = XChaCha20Poly1305::new(key.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_vec(data).unwrap();
+        let enc_data = cipher.encrypt(&nonce, plaintext.as_slice()).unwrap();
+
+        serde_json::json!({
+            "enc_data": enc_data,
+            "nonce": nonce.as_slice(),
+            "key_id": key_id,
+            "_phantom": null,
+        })
+        .to_string()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn migrates_json_encrypted_data_in_place(pool: SqlitePool) {
+        let mut migrations = MIGRATOR.iter();
+        let initial_migration = migrations.next().unwrap();
+        sqlx::raw_sql(&initial_migration.sql)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let mut parent_key = [0u8; 32];
+        OsRng.fill_bytes(&mut parent_key);
+        let mut overview_key = [0u8; 32];
+        OsRng.fill_bytes(&mut overview_key);
+
+        let key_id: i64 =
+            sqlx::query_scalar("INSERT INTO keys(key_encrypted_data) VALUES (?) RETURNING id")
+                .bind(legacy_encrypted_json(
+                    &parent_key,
+                    &overview_key.to_vec(),
+                    None,
+                ))
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let vault_id: i64 =
+            sqlx::query_scalar("INSERT INTO vaults(name, key_id) VALUES ('vault', ?) RETURNING id")
+                .bind(key_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        sqlx::query(
+            "INSERT INTO items(
+                 vault_id,
+                 overview_encrypted_data,
+                 overview_key_id,
+                 item_encrypted_data,
+                 item_key_id
+             )
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(vault_id)
+        .bind(legacy_encrypted_json(
+            &overview_key,
+            &ItemOverview::new("name".into(), "site".into()),
+            None,
+        ))
+        .bind(key_id)
+        .bind(legacy_encrypted_json(
+            &overview_key,
+            &ItemData::new("secret".into()),
+            None,
+        ))
+        .bind(key_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        for migration in migrations {
+            sqlx::raw_sql(&migration.sql).execute(&pool).await.unwrap();
+        }
+
+        // existing rows are given a UUIDv7
+        let item_uuid: Uuid = sqlx::query_scalar("SELECT uuid FROM items")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(item_uuid.get_version_num(), 7);
+
+        let mut database = SqliteRepository(pool);
+        let parent_key = SymmetricKey::new(parent_key.to_vec().into(), None);
+
+        let key = database
+            .find_key(key_id)
+            .await
+            .unwrap()
+            .unwrap()
+            .try_into_encrypted_key()
+            .unwrap()
+            .try_to_symmetric_key(&parent_key)
+            .unwrap();
+        let item = database
+            .find_item(vault_id, item_uuid)
+            .await
+            .unwrap()
+            .item_record;
+        let enc_data = EncryptedData::<ItemData>::from_parts(
+            item.item_format_version,
+            item.item_ciphertext,
+            item.item_nonce,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            key.decrypt(&enc_data).unwrap().secret().expose_secret(),
+            "secret"
+        );
+
+        let previews = database
+            .list_item_previews(Some(vault_id), ItemSortKey::Id, None, None)
+            .await
+            .unwrap();
+        let preview = previews
+            .into_iter()
+            .next()
+            .unwrap()
+            .try_into_item_preview(&parent_key)
+            .unwrap();
+        assert_eq!(preview.id(), item_uuid);
+        assert_eq!(preview.overview().name(), "name");
+        assert_eq!(preview.overview().site(), "site");
+    }
+
+    async fn insert_legacy_key(
+        pool: &SqlitePool,
+        parent_key: &[u8],
+        parent_key_id: Option<i64>,
+    ) -> ([u8; 32], i64) {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let id = sqlx::query_scalar("INSERT INTO keys(key_encrypted_data) VALUES (?) RETURNING id")
+            .bind(legacy_encrypted_json(
+                parent_key,
+                &key.to_vec(),
+                parent_key_id,
+            ))
+            .fetch_one(pool)
+            .await
+            .unwrap();
+
+        (key, id)
+    }
+
+    async fn count_legacy_rows(pool: &SqlitePool) -> i64 {
+        sqlx::query_scalar(
+            "SELECT (SELECT count(*) FROM keys WHERE format_version = 0)
+                  + (SELECT count(*) FROM items
+                     WHERE overview_format_version = 0 OR item_format_version = 0)",
+        )
+        .fetch_one(pool)
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn reencrypts_legacy_rows_on_unlock(pool: SqlitePool) {
+        let mut migrations = MIGRATOR.iter();
+        let initial_migration = migrations.next().unwrap();
+        sqlx::raw_sql(&initial_migration.sql)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let salt = crate::generate_salt();
+        let derived_key = crate::hash_password(b"password", &salt);
+        let (master_key, master_key_id) =
+            insert_legacy_key(&pool, derived_key.expose_secret(), None).await;
+        sqlx::query("INSERT INTO profiles(name, salt, key_id) VALUES ('User', ?, ?)")
+            .bind(&salt)
+            .bind(master_key_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let (vault_key, vault_key_id) =
+            insert_legacy_key(&pool, &master_key, Some(master_key_id)).await;
+        let vault_id: i64 =
+            sqlx::query_scalar("INSERT INTO vaults(name, key_id) VALUES ('vault', ?) RETURNING id")
+                .bind(vault_key_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let (item_key, item_key_id) =
+            insert_legacy_key(&pool, &vault_key, Some(vault_key_id)).await;
+        sqlx::query(
+            "INSERT INTO items(
+                 vault_id,
+                 overview_encrypted_data,
+                 overview_key_id,
+                 item_encrypted_data,
+                 item_key_id
+             )
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(vault_id)
+        .bind(legacy_encrypted_json(
+            &item_key,
+            &ItemOverview::new("name".into(), "site".into()),
+            Some(item_key_id),
+        ))
+        .bind(item_key_id)
+        .bind(legacy_encrypted_json(
+            &item_key,
+            &ItemData::new("secret".into()),
+            Some(item_key_id),
+        ))
+        .bind(item_key_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        for migration in migrations {
+            sqlx::raw_sql(&migration.sql).execute(&pool).await.unwrap();
+        }
+        assert_eq!(count_legacy_rows(&pool).await, 4);
+
+        let store = crate::Store::from_pool(pool.clone()).unwrap();
+        store.unlock("password").await.unwrap();
+        assert_eq!(count_legacy_rows(&pool).await, 0);
+
+        let vault_uuid: Uuid = sqlx::query_scalar("SELECT uuid FROM vaults")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let item_uuid: Uuid = sqlx::query_scalar("SELECT uuid FROM items")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let mut vault = store.get_vault(vault_uuid).await.unwrap().unwrap();
+        let item = vault.get_item(item_uuid).await.unwrap();
+        assert_eq!(item.overview().unwrap().name(), "name");
+        assert_eq!(
+            item.data().await.unwrap().secret().expose_secret(),
+            "secret"
+        );
+
+        // the rewrapped master key still opens with the password
+        let store = crate::Store::from_pool(pool).unwrap();
+        store.unlock("password").await.unwrap();
+    }
+}
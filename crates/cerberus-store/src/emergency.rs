@@ -0,0 +1,159 @@
+use cerberus_crypto::asymmetric::{AgreementKey, AgreementPublicKey, SealedBox};
+use cerberus_secret::SecretSlice;
+use chrono::{DateTime, TimeDelta, Utc};
+use uuid::Uuid;
+
+use crate::Error;
+
+/// Where a request for emergency access stands. A pending request is granted
+/// once its waiting period has passed without it being denied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmergencyRequestState {
+    Pending,
+    Approved,
+    Denied,
+}
+
+impl EmergencyRequestState {
+    /// How it is written in records.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmergencyRequestState::Pending => "pending",
+            EmergencyRequestState::Approved => "approved",
+            EmergencyRequestState::Denied => "denied",
+        }
+    }
+
+    pub(crate) fn parse(state: &str) -> Result<Self, Error> {
+        match state {
+            "pending" => Ok(EmergencyRequestState::Pending),
+            "approved" => Ok(EmergencyRequestState::Approved),
+            "denied" => Ok(EmergencyRequestState::Denied),
+            _ => Err(Error::MalformedEmergencyAccess),
+        }
+    }
+}
+
+/// A profile that can ask for access to some of this profile's vaults.
+#[derive(Debug, Clone)]
+pub struct EmergencyContact {
+    id: Uuid,
+    public_key: AgreementPublicKey,
+    waiting_period: TimeDelta,
+    created_at: DateTime<Utc>,
+}
+
+impl EmergencyContact {
+    pub(crate) fn new(
+        id: Uuid,
+        public_key: AgreementPublicKey,
+        waiting_period: TimeDelta,
+        created_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            public_key,
+            waiting_period,
+            created_at,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn public_key(&self) -> &AgreementPublicKey {
+        &self.public_key
+    }
+
+    /// How long a request stays pending before access is granted.
+    pub fn waiting_period(&self) -> TimeDelta {
+        self.waiting_period
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+/// A request of an emergency contact for access to the vaults chosen for it.
+#[derive(Debug, Clone)]
+pub struct EmergencyRequest {
+    id: Uuid,
+    contact: AgreementPublicKey,
+    state: EmergencyRequestState,
+    requested_at: DateTime<Utc>,
+    available_at: DateTime<Utc>,
+    decided_at: Option<DateTime<Utc>>,
+}
+
+impl EmergencyRequest {
+    pub(crate) fn new(
+        id: Uuid,
+        contact: AgreementPublicKey,
+        state: EmergencyRequestState,
+        requested_at: DateTime<Utc>,
+        available_at: DateTime<Utc>,
+        decided_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            id,
+            contact,
+            state,
+            requested_at,
+            available_at,
+            decided_at,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn contact(&self) -> &AgreementPublicKey {
+        &self.contact
+    }
+
+    pub fn state(&self) -> EmergencyRequestState {
+        self.state
+    }
+
+    pub fn requested_at(&self) -> DateTime<Utc> {
+        self.requested_at
+    }
+
+    /// When access is granted if the request isn't approved or denied first.
+    pub fn available_at(&self) -> DateTime<Utc> {
+        self.available_at
+    }
+
+    /// When the owner approved or denied the request.
+    pub fn decided_at(&self) -> Option<DateTime<Utc>> {
+        self.decided_at
+    }
+
+    pub fn is_granted_at(&self, now: DateTime<Utc>) -> bool {
+        match self.state {
+            EmergencyRequestState::Pending => now >= self.available_at,
+            EmergencyRequestState::Approved => true,
+            EmergencyRequestState::Denied => false,
+        }
+    }
+}
+
+/// Seals a vault key to an emergency contact, as stored until it is claimed.
+pub(crate) fn seal_vault_key(
+    contact: &AgreementPublicKey,
+    vault_key: &SecretSlice<u8>,
+) -> Result<String, Error> {
+    Ok(serde_json::to_string(&contact.seal(vault_key)?)?)
+}
+
+pub(crate) fn open_vault_key(
+    agreement_key: &AgreementKey,
+    sealed_key: &str,
+) -> Result<SecretSlice<u8>, Error> {
+    let sealed_key: SealedBox<SecretSlice<u8>> = serde_json::from_str(sealed_key)?;
+
+    Ok(agreement_key.open(&sealed_key)?)
+}
@@ -0,0 +1,152 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+use crate::Error;
+use crate::database::ChangeFeed;
+use crate::lock::LockState;
+
+// how often the database is checked for commits
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A change to the store. Changes to vaults and items only carry their ids,
+/// what changed has to be fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreEvent {
+    VaultCreated {
+        vault_id: Uuid,
+    },
+    VaultRenamed {
+        vault_id: Uuid,
+    },
+    VaultDeleted {
+        vault_id: Uuid,
+    },
+    ItemCreated {
+        vault_id: Uuid,
+        item_id: Uuid,
+    },
+    ItemUpdated {
+        vault_id: Uuid,
+        item_id: Uuid,
+    },
+    ItemDeleted {
+        vault_id: Uuid,
+        item_id: Uuid,
+    },
+    Locked,
+    Unlocked,
+    /// Changes were pruned from the database before they were seen, anything
+    /// shown from the store should be fetched again.
+    Lagged,
+}
+
+/// The events returned by [`Store::subscribe`](crate::Store::subscribe).
+/// Ends if the database can no longer be read.
+#[derive(Debug)]
+pub struct Subscription {
+    events: ReceiverStream<StoreEvent>,
+}
+
+impl Stream for Subscription {
+    type Item = StoreEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<StoreEvent>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+pub(crate) async fn subscribe(
+    mut change_feed: Box<dyn ChangeFeed>,
+    mut lock_state: watch::Receiver<LockState>,
+) -> Result<Subscription, Error> {
+    // read before the changes, so a commit in between is caught by the next
+    // poll rather than missed
+    let data_version = change_feed.data_version().await?;
+    let last_sequence = change_feed.find_last_change_sequence().await?;
+    lock_state.mark_unchanged();
+
+    let (sender, receiver) = mpsc::channel(64);
+    tokio::spawn(async move {
+        let watcher = Watcher {
+            change_feed,
+            data_version,
+            last_sequence,
+            sender,
+        };
+        // there is no one to report an error to, the stream just ends
+        let _ = watcher.run(lock_state).await;
+    });
+
+    Ok(Subscription {
+        events: ReceiverStream::new(receiver),
+    })
+}
+
+struct Watcher {
+    change_feed: Box<dyn ChangeFeed>,
+    data_version: i64,
+    last_sequence: i64,
+    sender: mpsc::Sender<StoreEvent>,
+}
+
+impl Watcher {
+    async fn run(mut self, mut lock_state: watch::Receiver<LockState>) -> Result<(), Error> {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut watching_lock_state = true;
+
+        loop {
+            tokio::select! {
+                _ = self.sender.closed() => return Ok(()),
+                _ = interval.tick() => self.poll_changes().await?,
+                changed = lock_state.changed(), if watching_lock_state => {
+                    if changed.is_err() {
+                        // the store is gone, its database may still change
+                        watching_lock_state = false;
+                        continue;
+                    }
+
+                    let event = match *lock_state.borrow_and_update() {
+                        LockState::Locked => StoreEvent::Locked,
+                        LockState::Unlocked => StoreEvent::Unlocked,
+                    };
+                    self.send(event).await;
+                }
+            }
+        }
+    }
+
+    async fn poll_changes(&mut self) -> Result<(), Error> {
+        let data_version = self.change_feed.data_version().await?;
+        if data_version == self.data_version {
+            return Ok(());
+        }
+        self.data_version = data_version;
+
+        let changes = self.change_feed.list_changes(self.last_sequence).await?;
+        // sequences are only skipped by pruning
+        if changes
+            .first()
+            .is_some_and(|change| change.sequence != self.last_sequence + 1)
+        {
+            self.send(StoreEvent::Lagged).await;
+        }
+
+        for change in changes {
+            self.last_sequence = change.sequence;
+            self.send(change.try_into_event()?).await;
+        }
+
+        Ok(())
+    }
+
+    async fn send(&mut self, event: StoreEvent) {
+        // fails once the subscription is dropped, which also stops `run`
+        let _ = self.sender.send(event).await;
+    }
+}
@@ -1,10 +1,33 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+
+use cerberus_secret::SecretString;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use tokio::sync::{Semaphore, mpsc};
+use tokio_stream::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use crate::{Error, crypto::EncryptedDataKeyPair, database::Database, vault::VaultKey};
+use crate::{
+    Error,
+    audit::AuditEvent,
+    crypto::{EncryptedData, EncryptedDataKeyPair, EncryptedKey, SymmetricKey},
+    database::{
+        Database,
+        record_types::{ItemPreviewRecord, ItemRecord},
+    },
+    vault::VaultKey,
+};
 
 pub struct Item {
-    id: i64,
+    uuid: Uuid,
     enc_overview: EncryptedDataKeyPair<ItemOverview>,
     enc_data: EncryptedDataKeyPair<ItemData>,
     created_at: DateTime<Utc>,
@@ -13,26 +36,36 @@ pub struct Item {
     database: Database,
 }
 
+#[derive(Debug, Clone)]
 pub struct ItemPreview {
-    id: i64,
+    id: Uuid,
+    vault_id: Uuid,
     overview: ItemOverview,
 }
 
 impl ItemPreview {
-    pub fn new(id: i64, overview: ItemOverview) -> Self {
-        Self { id, overview }
+    pub fn new(id: Uuid, vault_id: Uuid, overview: ItemOverview) -> Self {
+        Self {
+            id,
+            vault_id,
+            overview,
+        }
     }
 
-    pub fn id(&self) -> i64 {
+    pub fn id(&self) -> Uuid {
         self.id
     }
 
+    pub fn vault_id(&self) -> Uuid {
+        self.vault_id
+    }
+
     pub fn overview(&self) -> &ItemOverview {
         &self.overview
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct ItemOverview {
     name: String,
     site: String,
@@ -54,45 +87,321 @@ impl ItemOverview {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ItemData {
-    secret: String,
+    secret: SecretString,
 }
 
 impl ItemData {
     pub fn new(secret: String) -> Self {
-        Self { secret }
+        Self {
+            secret: SecretString::from(secret),
+        }
     }
 
-    pub fn secret(&self) -> &str {
+    pub fn secret(&self) -> &SecretString {
         &self.secret
     }
 }
 
 impl Item {
-    pub(crate) fn new(
-        id: i64,
-        enc_overview: EncryptedDataKeyPair<ItemOverview>,
-        enc_data: EncryptedDataKeyPair<ItemData>,
-        created_at: DateTime<Utc>,
-        updated_at: DateTime<Utc>,
+    pub(crate) fn from_record(
+        record: ItemRecord,
+        overview_key: EncryptedKey,
+        data_key: EncryptedKey,
         vault_key: VaultKey,
         database: Database,
-    ) -> Item {
-        Self {
-            id,
-            enc_overview,
-            enc_data,
-            created_at,
-            updated_at,
+    ) -> Result<Self, Error> {
+        let enc_overview = EncryptedData::from_parts(
+            record.overview_format_version,
+            record.overview_ciphertext,
+            record.overview_nonce,
+            Some(record.overview_key_id),
+        )?;
+        let enc_data = EncryptedData::from_parts(
+            record.item_format_version,
+            record.item_ciphertext,
+            record.item_nonce,
+            Some(record.item_key_id),
+        )?;
+
+        Ok(Self {
+            uuid: record.uuid,
+            enc_overview: EncryptedDataKeyPair::new(enc_overview, overview_key),
+            enc_data: EncryptedDataKeyPair::new(enc_data, data_key),
+            created_at: record.created_at.and_utc(),
+            updated_at: record.updated_at.and_utc(),
             vault_key,
             database,
-        }
+        })
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.uuid
     }
 
     pub fn overview(&self) -> Result<ItemOverview, Error> {
         self.enc_overview.decrypt(&self.vault_key)
     }
 
-    pub fn data(&self) -> Result<ItemData, Error> {
+    /// Decrypts the secret part of the item, which is recorded in the audit
+    /// log.
+    pub async fn data(&self) -> Result<ItemData, Error> {
+        let data = self.decrypt_data()?;
+        self.vault_key
+            .audit_log(self.database.clone())
+            .record(AuditEvent::ItemRevealed { item_id: self.uuid })
+            .await?;
+
+        Ok(data)
+    }
+
+    pub(crate) fn decrypt_data(&self) -> Result<ItemData, Error> {
         self.enc_data.decrypt(&self.vault_key)
     }
 }
+
+/// What item listings are ordered by. Overviews are encrypted, so only what
+/// the database can see is available.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemSortKey {
+    /// The order of the item UUIDs, which is the order the items were
+    /// created in.
+    #[default]
+    Id,
+    CreatedAt,
+    UpdatedAt,
+}
+
+/// Where a page of items ended. It can be serialized to hand to a client,
+/// and stays valid when items are added or removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ItemCursor {
+    sort_key: ItemSortKey,
+    sort_value: String,
+    // the UUID of the last item, which orders items with the same value
+    id: Uuid,
+}
+
+impl ItemCursor {
+    /// The sort value of the last item of the page, as a repository returned
+    /// it.
+    pub fn sort_value(&self) -> &str {
+        &self.sort_value
+    }
+
+    pub(crate) fn id(&self) -> Uuid {
+        self.id
+    }
+}
+
+/// Selects a page of item previews, the first 100 by id unless set
+/// otherwise.
+#[derive(Debug, Clone)]
+pub struct ItemQuery {
+    sort_key: ItemSortKey,
+    after: Option<ItemCursor>,
+    limit: u32,
+}
+
+impl Default for ItemQuery {
+    fn default() -> Self {
+        Self {
+            sort_key: ItemSortKey::default(),
+            after: None,
+            limit: 100,
+        }
+    }
+}
+
+impl ItemQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sort_by(self, sort_key: ItemSortKey) -> Self {
+        Self { sort_key, ..self }
+    }
+
+    /// Continues after the page `cursor` was returned with, in that page's
+    /// order.
+    pub fn after(self, cursor: ItemCursor) -> Self {
+        Self {
+            sort_key: cursor.sort_key,
+            after: Some(cursor),
+            ..self
+        }
+    }
+
+    pub fn limit(self, limit: u32) -> Self {
+        Self { limit, ..self }
+    }
+
+    pub(crate) fn sort_key(&self) -> ItemSortKey {
+        self.sort_key
+    }
+
+    pub(crate) fn cursor(&self) -> Option<&ItemCursor> {
+        self.after.as_ref()
+    }
+
+    pub(crate) fn page_size(&self) -> u32 {
+        self.limit
+    }
+}
+
+#[derive(Debug)]
+pub struct ItemPage {
+    items: Vec<ItemPreview>,
+    next: Option<ItemCursor>,
+}
+
+impl ItemPage {
+    /// Decrypts `records`, fetched for `query`, with the keys of their
+    /// vaults.
+    pub(crate) async fn from_records(
+        records: Vec<ItemPreviewRecord>,
+        query: &ItemQuery,
+        vault_keys: HashMap<i64, SymmetricKey>,
+        decrypt_permits: Arc<Semaphore>,
+    ) -> Result<Self, Error> {
+        // a short page is the last one
+        let next = match records.last() {
+            Some(last) if records.len() >= query.limit as usize => Some(ItemCursor {
+                sort_key: query.sort_key,
+                sort_value: last.sort_value.clone(),
+                id: last.uuid,
+            }),
+            _ => None,
+        };
+        let items = decrypt_previews(records, vault_keys, decrypt_permits).await?;
+
+        Ok(Self { items, next })
+    }
+
+    pub fn items(&self) -> &[ItemPreview] {
+        &self.items
+    }
+
+    pub fn into_items(self) -> Vec<ItemPreview> {
+        self.items
+    }
+
+    /// Passed to [`ItemQuery::after`] for the next page, `None` on the last
+    /// page.
+    pub fn next_cursor(&self) -> Option<&ItemCursor> {
+        self.next.as_ref()
+    }
+}
+
+/// Item previews fetched a page at a time as they are read.
+#[derive(Debug)]
+pub struct ItemStream {
+    items: ReceiverStream<Result<ItemPreview, Error>>,
+}
+
+impl ItemStream {
+    /// Reads the pages `fetch_page` returns for `query` and every page after
+    /// it. Stops at the first error.
+    pub(crate) fn new<F, Fut>(query: ItemQuery, mut fetch_page: F) -> Self
+    where
+        F: FnMut(ItemQuery) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<ItemPage, Error>> + Send,
+    {
+        let (sender, receiver) = mpsc::channel(query.limit.max(1) as usize);
+        tokio::spawn(async move {
+            let mut query = query;
+            loop {
+                let page = match fetch_page(query.clone()).await {
+                    Ok(page) => page,
+                    Err(err) => {
+                        let _ = sender.send(Err(err)).await;
+                        return;
+                    }
+                };
+
+                for item in page.items {
+                    if sender.send(Ok(item)).await.is_err() {
+                        return;
+                    }
+                }
+                match page.next {
+                    Some(cursor) => query = query.after(cursor),
+                    None => return,
+                }
+            }
+        });
+
+        Self {
+            items: ReceiverStream::new(receiver),
+        }
+    }
+}
+
+impl Stream for ItemStream {
+    type Item = Result<ItemPreview, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.items).poll_next(cx)
+    }
+}
+
+// below this, handing the work to other threads costs more than it saves
+const MIN_DECRYPT_BATCH: usize = 64;
+
+/// Decrypts item previews with the keys of their vaults, in batches on the
+/// blocking pool. A batch only starts once it holds one of `permits`, which
+/// the store's listings share.
+pub(crate) async fn decrypt_previews(
+    records: Vec<ItemPreviewRecord>,
+    vault_keys: HashMap<i64, SymmetricKey>,
+    permits: Arc<Semaphore>,
+) -> Result<Vec<ItemPreview>, Error> {
+    let decrypt = |records: Vec<ItemPreviewRecord>, vault_keys: &HashMap<i64, SymmetricKey>| {
+        records
+            .into_iter()
+            .map(|record| {
+                let vault_key = vault_keys
+                    .get(&record.vault_id)
+                    .ok_or(Error::KeyDoesNotExist)?;
+                record.try_into_item_preview(vault_key)
+            })
+            .collect::<Result<Vec<_>, Error>>()
+    };
+    if records.len() <= MIN_DECRYPT_BATCH {
+        return decrypt(records, &vault_keys);
+    }
+
+    let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let batch_size = records.len().div_ceil(workers).max(MIN_DECRYPT_BATCH);
+    let vault_keys = Arc::new(vault_keys);
+
+    let mut records = records.into_iter();
+    let mut batches = Vec::new();
+    loop {
+        let batch: Vec<_> = records.by_ref().take(batch_size).collect();
+        if batch.is_empty() {
+            break;
+        }
+
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        let vault_keys = vault_keys.clone();
+        batches.push(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            decrypt(batch, &vault_keys)
+        }));
+    }
+
+    let mut previews = Vec::new();
+    for batch in batches {
+        let batch = batch
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+        previews.extend(batch?);
+    }
+
+    Ok(previews)
+}
@@ -2,19 +2,31 @@ use argon2::{
     Argon2,
     password_hash::{PasswordHasher, Salt, SaltString},
 };
+use cerberus_secret::SecretSlice;
+use chrono::{DateTime, Utc};
 use rand::rngs::OsRng;
 
+pub mod audit;
+pub mod clock;
+pub mod database;
+pub mod emergency;
+pub mod events;
 pub mod item;
+pub mod lock;
+pub mod share;
 pub mod store;
+pub mod typed;
 pub mod vault;
 
+mod cache;
 mod crypto;
-mod database;
+mod upgrade;
 
 pub use store::*;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    #[cfg(feature = "sqlite")]
     #[error("unable to create database: {0}")]
     DatabaseError(#[from] sqlx::Error),
 
@@ -27,6 +39,18 @@ pub enum Error {
     #[error("malformed data in store")]
     DeserializationError(#[from] serde_json::Error),
 
+    #[error("malformed encrypted data in store")]
+    MalformedEncryptedData,
+
+    #[error("malformed profile key pair in store")]
+    MalformedKeyPair,
+
+    #[error("malformed vault in store")]
+    MalformedVault,
+
+    #[error(transparent)]
+    CipherError(#[from] cerberus_crypto::CipherError),
+
     #[error("incorrect symmetric key used for decryption")]
     IncorrectKey,
 
@@ -50,13 +74,76 @@ pub enum Error {
 
     #[error("incorrect password")]
     IncorrectPassword,
+
+    #[error("the vault is shared read-only")]
+    ReadOnlyVault,
+
+    #[error("only the owner of a vault can manage its shares")]
+    NotVaultOwner,
+
+    #[error("the share does not exist or was already revoked")]
+    ShareDoesNotExist,
+
+    #[error("invalid vault invitation")]
+    InvalidInvitation,
+
+    #[error("a newer invitation to this vault was already accepted")]
+    StaleInvitation,
+
+    #[error("vault does not exist")]
+    VaultDoesNotExist,
+
+    #[error("item does not exist")]
+    ItemDoesNotExist,
+
+    #[error("the audit log was tampered with at entry {0}")]
+    AuditLogTampered(i64),
+
+    #[error("malformed emergency access in store")]
+    MalformedEmergencyAccess,
+
+    #[error("the profile is already an emergency contact")]
+    EmergencyContactAlreadyExists,
+
+    #[error("the waiting period of an emergency contact must be at least a second")]
+    InvalidWaitingPeriod,
+
+    #[error("the profile is not an emergency contact")]
+    UnknownEmergencyContact,
+
+    #[error("the emergency access request does not exist")]
+    EmergencyRequestDoesNotExist,
+
+    #[error("the emergency access request is no longer pending")]
+    EmergencyRequestNotPending,
+
+    #[error("emergency access was denied")]
+    EmergencyAccessDenied,
+
+    #[error("emergency access is granted at {0}")]
+    EmergencyAccessPending(DateTime<Utc>),
+
+    #[error("malformed change log in store")]
+    MalformedChangeLog,
+
+    #[error("changes can't be followed in a private in-memory database, use a shared cache")]
+    PrivateInMemoryDatabase,
+
+    #[error("unable to access store files: {0}")]
+    FileError(#[from] std::io::Error),
+
+    #[error("malformed store directory")]
+    MalformedStoreDirectory,
+
+    #[error("a record being imported already exists in store")]
+    RecordAlreadyExists,
 }
 
 fn generate_salt() -> String {
     SaltString::generate(&mut OsRng).to_string()
 }
 
-fn hash_password(password: &[u8], salt: &str) -> Vec<u8> {
+fn hash_password(password: &[u8], salt: &str) -> SecretSlice<u8> {
     let salt = Salt::from_b64(salt).expect("salt is the correct format");
     let password_hash_data = Argon2::default().hash_password(password, salt).unwrap();
 
@@ -64,5 +151,5 @@ fn hash_password(password: &[u8], salt: &str) -> Vec<u8> {
         .hash
         .expect("hash_password was successful");
 
-    key.as_bytes().to_owned()
+    key.as_bytes().to_owned().into()
 }
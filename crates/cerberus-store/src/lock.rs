@@ -0,0 +1,113 @@
+use std::sync::{Arc, Weak};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use tokio::sync::watch;
+
+use crate::{clock::Clock, crypto::SecureKey};
+
+/// Whether the store's master key is available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockState {
+    Locked,
+    Unlocked,
+}
+
+/// When the store locks itself. Without a policy it stays unlocked until
+/// [`Store::lock`](crate::Store::lock) is called.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LockPolicy {
+    idle_timeout: Option<TimeDelta>,
+    max_unlocked: Option<TimeDelta>,
+}
+
+impl LockPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Locks once the master key hasn't been used for `idle_timeout`, every
+    /// use of the key starts the timeout over.
+    pub fn idle_timeout(self, idle_timeout: TimeDelta) -> Self {
+        Self {
+            idle_timeout: Some(idle_timeout),
+            ..self
+        }
+    }
+
+    /// Locks `max_unlocked` after unlocking, however much the key is used.
+    pub fn max_unlocked(self, max_unlocked: TimeDelta) -> Self {
+        Self {
+            max_unlocked: Some(max_unlocked),
+            ..self
+        }
+    }
+
+    /// When a key unlocked at `unlocked_at` and last used at `last_access`
+    /// has to be locked.
+    pub(crate) fn deadline(
+        &self,
+        unlocked_at: DateTime<Utc>,
+        last_access: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let idle_deadline = self.idle_timeout.map(|timeout| last_access + timeout);
+        let max_deadline = self.max_unlocked.map(|max| unlocked_at + max);
+
+        match (idle_deadline, max_deadline) {
+            (Some(idle), Some(max)) => Some(idle.min(max)),
+            (deadline, None) | (None, deadline) => deadline,
+        }
+    }
+}
+
+/// What a [`SecureKey`] needs to lock itself and tell everyone about it.
+#[derive(Debug, Clone)]
+pub(crate) struct AutoLock {
+    pub(crate) policy: LockPolicy,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) state: Arc<watch::Sender<LockState>>,
+}
+
+impl AutoLock {
+    pub(crate) fn broadcast(&self, state: LockState) {
+        self.state.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+
+            changed
+        });
+    }
+}
+
+/// Locks `master_key` when its policy says so, even if nothing uses it.
+/// Returns once the key is locked or dropped.
+pub(crate) async fn enforce_lock_policy(
+    master_key: Weak<SecureKey>,
+    clock: Arc<dyn Clock>,
+    mut state: watch::Receiver<LockState>,
+) {
+    loop {
+        let deadline = {
+            let Some(master_key) = master_key.upgrade() else {
+                return;
+            };
+
+            match master_key.lock_if_expired() {
+                Some(deadline) => deadline,
+                None => return,
+            }
+        };
+
+        // the deadline moves whenever the key is used, and the key may have
+        // been found expired and reported locked before it was cleared, so
+        // both are checked again at the top of the loop
+        let sleep = (deadline - clock.now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(sleep) => {}
+            changed = state.wait_for(|state| *state == LockState::Locked) => {
+                if changed.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use cerberus_crypto::asymmetric::{
+    AgreementKey, AgreementPublicKey, SealedBox, Signature, SigningKey, VerifyingKey,
+};
+use cerberus_secret::SecretSlice;
+use cerberus_serde::base64;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    Error,
+    item::{ItemData, ItemOverview},
+    store::profile_key_identifier,
+};
+
+const INVITATION_VERSION: u8 = 3;
+
+/// What a profile a vault is shared with is allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SharePermission {
+    ReadOnly,
+    ReadWrite,
+}
+
+impl SharePermission {
+    /// How it is written in records.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SharePermission::ReadOnly => "read_only",
+            SharePermission::ReadWrite => "read_write",
+        }
+    }
+
+    pub(crate) fn parse(permission: &str) -> Result<Self, Error> {
+        match permission {
+            "read_only" => Ok(SharePermission::ReadOnly),
+            "read_write" => Ok(SharePermission::ReadWrite),
+            _ => Err(Error::MalformedVault),
+        }
+    }
+}
+
+/// How this profile can access a vault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaultAccess {
+    Owner,
+    Shared(SharePermission),
+}
+
+impl VaultAccess {
+    pub fn can_write(&self) -> bool {
+        !matches!(self, VaultAccess::Shared(SharePermission::ReadOnly))
+    }
+
+    pub fn is_owner(&self) -> bool {
+        matches!(self, VaultAccess::Owner)
+    }
+
+    /// How it is written in records.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VaultAccess::Owner => "owner",
+            VaultAccess::Shared(permission) => permission.as_str(),
+        }
+    }
+
+    pub(crate) fn parse(access: &str) -> Result<Self, Error> {
+        match access {
+            "owner" => Ok(VaultAccess::Owner),
+            permission => SharePermission::parse(permission).map(VaultAccess::Shared),
+        }
+    }
+}
+
+/// A profile a vault has been shared with.
+#[derive(Debug, Clone)]
+pub struct Share {
+    id: Uuid,
+    recipient: AgreementPublicKey,
+    permission: SharePermission,
+    created_at: DateTime<Utc>,
+}
+
+impl Share {
+    pub(crate) fn new(
+        id: Uuid,
+        recipient: AgreementPublicKey,
+        permission: SharePermission,
+        created_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            recipient,
+            permission,
+            created_at,
+        }
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn recipient(&self) -> &AgreementPublicKey {
+        &self.recipient
+    }
+
+    pub fn permission(&self) -> SharePermission {
+        self.permission
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+}
+
+/// Everything the recipient needs to open a shared vault, only readable by
+/// the recipient.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct VaultShare {
+    pub(crate) name: String,
+    pub(crate) vault_key: SecretSlice<u8>,
+    pub(crate) permission: SharePermission,
+    pub(crate) items: Vec<SharedItem>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SharedItem {
+    pub(crate) overview: ItemOverview,
+    pub(crate) data: ItemData,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InvitationBody {
+    version: u8,
+    vault_id: Uuid,
+    /// Increases with every invitation the sender issues for the vault.
+    generation: i64,
+    sender: VerifyingKey,
+    recipient: AgreementPublicKey,
+    share: SealedBox<VaultShare>,
+}
+
+/// How an invitation is written: the body as the exact bytes that were
+/// signed.
+#[derive(Serialize, Deserialize)]
+struct SignedInvitation {
+    #[serde(with = "base64")]
+    body: Vec<u8>,
+    signature: Signature,
+}
+
+/// A vault shared with another profile, created by
+/// [`Vault::share_with`](crate::vault::Vault::share_with) and accepted with
+/// [`Store::accept_share`](crate::Store::accept_share).
+///
+/// The file is JSON. The vault key, name and items are sealed to the
+/// recipient's agreement key, and the serialized body is signed by the
+/// sender. The recipient checks the signature against the verifying key the
+/// sender handed out, not the one carried in the invitation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "SignedInvitation", into = "SignedInvitation")]
+pub struct VaultInvitation {
+    body: InvitationBody,
+    signed_body: Vec<u8>,
+    signature: Signature,
+}
+
+impl TryFrom<SignedInvitation> for VaultInvitation {
+    type Error = Error;
+
+    fn try_from(invitation: SignedInvitation) -> Result<Self, Self::Error> {
+        let body =
+            serde_json::from_slice(&invitation.body).map_err(|_| Error::InvalidInvitation)?;
+
+        Ok(Self {
+            body,
+            signed_body: invitation.body,
+            signature: invitation.signature,
+        })
+    }
+}
+
+impl From<VaultInvitation> for SignedInvitation {
+    fn from(invitation: VaultInvitation) -> Self {
+        Self {
+            body: invitation.signed_body,
+            signature: invitation.signature,
+        }
+    }
+}
+
+impl VaultInvitation {
+    pub(crate) fn seal(
+        signing_key: &SigningKey,
+        vault_id: Uuid,
+        generation: i64,
+        recipient: &AgreementPublicKey,
+        share: &VaultShare,
+    ) -> Result<Self, Error> {
+        let body = InvitationBody {
+            version: INVITATION_VERSION,
+            vault_id,
+            generation,
+            sender: signing_key.verifying_key(),
+            recipient: recipient.clone(),
+            share: recipient.seal(share)?,
+        };
+        let signed_body = serde_json::to_vec(&body)?;
+        let signature = signing_key.sign(&signed_body);
+
+        Ok(Self {
+            body,
+            signed_body,
+            signature,
+        })
+    }
+
+    /// Checks that the invitation is addressed to `agreement_key` and signed
+    /// by `sender` before opening it.
+    pub(crate) fn open(
+        &self,
+        agreement_key: &AgreementKey,
+        sender: &VerifyingKey,
+    ) -> Result<VaultShare, Error> {
+        if self.body.version != INVITATION_VERSION
+            || self.body.recipient != agreement_key.public_key()
+            || self.body.sender.as_bytes() != sender.as_bytes()
+        {
+            return Err(Error::InvalidInvitation);
+        }
+        sender
+            .verify(&self.signed_body, &self.signature)
+            .map_err(|_| Error::InvalidInvitation)?;
+
+        Ok(agreement_key.open(&self.body.share)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|_| Error::InvalidInvitation)
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    /// The verifying key of the profile that sent the invitation.
+    pub fn sender(&self) -> &VerifyingKey {
+        &self.body.sender
+    }
+
+    pub fn recipient(&self) -> &AgreementPublicKey {
+        &self.body.recipient
+    }
+
+    /// The id of the vault in the sender's store.
+    pub fn vault_id(&self) -> Uuid {
+        self.body.vault_id
+    }
+
+    /// Orders the invitations issued for the same vault.
+    pub fn generation(&self) -> i64 {
+        self.body.generation
+    }
+}
+
+pub(crate) fn recipient_public_key(key: Vec<u8>) -> Result<AgreementPublicKey, Error> {
+    let key = key.try_into().map_err(|_| Error::MalformedVault)?;
+
+    Ok(AgreementPublicKey::from_bytes(
+        key,
+        profile_key_identifier(&key),
+    ))
+}
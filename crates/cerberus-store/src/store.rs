@@ -1,16 +1,38 @@
 use crate::Error;
-use crate::crypto::{SecureKey, SecureKeyState, SymmetricKey};
-use crate::database::Database;
+use crate::audit::{AuditEntry, AuditEvent, AuditLog, AuditQuery};
+use crate::cache::OverviewCache;
+use crate::clock::{Clock, SystemClock};
+use crate::crypto::{Cipher, EncryptedKey, SecureKey, SecureKeyState, SymmetricKey};
 use crate::database::Repository;
+use crate::database::record_types::ProfileRecord;
+use crate::database::{Backend, Database};
+use crate::emergency::{self, EmergencyContact, EmergencyRequest, EmergencyRequestState};
+use crate::events::{self, Subscription};
 use crate::generate_salt;
-use crate::vault::{Vault, VaultKey, VaultPreview};
+use crate::item::{ItemPage, ItemQuery, ItemStream};
+use crate::lock::{self, AutoLock, LockPolicy, LockState};
+use crate::share::{SharePermission, VaultAccess, VaultInvitation};
+use crate::upgrade;
+use crate::vault::{self, Vault, VaultKey, VaultPreview};
+use cerberus_crypto::KeyIdentifier;
+use cerberus_crypto::NewKey;
+use cerberus_crypto::asymmetric::{AgreementKey, AgreementPublicKey, SigningKey, VerifyingKey};
+use cerberus_crypto::kdf::DeriveKey;
+use cerberus_secret::{ExposeSecretMut, SecretSlice};
 use chrono::DateTime;
+use chrono::TimeDelta;
 use chrono::Utc;
+use rand::RngCore;
 use rand::rngs::OsRng;
+#[cfg(feature = "sqlite")]
 use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+#[cfg(feature = "sqlite")]
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::Mutex;
+use tokio::sync::watch;
+use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct Profile {
@@ -18,6 +40,7 @@ pub struct Profile {
     name: String,
     salt: String,
     key_id: i64,
+    key_pairs: Option<ProfileKeyPairs>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -28,6 +51,7 @@ impl Profile {
         name: String,
         salt: String,
         key_id: i64,
+        key_pairs: Option<ProfileKeyPairs>,
         created_at: DateTime<Utc>,
         updated_at: DateTime<Utc>,
     ) -> Self {
@@ -36,150 +60,564 @@ impl Profile {
             name,
             salt,
             key_id,
+            key_pairs,
             created_at,
             updated_at,
         }
     }
+
+    pub(crate) fn key_pairs(&self) -> Result<&ProfileKeyPairs, Error> {
+        // profiles without key pairs get them when the store is unlocked
+        self.key_pairs.as_ref().ok_or(Error::Locked)
+    }
+}
+
+/// The profile's own key pairs. The private halves are stored in the keys
+/// table wrapped by the master key.
+#[derive(Debug)]
+pub(crate) struct ProfileKeyPairs {
+    pub(crate) agreement_key_id: i64,
+    pub(crate) agreement_public_key: AgreementPublicKey,
+    pub(crate) signing_key_id: i64,
+    pub(crate) verifying_key: VerifyingKey,
+}
+
+impl ProfileKeyPairs {
+    pub(crate) fn new(
+        agreement_key_id: i64,
+        agreement_public_key: [u8; 32],
+        signing_key_id: i64,
+        verifying_key: [u8; 32],
+    ) -> Self {
+        Self {
+            agreement_key_id,
+            agreement_public_key: AgreementPublicKey::from_bytes(
+                agreement_public_key,
+                profile_key_identifier(&agreement_public_key),
+            ),
+            signing_key_id,
+            verifying_key: VerifyingKey::from_bytes(
+                verifying_key,
+                profile_key_identifier(&verifying_key),
+            ),
+        }
+    }
+}
+
+// other profiles only know the public half of a key pair, so it is identified
+// by that
+pub(crate) fn profile_key_identifier(public_key: &[u8; 32]) -> KeyIdentifier {
+    KeyIdentifier::fingerprint(public_key)
+}
+
+/// Freshly generated key pairs that haven't been written yet.
+struct NewKeyPairs {
+    agreement_key: EncryptedKey,
+    agreement_public_key: AgreementPublicKey,
+    signing_key: EncryptedKey,
+    verifying_key: VerifyingKey,
+}
+
+impl NewKeyPairs {
+    fn generate(master_key: &impl Cipher) -> Result<Self, Error> {
+        let agreement_secret = generate_secret::<AgreementKey>();
+        let signing_secret = generate_secret::<SigningKey>();
+
+        // only used for their public halves, which get their identifier below
+        let agreement_public_key =
+            *AgreementKey::new(agreement_secret.clone(), KeyIdentifier::local())
+                .expect("secret is generated with the key size")
+                .public_key()
+                .as_bytes();
+        let verifying_key = *SigningKey::new(signing_secret.clone(), KeyIdentifier::local())
+            .expect("secret is generated with the key size")
+            .verifying_key()
+            .as_bytes();
+
+        Ok(Self {
+            agreement_key: EncryptedKey::from_secret(&agreement_secret, master_key)?,
+            agreement_public_key: AgreementPublicKey::from_bytes(
+                agreement_public_key,
+                profile_key_identifier(&agreement_public_key),
+            ),
+            signing_key: EncryptedKey::from_secret(&signing_secret, master_key)?,
+            verifying_key: VerifyingKey::from_bytes(
+                verifying_key,
+                profile_key_identifier(&verifying_key),
+            ),
+        })
+    }
+
+    async fn store<R: Repository + ?Sized>(
+        mut self,
+        repo: &mut R,
+        profile_id: i64,
+    ) -> Result<ProfileRecord, Error> {
+        self.agreement_key.store(repo).await?;
+        self.signing_key.store(repo).await?;
+
+        repo.store_profile_key_pairs(
+            profile_id,
+            self.agreement_key.id().unwrap(),
+            self.agreement_public_key.as_bytes(),
+            self.signing_key.id().unwrap(),
+            self.verifying_key.as_bytes(),
+        )
+        .await
+    }
+}
+
+/// Unwraps the private half of one of the profile's key pairs, giving it the
+/// identifier of its public half.
+pub(crate) async fn unwrap_profile_key<K: NewKey>(
+    database: &mut Database,
+    master_key: &SecureKey,
+    key_id: i64,
+    identifier: &KeyIdentifier,
+) -> Result<K, Error> {
+    let encrypted_key = database
+        .find_key(key_id)
+        .await?
+        .ok_or(Error::KeyDoesNotExist)?
+        .try_into_encrypted_key()?;
+    let secret = encrypted_key.try_to_secret(master_key)?;
+
+    K::new(secret, identifier.clone()).map_err(|_| Error::MalformedKeyPair)
+}
+
+fn generate_secret<K: NewKey>() -> SecretSlice<u8> {
+    // fill the secret allocation directly so no copy of the key is left
+    // on the stack
+    let mut secret = SecretSlice::from(vec![0u8; K::KEY_SIZE]);
+    OsRng.fill_bytes(secret.expose_secret_mut());
+
+    secret
+}
+
+/// What a key returned by [`Store::derived_key`] is used for. Every purpose
+/// yields a different key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPurpose {
+    SearchIndex,
+    Integrity,
+    Export,
+    DeviceBinding,
+}
+
+impl KeyPurpose {
+    // part of the derivation, changing a label changes the derived key
+    pub(crate) fn label(&self) -> &'static str {
+        match self {
+            KeyPurpose::SearchIndex => "search_index",
+            KeyPurpose::Integrity => "integrity",
+            KeyPurpose::Export => "export",
+            KeyPurpose::DeviceBinding => "device_binding",
+        }
+    }
 }
 
+/// State shared by every clone of a [`Store`]. The profile and master key are
+/// fetched the first time they are needed and then swapped as a whole, so
+/// reading them never waits on another request.
 #[derive(Debug)]
+struct Session {
+    profile: watch::Sender<Option<Arc<Profile>>>,
+    master_key: watch::Sender<Option<Arc<SecureKey>>>,
+    lock_state: Arc<watch::Sender<LockState>>,
+    // unlocking and initializing the profile happen one at a time
+    unlocking: tokio::sync::Mutex<()>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            profile: watch::Sender::new(None),
+            master_key: watch::Sender::new(None),
+            lock_state: Arc::new(watch::Sender::new(LockState::Locked)),
+            unlocking: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+/// A handle to a store. Clones share the same database and unlocked state,
+/// so one store can serve concurrent requests.
+#[derive(Debug, Clone)]
 pub struct Store {
     database: Database,
-    master_key: Option<Arc<Mutex<SecureKey>>>,
-    profile: Option<Profile>,
+    session: Arc<Session>,
+    clock: Arc<dyn Clock>,
+    lock_policy: LockPolicy,
+    overview_cache: Option<Arc<OverviewCache>>,
 }
 
 impl Store {
+    #[cfg(feature = "sqlite")]
     pub async fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
         Ok(Store {
             database: Database::new(path).await?,
-            master_key: None,
-            profile: None,
+            session: Arc::default(),
+            clock: Arc::new(SystemClock),
+            lock_policy: LockPolicy::default(),
+            overview_cache: None,
         })
     }
 
+    #[cfg(feature = "sqlite")]
     pub fn from_pool(pool: SqlitePool) -> Result<Self, Error> {
         Ok(Store {
             database: Database::from_pool(pool),
-            master_key: None,
-            profile: None,
+            session: Arc::default(),
+            clock: Arc::new(SystemClock),
+            lock_policy: LockPolicy::default(),
+            overview_cache: None,
         })
     }
 
-    pub async fn unlock(&mut self, password: &str) -> Result<(), Error> {
-        self.ensure_profile_retrieved().await?;
-        self.ensure_master_key_retrieved().await?;
+    /// A store kept in `backend` rather than a SQLite database, see
+    /// [`database`](crate::database).
+    pub fn from_backend(backend: impl Backend + 'static) -> Result<Self, Error> {
+        Ok(Store {
+            database: Database::from_backend(Arc::new(backend)),
+            session: Arc::default(),
+            clock: Arc::new(SystemClock),
+            lock_policy: LockPolicy::default(),
+            overview_cache: None,
+        })
+    }
 
-        let profile = self.profile.as_ref().unwrap();
-        let master_key = self.master_key.as_ref().unwrap();
+    /// Replaces the clock waiting periods and lock timeouts are measured
+    /// with.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
 
-        let mut master_key = master_key.lock().unwrap();
+    /// Sets when the store locks itself, takes effect the next time it is
+    /// unlocked.
+    pub fn with_lock_policy(self, lock_policy: LockPolicy) -> Self {
+        Self {
+            lock_policy,
+            ..self
+        }
+    }
 
-        match master_key.get_state() {
+    /// Keeps the decrypted previews of up to `capacity` items in memory, so
+    /// listing a vault again doesn't decrypt every overview. Previews are
+    /// dropped and zeroed when their vault changes and when the store is
+    /// locked. Takes effect the next time the store is unlocked.
+    pub fn with_overview_cache(self, capacity: usize) -> Self {
+        Self {
+            overview_cache: Some(Arc::new(OverviewCache::new(capacity))),
+            ..self
+        }
+    }
+
+    /// Follows the store being locked and unlocked, whether by
+    /// [`Store::lock`] or by its [`LockPolicy`].
+    pub fn lock_state(&self) -> watch::Receiver<LockState> {
+        self.session.lock_state.subscribe()
+    }
+
+    /// Streams changes to vaults and items made from now on, by this or any
+    /// other process using the same database, along with the store being
+    /// locked and unlocked.
+    pub async fn subscribe(&self) -> Result<Subscription, Error> {
+        let change_feed = self.database.change_feed().await?;
+
+        events::subscribe(change_feed, self.lock_state()).await
+    }
+
+    pub async fn unlock(&self, password: &str) -> Result<(), Error> {
+        let _unlocking = self.session.unlocking.lock().await;
+        let profile = self.ensure_profile_retrieved().await?;
+        let master_key = self.ensure_master_key_retrieved(&profile).await?;
+
+        let derived_key = match master_key.get_state() {
             SecureKeyState::Locked => {
-                let derived_key = SymmetricKey::from_password(password.as_bytes(), &profile.salt);
+                let derived_key =
+                    SymmetricKey::from_password_blocking(password, &profile.salt).await;
                 master_key.unlock(&derived_key)?;
-                Ok(())
+
+                derived_key
             }
-            SecureKeyState::Unlocked => Err(Error::StoreAlreadyUnlocked),
+            SecureKeyState::Unlocked => return Err(Error::StoreAlreadyUnlocked),
+        };
+
+        // these need the key, which is locked again if one of them fails so
+        // unlocking can be retried
+        let unlocked = async {
+            self.follow_changes().await?;
+            self.ensure_key_pairs_generated(&profile, &master_key)
+                .await?;
+            self.reencrypt_legacy_data(&profile, &master_key, derived_key)
+                .await?;
+            self.audit_log().record(AuditEvent::Unlocked).await
+        }
+        .await;
+        if let Err(err) = unlocked {
+            master_key.lock();
+            if let Some(cache) = &self.overview_cache {
+                cache.clear();
+            }
+
+            return Err(err);
         }
+        self.spawn_lock_policy(&master_key);
+
+        Ok(())
     }
 
-    pub fn lock(&mut self) -> Result<(), Error> {
-        match self.master_key.as_ref() {
-            Some(master_key_mutex) => {
-                let mut master_key = master_key_mutex.lock().unwrap();
-                match master_key.get_state() {
-                    SecureKeyState::Locked => Err(Error::Locked),
-                    SecureKeyState::Unlocked => {
-                        master_key.lock();
-                        Ok(())
-                    }
-                }
-            }
-            None => Err(Error::Locked),
+    pub fn is_locked(&self) -> bool {
+        self.master_key()
+            .map_or(true, |master_key| master_key.is_locked())
+    }
+
+    pub fn lock(&self) -> Result<(), Error> {
+        let master_key = self.master_key()?;
+        let state = master_key.get_state();
+        // clears the key even if it only expired
+        master_key.lock();
+        if let Some(cache) = &self.overview_cache {
+            cache.clear();
+        }
+
+        match state {
+            SecureKeyState::Locked => Err(Error::Locked),
+            SecureKeyState::Unlocked => Ok(()),
         }
     }
 
-    async fn ensure_profile_retrieved(&mut self) -> Result<(), Error> {
-        if self.profile.is_none() {
-            self.profile = Some(
-                self.database
-                    .get_profile()
-                    .await?
-                    .ok_or(Error::StoreNotInitialized)?
-                    .into_profile(),
-            );
+    /// Derives the key for `purpose` from the master key, so the same key is
+    /// returned every time the store is unlocked without it being stored.
+    pub fn derived_key<T: DeriveKey>(&self, purpose: KeyPurpose) -> Result<T, Error> {
+        let derivation_material = self.master_key()?.derivation_material()?;
+
+        Ok(derivation_material.derive_key(purpose.label()))
+    }
+
+    /// The public key other profiles seal data for this profile with.
+    pub fn agreement_public_key(&self) -> Result<AgreementPublicKey, Error> {
+        Ok(self.profile()?.key_pairs()?.agreement_public_key.clone())
+    }
+
+    /// The public key signatures made with [`Store::signing_key`] are
+    /// checked against.
+    pub fn verifying_key(&self) -> Result<VerifyingKey, Error> {
+        Ok(self.profile()?.key_pairs()?.verifying_key.clone())
+    }
+
+    pub async fn agreement_key(&self) -> Result<AgreementKey, Error> {
+        let profile = self.profile()?;
+        let key_pairs = profile.key_pairs()?;
+        let master_key = self.master_key()?;
+
+        unwrap_profile_key(
+            &mut self.database(),
+            &master_key,
+            key_pairs.agreement_key_id,
+            key_pairs.agreement_public_key.id(),
+        )
+        .await
+    }
+
+    pub async fn signing_key(&self) -> Result<SigningKey, Error> {
+        let profile = self.profile()?;
+        let key_pairs = profile.key_pairs()?;
+        let master_key = self.master_key()?;
+
+        unwrap_profile_key(
+            &mut self.database(),
+            &master_key,
+            key_pairs.signing_key_id,
+            key_pairs.verifying_key.id(),
+        )
+        .await
+    }
+
+    fn database(&self) -> Database {
+        self.database.clone()
+    }
+
+    fn profile(&self) -> Result<Arc<Profile>, Error> {
+        self.session.profile.borrow().clone().ok_or(Error::Locked)
+    }
+
+    fn master_key(&self) -> Result<Arc<SecureKey>, Error> {
+        self.session
+            .master_key
+            .borrow()
+            .clone()
+            .ok_or(Error::Locked)
+    }
+
+    async fn ensure_key_pairs_generated(
+        &self,
+        profile: &Profile,
+        master_key: &SecureKey,
+    ) -> Result<(), Error> {
+        if profile.key_pairs.is_some() {
+            return Ok(());
         }
 
+        let profile_id = profile.id;
+        let key_pairs = NewKeyPairs::generate(master_key)?;
+        let profile_record = self
+            .database
+            .transaction(|transaction| {
+                Box::pin(async move { key_pairs.store(transaction, profile_id).await })
+            })
+            .await?;
+
+        self.session
+            .profile
+            .send_replace(Some(Arc::new(profile_record.try_into_profile()?)));
         Ok(())
     }
 
-    async fn ensure_master_key_retrieved(&mut self) -> Result<(), Error> {
-        if self.master_key.is_none() {
-            let profile = self
-                .profile
-                .as_ref()
-                .expect("profile has been initialized and fetched");
-            let enc_master_key = self
-                .database
-                .find_key(profile.key_id)
+    async fn reencrypt_legacy_data(
+        &self,
+        profile: &Profile,
+        master_key: &Arc<SecureKey>,
+        derived_key: SymmetricKey,
+    ) -> Result<(), Error> {
+        let master_key = master_key.clone();
+        let master_key_id = profile.key_id;
+
+        self.database
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    upgrade::reencrypt_legacy_data(
+                        transaction,
+                        &master_key,
+                        master_key_id,
+                        &derived_key,
+                    )
+                    .await
+                })
+            })
+            .await
+    }
+
+    async fn ensure_profile_retrieved(&self) -> Result<Arc<Profile>, Error> {
+        if let Ok(profile) = self.profile() {
+            return Ok(profile);
+        }
+
+        let profile = Arc::new(
+            self.database()
+                .get_profile()
                 .await?
-                .expect("key exists because profile exists")
-                .into_encrypted_key();
+                .ok_or(Error::StoreNotInitialized)?
+                .try_into_profile()?,
+        );
+        self.session.profile.send_replace(Some(profile.clone()));
+
+        Ok(profile)
+    }
 
-            self.master_key = Some(Arc::new(Mutex::new(SecureKey::new(enc_master_key))));
+    async fn ensure_master_key_retrieved(
+        &self,
+        profile: &Profile,
+    ) -> Result<Arc<SecureKey>, Error> {
+        if let Ok(master_key) = self.master_key() {
+            return Ok(master_key);
         }
 
-        Ok(())
+        let enc_master_key = self
+            .database()
+            .find_key(profile.key_id)
+            .await?
+            .expect("key exists because profile exists")
+            .try_into_encrypted_key()?;
+        let master_key = Arc::new(SecureKey::new(enc_master_key, self.auto_lock()));
+        self.session
+            .master_key
+            .send_replace(Some(master_key.clone()));
+
+        Ok(master_key)
     }
 
-    pub async fn initialize_profile(&mut self, name: String, password: &str) -> Result<(), Error> {
-        self.database
+    pub async fn initialize_profile(&self, name: String, password: &str) -> Result<(), Error> {
+        let _unlocking = self.session.unlocking.lock().await;
+        self.database()
             .get_profile()
             .await?
             .map_or(Ok(()), |_| Err(Error::ProfileAlreadyExists))?;
 
         let salt = generate_salt();
-        let derived_key = SymmetricKey::from_password(password.as_bytes(), &salt);
+        let derived_key = SymmetricKey::from_password_blocking(password, &salt).await;
         let master_key = SymmetricKey::generate(&mut OsRng);
 
-        let mut encrypted_master_key = master_key.clone().into_encrypted_key(&derived_key);
-        let (profile, encrypted_master_key) = self
+        let key_pairs = NewKeyPairs::generate(&master_key)?;
+
+        let mut encrypted_master_key = master_key.clone().into_encrypted_key(&derived_key)?;
+        let derivation_material = master_key.derivation_material();
+        let (profile_record, encrypted_master_key) = self
             .database
-            .transaction(|mut transaction| {
+            .transaction(|transaction| {
                 Box::pin(async move {
-                    encrypted_master_key.store(&mut transaction).await?;
+                    encrypted_master_key.store(transaction).await?;
                     let profile_record = transaction
                         .store_profile(&name, &salt, encrypted_master_key.id().unwrap())
                         .await?;
+                    let profile_record = key_pairs.store(transaction, profile_record.id).await?;
+                    AuditLog::start_in(transaction, &derivation_material).await?;
 
-                    Ok::<_, Error>((profile_record.into_profile(), encrypted_master_key))
+                    Ok::<_, Error>((profile_record, encrypted_master_key))
                 })
             })
             .await?;
 
-        self.profile = Some(profile);
-        self.master_key = Some(Arc::new(Mutex::new(SecureKey::new_unlocked(
+        let master_key = Arc::new(SecureKey::new_unlocked(
             encrypted_master_key,
             master_key,
-        ))));
+            self.auto_lock(),
+        ));
+        self.session
+            .profile
+            .send_replace(Some(Arc::new(profile_record.try_into_profile()?)));
+        self.session
+            .master_key
+            .send_replace(Some(master_key.clone()));
+        self.spawn_lock_policy(&master_key);
+        self.follow_changes().await?;
+
+        Ok(())
+    }
+
+    fn auto_lock(&self) -> AutoLock {
+        AutoLock {
+            policy: self.lock_policy,
+            clock: self.clock.clone(),
+            state: self.session.lock_state.clone(),
+        }
+    }
+
+    fn spawn_lock_policy(&self, master_key: &Arc<SecureKey>) {
+        tokio::spawn(lock::enforce_lock_policy(
+            Arc::downgrade(master_key),
+            self.clock.clone(),
+            self.session.lock_state.subscribe(),
+        ));
+    }
+
+    // the overview cache is only filled while it is told about changes
+    async fn follow_changes(&self) -> Result<(), Error> {
+        if let Some(cache) = &self.overview_cache {
+            cache.follow(self.subscribe().await?, self.lock_state());
+        }
 
         Ok(())
     }
 
     pub async fn create_vault(&self, name: String) -> Result<Vault, Error> {
-        let mut encrypted_vault_key = {
-            let master_key = self
-                .master_key
-                .as_ref()
-                .ok_or(Error::Locked)?
-                .lock()
-                .unwrap();
-            let vault_key = SymmetricKey::generate(&mut OsRng);
-
-            vault_key.into_encrypted_key(&*master_key)
-        };
+        let master_key = self.master_key()?;
+        let mut encrypted_vault_key =
+            SymmetricKey::generate(&mut OsRng).into_encrypted_key(&*master_key)?;
+
+        let audit_log = self.audit_log();
 
         let (vault_record, encrypted_vault_key) = self
             .database
@@ -189,22 +627,376 @@ impl Store {
                     let vault_record = transaction
                         .store_vault(&name, encrypted_vault_key.id().unwrap())
                         .await?;
+                    audit_log
+                        .record_in(
+                            transaction,
+                            AuditEvent::VaultCreated {
+                                vault_id: vault_record.uuid,
+                            },
+                        )
+                        .await?;
 
                     Ok::<_, Error>((vault_record, encrypted_vault_key))
                 })
             })
             .await?;
 
-        let arc_master_key = self.master_key.as_ref().unwrap().clone();
-        let database = self.database.clone();
-        let vault_key = VaultKey::new(arc_master_key, encrypted_vault_key);
+        let vault_key = VaultKey::new(master_key, encrypted_vault_key);
 
-        Ok(vault_record.into_vault(vault_key, database))
+        Ok(vault_record
+            .try_into_vault(vault_key, self.database())?
+            .with_overview_cache(self.overview_cache.clone()))
     }
 
-    pub async fn list_vaults(&mut self) -> Result<Vec<VaultPreview>, Error> {
-        let vault_previews = self
+    /// Adds the vault in `invitation` from the profile owning `sender` to
+    /// this profile. Accepting an invitation reissued for a vault that was
+    /// already accepted replaces its key and items, but only if it is newer
+    /// than the last one accepted.
+    pub async fn accept_share(
+        &self,
+        invitation: &VaultInvitation,
+        sender: &VerifyingKey,
+    ) -> Result<Vault, Error> {
+        let agreement_key = self.agreement_key().await?;
+        let share = invitation.open(&agreement_key, sender)?;
+
+        let master_key = self.master_key()?;
+        let mut encrypted_vault_key =
+            SymmetricKey::new(share.vault_key, None).into_encrypted_key(&*master_key)?;
+        let shared_by = invitation.sender().as_bytes().to_vec();
+        let shared_vault_id = invitation.vault_id();
+        let generation = invitation.generation();
+        let access = VaultAccess::Shared(share.permission);
+        let name = share.name;
+        let items = share.items;
+        let vault_master_key = master_key.clone();
+        let audit_log = self.audit_log();
+
+        let (vault_record, encrypted_vault_key) = self
+            .database
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    encrypted_vault_key.store(transaction).await?;
+                    let vault_key_id = encrypted_vault_key.id().unwrap();
+
+                    let existing = transaction
+                        .find_shared_vault(&shared_by, shared_vault_id)
+                        .await?;
+                    let vault_record = match existing {
+                        Some(existing) if existing.share_generation >= generation => {
+                            return Err(Error::StaleInvitation);
+                        }
+                        Some(existing) => {
+                            transaction.delete_vault_items(existing.id).await?;
+                            transaction
+                                .update_share_generation(existing.id, generation)
+                                .await?;
+                            let vault_record = transaction
+                                .update_vault(existing.id, &name, vault_key_id, access)
+                                .await?;
+                            transaction.delete_key(existing.key_id).await?;
+
+                            vault_record
+                        }
+                        None => {
+                            transaction
+                                .store_shared_vault(
+                                    &name,
+                                    vault_key_id,
+                                    access,
+                                    &shared_by,
+                                    shared_vault_id,
+                                    generation,
+                                )
+                                .await?
+                        }
+                    };
+
+                    let vault_key = VaultKey::new(vault_master_key, encrypted_vault_key.clone());
+                    for item in &items {
+                        vault::insert_item(
+                            transaction,
+                            vault_record.id,
+                            &vault_key,
+                            &item.overview,
+                            &item.data,
+                        )
+                        .await?;
+                    }
+                    audit_log
+                        .record_in(
+                            transaction,
+                            AuditEvent::ShareAccepted {
+                                vault_id: vault_record.uuid,
+                            },
+                        )
+                        .await?;
+
+                    Ok::<_, Error>((vault_record, encrypted_vault_key))
+                })
+            })
+            .await?;
+        // a reissued invitation replaced the items of a vault
+        if let Some(cache) = &self.overview_cache {
+            cache.invalidate(vault_record.uuid);
+        }
+
+        let vault_key = VaultKey::new(master_key, encrypted_vault_key);
+        Ok(vault_record
+            .try_into_vault(vault_key, self.database())?
+            .with_overview_cache(self.overview_cache.clone()))
+    }
+
+    /// Makes the profile owning `contact` an emergency contact for the vaults
+    /// in `vault_ids`. Their keys are sealed to the contact now, and handed
+    /// over by [`Store::claim_emergency_access`] once a request for access is
+    /// approved or has been pending for `waiting_period`, which leaves the
+    /// owner time to deny it.
+    pub async fn add_emergency_contact(
+        &self,
+        contact: &AgreementPublicKey,
+        vault_ids: &[Uuid],
+        waiting_period: TimeDelta,
+    ) -> Result<EmergencyContact, Error> {
+        let master_key = self.master_key()?;
+        // periods are kept in whole seconds
+        if waiting_period.num_seconds() < 1 {
+            return Err(Error::InvalidWaitingPeriod);
+        }
+        if self
+            .database()
+            .find_emergency_contact(contact.as_bytes())
+            .await?
+            .is_some()
+        {
+            return Err(Error::EmergencyContactAlreadyExists);
+        }
+
+        let mut sealed_keys = Vec::new();
+        for &vault_id in vault_ids {
+            let vault_record = self
+                .database()
+                .find_vault_by_uuid(vault_id)
+                .await?
+                .ok_or(Error::VaultDoesNotExist)?;
+            if !VaultAccess::parse(&vault_record.access)?.is_owner() {
+                return Err(Error::NotVaultOwner);
+            }
+
+            let vault_key = self
+                .database()
+                .find_key(vault_record.key_id)
+                .await?
+                .ok_or(Error::KeyDoesNotExist)?
+                .try_into_encrypted_key()?
+                .try_to_secret(&*master_key)?;
+            sealed_keys.push((
+                vault_record.id,
+                emergency::seal_vault_key(contact, &vault_key)?,
+            ));
+        }
+
+        let public_key = contact.as_bytes().to_vec();
+        let wait_seconds = waiting_period.num_seconds();
+        let audit_log = self.audit_log();
+        let contact_record = self
+            .database
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let contact_record = transaction
+                        .store_emergency_contact(&public_key, wait_seconds)
+                        .await?;
+                    for (vault_id, sealed_key) in &sealed_keys {
+                        transaction
+                            .store_emergency_vault_key(contact_record.id, *vault_id, sealed_key)
+                            .await?;
+                    }
+                    audit_log
+                        .record_in(
+                            transaction,
+                            AuditEvent::EmergencyContactAdded {
+                                contact_id: contact_record.uuid,
+                            },
+                        )
+                        .await?;
+
+                    Ok::<_, Error>(contact_record)
+                })
+            })
+            .await?;
+
+        contact_record.try_into_emergency_contact()
+    }
+
+    /// Asks for access on behalf of the emergency contact owning `contact`.
+    /// The store doesn't need to be unlocked, and asking again returns the
+    /// request that is already open.
+    pub async fn request_emergency_access(
+        &self,
+        contact: &AgreementPublicKey,
+    ) -> Result<EmergencyRequest, Error> {
+        let contact = self
+            .database()
+            .find_emergency_contact(contact.as_bytes())
+            .await?
+            .ok_or(Error::UnknownEmergencyContact)?;
+
+        let request_record = match self
+            .database()
+            .find_open_emergency_request(contact.id)
+            .await?
+        {
+            Some(request_record) => request_record,
+            None => {
+                let requested_at = self.clock.now().naive_utc();
+                self.database()
+                    .store_emergency_request(contact.id, requested_at)
+                    .await?
+            }
+        };
+
+        request_record.try_into_emergency_request()
+    }
+
+    pub async fn emergency_requests(&self) -> Result<Vec<EmergencyRequest>, Error> {
+        self.database()
+            .list_emergency_requests()
+            .await?
+            .into_iter()
+            .map(|record| record.try_into_emergency_request())
+            .collect()
+    }
+
+    /// Grants a pending request without waiting for its waiting period.
+    pub async fn approve_emergency_access(
+        &self,
+        request_id: Uuid,
+    ) -> Result<EmergencyRequest, Error> {
+        self.decide_emergency_request(request_id, EmergencyRequestState::Approved)
+            .await
+    }
+
+    /// Denies a request before its waiting period has passed.
+    pub async fn deny_emergency_access(&self, request_id: Uuid) -> Result<EmergencyRequest, Error> {
+        self.decide_emergency_request(request_id, EmergencyRequestState::Denied)
+            .await
+    }
+
+    async fn decide_emergency_request(
+        &self,
+        request_id: Uuid,
+        state: EmergencyRequestState,
+    ) -> Result<EmergencyRequest, Error> {
+        // only the owner, who can unlock the store, decides
+        self.ensure_unlocked()?;
+
+        let now = self.clock.now();
+        let request = self
+            .database()
+            .find_emergency_request(request_id)
+            .await?
+            .ok_or(Error::EmergencyRequestDoesNotExist)?
+            .try_into_emergency_request()?;
+        if request.state() != EmergencyRequestState::Pending || request.is_granted_at(now) {
+            return Err(Error::EmergencyRequestNotPending);
+        }
+
+        let event = match state {
+            EmergencyRequestState::Denied => AuditEvent::EmergencyAccessDenied { request_id },
+            _ => AuditEvent::EmergencyAccessApproved { request_id },
+        };
+        let audit_log = self.audit_log();
+        let request_record = self
             .database
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let request_record = transaction
+                        .update_emergency_request_state(request_id, state, now.naive_utc())
+                        .await?;
+                    audit_log.record_in(transaction, event).await?;
+
+                    Ok::<_, Error>(request_record)
+                })
+            })
+            .await?;
+
+        request_record.try_into_emergency_request()
+    }
+
+    /// Opens the vaults chosen for an emergency contact with the contact's
+    /// `agreement_key`, once its request has been granted. The vaults are
+    /// read-only.
+    pub async fn claim_emergency_access(
+        &self,
+        request_id: Uuid,
+        agreement_key: &AgreementKey,
+    ) -> Result<Vec<Vault>, Error> {
+        let request_record = self
+            .database()
+            .find_emergency_request(request_id)
+            .await?
+            .ok_or(Error::EmergencyRequestDoesNotExist)?;
+        let contact_id = request_record.contact_id;
+        let request = request_record.try_into_emergency_request()?;
+        if request.contact().as_bytes() != agreement_key.public_key().as_bytes() {
+            return Err(Error::UnknownEmergencyContact);
+        }
+
+        if !request.is_granted_at(self.clock.now()) {
+            return Err(match request.state() {
+                EmergencyRequestState::Denied => Error::EmergencyAccessDenied,
+                _ => Error::EmergencyAccessPending(request.available_at()),
+            });
+        }
+
+        let mut vaults = Vec::new();
+        for record in self
+            .database()
+            .list_emergency_vault_keys(contact_id)
+            .await?
+        {
+            let vault_record = self
+                .database()
+                .find_vault(record.vault_id)
+                .await?
+                .ok_or(Error::VaultDoesNotExist)?;
+            let vault_key = emergency::open_vault_key(agreement_key, &record.sealed_key)?;
+            let vault_key =
+                VaultKey::unwrapped(SymmetricKey::new(vault_key, Some(vault_record.key_id)));
+
+            let vault = vault_record.try_into_vault(vault_key, self.database())?;
+            vaults.push(vault.with_access(VaultAccess::Shared(SharePermission::ReadOnly)));
+        }
+
+        Ok(vaults)
+    }
+
+    /// Decrypts the audit log entries selected by `query`, in the order they
+    /// were written.
+    pub async fn audit_entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        self.audit_log().entries(query).await
+    }
+
+    /// Checks that no audit log entry was removed, reordered or changed,
+    /// returning how many entries there are.
+    pub async fn verify_audit_log(&self) -> Result<i64, Error> {
+        self.audit_log().verify().await
+    }
+
+    fn audit_log(&self) -> AuditLog {
+        AuditLog::new(self.database(), self.master_key().ok())
+    }
+
+    fn ensure_unlocked(&self) -> Result<(), Error> {
+        match self.master_key()?.get_state() {
+            SecureKeyState::Unlocked => Ok(()),
+            SecureKeyState::Locked => Err(Error::Locked),
+        }
+    }
+
+    pub async fn list_vaults(&self) -> Result<Vec<VaultPreview>, Error> {
+        let vault_previews = self
+            .database()
             .list_vault_previews()
             .await?
             .into_iter()
@@ -214,20 +1006,68 @@ impl Store {
         Ok(vault_previews)
     }
 
-    pub async fn get_vault(&mut self, id: i64) -> Result<Option<Vault>, Error> {
-        match self.database.find_vault(id).await? {
+    /// Reads one page of item previews across every vault, pass its cursor
+    /// to [`ItemQuery::after`] for the next.
+    pub async fn items(&self, query: &ItemQuery) -> Result<ItemPage, Error> {
+        let master_key = self.master_key()?;
+        let records = self
+            .database()
+            .list_item_previews(
+                None,
+                query.sort_key(),
+                query.cursor(),
+                Some(query.page_size()),
+            )
+            .await?;
+
+        let mut vault_keys = HashMap::new();
+        for record in &records {
+            if let Entry::Vacant(entry) = vault_keys.entry(record.vault_id) {
+                let vault_record = self
+                    .database()
+                    .find_vault(record.vault_id)
+                    .await?
+                    .ok_or(Error::VaultDoesNotExist)?;
+                let vault_key = self
+                    .database()
+                    .find_key(vault_record.key_id)
+                    .await?
+                    .ok_or(Error::KeyDoesNotExist)?
+                    .try_into_encrypted_key()?
+                    .try_to_symmetric_key(&*master_key)?;
+                entry.insert(vault_key);
+            }
+        }
+
+        ItemPage::from_records(records, query, vault_keys, self.database.decrypt_permits()).await
+    }
+
+    /// Streams item previews across every vault from the page `query`
+    /// selects on, fetching a page at a time.
+    pub fn stream_items(&self, query: ItemQuery) -> ItemStream {
+        let store = self.clone();
+
+        ItemStream::new(query, move |query| {
+            let store = store.clone();
+            async move { store.items(&query).await }
+        })
+    }
+
+    pub async fn get_vault(&self, id: Uuid) -> Result<Option<Vault>, Error> {
+        match self.database().find_vault_by_uuid(id).await? {
             Some(vault_record) => {
                 let enc_vault_key = self
-                    .database
+                    .database()
                     .find_key(vault_record.key_id)
                     .await?
                     .ok_or(Error::KeyDoesNotExist)?
-                    .into_encrypted_key();
-                let vault_key =
-                    VaultKey::new(self.master_key.as_ref().unwrap().clone(), enc_vault_key);
+                    .try_into_encrypted_key()?;
+                let vault_key = VaultKey::new(self.master_key()?, enc_vault_key);
 
                 Ok(Some(
-                    vault_record.into_vault(vault_key, self.database.clone()),
+                    vault_record
+                        .try_into_vault(vault_key, self.database())?
+                        .with_overview_cache(self.overview_cache.clone()),
                 ))
             }
             None => Ok(None),
@@ -235,7 +1075,7 @@ impl Store {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "sqlite"))]
 mod tests {
     use super::*;
     use sqlx::SqlitePool;
@@ -244,7 +1084,7 @@ mod tests {
 
     #[sqlx::test(migrator = "MIGRATOR")]
     async fn can_create_vault(pool: SqlitePool) {
-        let mut store = Store::from_pool(pool).unwrap();
+        let store = Store::from_pool(pool).unwrap();
         store
             .initialize_profile("User".into(), "password".into())
             .await
@@ -254,4 +1094,110 @@ mod tests {
         let vault = store.create_vault(vault_name.clone()).await.unwrap();
         assert_eq!(vault.name(), vault_name);
     }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn derived_keys_survive_unlocking(pool: SqlitePool) {
+        use cerberus_crypto::{Cipher, symmetric::SymmetricKey};
+
+        let store = Store::from_pool(pool).unwrap();
+        store
+            .initialize_profile("User".into(), "password")
+            .await
+            .unwrap();
+
+        let export_key: SymmetricKey = store.derived_key(KeyPurpose::Export).unwrap();
+        let encrypted = export_key.encrypt(&String::from("exported")).unwrap();
+
+        store.lock().unwrap();
+        assert!(matches!(
+            store.derived_key::<SymmetricKey>(KeyPurpose::Export),
+            Err(Error::Locked)
+        ));
+        store.unlock("password").await.unwrap();
+
+        let export_key: SymmetricKey = store.derived_key(KeyPurpose::Export).unwrap();
+        assert_eq!(export_key.decrypt(&encrypted).unwrap(), "exported");
+
+        let integrity_key: SymmetricKey = store.derived_key(KeyPurpose::Integrity).unwrap();
+        assert!(integrity_key.decrypt(&encrypted).is_err());
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn derived_keys_cannot_open_the_audit_log(pool: SqlitePool) {
+        let store = Store::from_pool(pool).unwrap();
+        store
+            .initialize_profile("User".into(), "password")
+            .await
+            .unwrap();
+
+        let derivation_material = store.master_key().unwrap().derivation_material().unwrap();
+        let (encryption_key, _) = crate::audit::audit_keys(&derivation_material);
+        let encrypted = encryption_key.encrypt(&String::from("audited")).unwrap();
+        for purpose in [
+            KeyPurpose::SearchIndex,
+            KeyPurpose::Integrity,
+            KeyPurpose::Export,
+            KeyPurpose::DeviceBinding,
+        ] {
+            let key: SymmetricKey = store.derived_key(purpose).unwrap();
+            assert!(key.decrypt::<String>(&encrypted).is_err());
+        }
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn profile_key_pairs_survive_unlocking(pool: SqlitePool) {
+        let store = Store::from_pool(pool.clone()).unwrap();
+        store
+            .initialize_profile("User".into(), "password")
+            .await
+            .unwrap();
+
+        let sealed = store
+            .agreement_public_key()
+            .unwrap()
+            .seal(&String::from("shared"))
+            .unwrap();
+        let signature = store.signing_key().await.unwrap().sign(b"export");
+
+        let store = Store::from_pool(pool).unwrap();
+        store.unlock("password").await.unwrap();
+
+        let agreement_key = store.agreement_key().await.unwrap();
+        assert_eq!(agreement_key.open(&sealed).unwrap(), "shared");
+        store
+            .verifying_key()
+            .unwrap()
+            .verify(b"export", &signature)
+            .unwrap();
+
+        store.lock().unwrap();
+        assert!(matches!(store.signing_key().await, Err(Error::Locked)));
+    }
+
+    #[sqlx::test(migrator = "MIGRATOR")]
+    async fn generates_key_pairs_for_existing_profiles(pool: SqlitePool) {
+        let store = Store::from_pool(pool.clone()).unwrap();
+        store
+            .initialize_profile("User".into(), "password")
+            .await
+            .unwrap();
+
+        // as left behind by a version without key pairs
+        sqlx::query(
+            "UPDATE profiles SET
+                 agreement_key_id = NULL,
+                 agreement_public_key = NULL,
+                 signing_key_id = NULL,
+                 verifying_key = NULL",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let store = Store::from_pool(pool).unwrap();
+        store.unlock("password").await.unwrap();
+
+        let signing_key = store.signing_key().await.unwrap();
+        assert_eq!(signing_key.verifying_key(), store.verifying_key().unwrap());
+    }
 }
@@ -0,0 +1,252 @@
+//! A typestate view of [`Store`]: vault, item and key operations only exist
+//! on an [`UnlockedStore`], which is only handed out by unlocking.
+//!
+//! Both types are handles to the same shared store as the [`Store`] they were
+//! made from, so the store can still be locked underneath an
+//! [`UnlockedStore`], by another handle or by its
+//! [`LockPolicy`](crate::lock::LockPolicy). Operations then fail with
+//! [`Error::Locked`] as they do on [`Store`].
+
+#[cfg(feature = "sqlite")]
+use std::path::Path;
+
+use cerberus_crypto::asymmetric::{AgreementKey, AgreementPublicKey, SigningKey, VerifyingKey};
+use cerberus_crypto::kdf::DeriveKey;
+use chrono::TimeDelta;
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::audit::{AuditEntry, AuditQuery};
+use crate::clock::Clock;
+use crate::database::Backend;
+use crate::emergency::{EmergencyContact, EmergencyRequest};
+use crate::events::Subscription;
+use crate::item::{ItemPage, ItemQuery, ItemStream};
+use crate::lock::{LockPolicy, LockState};
+use crate::share::VaultInvitation;
+use crate::vault::{Vault, VaultPreview};
+use crate::{Error, KeyPurpose, Store};
+
+/// A [`Store`] sorted by whether it is locked right now.
+#[derive(Debug, Clone)]
+pub enum TypedStore {
+    Locked(LockedStore),
+    Unlocked(UnlockedStore),
+}
+
+impl From<Store> for TypedStore {
+    fn from(store: Store) -> Self {
+        if store.is_locked() {
+            TypedStore::Locked(LockedStore(store))
+        } else {
+            TypedStore::Unlocked(UnlockedStore(store))
+        }
+    }
+}
+
+/// A store that has to be unlocked before its vaults can be opened.
+#[derive(Debug, Clone)]
+pub struct LockedStore(Store);
+
+impl LockedStore {
+    #[cfg(feature = "sqlite")]
+    pub async fn new(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self(Store::new(path).await?))
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn from_pool(pool: SqlitePool) -> Result<Self, Error> {
+        Ok(Self(Store::from_pool(pool)?))
+    }
+
+    pub fn from_backend(backend: impl Backend + 'static) -> Result<Self, Error> {
+        Ok(Self(Store::from_backend(backend)?))
+    }
+
+    /// See [`Store::with_clock`].
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        Self(self.0.with_clock(clock))
+    }
+
+    /// See [`Store::with_lock_policy`].
+    pub fn with_lock_policy(self, lock_policy: LockPolicy) -> Self {
+        Self(self.0.with_lock_policy(lock_policy))
+    }
+
+    /// See [`Store::with_overview_cache`].
+    pub fn with_overview_cache(self, capacity: usize) -> Self {
+        Self(self.0.with_overview_cache(capacity))
+    }
+
+    /// Creates the profile, leaving the store unlocked.
+    pub async fn initialize_profile(
+        self,
+        name: String,
+        password: &str,
+    ) -> Result<UnlockedStore, Error> {
+        self.0.initialize_profile(name, password).await?;
+
+        Ok(UnlockedStore(self.0))
+    }
+
+    /// Hands the store back along with the error if it can't be unlocked,
+    /// so another password can be tried.
+    pub async fn unlock(self, password: &str) -> Result<UnlockedStore, (LockedStore, Error)> {
+        match self.0.unlock(password).await {
+            Ok(()) => Ok(UnlockedStore(self.0)),
+            Err(err) => Err((self, err)),
+        }
+    }
+
+    pub fn lock_state(&self) -> watch::Receiver<LockState> {
+        self.0.lock_state()
+    }
+
+    pub async fn subscribe(&self) -> Result<Subscription, Error> {
+        self.0.subscribe().await
+    }
+
+    /// Vault names and ids aren't encrypted, so they can be listed while
+    /// locked.
+    pub async fn list_vaults(&self) -> Result<Vec<VaultPreview>, Error> {
+        self.0.list_vaults().await
+    }
+
+    pub async fn request_emergency_access(
+        &self,
+        contact: &AgreementPublicKey,
+    ) -> Result<EmergencyRequest, Error> {
+        self.0.request_emergency_access(contact).await
+    }
+
+    pub async fn emergency_requests(&self) -> Result<Vec<EmergencyRequest>, Error> {
+        self.0.emergency_requests().await
+    }
+
+    pub async fn claim_emergency_access(
+        &self,
+        request_id: Uuid,
+        agreement_key: &AgreementKey,
+    ) -> Result<Vec<Vault>, Error> {
+        self.0
+            .claim_emergency_access(request_id, agreement_key)
+            .await
+    }
+}
+
+impl From<LockedStore> for Store {
+    fn from(store: LockedStore) -> Self {
+        store.0
+    }
+}
+
+/// A store that has been unlocked with the profile's password.
+#[derive(Debug, Clone)]
+pub struct UnlockedStore(Store);
+
+impl UnlockedStore {
+    pub fn lock(self) -> LockedStore {
+        // a store locked by its lock policy is just as locked
+        let _ = self.0.lock();
+
+        LockedStore(self.0)
+    }
+
+    pub fn lock_state(&self) -> watch::Receiver<LockState> {
+        self.0.lock_state()
+    }
+
+    pub async fn subscribe(&self) -> Result<Subscription, Error> {
+        self.0.subscribe().await
+    }
+
+    pub fn derived_key<T: DeriveKey>(&self, purpose: KeyPurpose) -> Result<T, Error> {
+        self.0.derived_key(purpose)
+    }
+
+    pub fn agreement_public_key(&self) -> Result<AgreementPublicKey, Error> {
+        self.0.agreement_public_key()
+    }
+
+    pub fn verifying_key(&self) -> Result<VerifyingKey, Error> {
+        self.0.verifying_key()
+    }
+
+    pub async fn agreement_key(&self) -> Result<AgreementKey, Error> {
+        self.0.agreement_key().await
+    }
+
+    pub async fn signing_key(&self) -> Result<SigningKey, Error> {
+        self.0.signing_key().await
+    }
+
+    pub async fn list_vaults(&self) -> Result<Vec<VaultPreview>, Error> {
+        self.0.list_vaults().await
+    }
+
+    pub async fn items(&self, query: &ItemQuery) -> Result<ItemPage, Error> {
+        self.0.items(query).await
+    }
+
+    pub fn stream_items(&self, query: ItemQuery) -> ItemStream {
+        self.0.stream_items(query)
+    }
+
+    pub async fn get_vault(&self, id: Uuid) -> Result<Option<Vault>, Error> {
+        self.0.get_vault(id).await
+    }
+
+    pub async fn create_vault(&self, name: String) -> Result<Vault, Error> {
+        self.0.create_vault(name).await
+    }
+
+    pub async fn accept_share(
+        &self,
+        invitation: &VaultInvitation,
+        sender: &VerifyingKey,
+    ) -> Result<Vault, Error> {
+        self.0.accept_share(invitation, sender).await
+    }
+
+    pub async fn add_emergency_contact(
+        &self,
+        contact: &AgreementPublicKey,
+        vault_ids: &[Uuid],
+        waiting_period: TimeDelta,
+    ) -> Result<EmergencyContact, Error> {
+        self.0
+            .add_emergency_contact(contact, vault_ids, waiting_period)
+            .await
+    }
+
+    pub async fn emergency_requests(&self) -> Result<Vec<EmergencyRequest>, Error> {
+        self.0.emergency_requests().await
+    }
+
+    pub async fn approve_emergency_access(
+        &self,
+        request_id: Uuid,
+    ) -> Result<EmergencyRequest, Error> {
+        self.0.approve_emergency_access(request_id).await
+    }
+
+    pub async fn deny_emergency_access(&self, request_id: Uuid) -> Result<EmergencyRequest, Error> {
+        self.0.deny_emergency_access(request_id).await
+    }
+
+    pub async fn audit_entries(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, Error> {
+        self.0.audit_entries(query).await
+    }
+
+    pub async fn verify_audit_log(&self) -> Result<i64, Error> {
+        self.0.verify_audit_log().await
+    }
+}
+
+impl From<UnlockedStore> for Store {
+    fn from(store: UnlockedStore) -> Self {
+        store.0
+    }
+}
@@ -0,0 +1,126 @@
+//! Rewrites what was encrypted in format version 0, whose version isn't
+//! authenticated, once the keys needed for it are unlocked.
+
+use std::collections::HashMap;
+
+use cerberus_secret::SecretSlice;
+
+use crate::Error;
+use crate::crypto::{Cipher, EncryptedData, EncryptedKey, SecureKey, SymmetricKey};
+use crate::database::Transaction;
+use crate::database::record_types::{EncryptedKeyRecord, ItemCiphertextsRecord, NewKeyRecord};
+use crate::item::{ItemData, ItemOverview};
+
+/// Re-encrypts the keys and items still in format version 0 with the
+/// current one. The master key is wrapped with `derived_key`, every other key
+/// with the master key or a key wrapped with it.
+pub(crate) async fn reencrypt_legacy_data(
+    transaction: &mut dyn Transaction,
+    master_key: &SecureKey,
+    master_key_id: i64,
+    derived_key: &SymmetricKey,
+) -> Result<(), Error> {
+    let mut keys = UnwrappedKeys {
+        master_key,
+        master_key_id,
+        keys: HashMap::new(),
+    };
+
+    for record in transaction.list_legacy_keys().await? {
+        let id = record.id;
+        let parent_key_id = record.parent_key_id;
+        let encrypted_key = record.try_into_encrypted_key()?;
+        let rewrapped = match parent_key_id {
+            None if id == master_key_id => rewrap(&encrypted_key, derived_key)?,
+            Some(parent_key_id) if parent_key_id == master_key_id => {
+                rewrap(&encrypted_key, master_key)?
+            }
+            Some(parent_key_id) => {
+                let parent_key = keys.unwrap(transaction, parent_key_id).await?;
+                rewrap(&encrypted_key, &parent_key)?
+            }
+            None => return Err(Error::KeyDoesNotExist),
+        };
+        transaction
+            .update_key(id, &NewKeyRecord::from_encrypted_data(&rewrapped))
+            .await?;
+    }
+
+    for record in transaction.list_legacy_items().await? {
+        let overview_key = keys.unwrap(transaction, record.overview_key_id).await?;
+        let data_key = keys.unwrap(transaction, record.item_key_id).await?;
+        let overview: ItemOverview = overview_key.decrypt(&EncryptedData::from_parts(
+            record.overview_format_version,
+            record.overview_ciphertext,
+            record.overview_nonce,
+            Some(record.overview_key_id),
+        )?)?;
+        let data: ItemData = data_key.decrypt(&EncryptedData::from_parts(
+            record.item_format_version,
+            record.item_ciphertext,
+            record.item_nonce,
+            Some(record.item_key_id),
+        )?)?;
+
+        let ciphertexts = ItemCiphertextsRecord::new(
+            &overview_key.encrypt(&overview)?,
+            &data_key.encrypt(&data)?,
+        );
+        transaction
+            .update_item_ciphertexts(record.id, &ciphertexts)
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn rewrap<K: Cipher>(
+    encrypted_key: &EncryptedKey,
+    parent_key: &K,
+) -> Result<EncryptedData<SecretSlice<u8>>, Error> {
+    parent_key.encrypt(&encrypted_key.try_to_secret(parent_key)?)
+}
+
+/// The keys below the master key unwrapped so far, by their id.
+struct UnwrappedKeys<'a> {
+    master_key: &'a SecureKey,
+    master_key_id: i64,
+    keys: HashMap<i64, SymmetricKey>,
+}
+
+impl UnwrappedKeys<'_> {
+    async fn unwrap(
+        &mut self,
+        transaction: &mut dyn Transaction,
+        id: i64,
+    ) -> Result<SymmetricKey, Error> {
+        // the keys up to one that is already unwrapped, innermost first
+        let mut records: Vec<EncryptedKeyRecord> = Vec::new();
+        let mut next = id;
+        while next != self.master_key_id && !self.keys.contains_key(&next) {
+            if records.iter().any(|record| record.id == next) {
+                return Err(Error::KeyDoesNotExist);
+            }
+            let record = transaction
+                .find_key(next)
+                .await?
+                .ok_or(Error::KeyDoesNotExist)?;
+            // only the master key is wrapped with the password
+            next = record.parent_key_id.ok_or(Error::KeyDoesNotExist)?;
+            records.push(record);
+        }
+
+        for record in records.into_iter().rev() {
+            let id = record.id;
+            let parent_key = record.parent_key_id.and_then(|id| self.keys.get(&id));
+            let encrypted_key = record.try_into_encrypted_key()?;
+            let key = match parent_key {
+                Some(parent_key) => encrypted_key.try_to_symmetric_key(parent_key)?,
+                None => encrypted_key.try_to_symmetric_key(self.master_key)?,
+            };
+            self.keys.insert(id, key);
+        }
+
+        self.keys.get(&id).cloned().ok_or(Error::KeyDoesNotExist)
+    }
+}
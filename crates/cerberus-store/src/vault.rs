@@ -1,35 +1,133 @@
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
 use rand::rngs::OsRng;
 use serde::{Serialize, de::DeserializeOwned};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use cerberus_crypto::asymmetric::{AgreementPublicKey, SigningKey, VerifyingKey};
+use cerberus_secret::SecretSlice;
 
 use crate::{
     Error,
+    audit::{AuditEvent, AuditLog},
+    cache::OverviewCache,
     crypto::{Cipher, EncryptedData, EncryptedKey, SecureKey, SymmetricKey},
-    database::{Database, Repository},
-    item::{Item, ItemData, ItemOverview, ItemPreview},
+    database::{
+        Database, Repository,
+        record_types::{ItemRecord, NewItemRecord, NewKeyRecord, VaultRecord},
+    },
+    emergency,
+    item::{
+        self, Item, ItemData, ItemOverview, ItemPage, ItemPreview, ItemQuery, ItemSortKey,
+        ItemStream,
+    },
+    lock::LockState,
+    share::{Share, SharePermission, SharedItem, VaultAccess, VaultInvitation, VaultShare},
+    store,
 };
 
 #[derive(Debug, Clone)]
-pub(crate) struct VaultKey {
-    master_key: Arc<Mutex<SecureKey>>,
-    vault_key: EncryptedKey,
+pub(crate) enum VaultKey {
+    /// Wrapped by the master key and unwrapped whenever it is used.
+    Wrapped {
+        master_key: Arc<SecureKey>,
+        vault_key: EncryptedKey,
+        lock_state: watch::Receiver<LockState>,
+    },
+    /// Handed over unwrapped, to an emergency contact who doesn't have the
+    /// master key.
+    Unwrapped(SymmetricKey),
 }
 
 impl VaultKey {
-    pub(crate) fn new(master_key: Arc<Mutex<SecureKey>>, vault_key: EncryptedKey) -> Self {
-        Self {
+    pub(crate) fn new(master_key: Arc<SecureKey>, vault_key: EncryptedKey) -> Self {
+        let lock_state = master_key.subscribe();
+
+        Self::Wrapped {
             master_key,
             vault_key,
+            lock_state,
         }
     }
 
+    pub(crate) fn unwrapped(vault_key: SymmetricKey) -> Self {
+        Self::Unwrapped(vault_key.sealed())
+    }
+
     pub(crate) fn get_symmetric_key(&self) -> Result<SymmetricKey, Error> {
-        let master_key = self.master_key.lock().unwrap();
-        let vault_key = self.vault_key.try_to_symmetric_key(&*master_key)?;
+        match self {
+            VaultKey::Wrapped {
+                master_key,
+                vault_key,
+                lock_state,
+            } => {
+                ensure_unlocked(lock_state)?;
+                vault_key.try_to_symmetric_key(&**master_key)
+            }
+            VaultKey::Unwrapped(vault_key) => Ok(vault_key.clone()),
+        }
+    }
+
+    fn secret(&self) -> Result<SecretSlice<u8>, Error> {
+        match self {
+            VaultKey::Wrapped {
+                master_key,
+                vault_key,
+                lock_state,
+            } => {
+                ensure_unlocked(lock_state)?;
+                vault_key.try_to_secret(&**master_key)
+            }
+            VaultKey::Unwrapped(vault_key) => Ok(vault_key.secret()),
+        }
+    }
+
+    pub(crate) fn audit_log(&self, database: Database) -> AuditLog {
+        AuditLog::new(database, self.master_key().ok().cloned())
+    }
 
-        Ok(vault_key)
+    // managing the vault needs the master key, which only the owner has
+    fn master_key(&self) -> Result<&Arc<SecureKey>, Error> {
+        match self {
+            VaultKey::Wrapped { master_key, .. } => Ok(master_key),
+            VaultKey::Unwrapped(_) => Err(Error::NotVaultOwner),
+        }
+    }
+}
+
+async fn item_page(
+    mut database: Database,
+    vault_id: i64,
+    vault_key: &VaultKey,
+    query: &ItemQuery,
+) -> Result<ItemPage, Error> {
+    let vault_key = vault_key.get_symmetric_key()?;
+    let records = database
+        .list_item_previews(
+            Some(vault_id),
+            query.sort_key(),
+            query.cursor(),
+            Some(query.page_size()),
+        )
+        .await?;
+
+    ItemPage::from_records(
+        records,
+        query,
+        HashMap::from([(vault_id, vault_key)]),
+        database.decrypt_permits(),
+    )
+    .await
+}
+
+// handles fail as soon as the store is locked, without waiting for the key
+fn ensure_unlocked(lock_state: &watch::Receiver<LockState>) -> Result<(), Error> {
+    match *lock_state.borrow() {
+        LockState::Unlocked => Ok(()),
+        LockState::Locked => Err(Error::Locked),
     }
 }
 
@@ -56,29 +154,56 @@ impl Cipher for VaultKey {
 #[derive(Debug)]
 pub struct Vault {
     id: i64,
+    uuid: Uuid,
     name: String,
+    access: VaultAccess,
+    shared_by: Option<VerifyingKey>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     database: Database,
     vault_key: VaultKey,
+    overview_cache: Option<Arc<OverviewCache>>,
 }
 
 impl Vault {
-    pub(crate) fn new(
-        id: i64,
-        name: String,
-        created_at: DateTime<Utc>,
-        updated_at: DateTime<Utc>,
-        database: Database,
+    pub(crate) fn from_record(
+        record: VaultRecord,
         vault_key: VaultKey,
-    ) -> Self {
-        Self {
-            id,
-            name,
-            created_at,
-            updated_at,
+        database: Database,
+    ) -> Result<Self, Error> {
+        let shared_by = record
+            .shared_by
+            .map(|key| {
+                let key = key.try_into().map_err(|_| Error::MalformedVault)?;
+                Ok::<_, Error>(VerifyingKey::from_bytes(
+                    key,
+                    store::profile_key_identifier(&key),
+                ))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            id: record.id,
+            uuid: record.uuid,
+            name: record.name,
+            access: VaultAccess::parse(&record.access)?,
+            shared_by,
+            created_at: record.created_at.and_utc(),
+            updated_at: record.updated_at.and_utc(),
             database,
             vault_key,
+            overview_cache: None,
+        })
+    }
+
+    pub(crate) fn with_access(self, access: VaultAccess) -> Self {
+        Self { access, ..self }
+    }
+
+    pub(crate) fn with_overview_cache(self, overview_cache: Option<Arc<OverviewCache>>) -> Self {
+        Self {
+            overview_cache,
+            ..self
         }
     }
 
@@ -87,72 +212,348 @@ impl Vault {
         item_overview: ItemOverview,
         item_data: ItemData,
     ) -> Result<Item, Error> {
-        let overview_key = SymmetricKey::generate(&mut OsRng);
-        let data_key = SymmetricKey::generate(&mut OsRng);
-
-        let enc_item_overview = overview_key.encrypt(&item_overview)?;
-        let enc_item_data = data_key.encrypt(&item_data)?;
+        if !self.access.can_write() {
+            return Err(Error::ReadOnlyVault);
+        }
 
         let id = self.id;
+        let uuid = self.uuid;
         let vault_key = self.vault_key.clone();
         let database = self.database.clone();
+        let audit_log = self.audit_log();
 
         let item = self
             .database
-            .transaction(|mut transaction| {
+            .transaction(|transaction| {
                 Box::pin(async move {
-                    let mut enc_overview_key = overview_key.into_encrypted_key(&vault_key);
-                    enc_overview_key.store(&mut transaction).await?;
-                    let mut enc_data_key = data_key.into_encrypted_key(&vault_key);
-                    enc_data_key.store(&mut transaction).await?;
-
-                    let item_record = transaction
-                        .store_item(
-                            id,
-                            &enc_item_overview,
-                            enc_overview_key.id().unwrap(),
-                            &enc_item_data,
-                            enc_data_key.id().unwrap(),
+                    let (item_record, enc_overview_key, enc_data_key) =
+                        insert_item(transaction, id, &vault_key, &item_overview, &item_data)
+                            .await?;
+                    audit_log
+                        .record_in(
+                            transaction,
+                            AuditEvent::ItemCreated {
+                                vault_id: uuid,
+                                item_id: item_record.uuid,
+                            },
                         )
                         .await?;
 
-                    Ok::<_, Error>(item_record.into_item(
-                        enc_overview_key,
-                        enc_data_key,
-                        vault_key,
-                        database,
-                    ))
+                    item_record.try_into_item(enc_overview_key, enc_data_key, vault_key, database)
                 })
             })
             .await?;
+        self.invalidate_overviews();
 
         Ok(item)
     }
 
-    pub async fn get_item(&mut self, id: i64) -> Result<Item, Error> {
-        let item = self.database.find_item(id).await?;
+    pub async fn get_item(&mut self, id: Uuid) -> Result<Item, Error> {
+        let item = self.database.find_item(self.id, id).await?;
 
-        Ok(item.into_item(self.vault_key.clone(), self.database.clone()))
+        item.try_into_item(self.vault_key.clone(), self.database.clone())
     }
 
+    pub async fn delete_item(&mut self, id: Uuid) -> Result<(), Error> {
+        if !self.access.can_write() {
+            return Err(Error::ReadOnlyVault);
+        }
+
+        let vault_id = self.id;
+        let uuid = self.uuid;
+        let audit_log = self.audit_log();
+        self.database
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    if !transaction.delete_item(vault_id, id).await? {
+                        return Err(Error::ItemDoesNotExist);
+                    }
+
+                    audit_log
+                        .record_in(
+                            transaction,
+                            AuditEvent::ItemDeleted {
+                                vault_id: uuid,
+                                item_id: id,
+                            },
+                        )
+                        .await
+                })
+            })
+            .await?;
+        self.invalidate_overviews();
+
+        Ok(())
+    }
+
+    /// Lists every item in the vault. The previews are served from the
+    /// store's overview cache when it has them.
     pub async fn list_items(&mut self) -> Result<Vec<ItemPreview>, Error> {
         let vault_key = self.vault_key.get_symmetric_key()?;
+        let cache = self.overview_cache.as_deref();
+        if let Some(previews) = cache.and_then(|cache| cache.get(self.uuid)) {
+            return Ok(previews);
+        }
+        let generation = cache.map(OverviewCache::generation);
 
-        let item_previews = self
+        let records = self
             .database
-            .list_item_previews(Some(self.id))
+            .list_item_previews(Some(self.id), ItemSortKey::Id, None, None)
+            .await?;
+        let previews = item::decrypt_previews(
+            records,
+            HashMap::from([(self.id, vault_key)]),
+            self.database.decrypt_permits(),
+        )
+        .await?;
+
+        if let Some((cache, generation)) = cache.zip(generation) {
+            cache.insert(self.uuid, generation, &previews);
+        }
+        Ok(previews)
+    }
+
+    /// Reads one page of the vault's item previews, pass its cursor to
+    /// [`ItemQuery::after`] for the next.
+    pub async fn items(&self, query: &ItemQuery) -> Result<ItemPage, Error> {
+        item_page(self.database.clone(), self.id, &self.vault_key, query).await
+    }
+
+    /// Streams the vault's item previews from the page `query` selects on,
+    /// fetching a page at a time.
+    pub fn stream_items(&self, query: ItemQuery) -> ItemStream {
+        let database = self.database.clone();
+        let vault_key = self.vault_key.clone();
+        let id = self.id;
+
+        ItemStream::new(query, move |query| {
+            let database = database.clone();
+            let vault_key = vault_key.clone();
+            async move { item_page(database, id, &vault_key, &query).await }
+        })
+    }
+
+    /// Shares the vault with the profile owning `recipient`, returning the
+    /// invitation to hand to it. The invitation holds the vault key and a copy
+    /// of the items as they are now.
+    pub async fn share_with(
+        &mut self,
+        recipient: &AgreementPublicKey,
+        permission: SharePermission,
+    ) -> Result<VaultInvitation, Error> {
+        if !self.access.is_owner() {
+            return Err(Error::NotVaultOwner);
+        }
+
+        let signing_key = self.signing_key().await?;
+        let share = self.share_contents(permission).await?;
+
+        let id = self.id;
+        let uuid = self.uuid;
+        let recipient_public_key = recipient.as_bytes().to_vec();
+        let audit_log = self.audit_log();
+        let generation = self
+            .database
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    let share_record = transaction
+                        .store_vault_share(id, &recipient_public_key, permission)
+                        .await?;
+                    let generation = transaction.next_share_generation(id).await?;
+                    audit_log
+                        .record_in(
+                            transaction,
+                            AuditEvent::VaultShared {
+                                vault_id: uuid,
+                                share_id: share_record.uuid,
+                            },
+                        )
+                        .await?;
+
+                    Ok::<_, Error>(generation)
+                })
+            })
+            .await?;
+
+        VaultInvitation::seal(&signing_key, self.uuid, generation, recipient, &share)
+    }
+
+    pub async fn shares(&mut self) -> Result<Vec<Share>, Error> {
+        self.database
+            .list_vault_shares(self.id)
             .await?
             .into_iter()
-            .map(|record| record.try_into_item_preview(&vault_key))
-            .collect::<Result<Vec<_>, _>>()?;
+            .map(|record| record.try_into_share())
+            .collect()
+    }
+
+    /// Revokes a share and rotates the vault key, so the revoked profile
+    /// can't open anything written from now on. The profiles the vault is
+    /// still shared with need the new key, their invitations are reissued
+    /// and returned.
+    pub async fn revoke_share(&mut self, share_id: Uuid) -> Result<Vec<VaultInvitation>, Error> {
+        if !self.access.is_owner() {
+            return Err(Error::NotVaultOwner);
+        }
+
+        let id = self.id;
+        let uuid = self.uuid;
+        let name = self.name.clone();
+        let access = self.access;
+        let old_vault_key = self.vault_key.get_symmetric_key()?;
+        let old_vault_key_id = old_vault_key.id().expect("stored keys have an id");
+        let master_key = self.vault_key.master_key()?.clone();
+        let mut new_vault_key =
+            SymmetricKey::generate(&mut OsRng).into_encrypted_key(&*master_key)?;
+        let audit_log = self.audit_log();
+
+        let new_vault_key = self
+            .database
+            .transaction(|transaction| {
+                Box::pin(async move {
+                    if !transaction.revoke_vault_share(id, share_id).await? {
+                        return Err(Error::ShareDoesNotExist);
+                    }
+
+                    new_vault_key.store(transaction).await?;
+                    let vault_key = new_vault_key.try_to_symmetric_key(&*master_key)?;
+
+                    // the item keys are the only keys wrapped by the vault key
+                    for record in transaction.list_child_keys(old_vault_key_id).await? {
+                        let child_key = record.try_into_encrypted_key()?;
+                        let secret = child_key.try_to_secret(&old_vault_key)?;
+                        let rewrapped = vault_key.encrypt(&secret)?;
+                        transaction
+                            .update_key(
+                                child_key.id().unwrap(),
+                                &NewKeyRecord::from_encrypted_data(&rewrapped),
+                            )
+                            .await?;
+                    }
+
+                    transaction
+                        .update_vault(id, &name, new_vault_key.id().unwrap(), access)
+                        .await?;
+                    transaction.delete_key(old_vault_key_id).await?;
+
+                    // emergency contacts hold the vault key sealed to them
+                    let secret = new_vault_key.try_to_secret(&*master_key)?;
+                    for record in transaction.list_vault_emergency_contacts(id).await? {
+                        let contact_id = record.id;
+                        let contact = record.try_into_emergency_contact()?;
+                        let sealed_key = emergency::seal_vault_key(contact.public_key(), &secret)?;
+                        transaction
+                            .store_emergency_vault_key(contact_id, id, &sealed_key)
+                            .await?;
+                    }
+                    audit_log
+                        .record_in(
+                            transaction,
+                            AuditEvent::ShareRevoked {
+                                vault_id: uuid,
+                                share_id,
+                            },
+                        )
+                        .await?;
+
+                    Ok::<_, Error>(new_vault_key)
+                })
+            })
+            .await?;
+        self.vault_key = VaultKey::new(self.vault_key.master_key()?.clone(), new_vault_key);
+
+        let signing_key = self.signing_key().await?;
+        let mut invitations = Vec::new();
+        for share in self.shares().await? {
+            invitations.push(
+                self.invitation(&signing_key, share.recipient(), share.permission())
+                    .await?,
+            );
+        }
 
-        Ok(item_previews)
+        Ok(invitations)
+    }
+
+    async fn invitation(
+        &mut self,
+        signing_key: &SigningKey,
+        recipient: &AgreementPublicKey,
+        permission: SharePermission,
+    ) -> Result<VaultInvitation, Error> {
+        let share = self.share_contents(permission).await?;
+        let generation = self.database.next_share_generation(self.id).await?;
+
+        VaultInvitation::seal(signing_key, self.uuid, generation, recipient, &share)
+    }
+
+    async fn share_contents(&mut self, permission: SharePermission) -> Result<VaultShare, Error> {
+        let mut items = Vec::new();
+        for preview in self.list_items().await? {
+            let item = self.get_item(preview.id()).await?;
+            items.push(SharedItem {
+                overview: item.overview()?,
+                data: item.decrypt_data()?,
+            });
+        }
+
+        Ok(VaultShare {
+            name: self.name.clone(),
+            vault_key: self.vault_key.secret()?,
+            permission,
+            items,
+        })
+    }
+
+    fn audit_log(&self) -> AuditLog {
+        self.vault_key.audit_log(self.database.clone())
+    }
+
+    // the change is also reported to the cache by the store's change feed,
+    // but not before the next poll
+    fn invalidate_overviews(&self) {
+        if let Some(cache) = &self.overview_cache {
+            cache.invalidate(self.uuid);
+        }
+    }
+
+    async fn signing_key(&mut self) -> Result<SigningKey, Error> {
+        let profile = self
+            .database
+            .get_profile()
+            .await?
+            .ok_or(Error::StoreNotInitialized)?
+            .try_into_profile()?;
+        let key_pairs = profile.key_pairs()?;
+
+        let master_key = self.vault_key.master_key()?.clone();
+
+        store::unwrap_profile_key(
+            &mut self.database,
+            &master_key,
+            key_pairs.signing_key_id,
+            key_pairs.verifying_key.id(),
+        )
+        .await
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.uuid
     }
 
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    pub fn access(&self) -> VaultAccess {
+        self.access
+    }
+
+    /// The verifying key of the profile that shared the vault, `None` for
+    /// vaults this profile owns.
+    pub fn shared_by(&self) -> Option<&VerifyingKey> {
+        self.shared_by.as_ref()
+    }
+
     pub fn created_at(&self) -> DateTime<Utc> {
         self.created_at
     }
@@ -162,17 +563,49 @@ impl Vault {
     }
 }
 
+/// Encrypts an item with fresh keys wrapped by the vault key and stores it.
+pub(crate) async fn insert_item<R: Repository + ?Sized>(
+    repo: &mut R,
+    vault_id: i64,
+    vault_key: &VaultKey,
+    item_overview: &ItemOverview,
+    item_data: &ItemData,
+) -> Result<(ItemRecord, EncryptedKey, EncryptedKey), Error> {
+    let overview_key = SymmetricKey::generate(&mut OsRng);
+    let data_key = SymmetricKey::generate(&mut OsRng);
+
+    let enc_item_overview = overview_key.encrypt(item_overview)?;
+    let enc_item_data = data_key.encrypt(item_data)?;
+
+    let mut enc_overview_key = overview_key.into_encrypted_key(vault_key)?;
+    enc_overview_key.store(repo).await?;
+    let mut enc_data_key = data_key.into_encrypted_key(vault_key)?;
+    enc_data_key.store(repo).await?;
+
+    let item_record = repo
+        .store_item(&NewItemRecord::new(
+            vault_id,
+            &enc_item_overview,
+            enc_overview_key.id().unwrap(),
+            &enc_item_data,
+            enc_data_key.id().unwrap(),
+        ))
+        .await?;
+
+    Ok((item_record, enc_overview_key, enc_data_key))
+}
+
 pub struct VaultPreview {
-    id: i64,
+    id: Uuid,
     name: String,
 }
 
 impl VaultPreview {
-    pub(crate) fn new(id: i64, name: String) -> Self {
+    pub(crate) fn new(id: Uuid, name: String) -> Self {
         Self { id, name }
     }
 
-    pub fn id(&self) -> i64 {
+    pub fn id(&self) -> Uuid {
         self.id
     }
 
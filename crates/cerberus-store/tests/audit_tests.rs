@@ -0,0 +1,249 @@
+mod common;
+
+use cerberus_store::audit::{AuditEvent, AuditEventKind, AuditQuery};
+use cerberus_store::item::{ItemData, ItemOverview};
+use cerberus_store::{Error, Store};
+use chrono::{TimeZone, Utc};
+use common::{ManualClock, with_profile};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+async fn store_with_history(pool: &SqlitePool) -> Store {
+    let store = with_profile(Store::from_pool(pool.clone()).unwrap(), "User").await;
+
+    let mut vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let item = vault
+        .create_item(
+            ItemOverview::new("mail".to_owned(), "https://mail.com".to_owned()),
+            ItemData::new("mail-password".to_owned()),
+        )
+        .await
+        .unwrap();
+    item.data().await.unwrap();
+    vault.delete_item(item.id()).await.unwrap();
+
+    store
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn records_store_vault_and_item_events(pool: SqlitePool) {
+    let store = store_with_history(&pool).await;
+    let before_unlock = Utc::now();
+    store.lock().unwrap();
+    store.unlock("password").await.unwrap();
+
+    let entries = store.audit_entries(&AuditQuery::new()).await.unwrap();
+    let kinds: Vec<_> = entries.iter().map(|entry| entry.event().kind()).collect();
+    assert_eq!(
+        kinds,
+        [
+            AuditEventKind::VaultCreated,
+            AuditEventKind::ItemCreated,
+            AuditEventKind::ItemRevealed,
+            AuditEventKind::ItemDeleted,
+            AuditEventKind::Unlocked,
+        ]
+    );
+    assert!(
+        entries
+            .iter()
+            .enumerate()
+            .all(|(i, entry)| entry.sequence() == i as i64 + 1)
+    );
+
+    let revealed = store
+        .audit_entries(&AuditQuery::new().kind(AuditEventKind::ItemRevealed))
+        .await
+        .unwrap();
+    let item_id = match entries[1].event() {
+        AuditEvent::ItemCreated { item_id, .. } => *item_id,
+        event => panic!("unexpected event {event:?}"),
+    };
+    assert_eq!(revealed.len(), 1);
+    assert_eq!(revealed[0].event(), &AuditEvent::ItemRevealed { item_id });
+
+    let since_unlock = store
+        .audit_entries(&AuditQuery::new().since(before_unlock))
+        .await
+        .unwrap();
+    assert_eq!(since_unlock.len(), 1);
+    assert_eq!(since_unlock[0].event(), &AuditEvent::Unlocked);
+    let until_unlock = store
+        .audit_entries(&AuditQuery::new().until(before_unlock))
+        .await
+        .unwrap();
+    assert_eq!(until_unlock.len(), 4);
+
+    assert_eq!(store.verify_audit_log().await.unwrap(), 5);
+
+    store.lock().unwrap();
+    assert!(matches!(
+        store.audit_entries(&AuditQuery::new()).await,
+        Err(Error::Locked)
+    ));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn audit_log_is_append_only(pool: SqlitePool) {
+    store_with_history(&pool).await;
+
+    assert!(
+        sqlx::query("DELETE FROM audit_log")
+            .execute(&pool)
+            .await
+            .is_err()
+    );
+    assert!(
+        sqlx::query("UPDATE audit_log SET kind = 'unlocked'")
+            .execute(&pool)
+            .await
+            .is_err()
+    );
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn detects_edited_and_missing_entries(pool: SqlitePool) {
+    let store = store_with_history(&pool).await;
+    // someone with access to the file can get rid of the triggers
+    sqlx::raw_sql(
+        "DROP TRIGGER audit_log_no_update;
+         DROP TRIGGER audit_log_no_delete;",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    sqlx::query("UPDATE audit_log SET kind = 'unlocked' WHERE sequence = 3")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.verify_audit_log().await,
+        Err(Error::AuditLogTampered(3))
+    ));
+
+    // removing the edited entry leaves a gap
+    sqlx::query("DELETE FROM audit_log WHERE sequence = 3")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.verify_audit_log().await,
+        Err(Error::AuditLogTampered(3))
+    ));
+
+    // and renumbering what follows breaks the chain
+    sqlx::query("UPDATE audit_log SET sequence = sequence - 1 WHERE sequence > 3")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.verify_audit_log().await,
+        Err(Error::AuditLogTampered(3))
+    ));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn detects_removed_newest_entries(pool: SqlitePool) {
+    let store = store_with_history(&pool).await;
+    let entries = store.verify_audit_log().await.unwrap();
+    sqlx::raw_sql("DROP TRIGGER audit_log_no_delete;")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    sqlx::query("DELETE FROM audit_log WHERE sequence = ?")
+        .bind(entries)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.verify_audit_log().await,
+        Err(Error::AuditLogTampered(sequence)) if sequence == entries
+    ));
+
+    // the next entry follows the one that was removed
+    store.create_vault("Work".to_owned()).await.unwrap();
+    assert!(matches!(
+        store.verify_audit_log().await,
+        Err(Error::AuditLogTampered(sequence)) if sequence == entries
+    ));
+
+    // and the head can't be moved back with them
+    sqlx::query("UPDATE audit_log_head SET sequence = sequence - 2")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(matches!(
+        store.verify_audit_log().await,
+        Err(Error::AuditLogTampered(_))
+    ));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn detects_a_log_emptied_with_its_head(pool: SqlitePool) {
+    let store = store_with_history(&pool).await;
+    sqlx::raw_sql(
+        "DROP TRIGGER audit_log_no_delete;
+         DELETE FROM audit_log;
+         DELETE FROM audit_log_head;",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        store.verify_audit_log().await,
+        Err(Error::AuditLogTampered(1))
+    ));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn entries_are_written_with_their_operation(pool: SqlitePool) {
+    let store = store_with_history(&pool).await;
+    let vault_id = store.list_vaults().await.unwrap()[0].id();
+    let mut vault = store.get_vault(vault_id).await.unwrap().unwrap();
+
+    // nothing is recorded for operations that fail
+    assert!(matches!(
+        vault.delete_item(Uuid::now_v7()).await,
+        Err(Error::ItemDoesNotExist)
+    ));
+    assert!(matches!(
+        vault.revoke_share(Uuid::now_v7()).await,
+        Err(Error::ShareDoesNotExist)
+    ));
+    assert_eq!(store.verify_audit_log().await.unwrap(), 4);
+}
+
+#[test]
+fn integer_ids_of_earlier_entries_read_as_nil() {
+    let event: AuditEvent =
+        serde_json::from_str(r#"{"kind": "item_deleted", "vault_id": 1, "item_id": 7}"#).unwrap();
+    assert_eq!(
+        event,
+        AuditEvent::ItemDeleted {
+            vault_id: Uuid::nil(),
+            item_id: Uuid::nil(),
+        }
+    );
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn entries_are_timed_by_the_store_clock(pool: SqlitePool) {
+    let now = Utc.with_ymd_and_hms(2024, 2, 29, 12, 0, 0).unwrap();
+    let store = with_profile(
+        Store::from_pool(pool)
+            .unwrap()
+            .with_clock(ManualClock::at(now)),
+        "User",
+    )
+    .await;
+    store.create_vault("Personal".to_owned()).await.unwrap();
+
+    let entries = store.audit_entries(&AuditQuery::new()).await.unwrap();
+    assert!(!entries.is_empty());
+    assert!(entries.iter().all(|entry| entry.occurred_at() == now));
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::time::Duration;
+
+use cerberus_store::Store;
+use cerberus_store::item::{ItemData, ItemOverview};
+use common::with_profile;
+use sqlx::SqlitePool;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn cached_listings_follow_changes(pool: SqlitePool) {
+    let store = with_profile(
+        Store::from_pool(pool.clone())
+            .unwrap()
+            .with_overview_cache(100),
+        "User",
+    )
+    .await;
+    let mut vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let overview = || ItemOverview::new("My item".to_owned(), "https://my-item.com".to_owned());
+    let item = vault
+        .create_item(overview(), ItemData::new("item-password".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(vault.list_items().await.unwrap().len(), 1);
+
+    // changes made through the vault are seen straight away
+    vault
+        .create_item(overview(), ItemData::new("item-password".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(vault.list_items().await.unwrap().len(), 2);
+
+    // as another process writing to the same file would
+    sqlx::query("DELETE FROM items WHERE uuid = ?")
+        .bind(item.id())
+        .execute(&pool)
+        .await
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while vault.list_items().await.unwrap().len() != 1 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the deleted item to leave the listing");
+
+    store.lock().unwrap();
+    assert!(vault.list_items().await.is_err());
+}
@@ -0,0 +1,49 @@
+//! Fixtures shared by the integration tests, each of which uses only some of
+//! them.
+#![allow(dead_code)]
+
+use std::sync::{Arc, Mutex};
+
+use cerberus_store::Store;
+use cerberus_store::clock::Clock;
+use cerberus_store::database::MemoryBackend;
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// A clock that only moves when told to. Clones share the same time.
+#[derive(Debug, Clone)]
+pub struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::at(Utc::now())
+    }
+
+    pub fn at(now: DateTime<Utc>) -> Self {
+        Self(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn advance(&self, by: TimeDelta) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Initializes the profile of `store` as `name`, unlocked by `password`.
+pub async fn with_profile(store: Store, name: &str) -> Store {
+    store
+        .initialize_profile(name.to_owned(), "password")
+        .await
+        .unwrap();
+
+    store
+}
+
+/// A store of its own with a profile named `name`, kept in memory.
+pub async fn profile(name: &str) -> Store {
+    with_profile(Store::from_backend(MemoryBackend::new()).unwrap(), name).await
+}
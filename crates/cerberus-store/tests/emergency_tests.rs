@@ -0,0 +1,213 @@
+mod common;
+
+use cerberus_secret::ExposeSecret;
+use cerberus_store::emergency::EmergencyRequestState;
+use cerberus_store::item::{ItemData, ItemOverview};
+use cerberus_store::share::{SharePermission, VaultAccess};
+use cerberus_store::{Error, Store};
+use chrono::TimeDelta;
+use common::{ManualClock, profile, with_profile};
+use sqlx::SqlitePool;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+async fn owner(pool: &SqlitePool, clock: &ManualClock) -> Store {
+    with_profile(
+        Store::from_pool(pool.clone())
+            .unwrap()
+            .with_clock(clock.clone()),
+        "Owner",
+    )
+    .await
+}
+
+const WAITING_PERIOD: TimeDelta = TimeDelta::days(2);
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn grants_access_after_the_waiting_period(pool: SqlitePool) {
+    let clock = ManualClock::new();
+    let owner = owner(&pool, &clock).await;
+    let contact = profile("Contact").await;
+
+    let vault = owner.create_vault("Family".to_owned()).await.unwrap();
+    vault
+        .create_item(
+            ItemOverview::new("bank".to_owned(), "https://bank.com".to_owned()),
+            ItemData::new("bank-password".to_owned()),
+        )
+        .await
+        .unwrap();
+    owner.create_vault("Private".to_owned()).await.unwrap();
+    let contact_key = contact.agreement_public_key().unwrap();
+    owner
+        .add_emergency_contact(&contact_key, &[vault.id()], WAITING_PERIOD)
+        .await
+        .unwrap();
+    owner.lock().unwrap();
+
+    // the contact asks through a store that was never unlocked
+    let locked = Store::from_pool(pool).unwrap().with_clock(clock.clone());
+    let request = locked.request_emergency_access(&contact_key).await.unwrap();
+    assert_eq!(request.state(), EmergencyRequestState::Pending);
+    assert_eq!(
+        locked
+            .request_emergency_access(&contact_key)
+            .await
+            .unwrap()
+            .id(),
+        request.id()
+    );
+
+    let agreement_key = contact.agreement_key().await.unwrap();
+    assert!(matches!(
+        locked.claim_emergency_access(request.id(), &agreement_key).await,
+        Err(Error::EmergencyAccessPending(available_at)) if available_at == request.available_at()
+    ));
+
+    clock.advance(WAITING_PERIOD);
+    let mut vaults = locked
+        .claim_emergency_access(request.id(), &agreement_key)
+        .await
+        .unwrap();
+    assert_eq!(vaults.len(), 1);
+    let vault = &mut vaults[0];
+    assert_eq!(vault.name(), "Family");
+    assert_eq!(
+        vault.access(),
+        VaultAccess::Shared(SharePermission::ReadOnly)
+    );
+
+    let previews = vault.list_items().await.unwrap();
+    let item = vault.get_item(previews[0].id()).await.unwrap();
+    assert_eq!(
+        item.data().await.unwrap().secret().expose_secret(),
+        "bank-password"
+    );
+    assert!(matches!(
+        vault
+            .create_item(
+                ItemOverview::new("new".to_owned(), "https://new.com".to_owned()),
+                ItemData::new("new-password".to_owned()),
+            )
+            .await,
+        Err(Error::ReadOnlyVault)
+    ));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn owner_can_deny_access_while_waiting(pool: SqlitePool) {
+    let clock = ManualClock::new();
+    let owner = owner(&pool, &clock).await;
+    let contact = profile("Contact").await;
+
+    let vault = owner.create_vault("Family".to_owned()).await.unwrap();
+    let contact_key = contact.agreement_public_key().unwrap();
+    owner
+        .add_emergency_contact(&contact_key, &[vault.id()], WAITING_PERIOD)
+        .await
+        .unwrap();
+
+    let request = owner.request_emergency_access(&contact_key).await.unwrap();
+    owner.lock().unwrap();
+    assert!(matches!(
+        owner.deny_emergency_access(request.id()).await,
+        Err(Error::Locked)
+    ));
+    owner.unlock("password").await.unwrap();
+
+    clock.advance(WAITING_PERIOD / 2);
+    let denied = owner.deny_emergency_access(request.id()).await.unwrap();
+    assert_eq!(denied.state(), EmergencyRequestState::Denied);
+    assert!(matches!(
+        owner.approve_emergency_access(request.id()).await,
+        Err(Error::EmergencyRequestNotPending)
+    ));
+
+    clock.advance(WAITING_PERIOD);
+    let agreement_key = contact.agreement_key().await.unwrap();
+    assert!(matches!(
+        owner
+            .claim_emergency_access(request.id(), &agreement_key)
+            .await,
+        Err(Error::EmergencyAccessDenied)
+    ));
+
+    // a denied request can be followed by a new one, which can't be denied
+    // once it has waited long enough
+    let request = owner.request_emergency_access(&contact_key).await.unwrap();
+    assert_ne!(request.id(), denied.id());
+    clock.advance(WAITING_PERIOD);
+    assert!(matches!(
+        owner.deny_emergency_access(request.id()).await,
+        Err(Error::EmergencyRequestNotPending)
+    ));
+    assert_eq!(owner.emergency_requests().await.unwrap().len(), 2);
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn approved_access_survives_key_rotation(pool: SqlitePool) {
+    let clock = ManualClock::new();
+    let owner = owner(&pool, &clock).await;
+    let contact = profile("Contact").await;
+    let other = profile("Other").await;
+
+    let mut vault = owner.create_vault("Family".to_owned()).await.unwrap();
+    let contact_key = contact.agreement_public_key().unwrap();
+    owner
+        .add_emergency_contact(&contact_key, &[vault.id()], WAITING_PERIOD)
+        .await
+        .unwrap();
+    assert!(matches!(
+        owner
+            .add_emergency_contact(&contact_key, &[vault.id()], WAITING_PERIOD)
+            .await,
+        Err(Error::EmergencyContactAlreadyExists)
+    ));
+
+    vault
+        .share_with(
+            &other.agreement_public_key().unwrap(),
+            SharePermission::ReadOnly,
+        )
+        .await
+        .unwrap();
+    let share = vault.shares().await.unwrap().remove(0);
+    vault.revoke_share(share.id()).await.unwrap();
+
+    let request = owner.request_emergency_access(&contact_key).await.unwrap();
+    let approved = owner.approve_emergency_access(request.id()).await.unwrap();
+    assert_eq!(approved.state(), EmergencyRequestState::Approved);
+
+    let other_key = other.agreement_key().await.unwrap();
+    assert!(matches!(
+        owner.claim_emergency_access(request.id(), &other_key).await,
+        Err(Error::UnknownEmergencyContact)
+    ));
+
+    let agreement_key = contact.agreement_key().await.unwrap();
+    let mut vaults = owner
+        .claim_emergency_access(request.id(), &agreement_key)
+        .await
+        .unwrap();
+    assert!(vaults[0].list_items().await.unwrap().is_empty());
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn rejects_waiting_periods_under_a_second(pool: SqlitePool) {
+    let owner = owner(&pool, &ManualClock::new()).await;
+    let contact_key = profile("Contact").await.agreement_public_key().unwrap();
+    let vault = owner.create_vault("Family".to_owned()).await.unwrap();
+
+    for waiting_period in [
+        TimeDelta::zero(),
+        TimeDelta::milliseconds(500),
+        -WAITING_PERIOD,
+    ] {
+        assert!(matches!(
+            owner
+                .add_emergency_contact(&contact_key, &[vault.id()], waiting_period)
+                .await,
+            Err(Error::InvalidWaitingPeriod)
+        ));
+    }
+}
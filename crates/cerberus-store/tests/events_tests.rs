@@ -0,0 +1,153 @@
+mod common;
+
+use std::time::Duration;
+
+use cerberus_store::events::{StoreEvent, Subscription};
+use cerberus_store::item::{ItemData, ItemOverview};
+use cerberus_store::{Error, Store};
+use common::with_profile;
+use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use tokio_stream::StreamExt;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+async fn next_event(subscription: &mut Subscription) -> StoreEvent {
+    tokio::time::timeout(Duration::from_secs(5), subscription.next())
+        .await
+        .expect("an event within the timeout")
+        .expect("the subscription is open")
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn streams_changes_made_through_the_store(pool: SqlitePool) {
+    let store = with_profile(Store::from_pool(pool).unwrap(), "User").await;
+    let mut subscription = store.subscribe().await.unwrap();
+
+    let vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let vault_id = vault.id();
+    let item = vault
+        .create_item(
+            ItemOverview::new("My item".to_owned(), "https://my-item.com".to_owned()),
+            ItemData::new("item-password".to_owned()),
+        )
+        .await
+        .unwrap();
+    let item_id = item.id();
+    assert_eq!(
+        next_event(&mut subscription).await,
+        StoreEvent::VaultCreated { vault_id }
+    );
+    assert_eq!(
+        next_event(&mut subscription).await,
+        StoreEvent::ItemCreated { vault_id, item_id }
+    );
+
+    let mut vault = store.get_vault(vault_id).await.unwrap().unwrap();
+    vault.delete_item(item_id).await.unwrap();
+    assert_eq!(
+        next_event(&mut subscription).await,
+        StoreEvent::ItemDeleted { vault_id, item_id }
+    );
+
+    store.lock().unwrap();
+    assert_eq!(next_event(&mut subscription).await, StoreEvent::Locked);
+    store.unlock("password").await.unwrap();
+    assert_eq!(next_event(&mut subscription).await, StoreEvent::Unlocked);
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn streams_changes_made_by_other_connections(pool: SqlitePool) {
+    let store = with_profile(Store::from_pool(pool.clone()).unwrap(), "User").await;
+    let vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let item = vault
+        .create_item(
+            ItemOverview::new("My item".to_owned(), "https://my-item.com".to_owned()),
+            ItemData::new("item-password".to_owned()),
+        )
+        .await
+        .unwrap();
+    let (vault_id, item_id) = (vault.id(), item.id());
+
+    let mut subscription = store.subscribe().await.unwrap();
+    // as another process writing to the same file would
+    sqlx::query("UPDATE vaults SET name = 'Renamed' WHERE uuid = ?")
+        .bind(vault_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE items SET updated_at = CURRENT_TIMESTAMP WHERE uuid = ?")
+        .bind(item_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        next_event(&mut subscription).await,
+        StoreEvent::VaultRenamed { vault_id }
+    );
+    assert_eq!(
+        next_event(&mut subscription).await,
+        StoreEvent::ItemUpdated { vault_id, item_id }
+    );
+}
+
+async fn in_memory_store(options: SqliteConnectOptions) -> Store {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .unwrap();
+    MIGRATOR.run(&pool).await.unwrap();
+
+    Store::from_pool(pool).unwrap()
+}
+
+#[tokio::test]
+async fn follows_shared_in_memory_databases() {
+    let store = with_profile(
+        in_memory_store("sqlite::memory:".parse().unwrap())
+            .await
+            .with_overview_cache(16),
+        "User",
+    )
+    .await;
+    let mut subscription = store.subscribe().await.unwrap();
+
+    let vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    assert_eq!(
+        next_event(&mut subscription).await,
+        StoreEvent::VaultCreated {
+            vault_id: vault.id()
+        }
+    );
+    store.lock().unwrap();
+    store.unlock("password").await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_private_in_memory_databases() {
+    let store = with_profile(
+        in_memory_store(SqliteConnectOptions::new().in_memory(true)).await,
+        "User",
+    )
+    .await;
+
+    assert!(matches!(
+        store.subscribe().await,
+        Err(Error::PrivateInMemoryDatabase)
+    ));
+    store.lock().unwrap();
+    let store = store.with_overview_cache(16);
+    assert!(matches!(
+        store.unlock("password").await,
+        Err(Error::PrivateInMemoryDatabase)
+    ));
+
+    // the failed unlock left the store locked, so it can be tried again
+    assert!(store.is_locked());
+    assert!(matches!(
+        store.unlock("password").await,
+        Err(Error::PrivateInMemoryDatabase)
+    ));
+}
@@ -0,0 +1,272 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use cerberus_secret::ExposeSecret;
+use cerberus_store::database::{self, Backend, FileBackend, MemoryBackend, SqliteBackend};
+use cerberus_store::item::{ItemData, ItemOverview, ItemQuery};
+use cerberus_store::share::SharePermission;
+use cerberus_store::{Error, Store};
+use chrono::TimeDelta;
+use common::{profile, with_profile};
+use sqlx::SqlitePool;
+use uuid::Uuid;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+fn overview() -> ItemOverview {
+    ItemOverview::new("My item".to_owned(), "https://my-item.com".to_owned())
+}
+
+#[tokio::test]
+async fn keeps_a_file_per_record() {
+    let directory = tempfile::tempdir().unwrap();
+    let store = with_profile(
+        Store::from_backend(FileBackend::new(directory.path()).await.unwrap()).unwrap(),
+        "User",
+    )
+    .await;
+    let mut vault = store.create_vault("Team".to_owned()).await.unwrap();
+    let kept = vault
+        .create_item(overview(), ItemData::new("kept".to_owned()))
+        .await
+        .unwrap();
+    let deleted = vault
+        .create_item(overview(), ItemData::new("deleted".to_owned()))
+        .await
+        .unwrap();
+    vault.delete_item(deleted.id()).await.unwrap();
+
+    let item_path = |id: Uuid| directory.path().join(format!("items/{id}.json"));
+    assert!(directory.path().join("index.json").is_file());
+    assert!(item_path(kept.id()).is_file());
+    assert!(!item_path(deleted.id()).exists());
+
+    let store = Store::from_backend(FileBackend::new(directory.path()).await.unwrap()).unwrap();
+    store.unlock("password").await.unwrap();
+    let mut vault = store.get_vault(vault.id()).await.unwrap().unwrap();
+    assert_eq!(vault.list_items().await.unwrap().len(), 1);
+    let data = vault
+        .get_item(kept.id())
+        .await
+        .unwrap()
+        .data()
+        .await
+        .unwrap();
+    assert_eq!(data.secret().expose_secret(), "kept");
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn migrates_between_sqlite_and_files(pool: SqlitePool) {
+    let sqlite = SqliteBackend::from_pool(pool);
+    let store = with_profile(Store::from_backend(sqlite.clone()).unwrap(), "User").await;
+    let vault = store.create_vault("Team".to_owned()).await.unwrap();
+    let item = vault
+        .create_item(overview(), ItemData::new("secret".to_owned()))
+        .await
+        .unwrap();
+
+    let directory = tempfile::tempdir().unwrap();
+    database::migrate(&sqlite, &FileBackend::new(directory.path()).await.unwrap())
+        .await
+        .unwrap();
+
+    let files = FileBackend::new(directory.path()).await.unwrap();
+    assert!(matches!(
+        database::migrate(&sqlite, &files).await,
+        Err(Error::ProfileAlreadyExists)
+    ));
+    let store = Store::from_backend(files.clone()).unwrap();
+    store.unlock("password").await.unwrap();
+    let mut vault = store.get_vault(vault.id()).await.unwrap().unwrap();
+    let data = vault
+        .get_item(item.id())
+        .await
+        .unwrap()
+        .data()
+        .await
+        .unwrap();
+    assert_eq!(data.secret().expose_secret(), "secret");
+    store.verify_audit_log().await.unwrap();
+
+    // and back again, into a database of its own
+    let copy = SqliteBackend::new(directory.path().join("store.db"))
+        .await
+        .unwrap();
+    database::migrate(&files, &copy).await.unwrap();
+    assert_eq!(copy.export().await.unwrap(), files.export().await.unwrap());
+}
+
+/// The files git would track in `root`, by their path in it.
+fn tracked_files(root: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+    let gitignore = fs::read_to_string(root.join(".gitignore")).unwrap();
+    assert!(gitignore.lines().any(|line| line == "/audit-log/"));
+
+    let mut files = BTreeMap::new();
+    let mut directories = vec![root.to_owned()];
+    while let Some(directory) = directories.pop() {
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                if path != root.join("audit-log") {
+                    directories.push(path);
+                }
+            } else {
+                let contents = fs::read(&path).unwrap();
+                files.insert(path.strip_prefix(root).unwrap().to_owned(), contents);
+            }
+        }
+    }
+
+    files
+}
+
+/// Copies the files git would track from `from` into `to`, as a clone would.
+fn clone(from: &Path, to: &Path) {
+    for (path, contents) in tracked_files(from) {
+        write_tracked_file(to, &path, contents);
+    }
+}
+
+/// Brings the changes made to `theirs` since `base` into `ours`, as a merge
+/// without conflicts would.
+fn merge(ours: &Path, theirs: &Path, base: &BTreeMap<PathBuf, Vec<u8>>) {
+    let ours_files = tracked_files(ours);
+    for (path, contents) in tracked_files(theirs) {
+        if base.get(&path) == Some(&contents) {
+            continue;
+        }
+        match ours_files.get(&path) {
+            Some(ours_contents) if base.get(&path) != Some(ours_contents) => {
+                assert_eq!(ours_contents, &contents, "{path:?} conflicts");
+            }
+            _ => write_tracked_file(ours, &path, contents),
+        }
+    }
+}
+
+fn write_tracked_file(root: &Path, path: &Path, contents: Vec<u8>) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+async fn open(root: &Path) -> Store {
+    let store = Store::from_backend(FileBackend::new(root).await.unwrap()).unwrap();
+    store.unlock("password").await.unwrap();
+
+    store
+}
+
+#[tokio::test]
+async fn merges_copies_changed_apart() {
+    let ours = tempfile::tempdir().unwrap();
+    let store = with_profile(
+        Store::from_backend(FileBackend::new(ours.path()).await.unwrap()).unwrap(),
+        "User",
+    )
+    .await;
+    let vault_id = store.create_vault("Team".to_owned()).await.unwrap().id();
+    let theirs = tempfile::tempdir().unwrap();
+    clone(ours.path(), theirs.path());
+    let base = tracked_files(ours.path());
+
+    let store = open(ours.path()).await;
+    let mut vault = store.get_vault(vault_id).await.unwrap().unwrap();
+    let our_item = vault
+        .create_item(overview(), ItemData::new("ours".to_owned()))
+        .await
+        .unwrap();
+    let cursor = vault
+        .items(&ItemQuery::new().limit(1))
+        .await
+        .unwrap()
+        .next_cursor()
+        .unwrap()
+        .clone();
+    let recipient = profile("Contact").await.agreement_public_key().unwrap();
+    vault
+        .share_with(&recipient, SharePermission::ReadOnly)
+        .await
+        .unwrap();
+    let share_id = vault.shares().await.unwrap()[0].id();
+
+    let store = open(theirs.path()).await;
+    let vault = store.get_vault(vault_id).await.unwrap().unwrap();
+    let their_item = vault
+        .create_item(overview(), ItemData::new("theirs".to_owned()))
+        .await
+        .unwrap();
+    let emergency_contact = profile("Contact").await.agreement_public_key().unwrap();
+    store
+        .add_emergency_contact(&emergency_contact, &[vault_id], TimeDelta::days(1))
+        .await
+        .unwrap();
+
+    merge(ours.path(), theirs.path(), &base);
+
+    let files = FileBackend::new(ours.path()).await.unwrap();
+    let snapshot = files.export().await.unwrap();
+    assert_eq!(snapshot.items.len(), 2);
+    assert_eq!(snapshot.vault_shares.len(), 1);
+    assert_eq!(snapshot.emergency_contacts.len(), 1);
+    assert_eq!(snapshot.emergency_vault_keys.len(), 1);
+
+    let store = Store::from_backend(files).unwrap();
+    store.unlock("password").await.unwrap();
+    let mut vault = store.get_vault(vault_id).await.unwrap().unwrap();
+    let shares = vault.shares().await.unwrap();
+    assert_eq!(shares.len(), 1);
+    assert_eq!(shares[0].id(), share_id);
+    // the merge renumbered the items, which a cursor taken before it doesn't notice
+    let page = vault.items(&ItemQuery::new().after(cursor)).await.unwrap();
+    let listed: Vec<_> = page.items().iter().map(|item| item.id()).collect();
+    assert_eq!(listed, [their_item.id()]);
+    for (item, secret) in [(our_item, "ours"), (their_item, "theirs")] {
+        let data = vault
+            .get_item(item.id())
+            .await
+            .unwrap()
+            .data()
+            .await
+            .unwrap();
+        assert_eq!(data.secret().expose_secret(), secret);
+    }
+    vault.revoke_share(share_id).await.unwrap();
+    store.verify_audit_log().await.unwrap();
+}
+
+#[tokio::test]
+async fn rejects_records_imported_twice() {
+    let source = MemoryBackend::new();
+    let store = with_profile(Store::from_backend(source.clone()).unwrap(), "User").await;
+    store.create_vault("Team".to_owned()).await.unwrap();
+
+    let memory = MemoryBackend::new();
+    let snapshot = source.export().await.unwrap();
+    memory.import(snapshot.clone()).await.unwrap();
+    assert!(matches!(
+        memory.import(snapshot).await,
+        Err(Error::RecordAlreadyExists)
+    ));
+}
+
+#[tokio::test]
+async fn migrates_only_from_existing_stores() {
+    let directory = tempfile::tempdir().unwrap();
+    let missing = directory.path().join("missing");
+    assert!(FileBackend::open(&missing).await.is_err());
+    assert!(SqliteBackend::open(missing.join("store.db")).await.is_err());
+    assert!(!missing.exists());
+
+    let empty = FileBackend::new(directory.path().join("empty"))
+        .await
+        .unwrap();
+    let to = FileBackend::new(directory.path().join("to")).await.unwrap();
+    assert!(matches!(
+        database::migrate(&empty, &to).await,
+        Err(Error::StoreNotInitialized)
+    ));
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use cerberus_store::Store;
+use cerberus_store::item::{ItemCursor, ItemData, ItemOverview, ItemQuery, ItemSortKey};
+use cerberus_store::vault::Vault;
+use common::with_profile;
+use sqlx::SqlitePool;
+use tokio_stream::StreamExt;
+use uuid::Uuid;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+async fn create_items(vault: &Vault, count: usize) -> Vec<Uuid> {
+    let mut ids = Vec::new();
+    for i in 0..count {
+        let item = vault
+            .create_item(
+                ItemOverview::new(format!("item {i}"), "https://site.com".to_owned()),
+                ItemData::new("secret".to_owned()),
+            )
+            .await
+            .unwrap();
+        ids.push(item.id());
+    }
+
+    ids
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn pages_through_a_vault(pool: SqlitePool) {
+    let store = with_profile(Store::from_pool(pool.clone()).unwrap(), "User").await;
+    let vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let ids = create_items(&vault, 5).await;
+
+    let mut listed = Vec::new();
+    let mut query = ItemQuery::new().limit(2);
+    loop {
+        let page = vault.items(&query).await.unwrap();
+        listed.extend(page.items().iter().map(|item| item.id()));
+        match page.next_cursor() {
+            // cursors survive being handed to a client and back
+            Some(cursor) => {
+                let cursor: ItemCursor =
+                    serde_json::from_str(&serde_json::to_string(cursor).unwrap()).unwrap();
+                query = query.after(cursor);
+            }
+            None => break,
+        }
+    }
+    assert_eq!(listed, ids);
+
+    // the most recently updated item sorts last
+    sqlx::query("UPDATE items SET updated_at = '2100-01-01 00:00:00' WHERE uuid = ?")
+        .bind(ids[0])
+        .execute(&pool)
+        .await
+        .unwrap();
+    let page = vault
+        .items(&ItemQuery::new().sort_by(ItemSortKey::UpdatedAt).limit(4))
+        .await
+        .unwrap();
+    let query = ItemQuery::new().after(page.next_cursor().unwrap().clone());
+    let page = vault.items(&query).await.unwrap();
+    assert_eq!(page.items().len(), 1);
+    assert_eq!(page.items()[0].id(), ids[0]);
+    assert!(page.next_cursor().is_none());
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn streams_items_across_every_vault(pool: SqlitePool) {
+    let store = with_profile(Store::from_pool(pool).unwrap(), "User").await;
+    let personal = store.create_vault("Personal".to_owned()).await.unwrap();
+    let work = store.create_vault("Work".to_owned()).await.unwrap();
+    let mut ids = create_items(&personal, 3).await;
+    ids.extend(create_items(&work, 4).await);
+
+    let items: Vec<_> = store
+        .stream_items(ItemQuery::new().limit(2))
+        .collect::<Result<_, _>>()
+        .await
+        .unwrap();
+    assert_eq!(items.iter().map(|item| item.id()).collect::<Vec<_>>(), ids);
+    assert_eq!(items[0].vault_id(), personal.id());
+    assert_eq!(items[6].vault_id(), work.id());
+    assert_eq!(items[6].overview().name(), "item 3");
+
+    let work_items: Vec<_> = work
+        .stream_items(ItemQuery::new().limit(3))
+        .collect::<Result<_, _>>()
+        .await
+        .unwrap();
+    assert_eq!(work_items.len(), 4);
+
+    store.lock().unwrap();
+    let mut stream = store.stream_items(ItemQuery::new());
+    assert!(matches!(
+        stream.next().await,
+        Some(Err(cerberus_store::Error::Locked))
+    ));
+    assert!(stream.next().await.is_none());
+}
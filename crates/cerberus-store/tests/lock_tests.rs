@@ -0,0 +1,126 @@
+mod common;
+
+use std::time::Duration;
+
+use cerberus_store::item::{ItemData, ItemOverview};
+use cerberus_store::lock::{LockPolicy, LockState};
+use cerberus_store::{Error, Store};
+use chrono::TimeDelta;
+use common::{ManualClock, with_profile};
+use sqlx::SqlitePool;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+async fn store(pool: SqlitePool, clock: &ManualClock, lock_policy: LockPolicy) -> Store {
+    with_profile(
+        Store::from_pool(pool)
+            .unwrap()
+            .with_clock(clock.clone())
+            .with_lock_policy(lock_policy),
+        "User",
+    )
+    .await
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn locks_after_being_idle(pool: SqlitePool) {
+    let clock = ManualClock::new();
+    let lock_policy = LockPolicy::new().idle_timeout(TimeDelta::minutes(5));
+    let store = store(pool, &clock, lock_policy).await;
+    let lock_state = store.lock_state();
+
+    let mut vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    vault
+        .create_item(
+            ItemOverview::new("mail".to_owned(), "https://mail.com".to_owned()),
+            ItemData::new("mail-password".to_owned()),
+        )
+        .await
+        .unwrap();
+
+    // every use of the key starts the timeout over
+    for _ in 0..3 {
+        clock.advance(TimeDelta::minutes(4));
+        assert_eq!(vault.list_items().await.unwrap().len(), 1);
+    }
+    assert_eq!(*lock_state.borrow(), LockState::Unlocked);
+
+    clock.advance(TimeDelta::minutes(5));
+    assert!(matches!(vault.list_items().await, Err(Error::Locked)));
+    assert_eq!(*lock_state.borrow(), LockState::Locked);
+    assert!(matches!(
+        store.create_vault("Work".to_owned()).await,
+        Err(Error::Locked)
+    ));
+
+    store.unlock("password").await.unwrap();
+    assert_eq!(vault.list_items().await.unwrap().len(), 1);
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn locks_after_the_maximum_unlocked_duration(pool: SqlitePool) {
+    let clock = ManualClock::new();
+    let lock_policy = LockPolicy::new()
+        .idle_timeout(TimeDelta::minutes(30))
+        .max_unlocked(TimeDelta::hours(1));
+    let store = store(pool, &clock, lock_policy).await;
+
+    let mut vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    for _ in 0..2 {
+        clock.advance(TimeDelta::minutes(25));
+        vault.list_items().await.unwrap();
+    }
+
+    clock.advance(TimeDelta::minutes(10));
+    assert!(matches!(vault.list_items().await, Err(Error::Locked)));
+    assert!(matches!(store.lock(), Err(Error::Locked)));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn handles_fail_once_the_store_is_locked(pool: SqlitePool) {
+    let clock = ManualClock::new();
+    let store = store(pool, &clock, LockPolicy::new()).await;
+    let mut lock_state = store.lock_state();
+
+    let mut vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let item = vault
+        .create_item(
+            ItemOverview::new("mail".to_owned(), "https://mail.com".to_owned()),
+            ItemData::new("mail-password".to_owned()),
+        )
+        .await
+        .unwrap();
+
+    // without a policy the store stays unlocked
+    clock.advance(TimeDelta::days(365));
+    item.data().await.unwrap();
+
+    store.lock().unwrap();
+    lock_state
+        .wait_for(|state| *state == LockState::Locked)
+        .await
+        .unwrap();
+    assert!(matches!(item.data().await, Err(Error::Locked)));
+    assert!(matches!(item.overview(), Err(Error::Locked)));
+    assert!(matches!(vault.list_items().await, Err(Error::Locked)));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn locks_in_the_background(pool: SqlitePool) {
+    let store = with_profile(
+        Store::from_pool(pool)
+            .unwrap()
+            .with_lock_policy(LockPolicy::new().idle_timeout(TimeDelta::milliseconds(50))),
+        "User",
+    )
+    .await;
+    let mut lock_state = store.lock_state();
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        lock_state.wait_for(|state| *state == LockState::Locked),
+    )
+    .await
+    .expect("the store locks without being used")
+    .unwrap();
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use std::time::Duration;
+
+use cerberus_secret::ExposeSecret;
+use cerberus_store::Store;
+use cerberus_store::database::record_types::NewKeyRecord;
+use cerberus_store::database::{Backend, MemoryBackend};
+use cerberus_store::events::StoreEvent;
+use cerberus_store::item::{ItemData, ItemOverview, ItemQuery};
+use common::with_profile;
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn stores_vaults_and_items_in_memory() {
+    let backend = MemoryBackend::new();
+    let store = with_profile(Store::from_backend(backend.clone()).unwrap(), "User").await;
+
+    let mut vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let mut ids = Vec::new();
+    for i in 0..3 {
+        let item = vault
+            .create_item(
+                ItemOverview::new(format!("item {i}"), "https://site.com".to_owned()),
+                ItemData::new(format!("secret {i}")),
+            )
+            .await
+            .unwrap();
+        ids.push(item.id());
+    }
+    vault.delete_item(ids[1]).await.unwrap();
+
+    let page = vault.items(&ItemQuery::new().limit(1)).await.unwrap();
+    let query = ItemQuery::new().after(page.next_cursor().unwrap().clone());
+    let rest = vault.items(&query).await.unwrap();
+    assert_eq!(page.items()[0].id(), ids[0]);
+    assert_eq!(rest.items().len(), 1);
+    assert_eq!(rest.items()[0].id(), ids[2]);
+
+    // another store on the same backend sees the same data
+    let other = Store::from_backend(backend).unwrap();
+    other.unlock("password").await.unwrap();
+    let mut vault = other.get_vault(vault.id()).await.unwrap().unwrap();
+    let data = vault.get_item(ids[2]).await.unwrap().data().await.unwrap();
+    assert_eq!(data.secret().expose_secret(), "secret 2");
+    assert!(other.verify_audit_log().await.unwrap() > 0);
+
+    other.lock().unwrap();
+    assert!(other.unlock("wrong password").await.is_err());
+}
+
+#[tokio::test]
+async fn subscriptions_see_changes_in_memory() {
+    let store = with_profile(Store::from_backend(MemoryBackend::new()).unwrap(), "User").await;
+    let mut events = store.subscribe().await.unwrap();
+
+    let vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .unwrap();
+    assert_eq!(
+        event,
+        Some(StoreEvent::VaultCreated {
+            vault_id: vault.id()
+        })
+    );
+}
+
+#[tokio::test]
+async fn transactions_are_only_seen_once_committed() {
+    let backend = MemoryBackend::new();
+    let key = |nonce: u8| NewKeyRecord {
+        format_version: 1,
+        encrypted_key: vec![1],
+        nonce: vec![nonce],
+        parent_key_id: None,
+    };
+    let first = backend.repository().store_key(&key(1)).await.unwrap();
+    // deleting the last key would free its id for the next one
+    backend.repository().store_key(&key(0)).await.unwrap();
+
+    let mut transaction = backend.begin().await.unwrap();
+    transaction.delete_key(first.id).await.unwrap();
+    let second = transaction.store_key(&key(2)).await.unwrap();
+    assert!(transaction.find_key(first.id).await.unwrap().is_none());
+    assert!(
+        backend
+            .repository()
+            .find_key(second.id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    transaction.rollback().await.unwrap();
+    assert!(
+        backend
+            .repository()
+            .find_key(first.id)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    let mut transaction = backend.begin().await.unwrap();
+    transaction.update_key(first.id, &key(3)).await.unwrap();
+    let second = transaction.store_key(&key(2)).await.unwrap();
+    let child = NewKeyRecord {
+        parent_key_id: Some(first.id),
+        ..key(4)
+    };
+    transaction.store_key(&child).await.unwrap();
+    assert_eq!(
+        backend
+            .repository()
+            .find_key(first.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .nonce,
+        [1]
+    );
+    transaction.commit().await.unwrap();
+
+    let mut repository = backend.repository();
+    assert_eq!(
+        repository.find_key(first.id).await.unwrap().unwrap().nonce,
+        [3]
+    );
+    assert_eq!(
+        repository.find_key(second.id).await.unwrap().unwrap().nonce,
+        [2]
+    );
+    let children = repository.list_child_keys(first.id).await.unwrap();
+    assert_eq!(children.len(), 1);
+    assert_eq!(children[0].nonce, [4]);
+}
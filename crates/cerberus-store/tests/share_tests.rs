@@ -0,0 +1,209 @@
+mod common;
+
+use cerberus_secret::ExposeSecret;
+use cerberus_store::item::{ItemData, ItemOverview};
+use cerberus_store::share::{SharePermission, VaultAccess, VaultInvitation};
+use cerberus_store::vault::Vault;
+use cerberus_store::{Error, Store};
+use common::{profile, with_profile};
+use sqlx::SqlitePool;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+fn new_item(name: &str) -> (ItemOverview, ItemData) {
+    (
+        ItemOverview::new(name.to_owned(), format!("https://{name}.com")),
+        ItemData::new(format!("{name}-password")),
+    )
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn shares_vault_with_another_profile(pool: SqlitePool) {
+    let owner = with_profile(Store::from_pool(pool).unwrap(), "Owner").await;
+    let recipient = profile("Recipient").await;
+
+    let mut vault = owner.create_vault("Team".to_owned()).await.unwrap();
+    let (overview, data) = new_item("shared");
+    vault.create_item(overview, data).await.unwrap();
+
+    let invitation = vault
+        .share_with(
+            &recipient.agreement_public_key().unwrap(),
+            SharePermission::ReadOnly,
+        )
+        .await
+        .unwrap();
+    assert_eq!(vault.shares().await.unwrap().len(), 1);
+
+    // the invitation travels as a file
+    let invitation = VaultInvitation::from_bytes(&invitation.to_bytes().unwrap()).unwrap();
+    assert_eq!(invitation.sender(), &owner.verifying_key().unwrap());
+    assert_eq!(invitation.vault_id(), vault.id());
+
+    let mut shared = recipient
+        .accept_share(&invitation, &owner.verifying_key().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(shared.name(), "Team");
+    assert_eq!(shared.shared_by(), owner.verifying_key().ok().as_ref());
+    assert_eq!(
+        shared.access(),
+        VaultAccess::Shared(SharePermission::ReadOnly)
+    );
+
+    let previews = shared.list_items().await.unwrap();
+    assert_eq!(previews.len(), 1);
+    let item = shared.get_item(previews[0].id()).await.unwrap();
+    assert_eq!(item.overview().unwrap().name(), "shared");
+    assert_eq!(
+        item.data().await.unwrap().secret().expose_secret(),
+        "shared-password"
+    );
+
+    let (overview, data) = new_item("denied");
+    assert!(matches!(
+        shared.create_item(overview, data).await,
+        Err(Error::ReadOnlyVault)
+    ));
+    assert!(matches!(
+        shared
+            .share_with(
+                &owner.agreement_public_key().unwrap(),
+                SharePermission::ReadOnly
+            )
+            .await,
+        Err(Error::NotVaultOwner)
+    ));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn revoking_a_share_rotates_the_vault_key(pool: SqlitePool) {
+    let owner = with_profile(Store::from_pool(pool).unwrap(), "Owner").await;
+    let kept = profile("Kept").await;
+    let revoked = profile("Revoked").await;
+
+    let mut vault = owner.create_vault("Team".to_owned()).await.unwrap();
+    let (overview, data) = new_item("before");
+    vault.create_item(overview, data).await.unwrap();
+
+    let kept_invitation = vault
+        .share_with(
+            &kept.agreement_public_key().unwrap(),
+            SharePermission::ReadWrite,
+        )
+        .await
+        .unwrap();
+    vault
+        .share_with(
+            &revoked.agreement_public_key().unwrap(),
+            SharePermission::ReadOnly,
+        )
+        .await
+        .unwrap();
+    let sender = owner.verifying_key().unwrap();
+    let kept_vault_id = kept
+        .accept_share(&kept_invitation, &sender)
+        .await
+        .unwrap()
+        .id();
+
+    let revoked_share = vault
+        .shares()
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|share| share.permission() == SharePermission::ReadOnly)
+        .unwrap();
+    let reissued = vault.revoke_share(revoked_share.id()).await.unwrap();
+    assert_eq!(reissued.len(), 1);
+    assert!(matches!(
+        vault.revoke_share(revoked_share.id()).await,
+        Err(Error::ShareDoesNotExist)
+    ));
+
+    // items written before the rotation are still readable by the owner
+    let (overview, data) = new_item("after");
+    vault.create_item(overview, data).await.unwrap();
+    let mut vault = owner.get_vault(vault.id()).await.unwrap().unwrap();
+    assert_eq!(item_names(&mut vault).await, ["before", "after"]);
+
+    // the reissued invitation updates the vault accepted before, with the
+    // items as they were when it was issued
+    let mut shared = kept.accept_share(&reissued[0], &sender).await.unwrap();
+    assert_eq!(shared.id(), kept_vault_id);
+    assert_eq!(kept.list_vaults().await.unwrap().len(), 1);
+    assert_eq!(item_names(&mut shared).await, ["before"]);
+    let (overview, data) = new_item("from kept");
+    shared.create_item(overview, data).await.unwrap();
+
+    // replaying the first invitation can't roll the key back
+    assert!(matches!(
+        kept.accept_share(&kept_invitation, &sender).await,
+        Err(Error::StaleInvitation)
+    ));
+    assert!(matches!(
+        kept.accept_share(&reissued[0], &sender).await,
+        Err(Error::StaleInvitation)
+    ));
+    assert_eq!(item_names(&mut shared).await, ["before", "from kept"]);
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn rejects_invitations_for_other_profiles(pool: SqlitePool) {
+    let owner = with_profile(Store::from_pool(pool).unwrap(), "Owner").await;
+    let recipient = profile("Recipient").await;
+    let other = profile("Other").await;
+
+    let mut vault = owner.create_vault("Team".to_owned()).await.unwrap();
+    let invitation = vault
+        .share_with(
+            &recipient.agreement_public_key().unwrap(),
+            SharePermission::ReadOnly,
+        )
+        .await
+        .unwrap();
+
+    let sender = owner.verifying_key().unwrap();
+    assert!(matches!(
+        other.accept_share(&invitation, &sender).await,
+        Err(Error::InvalidInvitation)
+    ));
+
+    // the invitation carries its sender's key, but only the one handed out
+    // by the sender is trusted
+    assert!(matches!(
+        recipient
+            .accept_share(&invitation, &other.verifying_key().unwrap())
+            .await,
+        Err(Error::InvalidInvitation)
+    ));
+
+    // the signature of another invitation doesn't cover this body
+    let other_invitation = vault
+        .share_with(
+            &other.agreement_public_key().unwrap(),
+            SharePermission::ReadOnly,
+        )
+        .await
+        .unwrap();
+    let mut tampered: serde_json::Value =
+        serde_json::from_slice(&invitation.to_bytes().unwrap()).unwrap();
+    let other_invitation: serde_json::Value =
+        serde_json::from_slice(&other_invitation.to_bytes().unwrap()).unwrap();
+    tampered["signature"] = other_invitation["signature"].clone();
+    let tampered = VaultInvitation::from_bytes(tampered.to_string().as_bytes()).unwrap();
+    assert!(matches!(
+        recipient.accept_share(&tampered, &sender).await,
+        Err(Error::InvalidInvitation)
+    ));
+}
+
+async fn item_names(vault: &mut Vault) -> Vec<String> {
+    let mut names = Vec::new();
+    for preview in vault.list_items().await.unwrap() {
+        let item = vault.get_item(preview.id()).await.unwrap();
+        names.push(item.overview().unwrap().name().to_owned());
+    }
+
+    names
+}
@@ -1,12 +1,17 @@
-use cerberus_store::Store;
+mod common;
+
+use cerberus_secret::ExposeSecret;
+use cerberus_store::{Error, Store};
+use cerberus_store::database::MemoryBackend;
 use cerberus_store::item::{ItemData, ItemOverview};
+use common::with_profile;
 use sqlx::SqlitePool;
 
 static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
 
 #[sqlx::test(migrator = "MIGRATOR")]
 async fn can_store_and_retrieve_data(pool: SqlitePool) {
-    let mut store = Store::from_pool(pool).unwrap();
+    let store = Store::from_pool(pool).unwrap();
 
     let password = String::from("mypassword");
     store
@@ -30,11 +35,11 @@ async fn can_store_and_retrieve_data(pool: SqlitePool) {
         let item = vault.create_item(item_overview, item_data).await.unwrap();
 
         let item_overview = item.overview().unwrap();
-        let item_data = item.data().unwrap();
+        let item_data = item.data().await.unwrap();
 
         assert_eq!(item_overview.site(), site);
         assert_eq!(item_overview.name(), name);
-        assert_eq!(item_data.secret(), secret);
+        assert_eq!(item_data.secret().expose_secret(), &secret);
     }
 
     store.lock().unwrap();
@@ -52,10 +57,89 @@ async fn can_store_and_retrieve_data(pool: SqlitePool) {
     let item = vault.get_item(item_preview.id()).await.unwrap();
 
     let item_overview = item.overview().unwrap();
-    let item_data = item.data().unwrap();
+    let item_data = item.data().await.unwrap();
 
     assert_eq!(item_overview.site(), "https://my-item.com");
     assert_eq!(item_overview.name(), "My item");
 
-    assert_eq!(item_data.secret(), "item-password");
+    assert_eq!(item_data.secret().expose_secret(), "item-password");
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn clones_share_one_unlocked_store(pool: SqlitePool) {
+    let store = with_profile(Store::from_pool(pool).unwrap(), "User").await;
+    store.lock().unwrap();
+
+    let handle = store.clone();
+    tokio::spawn(async move { handle.unlock("password").await })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let tasks: Vec<_> = (0..8)
+        .map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move {
+                let vault = store.create_vault(format!("vault {i}")).await?;
+                store.get_vault(vault.id()).await
+            })
+        })
+        .collect();
+    for task in tasks {
+        assert!(task.await.unwrap().unwrap().is_some());
+    }
+    assert_eq!(store.list_vaults().await.unwrap().len(), 8);
+
+    store.clone().lock().unwrap();
+    assert!(matches!(
+        store.create_vault("locked".to_owned()).await,
+        Err(cerberus_store::Error::Locked)
+    ));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn key_pairs_are_identified_by_their_public_key(pool: SqlitePool) {
+    let store = with_profile(Store::from_pool(pool).unwrap(), "User").await;
+    let other = with_profile(Store::from_backend(MemoryBackend::new()).unwrap(), "Other").await;
+
+    let agreement_public_key = store.agreement_public_key().unwrap();
+    assert_eq!(
+        store.agreement_key().await.unwrap().public_key(),
+        agreement_public_key
+    );
+    assert_ne!(
+        other.agreement_public_key().unwrap().id(),
+        agreement_public_key.id()
+    );
+
+    let signature = store.signing_key().await.unwrap().sign(b"message");
+    let verifying_key = store.verifying_key().unwrap();
+    assert_eq!(signature.key_id(), verifying_key.id());
+    verifying_key.verify(b"message", &signature).unwrap();
+    assert!(
+        other
+            .verifying_key()
+            .unwrap()
+            .verify(b"message", &signature)
+            .is_err()
+    );
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn items_are_only_found_in_their_vault(pool: SqlitePool) {
+    let store = with_profile(Store::from_pool(pool).unwrap(), "User").await;
+    let personal = store.create_vault("Personal".to_owned()).await.unwrap();
+    let mut work = store.create_vault("Work".to_owned()).await.unwrap();
+    let item = personal
+        .create_item(
+            ItemOverview::new("mail".to_owned(), "https://mail.com".to_owned()),
+            ItemData::new("mail-password".to_owned()),
+        )
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        work.get_item(item.id()).await,
+        Err(Error::ItemDoesNotExist)
+    ));
 }
@@ -0,0 +1,71 @@
+mod common;
+
+use cerberus_store::item::{ItemData, ItemOverview};
+use cerberus_store::lock::LockPolicy;
+use cerberus_store::typed::{LockedStore, TypedStore};
+use cerberus_store::{Error, Store};
+use chrono::TimeDelta;
+use common::ManualClock;
+use sqlx::SqlitePool;
+
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migrations");
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn vaults_are_opened_through_an_unlocked_store(pool: SqlitePool) {
+    let store = LockedStore::from_pool(pool.clone()).unwrap();
+    let store = store
+        .initialize_profile("User".to_owned(), "password")
+        .await
+        .unwrap();
+    let vault = store.create_vault("Personal".to_owned()).await.unwrap();
+    vault
+        .create_item(
+            ItemOverview::new("My item".to_owned(), "https://my-item.com".to_owned()),
+            ItemData::new("item-password".to_owned()),
+        )
+        .await
+        .unwrap();
+
+    let store = store.lock();
+    assert_eq!(store.list_vaults().await.unwrap().len(), 1);
+    let Err((store, _)) = store.unlock("wrong password").await else {
+        panic!("unlocked with the wrong password");
+    };
+
+    let store = store.unlock("password").await.unwrap();
+    let mut vault = store.get_vault(vault.id()).await.unwrap().unwrap();
+    assert_eq!(vault.list_items().await.unwrap().len(), 1);
+
+    let store = Store::from(store);
+    assert!(matches!(
+        TypedStore::from(store.clone()),
+        TypedStore::Unlocked(_)
+    ));
+    store.lock().unwrap();
+    assert!(matches!(TypedStore::from(store), TypedStore::Locked(_)));
+}
+
+#[sqlx::test(migrator = "MIGRATOR")]
+async fn locked_stores_are_configured_before_unlocking(pool: SqlitePool) {
+    LockedStore::from_pool(pool.clone())
+        .unwrap()
+        .initialize_profile("User".to_owned(), "password")
+        .await
+        .unwrap()
+        .lock();
+
+    let clock = ManualClock::new();
+    let store = LockedStore::from_pool(pool)
+        .unwrap()
+        .with_clock(clock.clone())
+        .with_lock_policy(LockPolicy::new().max_unlocked(TimeDelta::minutes(5)))
+        .with_overview_cache(16);
+    let store = store.unlock("password").await.unwrap();
+    store.create_vault("Personal".to_owned()).await.unwrap();
+
+    clock.advance(TimeDelta::minutes(5));
+    assert!(matches!(
+        store.create_vault("Work".to_owned()).await,
+        Err(Error::Locked)
+    ));
+}